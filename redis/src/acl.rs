@@ -0,0 +1,917 @@
+use crate::command::Command;
+use crate::config::split_directive;
+use crate::error::Error;
+use crate::frame::*;
+use crate::glob::glob_match;
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/// Categories of `ACL CAT`, `all` aside
+const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "stream",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+];
+
+/// Commands with their categories, the subcommands of container commands
+/// being named `container|subcommand`
+const COMMANDS: &[(&str, &[&str])] = &[
+    ("acl|cat", &["slow"]),
+    ("acl|deluser", &["admin", "slow", "dangerous"]),
+    ("acl|getuser", &["admin", "slow", "dangerous"]),
+    ("acl|list", &["admin", "slow", "dangerous"]),
+    ("acl|load", &["admin", "slow", "dangerous"]),
+    ("acl|save", &["admin", "slow", "dangerous"]),
+    ("acl|setuser", &["admin", "slow", "dangerous"]),
+    ("acl|users", &["admin", "slow", "dangerous"]),
+    ("acl|whoami", &["slow"]),
+    ("append", &["write", "string", "fast"]),
+    ("asking", &["fast", "connection"]),
+    ("auth", &["fast", "connection"]),
+    ("bgrewriteaof", &["admin", "slow", "dangerous"]),
+    ("bgsave", &["admin", "slow", "dangerous"]),
+    ("bitcount", &["read", "bitmap", "slow"]),
+    ("bitfield", &["write", "bitmap", "slow"]),
+    ("bitop", &["write", "bitmap", "slow"]),
+    ("bitpos", &["read", "bitmap", "slow"]),
+    ("blmove", &["write", "list", "slow", "blocking"]),
+    ("blpop", &["write", "list", "slow", "blocking"]),
+    ("brpop", &["write", "list", "slow", "blocking"]),
+    ("client|getname", &["slow", "connection"]),
+    ("client|id", &["slow", "connection"]),
+    ("client|info", &["slow", "connection"]),
+    ("client|kill", &["admin", "slow", "dangerous", "connection"]),
+    ("client|list", &["admin", "slow", "dangerous", "connection"]),
+    ("client|setname", &["slow", "connection"]),
+    ("cluster|info", &["slow"]),
+    ("cluster|keyslot", &["slow"]),
+    ("cluster|myid", &["slow"]),
+    ("cluster|nodes", &["slow"]),
+    ("cluster|setslot", &["admin", "slow", "dangerous"]),
+    ("cluster|shards", &["slow"]),
+    ("cluster|slots", &["slow"]),
+    ("config|get", &["admin", "slow", "dangerous"]),
+    ("config|rewrite", &["admin", "slow", "dangerous"]),
+    ("config|set", &["admin", "slow", "dangerous"]),
+    ("dbsize", &["keyspace", "read", "fast"]),
+    ("decr", &["write", "string", "fast"]),
+    ("decrby", &["write", "string", "fast"]),
+    ("del", &["keyspace", "write", "slow"]),
+    ("discard", &["fast", "transaction"]),
+    ("exec", &["slow", "transaction"]),
+    ("exists", &["keyspace", "read", "fast"]),
+    ("expire", &["keyspace", "write", "fast"]),
+    ("expireat", &["keyspace", "write", "fast"]),
+    ("flushall", &["keyspace", "write", "slow", "dangerous"]),
+    ("flushdb", &["keyspace", "write", "slow", "dangerous"]),
+    ("geoadd", &["write", "geo", "slow"]),
+    ("geodist", &["read", "geo", "slow"]),
+    ("geohash", &["read", "geo", "slow"]),
+    ("geopos", &["read", "geo", "slow"]),
+    ("geosearch", &["read", "geo", "slow"]),
+    ("geosearchstore", &["write", "geo", "slow"]),
+    ("get", &["read", "string", "fast"]),
+    ("getbit", &["read", "bitmap", "fast"]),
+    ("getdel", &["write", "string", "fast"]),
+    ("getex", &["write", "string", "fast"]),
+    ("getrange", &["read", "string", "slow"]),
+    ("hdel", &["write", "hash", "fast"]),
+    ("hello", &["fast", "connection"]),
+    ("hexists", &["read", "hash", "fast"]),
+    ("hget", &["read", "hash", "fast"]),
+    ("hgetall", &["read", "hash", "slow"]),
+    ("hincrby", &["write", "hash", "fast"]),
+    ("hkeys", &["read", "hash", "slow"]),
+    ("hlen", &["read", "hash", "fast"]),
+    ("hmget", &["read", "hash", "fast"]),
+    ("hscan", &["read", "hash", "slow"]),
+    ("hset", &["write", "hash", "fast"]),
+    ("hvals", &["read", "hash", "slow"]),
+    ("incr", &["write", "string", "fast"]),
+    ("incrby", &["write", "string", "fast"]),
+    ("incrbyfloat", &["write", "string", "fast"]),
+    ("info", &["slow", "dangerous"]),
+    ("keys", &["keyspace", "read", "slow", "dangerous"]),
+    ("lastsave", &["admin", "fast", "dangerous"]),
+    ("latency|histogram", &["admin", "slow", "dangerous"]),
+    ("lindex", &["read", "list", "slow"]),
+    ("llen", &["read", "list", "fast"]),
+    ("lmove", &["write", "list", "slow"]),
+    ("lpop", &["write", "list", "fast"]),
+    ("lpush", &["write", "list", "fast"]),
+    ("lrange", &["read", "list", "slow"]),
+    ("lrem", &["write", "list", "slow"]),
+    ("lset", &["write", "list", "slow"]),
+    ("ltrim", &["write", "list", "slow"]),
+    ("mget", &["read", "string", "fast"]),
+    ("monitor", &["admin", "slow", "dangerous"]),
+    ("move", &["keyspace", "write", "fast"]),
+    ("mset", &["write", "string", "slow"]),
+    ("msetnx", &["write", "string", "slow"]),
+    ("multi", &["fast", "transaction"]),
+    ("persist", &["keyspace", "write", "fast"]),
+    ("pexpire", &["keyspace", "write", "fast"]),
+    ("pexpireat", &["keyspace", "write", "fast"]),
+    ("pfadd", &["write", "hyperloglog", "fast"]),
+    ("pfcount", &["read", "hyperloglog", "slow"]),
+    ("pfmerge", &["write", "hyperloglog", "slow"]),
+    ("ping", &["fast", "connection"]),
+    ("psubscribe", &["pubsub", "slow"]),
+    ("psync", &["admin", "slow", "dangerous"]),
+    ("pttl", &["keyspace", "read", "fast"]),
+    ("publish", &["pubsub", "fast"]),
+    ("pubsub|channels", &["pubsub", "slow"]),
+    ("pubsub|numpat", &["pubsub", "slow"]),
+    ("pubsub|numsub", &["pubsub", "slow"]),
+    ("punsubscribe", &["pubsub", "slow"]),
+    ("randomkey", &["keyspace", "read", "slow"]),
+    ("rename", &["keyspace", "write", "slow"]),
+    ("renamenx", &["keyspace", "write", "fast"]),
+    ("replconf", &["admin", "slow", "dangerous"]),
+    ("replicaof", &["admin", "slow", "dangerous"]),
+    ("rpop", &["write", "list", "fast"]),
+    ("rpush", &["write", "list", "fast"]),
+    ("sadd", &["write", "set", "fast"]),
+    ("save", &["admin", "slow", "dangerous"]),
+    ("scan", &["keyspace", "read", "slow"]),
+    ("scard", &["read", "set", "fast"]),
+    ("sdiff", &["read", "set", "slow"]),
+    ("select", &["fast", "connection"]),
+    ("set", &["write", "string", "slow"]),
+    ("setbit", &["write", "bitmap", "slow"]),
+    ("setnx", &["write", "string", "fast"]),
+    ("setrange", &["write", "string", "slow"]),
+    ("sinter", &["read", "set", "slow"]),
+    ("sismember", &["read", "set", "fast"]),
+    ("slowlog|get", &["admin", "slow", "dangerous"]),
+    ("slowlog|len", &["admin", "slow", "dangerous"]),
+    ("slowlog|reset", &["admin", "slow", "dangerous"]),
+    ("smembers", &["read", "set", "slow"]),
+    ("srem", &["write", "set", "fast"]),
+    ("strlen", &["read", "string", "fast"]),
+    ("subscribe", &["pubsub", "slow"]),
+    ("sunion", &["read", "set", "slow"]),
+    ("swapdb", &["keyspace", "write", "fast", "dangerous"]),
+    ("ttl", &["keyspace", "read", "fast"]),
+    ("type", &["keyspace", "read", "fast"]),
+    ("unlink", &["keyspace", "write", "fast"]),
+    ("unsubscribe", &["pubsub", "slow"]),
+    ("unwatch", &["fast", "transaction"]),
+    ("watch", &["fast", "transaction"]),
+    ("xack", &["write", "stream", "fast"]),
+    ("xadd", &["write", "stream", "fast"]),
+    ("xautoclaim", &["write", "stream", "fast"]),
+    ("xclaim", &["write", "stream", "fast"]),
+    ("xdel", &["write", "stream", "fast"]),
+    ("xgroup|create", &["write", "stream", "slow"]),
+    ("xgroup|createconsumer", &["write", "stream", "slow"]),
+    ("xgroup|delconsumer", &["write", "stream", "slow"]),
+    ("xgroup|destroy", &["write", "stream", "slow"]),
+    ("xgroup|setid", &["write", "stream", "slow"]),
+    ("xlen", &["read", "stream", "fast"]),
+    ("xpending", &["read", "stream", "slow"]),
+    ("xrange", &["read", "stream", "slow"]),
+    ("xread", &["read", "stream", "slow", "blocking"]),
+    ("xreadgroup", &["write", "stream", "slow", "blocking"]),
+    ("xrevrange", &["read", "stream", "slow"]),
+    ("xsetid", &["write", "stream", "fast"]),
+    ("zadd", &["write", "sortedset", "fast"]),
+    ("zcard", &["read", "sortedset", "fast"]),
+    ("zincrby", &["write", "sortedset", "fast"]),
+    ("zrange", &["read", "sortedset", "slow"]),
+    ("zrank", &["read", "sortedset", "fast"]),
+    ("zrem", &["write", "sortedset", "fast"]),
+    ("zrevrank", &["read", "sortedset", "fast"]),
+    ("zscore", &["read", "sortedset", "fast"]),
+];
+
+/// `ACL CAT`, the categories or the commands of a category
+pub fn categories(category: Option<&str>) -> Result<Vec<&'static str>, String> {
+    let Some(category) = category else {
+        return Ok(CATEGORIES.to_vec());
+    };
+    let category = category.to_lowercase();
+    if !CATEGORIES.contains(&&category[..]) {
+        return Err(format!("ERR Unknown category '{}'", category));
+    }
+    Ok(COMMANDS
+        .iter()
+        .filter(|(_, categories)| categories.contains(&&category[..]))
+        .map(|(name, _)| *name)
+        .collect())
+}
+
+/// Keys matching a pattern may be read, written, or both
+#[derive(Clone, PartialEq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        match (self.read, self.write) {
+            (true, true) => format!("~{}", self.pattern),
+            (true, false) => format!("%R~{}", self.pattern),
+            (false, _) => format!("%W~{}", self.pattern),
+        }
+    }
+}
+
+/// An ACL user, with the commands, keys and channels it can access
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    enabled: bool,
+    /// Any password is valid
+    nopass: bool,
+    /// SHA-256 digests of the passwords, in hexadecimal
+    passwords: Vec<String>,
+    /// Commands allowed, subcommands included
+    commands: HashSet<&'static str>,
+    /// Whether `+@all` is the base of the command rules
+    all_commands: bool,
+    /// Rules applied since the last `+@all` or `-@all`, for `ACL LIST`
+    command_rules: Vec<String>,
+    keys: Vec<KeyPattern>,
+    channels: Vec<String>,
+}
+
+impl User {
+    /// A new user is disabled and cannot access anything
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            commands: HashSet::new(),
+            all_commands: false,
+            command_rules: vec![],
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+
+    /// The default user is enabled and can access everything, until a
+    /// password or rules are set
+    fn default_user() -> Self {
+        let mut user = Self::new("default");
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            user.apply(rule).unwrap();
+        }
+        user
+    }
+
+    pub fn nopass(&self) -> bool {
+        self.nopass
+    }
+
+    fn authenticate(&self, password: &str) -> bool {
+        self.enabled && (self.nopass || self.passwords.contains(&hash_password(password)))
+    }
+
+    /// Apply a rule of `ACL SETUSER`
+    fn apply(&mut self, rule: &str) -> Result<(), String> {
+        match &rule.to_lowercase()[..] {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => return self.apply("~*"),
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => return self.apply("&*"),
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => {
+                for rule in ["resetpass", "resetkeys", "resetchannels", "off", "-@all"] {
+                    self.apply(rule)?;
+                }
+            }
+            _ => return self.apply_pattern(rule),
+        }
+        Ok(())
+    }
+
+    /// Apply a rule starting with a prefix, as `>password` or `~pattern`
+    fn apply_pattern(&mut self, rule: &str) -> Result<(), String> {
+        if let Some(password) = rule.strip_prefix('>') {
+            self.add_password(hash_password(password));
+        } else if let Some(password) = rule.strip_prefix('<') {
+            self.remove_password(&hash_password(password))?;
+        } else if let Some(hash) = rule.strip_prefix('#') {
+            if hash.len() != 64 || !hash.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f')) {
+                return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".to_string());
+            }
+            self.add_password(hash.to_string());
+        } else if let Some(hash) = rule.strip_prefix('!') {
+            self.remove_password(hash)?;
+        } else if let Some(pattern) = rule.strip_prefix('~') {
+            self.add_keys(pattern, true, true)?;
+        } else if let Some(rule) = rule.strip_prefix('%') {
+            let (permissions, pattern) = rule.split_once('~').ok_or("Syntax error")?;
+            let permissions = permissions.to_uppercase();
+            if permissions.is_empty() || permissions.contains(|c| c != 'R' && c != 'W') {
+                return Err("Syntax error".to_string());
+            }
+            let (read, write) = (permissions.contains('R'), permissions.contains('W'));
+            self.add_keys(pattern, read, write)?;
+        } else if let Some(pattern) = rule.strip_prefix('&') {
+            if self.channels.iter().any(|channel| channel == "*") {
+                return Err("Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid and does not have any effect. Try 'resetchannels' to start with an empty list of channels".to_string());
+            }
+            match pattern {
+                "*" => self.channels = vec![pattern.to_string()],
+                _ if self.channels.iter().any(|channel| channel == pattern) => {}
+                _ => self.channels.push(pattern.to_string()),
+            }
+        } else if let Some(name) = rule.strip_prefix('+') {
+            self.allow(name, true)?;
+        } else if let Some(name) = rule.strip_prefix('-') {
+            self.allow(name, false)?;
+        } else {
+            return Err("Syntax error".to_string());
+        }
+        Ok(())
+    }
+
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn remove_password(&mut self, hash: &str) -> Result<(), String> {
+        match self.passwords.iter().position(|password| password == hash) {
+            Some(index) => {
+                self.passwords.remove(index);
+                Ok(())
+            }
+            None => Err(
+                "The password you are trying to remove from the user does not exist".to_string(),
+            ),
+        }
+    }
+
+    fn add_keys(&mut self, pattern: &str, read: bool, write: bool) -> Result<(), String> {
+        let all = KeyPattern {
+            pattern: "*".to_string(),
+            read: true,
+            write: true,
+        };
+        if self.keys.contains(&all) {
+            return Err("Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have any effect. Try 'resetkeys' to start with an empty list of patterns".to_string());
+        }
+        let added = KeyPattern {
+            pattern: pattern.to_string(),
+            read,
+            write,
+        };
+        if added == all {
+            self.keys = vec![all];
+        } else if !self.keys.contains(&added) {
+            self.keys.push(added);
+        }
+        Ok(())
+    }
+
+    /// `+command`, `-command`, `+@category` and `-@category`
+    fn allow(&mut self, name: &str, allowed: bool) -> Result<(), String> {
+        let name = name.to_lowercase();
+        let commands: Vec<&'static str> = match name.strip_prefix('@') {
+            Some("all") => COMMANDS.iter().map(|(command, _)| *command).collect(),
+            Some(category) if CATEGORIES.contains(&category) => COMMANDS
+                .iter()
+                .filter(|(_, categories)| categories.contains(&category))
+                .map(|(command, _)| *command)
+                .collect(),
+            Some(_) => vec![],
+            // A container command stands for all its subcommands
+            None => COMMANDS
+                .iter()
+                .map(|(command, _)| *command)
+                .filter(|command| {
+                    *command == name
+                        || command
+                            .split_once('|')
+                            .is_some_and(|(container, _)| container == name)
+                })
+                .collect(),
+        };
+        if commands.is_empty() {
+            return Err("Unknown command or category name in ACL".to_string());
+        }
+
+        for command in commands {
+            match allowed {
+                true => self.commands.insert(command),
+                false => self.commands.remove(command),
+            };
+        }
+        let rule = format!("{}{}", if allowed { '+' } else { '-' }, name);
+        if name == "@all" {
+            self.all_commands = allowed;
+            self.command_rules.clear();
+        } else {
+            // A later rule for the same command replaces the previous one
+            if !name.starts_with('@') {
+                self.command_rules.retain(|previous| previous[1..] != name);
+            }
+            self.command_rules.push(rule);
+        }
+        Ok(())
+    }
+
+    /// Refuse a command the user cannot run, or with keys or channels it
+    /// cannot access, with a `NOPERM` error
+    pub fn check(&self, cmd: &Command) -> Result<(), String> {
+        let name = cmd.acl_name();
+        if !self.commands.contains(name) {
+            return Err(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                self.name, name
+            ));
+        }
+
+        let write = cmd.is_write();
+        let allowed_key = |key: &str| {
+            self.keys.iter().any(|pattern| {
+                (if write { pattern.write } else { pattern.read })
+                    && glob_match(pattern.pattern.as_bytes(), key.as_bytes())
+            })
+        };
+        if !cmd.keys().into_iter().all(allowed_key) {
+            return Err("NOPERM No permissions to access a key".to_string());
+        }
+
+        let allowed_channel = |channel: &String| {
+            self.channels
+                .iter()
+                .any(|pattern| glob_match(pattern.as_bytes(), channel.as_bytes()))
+        };
+        // Patterns are only allowed when listed as is
+        let allowed_pattern = |pattern: &String| {
+            self.channels
+                .iter()
+                .any(|allowed| allowed == "*" || allowed == pattern)
+        };
+        let allowed = match cmd {
+            Command::Publish(cmd) => allowed_channel(&cmd.channel),
+            Command::Subscribe(cmd) => cmd.channels.iter().all(allowed_channel),
+            Command::PSubscribe(cmd) => cmd.patterns.iter().all(allowed_pattern),
+            _ => true,
+        };
+        if !allowed {
+            return Err("NOPERM No permissions to access a channel".to_string());
+        }
+        Ok(())
+    }
+
+    /// Rules recreating the user, as listed by `ACL LIST`
+    fn describe(&self) -> String {
+        let mut rules = vec![format!("user {}", self.name)];
+        rules.push(self.flags().join(" "));
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        rules.extend(self.keys.iter().map(KeyPattern::describe));
+        match self.channels.is_empty() {
+            true => rules.push("resetchannels".to_string()),
+            false => rules.push(self.describe_channels()),
+        }
+        rules.push(self.describe_commands());
+        rules.join(" ")
+    }
+
+    fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    fn describe_commands(&self) -> String {
+        let base = if self.all_commands { "+@all" } else { "-@all" };
+        std::iter::once(base)
+            .chain(self.command_rules.iter().map(|rule| &rule[..]))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn describe_channels(&self) -> String {
+        self.channels
+            .iter()
+            .map(|channel| format!("&{}", channel))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Reply to `ACL GETUSER`
+    fn to_frame(&self) -> Frame {
+        let bulk = |value: String| Frame::Bulk(Bytes::from(value));
+        let field = |name: &'static str, value: Frame| {
+            (Frame::Bulk(Bytes::from_static(name.as_bytes())), value)
+        };
+        let keys = self.keys.iter().map(KeyPattern::describe);
+        Frame::Map(vec![
+            field(
+                "flags",
+                Frame::Array(
+                    self.flags()
+                        .into_iter()
+                        .map(|flag| bulk(flag.to_string()))
+                        .collect(),
+                ),
+            ),
+            field(
+                "passwords",
+                Frame::Array(self.passwords.iter().cloned().map(bulk).collect()),
+            ),
+            field("commands", bulk(self.describe_commands())),
+            field("keys", bulk(keys.collect::<Vec<_>>().join(" "))),
+            field("channels", bulk(self.describe_channels())),
+            field("selectors", Frame::Array(vec![])),
+        ])
+    }
+}
+
+/// Users by name, the `default` user always exists
+pub struct Acl {
+    users: BTreeMap<String, User>,
+}
+
+impl Acl {
+    /// Only the default user, with the password of `requirepass` when not
+    /// empty
+    pub fn new(requirepass: &str) -> Self {
+        let mut acl = Self {
+            users: BTreeMap::new(),
+        };
+        acl.users
+            .insert("default".to_string(), User::default_user());
+        acl.require_pass(requirepass);
+        acl
+    }
+
+    /// Load the users of an ACL file, one `user <name> <rules...>` per line
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut users = BTreeMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            let error = |err: String| format!("{}:{}: {}", path.display(), index + 1, err);
+            let args = split_directive(line).map_err(|err| error(err.to_string()))?;
+            let Some((directive, args)) = args.split_first() else {
+                continue;
+            };
+            if directive.starts_with('#') {
+                continue;
+            }
+            let Some((name, rules)) = args.split_first().filter(|_| directive == "user") else {
+                return Err(error("should start with user keyword".to_string()).into());
+            };
+            if users.contains_key(name) {
+                return Err(error(format!("Duplicate user '{}' found", name)).into());
+            }
+            let mut user = User::new(name);
+            for rule in rules {
+                user.apply(rule).map_err(|err| {
+                    error(format!("Error in user declaration '{}': {}", rule, err))
+                })?;
+            }
+            users.insert(name.clone(), user);
+        }
+        users
+            .entry("default".to_string())
+            .or_insert_with(User::default_user);
+        Ok(Self { users })
+    }
+
+    /// `ACL SAVE`, the file is replaced once written
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let temp = path.with_extension(format!("save-{}", std::process::id()));
+        std::fs::write(&temp, self.list().join("\n") + "\n")?;
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    /// `requirepass` sets the only password of the default user
+    pub fn require_pass(&mut self, password: &str) {
+        let user = self.users.get_mut("default").unwrap();
+        user.apply("resetpass").unwrap();
+        match password {
+            "" => user.apply("nopass").unwrap(),
+            password => user.add_password(hash_password(password)),
+        }
+    }
+
+    /// Connections are authenticated as the default user from the start,
+    /// unless it requires a password or is disabled
+    pub fn default_login(&self) -> bool {
+        let user = &self.users["default"];
+        user.enabled && user.nopass
+    }
+
+    pub fn user(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    /// Whether the password is valid for the user, which has to be enabled
+    pub fn authenticate(&self, name: &str, password: &str) -> bool {
+        self.users
+            .get(name)
+            .is_some_and(|user| user.authenticate(password))
+    }
+
+    /// `ACL SETUSER`, the user is only changed when all the rules are valid
+    pub fn set_user(&mut self, name: &str, rules: &[String]) -> Result<(), String> {
+        if name.contains(|c: char| c.is_whitespace() || c == '\0') {
+            return Err("ERR Usernames can't contain spaces or null characters".to_string());
+        }
+        let mut user = match self.users.get(name) {
+            Some(user) => user.clone(),
+            None => User::new(name),
+        };
+        for rule in rules {
+            user.apply(rule)
+                .map_err(|err| format!("ERR Error in ACL SETUSER modifier '{}': {}", rule, err))?;
+        }
+        self.users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// `ACL DELUSER`, the users which existed
+    pub fn del_users(&mut self, names: &[String]) -> Result<Vec<String>, String> {
+        if names.iter().any(|name| name == "default") {
+            return Err("ERR The 'default' user cannot be removed".to_string());
+        }
+        Ok(names
+            .iter()
+            .filter(|name| self.users.remove(&name[..]).is_some())
+            .cloned()
+            .collect())
+    }
+
+    /// `ACL GETUSER`
+    pub fn get_user(&self, name: &str) -> Frame {
+        self.users.get(name).map_or(Frame::Null, User::to_frame)
+    }
+
+    /// `ACL LIST`
+    pub fn list(&self) -> Vec<String> {
+        self.users.values().map(User::describe).collect()
+    }
+
+    /// `ACL USERS`
+    pub fn names(&self) -> Vec<String> {
+        self.users.keys().cloned().collect()
+    }
+}
+
+/// Passwords are only kept as SHA-256 digests
+fn hash_password(password: &str) -> String {
+    sha256(password.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Round constants of SHA-256
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    // Padded with a single set bit, then zeroes up to the length in bits
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Command {
+        Command::from_frame(request(args)).unwrap()
+    }
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        rules.iter().map(|rule| rule.to_string()).collect()
+    }
+
+    #[test]
+    fn sha256_test() {
+        assert_eq!(
+            hash_password(""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hash_password("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn authenticate_test() {
+        let mut acl = Acl::new("");
+        assert!(acl.authenticate("default", "anything"));
+        acl.require_pass("secret");
+        assert!(!acl.authenticate("default", "anything"));
+        assert!(acl.authenticate("default", "secret"));
+
+        acl.set_user("alice", &rules(&[">pass"])).unwrap();
+        assert!(!acl.authenticate("alice", "pass"));
+        acl.set_user("alice", &rules(&["on", ">other", "<pass"]))
+            .unwrap();
+        assert!(acl.authenticate("alice", "other"));
+        assert!(!acl.authenticate("alice", "pass"));
+        assert!(acl.set_user("alice", &rules(&["<pass"])).is_err());
+        assert!(!acl.authenticate("bob", "other"));
+    }
+
+    #[test]
+    fn check_test() {
+        let mut acl = Acl::new("");
+        let setuser = rules(&[
+            "on",
+            "+@read",
+            "-@dangerous",
+            "+client|id",
+            "~app:*",
+            "%W~log:*",
+        ]);
+        acl.set_user("alice", &setuser).unwrap();
+        let alice = acl.user("alice").unwrap();
+        assert!(alice.check(&command(&["get", "app:1"])).is_ok());
+        assert!(alice.check(&command(&["client", "id"])).is_ok());
+        assert_eq!(
+            alice.check(&command(&["set", "app:1", "v"])).unwrap_err(),
+            "NOPERM User alice has no permissions to run the 'set' command"
+        );
+        assert_eq!(
+            alice
+                .check(&command(&["client", "kill", "id", "1"]))
+                .unwrap_err(),
+            "NOPERM User alice has no permissions to run the 'client|kill' command"
+        );
+        assert!(alice.check(&command(&["keys", "*"])).is_err());
+        assert_eq!(
+            alice
+                .check(&command(&["mget", "app:1", "other"]))
+                .unwrap_err(),
+            "NOPERM No permissions to access a key"
+        );
+        // Write only
+        assert!(alice.check(&command(&["get", "log:1"])).is_err());
+
+        acl.set_user("alice", &rules(&["+publish", "+psubscribe", "&news.*"]))
+            .unwrap();
+        let alice = acl.user("alice").unwrap();
+        assert!(alice
+            .check(&command(&["publish", "news.tech", "m"]))
+            .is_ok());
+        assert!(alice.check(&command(&["publish", "sport", "m"])).is_err());
+        assert!(alice.check(&command(&["psubscribe", "news.*"])).is_ok());
+        assert!(alice.check(&command(&["psubscribe", "news.t*"])).is_err());
+    }
+
+    #[test]
+    fn set_user_test() {
+        let mut acl = Acl::new("");
+        assert_eq!(
+            acl.set_user("bob", &rules(&["+unknown"])).unwrap_err(),
+            "ERR Error in ACL SETUSER modifier '+unknown': Unknown command or category name in ACL"
+        );
+        assert!(acl.set_user("bob", &rules(&["bogus"])).is_err());
+        assert!(acl.set_user("bob", &rules(&["allkeys", "~other"])).is_err());
+        assert!(acl.user("bob").is_none());
+
+        let setuser = rules(&[
+            "on",
+            "nopass",
+            "+@all",
+            "-@dangerous",
+            "+get",
+            "-get",
+            "~a*",
+            "%R~b*",
+        ]);
+        acl.set_user("bob", &setuser).unwrap();
+        assert_eq!(
+            acl.list(),
+            [
+                "user bob on nopass ~a* %R~b* resetchannels +@all -@dangerous -get",
+                "user default on nopass ~* &* +@all",
+            ]
+        );
+        assert_eq!(acl.del_users(&rules(&["bob", "carol"])).unwrap(), ["bob"]);
+        assert!(acl.del_users(&rules(&["default"])).is_err());
+        assert_eq!(acl.names(), ["default"]);
+
+        let categories = categories(Some("transaction")).unwrap();
+        assert_eq!(categories, ["discard", "exec", "multi", "unwatch", "watch"]);
+        assert!(super::categories(Some("unknown")).is_err());
+    }
+
+    #[test]
+    fn load_test() {
+        let path = std::env::temp_dir().join(format!("acl-test-{}.acl", std::process::id()));
+        std::fs::write(&path, "user alice on #ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad ~* +@all\n").unwrap();
+        let acl = Acl::load(&path).unwrap();
+        assert!(acl.authenticate("alice", "abc"));
+        assert!(acl.authenticate("default", ""));
+        acl.save(&path).unwrap();
+        assert_eq!(Acl::load(&path).unwrap().list(), acl.list());
+
+        std::fs::write(&path, "user alice on\nuser alice off\n").unwrap();
+        let err = Acl::load(&path).err().unwrap().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.ends_with(":2: Duplicate user 'alice' found"));
+    }
+}
@@ -0,0 +1,597 @@
+use crate::buffer::BufferedStream;
+use crate::command::Command;
+use crate::db::{Entry, Value};
+use crate::error::Error;
+use crate::frame::*;
+use crate::log;
+use crate::replication::{ReplicationLog, Resync};
+use crate::stream::{PendingEntry, Stream, StreamId};
+use bytes::Bytes;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, MissedTickBehavior};
+
+pub type Feed = mpsc::UnboundedSender<FeedMessage>;
+
+pub enum FeedMessage {
+    /// A write to append to the database at the given index, the response is
+    /// only sent once the write is persisted
+    Command(usize, Frame, Option<(mpsc::UnboundedSender<Frame>, Frame)>),
+    /// Start a rewrite of the AOF, completed once a dump of every shard is
+    /// received
+    StartRewrite(usize, oneshot::Sender<Result<(), Error>>),
+    /// Entries of every database of a shard, by index
+    Dump(Vec<Vec<(String, Entry)>>),
+    /// Send the following writes to a replica, see `ReplicationLog::attach`
+    Replicate(mpsc::UnboundedSender<Frame>, Resync, oneshot::Sender<bool>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsyncPolicy {
+    Always,
+    EverySec,
+    No,
+}
+
+impl FsyncPolicy {
+    pub fn parse(value: &str) -> Result<FsyncPolicy, Error> {
+        match &value.to_lowercase()[..] {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(format!("invalid appendfsync '{}'", value).into()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::EverySec => "everysec",
+            FsyncPolicy::No => "no",
+        }
+    }
+}
+
+/// The AOF, or a file being rewritten. The writes are buffered until flushed,
+/// a failed flush leaves the file as it was and keeps them for the next one.
+struct AppendFile {
+    file: File,
+    /// Length of the file with every flushed write
+    len: u64,
+    /// Database of the last write, see `append`
+    selected: Option<usize>,
+    buffer: Vec<u8>,
+}
+
+impl AppendFile {
+    async fn open(path: &Path) -> std::io::Result<AppendFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let len = file.metadata().await?.len();
+        // The database selected at the end of an existing file is unknown
+        Ok(AppendFile {
+            file,
+            len,
+            selected: None,
+            buffer: vec![],
+        })
+    }
+
+    async fn create(path: &Path) -> std::io::Result<AppendFile> {
+        Ok(AppendFile {
+            file: File::create(path).await?,
+            len: 0,
+            selected: None,
+            buffer: vec![],
+        })
+    }
+
+    /// Append a write, preceded by a `SELECT` when the previous write applied
+    /// to another database
+    fn append(&mut self, db: usize, frame: &Frame) {
+        if self.selected != Some(db) {
+            let select = Frame::Array(vec![
+                Frame::Bulk(Bytes::from_static(b"SELECT")),
+                Frame::Bulk(Bytes::from(db.to_string())),
+            ]);
+            select.encode(&mut self.buffer, Protocol::Resp2);
+            self.selected = Some(db);
+        }
+        frame.encode(&mut self.buffer, Protocol::Resp2);
+    }
+
+    async fn flush(&mut self, fsync: bool) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            let written = match self.file.write_all(&self.buffer).await {
+                Ok(()) => self.file.flush().await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = written {
+                // A partially written command would not be replayed
+                let _ = self.file.set_len(self.len).await;
+                return Err(err);
+            }
+            self.len += self.buffer.len() as u64;
+            self.buffer.clear();
+        }
+        if fsync {
+            self.file.sync_data().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Error of the last write to the AOF, the write commands are refused while
+/// there is one
+#[derive(Clone, Default)]
+pub struct WriteStatus(Arc<Mutex<Option<String>>>);
+
+impl WriteStatus {
+    pub fn error(&self) -> Option<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn update(&self, result: std::io::Result<()>) {
+        let mut error = self.0.lock().unwrap();
+        match result {
+            Ok(()) => {
+                if error.take().is_some() {
+                    log::warning(format_args!(
+                        "AOF write error looks solved, writes are accepted again."
+                    ));
+                }
+            }
+            Err(err) => {
+                if error.is_none() {
+                    log::warning(format_args!("Error writing to the AOF file: {}", err));
+                }
+                *error = Some(err.to_string());
+            }
+        }
+    }
+}
+
+struct Rewrite {
+    file: AppendFile,
+    path: PathBuf,
+    pending_dumps: usize,
+    /// Keys written to the file, possibly before the dump of their shard
+    written: HashSet<String>,
+    done: oneshot::Sender<Result<(), Error>>,
+}
+
+impl Rewrite {
+    /// Give up the rewrite after an error, the AOF is left as it is
+    async fn abort(self, err: Error) {
+        log::warning(format_args!("Unable to rewrite the AOF: {}", err));
+        let _ = tokio::fs::remove_file(&self.path).await;
+        let _ = self.done.send(Err(err));
+    }
+}
+
+/// Append the writes fed by the shards to the AOF at `path`, when `enabled`,
+/// and send them to the replicas.
+///
+/// A rewrite creates a new file from the dumps of the shards, the writes
+/// received in the meantime are appended to both files. Writes of a shard
+/// received before its dump are also part of the dump, the keys they wrote
+/// are deleted before being recreated from the dump.
+///
+/// The errors writing the AOF are reported to `status`, the writes are kept
+/// and retried every second until they succeed.
+pub async fn process_feed(
+    path: PathBuf,
+    enabled: bool,
+    rewrite_path: PathBuf,
+    fsync: FsyncPolicy,
+    mut replication: ReplicationLog,
+    feed_rx: &mut mpsc::UnboundedReceiver<FeedMessage>,
+    status: WriteStatus,
+) {
+    let mut aof = None;
+    if enabled {
+        match AppendFile::open(&path).await {
+            Ok(file) => aof = Some(file),
+            Err(err) => status.update(Err(err)),
+        }
+    }
+    let mut rewrite: Option<Rewrite> = None;
+    let mut responses = vec![];
+
+    let mut fsync_interval = time::interval(Duration::from_secs(1));
+    fsync_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let message = tokio::select! {
+            message = feed_rx.recv() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = fsync_interval.tick() => {
+                match &mut aof {
+                    Some(aof) if fsync == FsyncPolicy::EverySec || !aof.buffer.is_empty() => {
+                        status.update(aof.flush(fsync != FsyncPolicy::No).await);
+                    }
+                    Some(_) => {}
+                    None if enabled => match AppendFile::open(&path).await {
+                        Ok(file) => {
+                            aof = Some(file);
+                            status.update(Ok(()));
+                        }
+                        Err(err) => status.update(Err(err)),
+                    },
+                    None => {}
+                }
+                continue;
+            }
+        };
+
+        // Handle every available message before flushing
+        let mut next = Some(message);
+        while let Some(message) = next {
+            match message {
+                FeedMessage::Command(db, frame, response) => {
+                    replication.feed(db, &frame);
+                    if let Some(aof) = &mut aof {
+                        aof.append(db, &frame);
+                    }
+                    if let Some(rewrite) = &mut rewrite {
+                        if let Ok(cmd) = Command::from_frame(frame.clone()) {
+                            let keys = cmd.keys().into_iter().map(String::from);
+                            rewrite.written.extend(keys);
+                        }
+                        rewrite.file.append(db, &frame);
+                    }
+                    responses.extend(response);
+                }
+                FeedMessage::StartRewrite(shards, done) => {
+                    if rewrite.is_some() {
+                        let _ = done.send(Err(
+                            "Background append only file rewriting already in progress".into(),
+                        ));
+                    } else {
+                        match AppendFile::create(&rewrite_path).await {
+                            Ok(file) => {
+                                rewrite = Some(Rewrite {
+                                    file,
+                                    path: rewrite_path.clone(),
+                                    pending_dumps: shards,
+                                    written: HashSet::new(),
+                                    done,
+                                })
+                            }
+                            Err(err) => {
+                                let _ = done.send(Err(err.into()));
+                            }
+                        }
+                    }
+                }
+                FeedMessage::Replicate(replica, resync, attached) => {
+                    let _ = attached.send(replication.attach(replica, resync));
+                }
+                FeedMessage::Dump(dbs) => {
+                    if let Some(current) = &mut rewrite {
+                        for (db, entries) in dbs.into_iter().enumerate() {
+                            for (key, entry) in entries {
+                                if current.written.contains(&key) {
+                                    let del = Frame::Array(vec![
+                                        Frame::Bulk(Bytes::from_static(b"DEL")),
+                                        Frame::Bulk(Bytes::from(key.clone())),
+                                    ]);
+                                    current.file.append(db, &del);
+                                }
+                                for frame in rewrite_entry(key, entry) {
+                                    current.file.append(db, &frame);
+                                }
+                            }
+                        }
+                        current.pending_dumps -= 1;
+
+                        if current.pending_dumps == 0 {
+                            let Rewrite {
+                                file,
+                                path: temp,
+                                done,
+                                ..
+                            } = rewrite.take().unwrap();
+                            match finish_rewrite(file, &temp, &path).await {
+                                Ok(file) => {
+                                    if enabled {
+                                        aof = Some(file);
+                                        status.update(Ok(()));
+                                    }
+                                    let _ = done.send(Ok(()));
+                                }
+                                Err(err) => {
+                                    log::warning(format_args!(
+                                        "Unable to rewrite the AOF: {}",
+                                        err
+                                    ));
+                                    let _ = done.send(Err(err));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            next = feed_rx.try_recv().ok();
+        }
+
+        if let Some(current) = &mut rewrite {
+            if let Err(err) = current.file.flush(false).await {
+                rewrite.take().unwrap().abort(err.into()).await;
+            }
+        }
+        let mut persisted = true;
+        if let Some(aof) = &mut aof {
+            let result = aof.flush(fsync == FsyncPolicy::Always).await;
+            persisted = result.is_ok() || fsync != FsyncPolicy::Always;
+            status.update(result);
+        }
+        for (respond, response) in responses.drain(..) {
+            // Applied but not persisted as promised by `appendfsync always`
+            let response = match (persisted, status.error()) {
+                (false, Some(err)) => Frame::Error(misconf(&err)),
+                _ => response,
+            };
+            let _ = respond.send(response);
+        }
+    }
+}
+
+/// Reply to the writes while the AOF can not be written
+pub fn misconf(err: &str) -> String {
+    format!("MISCONF Errors writing to the AOF file: {}", err)
+}
+
+/// Persist the rewritten file and move it to the place of the AOF
+async fn finish_rewrite(
+    mut file: AppendFile,
+    temp: &Path,
+    path: &Path,
+) -> Result<AppendFile, Error> {
+    file.flush(false).await?;
+    file.file.sync_all().await?;
+    tokio::fs::rename(temp, path).await?;
+    Ok(file)
+}
+
+/// Commands recreating an entry
+pub fn rewrite_entry(key: String, entry: Entry) -> Vec<Frame> {
+    let key = Bytes::from(key);
+    let mut frames = match entry.value {
+        Value::String(value) => vec![Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"SET")),
+            Frame::Bulk(key.clone()),
+            Frame::Bulk(Bytes::from(value)),
+        ])],
+        Value::List(list) => {
+            let mut args = vec![
+                Frame::Bulk(Bytes::from_static(b"RPUSH")),
+                Frame::Bulk(key.clone()),
+            ];
+            args.extend(list.into_iter().map(Frame::Bulk));
+            vec![Frame::Array(args)]
+        }
+        Value::Hash(hash) => {
+            let mut args = vec![
+                Frame::Bulk(Bytes::from_static(b"HSET")),
+                Frame::Bulk(key.clone()),
+            ];
+            for (field, value) in hash {
+                args.push(Frame::Bulk(field));
+                args.push(Frame::Bulk(value));
+            }
+            vec![Frame::Array(args)]
+        }
+        Value::Set(set) => {
+            let mut args = vec![
+                Frame::Bulk(Bytes::from_static(b"SADD")),
+                Frame::Bulk(key.clone()),
+            ];
+            args.extend(set.into_iter().map(Frame::Bulk));
+            vec![Frame::Array(args)]
+        }
+        Value::ZSet(zset) => {
+            let mut args = vec![
+                Frame::Bulk(Bytes::from_static(b"ZADD")),
+                Frame::Bulk(key.clone()),
+            ];
+            for (member, score) in zset.iter() {
+                args.push(Frame::Bulk(Bytes::from(format_double(score))));
+                args.push(Frame::Bulk(member.clone()));
+            }
+            vec![Frame::Array(args)]
+        }
+        Value::Stream(stream) => rewrite_stream(&key, &stream),
+    };
+    if let Some(at) = entry.expire_at {
+        frames.push(Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"PEXPIREAT")),
+            Frame::Bulk(key),
+            Frame::Bulk(Bytes::from(at.to_string())),
+        ]));
+    }
+    frames
+}
+
+/// Commands recreating a stream with its consumer groups, the pending
+/// entries of the groups being claimed again by their consumer
+fn rewrite_stream(key: &Bytes, stream: &Stream) -> Vec<Frame> {
+    let command = |args: Vec<Bytes>| Frame::Array(args.into_iter().map(Frame::Bulk).collect());
+    let mut frames = vec![];
+    for (id, fields) in stream.entries() {
+        let mut args = vec![Bytes::from_static(b"XADD"), key.clone(), id.to_bytes()];
+        for (field, value) in fields {
+            args.push(field.clone());
+            args.push(value.clone());
+        }
+        frames.push(command(args));
+    }
+    if stream.len() == 0 {
+        // Created by an entry removed right away, the last id is set below
+        frames.push(command(vec![
+            Bytes::from_static(b"XADD"),
+            key.clone(),
+            Bytes::from_static(b"MAXLEN"),
+            Bytes::from_static(b"0"),
+            Bytes::from_static(b"0-1"),
+            Bytes::from_static(b"x"),
+            Bytes::from_static(b"y"),
+        ]));
+    }
+    frames.push(command(vec![
+        Bytes::from_static(b"XSETID"),
+        key.clone(),
+        stream.last_id().to_bytes(),
+    ]));
+
+    for (name, group) in stream.groups() {
+        let name = Bytes::from(name.clone());
+        frames.push(command(vec![
+            Bytes::from_static(b"XGROUP"),
+            Bytes::from_static(b"CREATE"),
+            key.clone(),
+            name.clone(),
+            group.last_id.to_bytes(),
+        ]));
+        for consumer in group.consumers.keys() {
+            frames.push(command(vec![
+                Bytes::from_static(b"XGROUP"),
+                Bytes::from_static(b"CREATECONSUMER"),
+                key.clone(),
+                name.clone(),
+                Bytes::from(consumer.clone()),
+            ]));
+        }
+        for (id, entry) in &group.pending {
+            frames.push(command(claim_args(key, &name, *id, entry, None)));
+        }
+    }
+    frames
+}
+
+/// `XCLAIM` giving a pending entry to its consumer as it is, moving the last
+/// delivered id of the group to `last_id` when given
+pub fn claim_args(
+    key: &Bytes,
+    group: &Bytes,
+    id: StreamId,
+    entry: &PendingEntry,
+    last_id: Option<StreamId>,
+) -> Vec<Bytes> {
+    let mut args = vec![
+        Bytes::from_static(b"XCLAIM"),
+        key.clone(),
+        group.clone(),
+        Bytes::from(entry.consumer.clone()),
+        Bytes::from_static(b"0"),
+        id.to_bytes(),
+        Bytes::from_static(b"TIME"),
+        Bytes::from(entry.delivered_at.to_string()),
+        Bytes::from_static(b"RETRYCOUNT"),
+        Bytes::from(entry.deliveries.to_string()),
+        Bytes::from_static(b"FORCE"),
+        Bytes::from_static(b"JUSTID"),
+    ];
+    if let Some(last_id) = last_id {
+        args.push(Bytes::from_static(b"LASTID"));
+        args.push(last_id.to_bytes());
+    }
+    args
+}
+
+/// Replay the commands of an AOF, a truncated last command is ignored.
+///
+/// Returns the number of replayed commands.
+pub async fn load(path: &Path, mut replay: impl FnMut(Frame)) -> Result<usize, Error> {
+    let file = File::open(path).await?;
+    let mut buffer = BufferedStream::new(file);
+    let mut count = 0;
+
+    loop {
+        match Frame::parse(&mut buffer).await {
+            Ok(frame) => replay(frame),
+            Err(FrameParseError::Incomplete) => break,
+            Err(err) => return Err(err.into()),
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn load_test() {
+        let path = std::env::temp_dir().join(format!("load-test-{}.aof", std::process::id()));
+        let commands = [request(&["SET", "a", "1"]), request(&["INCR", "a"])];
+        let mut contents = vec![];
+        for frame in &commands {
+            frame.encode(&mut contents, Protocol::Resp2);
+        }
+        // A write interrupted by a crash is ignored
+        let mut truncated = vec![];
+        request(&["SET", "b", "2"]).encode(&mut truncated, Protocol::Resp2);
+        contents.extend_from_slice(&truncated[..truncated.len() - 3]);
+        std::fs::write(&path, &contents).unwrap();
+
+        let mut replayed = vec![];
+        let count = load(&path, |frame| replayed.push(frame)).await.unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(replayed, commands);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_error_test() {
+        let (feed, mut feed_rx) = mpsc::unbounded_channel();
+        let status = WriteStatus::default();
+        let rewrite_path = std::env::temp_dir().join("write-error-test.aof");
+        let replication = ReplicationLog::new(1024);
+        let feeding = status.clone();
+        tokio::spawn(async move {
+            let path = PathBuf::from("/dev/full");
+            let (enabled, fsync) = (true, FsyncPolicy::Always);
+            process_feed(
+                path,
+                enabled,
+                rewrite_path,
+                fsync,
+                replication,
+                &mut feed_rx,
+                feeding,
+            )
+            .await;
+        });
+
+        // Applied, but not persisted
+        let (respond, mut response) = mpsc::unbounded_channel();
+        let ok = Frame::Simple("OK".to_string());
+        let write = FeedMessage::Command(0, request(&["SET", "a", "1"]), Some((respond, ok)));
+        feed.send(write).unwrap();
+        assert!(matches!(
+            response.recv().await.unwrap(),
+            Frame::Error(err) if err.starts_with("MISCONF")
+        ));
+        assert!(status.error().is_some());
+
+        // The feed keeps serving the other writes
+        let (respond, mut response) = mpsc::unbounded_channel();
+        let ok = Frame::Simple("OK".to_string());
+        let write = FeedMessage::Command(0, request(&["SET", "b", "2"]), Some((respond, ok)));
+        feed.send(write).unwrap();
+        assert!(matches!(response.recv().await.unwrap(), Frame::Error(_)));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::error::Error;
+
+/// Split a line in arguments as redis does, separated by spaces.
+///
+/// Double quoted arguments take escapes such as `\n`, `\"` or `\x00`,
+/// single quoted ones are verbatim but for `\'`.
+pub fn split_args(line: &str) -> Result<Vec<Vec<u8>>, Error> {
+    let mut args = vec![];
+    let mut bytes = line.bytes().peekable();
+    loop {
+        while bytes.next_if(u8::is_ascii_whitespace).is_some() {}
+        let Some(first) = bytes.next() else {
+            return Ok(args);
+        };
+
+        let mut arg = vec![];
+        match first {
+            b'"' => loop {
+                match bytes.next() {
+                    Some(b'"') => break,
+                    Some(b'\\') => match bytes.next() {
+                        Some(b'n') => arg.push(b'\n'),
+                        Some(b'r') => arg.push(b'\r'),
+                        Some(b't') => arg.push(b'\t'),
+                        Some(b'b') => arg.push(0x08),
+                        Some(b'a') => arg.push(0x07),
+                        Some(b'x') => {
+                            let digits = [bytes.next(), bytes.next()];
+                            let hex = digits.map(|digit| (digit? as char).to_digit(16));
+                            match hex {
+                                [Some(high), Some(low)] => arg.push((high << 4 | low) as u8),
+                                _ => return Err("invalid hexadecimal escape".into()),
+                            }
+                        }
+                        Some(c) => arg.push(c),
+                        None => return Err("unbalanced quotes".into()),
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err("unbalanced quotes".into()),
+                }
+            },
+            b'\'' => loop {
+                match bytes.next() {
+                    Some(b'\'') => break,
+                    Some(b'\\') if bytes.next_if_eq(&b'\'').is_some() => arg.push(b'\''),
+                    Some(c) => arg.push(c),
+                    None => return Err("unbalanced quotes".into()),
+                }
+            },
+            c => {
+                arg.push(c);
+                while let Some(c) = bytes.next_if(|c| !c.is_ascii_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+        // A closing quote has to end the argument
+        if matches!(first, b'"' | b'\'') && bytes.peek().is_some_and(|c| !c.is_ascii_whitespace()) {
+            return Err("closing quote must be followed by a space".into());
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_args_test() {
+        assert_eq!(
+            split_args(r#"  set "a b" 'c\'d' "\x41\n\"" '' e "#).unwrap(),
+            [&b"set"[..], b"a b", b"c'd", b"A\n\"", b"", b"e"]
+        );
+        assert_eq!(split_args(r#""\xff""#).unwrap(), [b"\xff"]);
+        assert!(split_args("").unwrap().is_empty());
+        assert!(split_args(r#"get "unbalanced"#).is_err());
+        assert!(split_args(r#"get "a"b"#).is_err());
+        assert!(split_args(r#"get "\xzz""#).is_err());
+    }
+}
@@ -0,0 +1,530 @@
+#[path = "../../args.rs"]
+mod args;
+#[path = "../../buffer.rs"]
+mod buffer;
+#[path = "../../error.rs"]
+mod error;
+// Shared with the server, which negotiates the protocol
+#[allow(dead_code)]
+#[path = "../../frame.rs"]
+mod frame;
+mod reply;
+
+use crate::args::split_args;
+use crate::buffer::BufferedStream;
+use crate::error::*;
+use crate::frame::*;
+use bytes::Bytes;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+const USAGE: &str = "\
+Usage: redis-cli [OPTIONS] [cmd [arg [arg ...]]]
+  -h <hostname>      Server hostname (default: 127.0.0.1).
+  -p <port>          Server port (default: 6379).
+  -a <password>      Password to use when connecting to the server.
+  --user <username>  Used to send ACL style 'AUTH username pass'. Needs -a.
+  -n <db>            Database number.
+  -3                 Start session in RESP3 protocol mode.
+  -r <repeat>        Execute specified command N times, forever when negative.
+  -i <interval>      When -r is used, waits <interval> seconds per command.
+  -x                 Read last argument from STDIN.
+  --raw              Use raw formatting for replies (default when STDOUT is
+                     not a tty).
+  --no-raw           Force formatted output even when STDOUT is not a tty.
+  --pipe             Transfer raw Redis protocol from stdin to server.
+  --scan             List all keys using the SCAN command.
+  --pattern <pat>    Keys pattern when using the --scan option.
+  --count <count>    Count option when using the --scan option.
+  --help             Output this help and exit.
+";
+
+/// Options of the command line, named as the ones of `redis-cli`
+struct Options {
+    host: String,
+    port: u16,
+    user: Option<String>,
+    password: Option<String>,
+    db: usize,
+    resp3: bool,
+    /// Times to run the command, forever when negative
+    repeat: i64,
+    interval: Duration,
+    /// Read the last argument of the command from stdin
+    stdin_arg: bool,
+    /// Print values only, decided by whether stdout is a terminal if unset
+    raw: Option<bool>,
+    pipe: bool,
+    scan: bool,
+    pattern: Option<String>,
+    count: Option<usize>,
+    command: Vec<Bytes>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            user: None,
+            password: None,
+            db: 0,
+            resp3: false,
+            repeat: 1,
+            interval: Duration::ZERO,
+            stdin_arg: false,
+            raw: None,
+            pipe: false,
+            scan: false,
+            pattern: None,
+            count: None,
+            command: vec![],
+        }
+    }
+}
+
+impl Options {
+    /// Build the options from command line arguments, the command starting
+    /// at the first argument which is not an option
+    fn from_args(args: impl Iterator<Item = String>) -> Result<Self, Error> {
+        let mut args = args.peekable();
+        let mut options = Self::default();
+        while let Some(arg) = args.next_if(|arg| arg.starts_with('-')) {
+            match &arg[..] {
+                "-h" => options.host = value(&mut args, &arg)?,
+                "-p" => options.port = parse(&arg, &value(&mut args, &arg)?)?,
+                "-a" | "--pass" => options.password = Some(value(&mut args, &arg)?),
+                "--user" => options.user = Some(value(&mut args, &arg)?),
+                "-n" => options.db = parse(&arg, &value(&mut args, &arg)?)?,
+                "-3" => options.resp3 = true,
+                "-r" => options.repeat = parse(&arg, &value(&mut args, &arg)?)?,
+                "-i" => {
+                    let seconds: f64 = parse(&arg, &value(&mut args, &arg)?)?;
+                    options.interval = Duration::try_from_secs_f64(seconds)
+                        .map_err(|_| format!("invalid value '{}' for '{}'", seconds, arg))?;
+                }
+                "-x" => options.stdin_arg = true,
+                "--raw" => options.raw = Some(true),
+                "--no-raw" => options.raw = Some(false),
+                "--pipe" => options.pipe = true,
+                "--scan" => options.scan = true,
+                "--pattern" => options.pattern = Some(value(&mut args, &arg)?),
+                "--count" => options.count = Some(parse(&arg, &value(&mut args, &arg)?)?),
+                "--help" => {
+                    print!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unrecognized option '{}'", arg).into()),
+            }
+        }
+        options.command = args.map(Bytes::from).collect();
+        if options.stdin_arg && options.command.is_empty() {
+            return Err("-x needs a command".into());
+        }
+        Ok(options)
+    }
+
+    fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+fn value(args: &mut impl Iterator<Item = String>, arg: &str) -> Result<String, Error> {
+    args.next()
+        .ok_or_else(|| format!("missing value for '{}'", arg).into())
+}
+
+fn parse<T: FromStr>(arg: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for '{}'", value, arg).into())
+}
+
+/// Connection to the server, replies being read with the parser of the
+/// server
+struct Connection {
+    reader: BufferedStream<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    /// Connect, authenticate and select the database `db`
+    async fn open(options: &Options, db: usize) -> Result<Connection, Error> {
+        let stream = TcpStream::connect((&options.host[..], options.port))
+            .await
+            .map_err(|err| {
+                format!(
+                    "Could not connect to Redis at {}: {}",
+                    options.address(),
+                    err
+                )
+            })?;
+        let (reader, writer) = stream.into_split();
+        let mut connection = Connection {
+            reader: BufferedStream::new(reader),
+            writer,
+        };
+
+        let mut auth = vec![];
+        if let Some(password) = &options.password {
+            auth = vec![
+                "AUTH",
+                options.user.as_deref().unwrap_or("default"),
+                password,
+            ];
+        }
+        let mut handshake = vec![];
+        if options.resp3 {
+            // HELLO takes the credentials as well
+            handshake.push([&["HELLO", "3"][..], &auth].concat());
+        } else if !auth.is_empty() {
+            handshake.push(auth);
+        }
+        let select = db.to_string();
+        if db != 0 {
+            handshake.push(vec!["SELECT", &select]);
+        }
+        for command in handshake {
+            let args: Vec<_> = command
+                .iter()
+                .map(|arg| Bytes::copy_from_slice(arg.as_bytes()))
+                .collect();
+            if let Frame::Error(err) = connection.call(&args).await? {
+                return Err(err.into());
+            }
+        }
+        Ok(connection)
+    }
+
+    async fn send(&mut self, args: &[Bytes]) -> Result<(), Error> {
+        let frame = Frame::Array(args.iter().cloned().map(Frame::Bulk).collect());
+        frame.write(&mut self.writer, Protocol::Resp2).await?;
+        Ok(())
+    }
+
+    async fn read(&mut self) -> Result<Frame, Error> {
+        Frame::parse(&mut self.reader)
+            .await
+            .map_err(|err| match err {
+                FrameParseError::Incomplete => "Server closed the connection".into(),
+                err => err.into(),
+            })
+    }
+
+    async fn call(&mut self, args: &[Bytes]) -> Result<Frame, Error> {
+        self.send(args).await?;
+        self.read().await
+    }
+}
+
+/// Arguments of a command line, typed or read from the standard input
+fn split_line(line: &str) -> Result<Vec<Bytes>, Error> {
+    Ok(split_args(line)?.into_iter().map(Bytes::from).collect())
+}
+
+fn print(frame: &Frame, raw: bool) -> Result<(), Error> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&reply::format(frame, raw))?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Send a command and print its reply, then the messages received by the
+/// commands switching to the subscribed or monitor modes
+async fn run(connection: &mut Connection, args: &[Bytes], raw: bool) -> Result<Frame, Error> {
+    let reply = connection.call(args).await?;
+    print(&reply, raw)?;
+    let name = args[0].to_ascii_lowercase();
+    let streaming = matches!(&name[..], b"subscribe" | b"psubscribe" | b"monitor");
+    if streaming && !matches!(reply, Frame::Error(_)) {
+        if !raw {
+            println!("Reading messages... (press Ctrl-C to quit)");
+        }
+        loop {
+            print(&connection.read().await?, raw)?;
+        }
+    }
+    Ok(reply)
+}
+
+/// Run the command of the command line, as many times as asked, true when
+/// no reply was an error
+async fn run_command(options: &Options, raw: bool) -> Result<bool, Error> {
+    let mut connection = Connection::open(options, options.db).await?;
+    let mut args = options.command.clone();
+    if options.stdin_arg {
+        let mut data = vec![];
+        io::stdin().read_to_end(&mut data)?;
+        args.push(Bytes::from(data));
+    }
+
+    let mut succeeded = true;
+    let mut count = 0;
+    while options.repeat < 0 || count < options.repeat {
+        if count > 0 {
+            tokio::time::sleep(options.interval).await;
+        }
+        let reply = run(&mut connection, &args, raw).await?;
+        succeeded &= !matches!(reply, Frame::Error(_));
+        count += 1;
+    }
+    Ok(succeeded)
+}
+
+/// Run the commands read from stdin, one per line
+async fn run_lines(options: &Options, raw: bool) -> Result<bool, Error> {
+    let mut connection = Connection::open(options, options.db).await?;
+    let mut succeeded = true;
+    for line in io::stdin().lock().lines() {
+        let args = split_line(&line?)?;
+        if args.is_empty() {
+            continue;
+        }
+        let reply = run(&mut connection, &args, raw).await?;
+        succeeded &= !matches!(reply, Frame::Error(_));
+    }
+    Ok(succeeded)
+}
+
+/// Send the protocol read from stdin as is, followed by a `PING` of a
+/// random marker telling when the last reply is received
+async fn pipe(options: &Options) -> Result<bool, Error> {
+    let Connection {
+        mut reader,
+        mut writer,
+    } = Connection::open(options, options.db).await?;
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let marker = Bytes::from(format!(
+        "{:020x}",
+        nanos.as_nanos() ^ std::process::id() as u128
+    ));
+    let ping = Frame::Array(vec![
+        Frame::Bulk(Bytes::from("PING")),
+        Frame::Bulk(marker.clone()),
+    ]);
+
+    // Replies are read while sending, the server would block otherwise
+    let sender = tokio::spawn(async move {
+        tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await?;
+        ping.write(&mut writer, Protocol::Resp2).await?;
+        println!("All data transferred. Waiting for the last reply...");
+        // Dropping the writer would close the connection early
+        Ok::<_, io::Error>(writer)
+    });
+
+    let (mut errors, mut replies) = (0, 0);
+    loop {
+        let reply = Frame::parse(&mut reader).await?;
+        match reply {
+            // The server replies to `PING` with a message as `PONG` followed
+            // by the message
+            Frame::Array(items) if matches!(&items[..], [_, Frame::Bulk(data)] if *data == marker) => {
+                break
+            }
+            Frame::Error(err) => {
+                println!("{}", err);
+                errors += 1;
+            }
+            _ => {}
+        }
+        replies += 1;
+    }
+    sender.await.map_err(|err| err.to_string())??;
+    println!("Last reply received from server.");
+    println!("errors: {}, replies: {}", errors, replies);
+    Ok(errors == 0)
+}
+
+/// List the keys matching the pattern, one per line
+async fn scan(options: &Options) -> Result<bool, Error> {
+    let mut connection = Connection::open(options, options.db).await?;
+    let mut cursor = Bytes::from("0");
+    loop {
+        let mut args = vec![Bytes::from("SCAN"), cursor];
+        if let Some(pattern) = &options.pattern {
+            args.extend([Bytes::from("MATCH"), Bytes::from(pattern.clone())]);
+        }
+        if let Some(count) = options.count {
+            args.extend([Bytes::from("COUNT"), Bytes::from(count.to_string())]);
+        }
+        let keys = match connection.call(&args).await? {
+            Frame::Array(reply) => match &reply[..] {
+                [Frame::Bulk(next), Frame::Array(keys)] => {
+                    cursor = next.clone();
+                    keys.clone()
+                }
+                _ => return Err("unexpected reply to SCAN".into()),
+            },
+            Frame::Error(err) => return Err(err.into()),
+            _ => return Err("unexpected reply to SCAN".into()),
+        };
+
+        let mut stdout = io::stdout().lock();
+        for key in keys {
+            if let Frame::Bulk(key) = key {
+                stdout.write_all(&key)?;
+                stdout.write_all(b"\n")?;
+            }
+        }
+        if cursor == "0" {
+            return Ok(true);
+        }
+    }
+}
+
+/// Open a connection for the interactive mode, which goes on without it
+async fn connect(options: &Options, db: usize) -> Option<Connection> {
+    Connection::open(options, db)
+        .await
+        .inspect_err(|err| println!("{}", err))
+        .ok()
+}
+
+/// Read commands with line editing and history until `quit`, reconnecting
+/// when the connection is lost
+async fn interactive(options: &Options, raw: bool) -> Result<bool, Error> {
+    let mut editor = DefaultEditor::new().map_err(|err| err.to_string())?;
+    let history = std::env::var_os("REDISCLI_HISTFILE")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rediscli_history"))
+        });
+    if let Some(history) = &history {
+        // There is no history yet on the first run
+        let _ = editor.load_history(history);
+    }
+
+    let mut db = options.db;
+    let mut connection = connect(options, db).await;
+    loop {
+        let prompt = match (&connection, db) {
+            (None, _) => "not connected> ".to_string(),
+            (Some(_), 0) => format!("{}> ", options.address()),
+            (Some(_), db) => format!("{}[{}]> ", options.address(), db),
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(err) => return Err(err.to_string().into()),
+        };
+        let mut args = match split_line(&line) {
+            Ok(args) if args.is_empty() => continue,
+            Ok(args) => args,
+            Err(_) => {
+                println!("Invalid argument(s)");
+                continue;
+            }
+        };
+
+        let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+        // Passwords are kept out of the history
+        if name != "auth" {
+            let _ = editor.add_history_entry(&line);
+        }
+        match &name[..] {
+            "quit" | "exit" => break,
+            "clear" => {
+                print!("\x1b[H\x1b[2J");
+                continue;
+            }
+            _ => {}
+        }
+
+        // A leading number repeats the command
+        let mut repeat = 1;
+        if let Some(times) = name.parse::<i64>().ok().filter(|_| args.len() > 1) {
+            repeat = times;
+            args.remove(0);
+        }
+        if connection.is_none() {
+            connection = connect(options, db).await;
+        }
+        let Some(open) = connection.as_mut() else {
+            continue;
+        };
+        for i in 0..repeat {
+            if i > 0 {
+                tokio::time::sleep(options.interval).await;
+            }
+            match run(open, &args, raw).await {
+                Ok(Frame::Simple(ok)) if ok == "OK" && args[0].eq_ignore_ascii_case(b"select") => {
+                    db = parse("select", &String::from_utf8_lossy(&args[1]))?;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    println!("Error: {}", err);
+                    connection = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        editor
+            .save_history(history)
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(true)
+}
+
+// Reading lines blocks, with nothing else to run meanwhile
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let options = Options::from_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        std::process::exit(1);
+    });
+    let raw = options.raw.unwrap_or(!io::stdout().is_terminal());
+
+    let result = if options.pipe {
+        pipe(&options).await
+    } else if options.scan {
+        scan(&options).await
+    } else if !options.command.is_empty() {
+        run_command(&options, raw).await
+    } else if io::stdin().is_terminal() {
+        interactive(&options, raw).await
+    } else {
+        run_lines(&options, raw).await
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_test() {
+        let args = [
+            "-p", "7000", "-n", "2", "--raw", "-r", "-1", "set", "-a", "b",
+        ];
+        let options = Options::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert_eq!(options.address(), "127.0.0.1:7000");
+        assert_eq!(
+            (options.db, options.raw, options.repeat),
+            (2, Some(true), -1)
+        );
+        assert_eq!(options.command, ["set", "-a", "b"]);
+
+        let parse = |args: &[&str]| Options::from_args(args.iter().map(|arg| arg.to_string()));
+        assert!(parse(&["-p"]).is_err());
+        assert!(parse(&["-p", "port"]).is_err());
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::frame::*;
+use std::io::Write;
+
+/// Reply as printed by `redis-cli`, with the types of the values and the
+/// indexes of nested arrays to a terminal, only the values otherwise
+pub fn format(frame: &Frame, raw: bool) -> Vec<u8> {
+    let mut out = vec![];
+    if raw {
+        format_raw(frame, &mut out);
+        out.push(b'\n');
+    } else {
+        format_tty(frame, "", &mut out);
+    }
+    out
+}
+
+fn format_tty(frame: &Frame, prefix: &str, out: &mut Vec<u8>) {
+    match frame {
+        Frame::Simple(val) => out.extend_from_slice(val.as_bytes()),
+        Frame::Error(val) => out.extend_from_slice(format!("(error) {}", val).as_bytes()),
+        Frame::Integer(val) => out.extend_from_slice(format!("(integer) {}", val).as_bytes()),
+        Frame::Bulk(val) => out.extend_from_slice(quote(val).as_bytes()),
+        Frame::Null | Frame::NullArray => out.extend_from_slice(b"(nil)"),
+        Frame::Double(val) => {
+            out.extend_from_slice(format!("(double) {}", format_double(*val)).as_bytes())
+        }
+        Frame::Boolean(val) => out.extend_from_slice(format!("({})", val).as_bytes()),
+        Frame::BigNumber(val) => out.extend_from_slice(format!("(big number) {}", val).as_bytes()),
+        Frame::Verbatim(_, val) => out.extend_from_slice(val.strip_suffix(b"\n").unwrap_or(val)),
+        Frame::Array(items) | Frame::Push(items) => {
+            let entries: Vec<_> = items.iter().map(|item| (item, None)).collect();
+            return format_entries(&entries, ')', "(empty array)", prefix, out);
+        }
+        Frame::Set(items) => {
+            let entries: Vec<_> = items.iter().map(|item| (item, None)).collect();
+            return format_entries(&entries, '~', "(empty set)", prefix, out);
+        }
+        Frame::Map(pairs) => {
+            let entries: Vec<_> = pairs.iter().map(|(key, val)| (key, Some(val))).collect();
+            return format_entries(&entries, '#', "(empty hash)", prefix, out);
+        }
+    }
+    out.push(b'\n');
+}
+
+/// Entries of an aggregate, one per line with their index, map values
+/// following their key
+fn format_entries(
+    entries: &[(&Frame, Option<&Frame>)],
+    separator: char,
+    empty: &str,
+    prefix: &str,
+    out: &mut Vec<u8>,
+) {
+    if entries.is_empty() {
+        out.extend_from_slice(empty.as_bytes());
+        out.push(b'\n');
+        return;
+    }
+    let width = entries.len().to_string().len();
+    let nested = format!("{}{}", prefix, " ".repeat(width + 2));
+    for (i, (item, val)) in entries.iter().enumerate() {
+        // The first entry goes on the line of the index of the parent
+        let indent = if i == 0 { "" } else { prefix };
+        let _ = write!(out, "{}{:>width$}{} ", indent, i + 1, separator);
+        format_tty(item, &nested, out);
+        if let Some(val) = val {
+            out.pop();
+            out.extend_from_slice(b" => ");
+            format_tty(val, &nested, out);
+        }
+    }
+}
+
+fn format_raw(frame: &Frame, out: &mut Vec<u8>) {
+    match frame {
+        Frame::Simple(val) | Frame::Error(val) | Frame::BigNumber(val) => {
+            out.extend_from_slice(val.as_bytes())
+        }
+        Frame::Integer(val) => out.extend_from_slice(val.to_string().as_bytes()),
+        Frame::Bulk(val) | Frame::Verbatim(_, val) => out.extend_from_slice(val),
+        Frame::Null | Frame::NullArray => {}
+        Frame::Double(val) => out.extend_from_slice(format_double(*val).as_bytes()),
+        Frame::Boolean(val) => out.extend_from_slice(format!("({})", val).as_bytes()),
+        Frame::Array(items) | Frame::Set(items) | Frame::Push(items) => {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b'\n');
+                }
+                format_raw(item, out);
+            }
+        }
+        Frame::Map(pairs) => {
+            for (i, (key, val)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    out.push(b'\n');
+                }
+                format_raw(key, out);
+                out.push(b'\n');
+                format_raw(val, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn bulk(val: &'static str) -> Frame {
+        Frame::Bulk(Bytes::from(val))
+    }
+
+    #[test]
+    fn tty_test() {
+        let format = |frame: &Frame| String::from_utf8(format(frame, false)).unwrap();
+        assert_eq!(format(&Frame::Simple("OK".into())), "OK\n");
+        assert_eq!(format(&Frame::Integer(3)), "(integer) 3\n");
+        assert_eq!(format(&bulk("a\"b\n")), "\"a\\\"b\\n\"\n");
+        assert_eq!(format(&Frame::Null), "(nil)\n");
+        assert_eq!(format(&Frame::Array(vec![])), "(empty array)\n");
+        assert_eq!(
+            format(&Frame::Error("ERR unknown command".into())),
+            "(error) ERR unknown command\n"
+        );
+
+        let items = (0..10).map(Frame::Integer).collect();
+        let frame = Frame::Array(vec![
+            Frame::Array(vec![bulk("a"), Frame::Array(vec![bulk("b"), bulk("c")])]),
+            Frame::Array(items),
+        ]);
+        let expected = [
+            "1) 1) \"a\"",
+            "   2) 1) \"b\"",
+            "      2) \"c\"",
+            "2)  1) (integer) 0",
+            "    2) (integer) 1",
+        ];
+        let output = format(&frame);
+        assert!(
+            output.starts_with(&(expected.join("\n") + "\n")),
+            "{}",
+            output
+        );
+        assert!(output.ends_with("   10) (integer) 9\n"), "{}", output);
+
+        let frame = Frame::Map(vec![
+            (bulk("k"), Frame::Set(vec![Frame::Boolean(true)])),
+            (bulk("d"), Frame::Double(1.5)),
+        ]);
+        assert_eq!(
+            format(&frame),
+            "1# \"k\" => 1~ (true)\n2# \"d\" => (double) 1.5\n"
+        );
+    }
+
+    #[test]
+    fn raw_test() {
+        let format = |frame: &Frame| String::from_utf8(format(frame, true)).unwrap();
+        assert_eq!(format(&Frame::Integer(3)), "3\n");
+        assert_eq!(format(&bulk("a\"b")), "a\"b\n");
+        assert_eq!(format(&Frame::Null), "\n");
+        let frame = Frame::Array(vec![bulk("a"), Frame::Array(vec![bulk("b"), bulk("c")])]);
+        assert_eq!(format(&frame), "a\nb\nc\n");
+        let frame = Frame::Map(vec![(bulk("k"), bulk("v"))]);
+        assert_eq!(format(&frame), "k\nv\n");
+    }
+}
@@ -0,0 +1,279 @@
+use std::fmt;
+
+/// Unit of the ranges of `BITCOUNT` and `BITPOS`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+/// Operation of `BITOP`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitOpKind {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// Type of a `BITFIELD` integer, as `i<bits>` or `u<bits>`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldType {
+    pub signed: bool,
+    pub bits: u8,
+}
+
+/// How `BITFIELD` handles integers out of the range of their type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// The bit at `offset`, the most significant bit of a byte comes first
+pub fn get_bit(value: &[u8], offset: usize) -> u8 {
+    match value.get(offset / 8) {
+        Some(byte) => (byte >> (7 - offset % 8)) & 1,
+        None => 0,
+    }
+}
+
+/// Set the bit at `offset`, growing the value with zeros as needed, and
+/// return the previous bit
+pub fn set_bit(value: &mut Vec<u8>, offset: usize, bit: u8) -> u8 {
+    let index = offset / 8;
+    if value.len() <= index {
+        value.resize(index + 1, 0);
+    }
+    let mask = 1 << (7 - offset % 8);
+    let previous = (value[index] & mask != 0) as u8;
+    match bit {
+        0 => value[index] &= !mask,
+        _ => value[index] |= mask,
+    }
+    previous
+}
+
+/// Inclusive range of bits of a value, from indexes in `unit` counted from
+/// the end when negative. Out of range indexes are clamped as redis does.
+pub fn bit_range(start: i64, end: i64, unit: BitUnit, len: usize) -> Option<(usize, usize)> {
+    let len = match unit {
+        BitUnit::Byte => len as i64,
+        BitUnit::Bit => len as i64 * 8,
+    };
+    let resolve = |index: i64| match index < 0 {
+        true => (index + len).max(0),
+        false => index,
+    };
+    let (start, end) = (resolve(start), resolve(end).min(len - 1));
+    if len == 0 || start > end {
+        return None;
+    }
+    match unit {
+        BitUnit::Byte => Some((start as usize * 8, end as usize * 8 + 7)),
+        BitUnit::Bit => Some((start as usize, end as usize)),
+    }
+}
+
+/// Number of set bits in the inclusive range of bits
+pub fn count_bits(value: &[u8], start: usize, end: usize) -> usize {
+    let mut count = 0;
+    let mut offset = start;
+    while offset <= end {
+        if offset.is_multiple_of(8) && offset + 7 <= end {
+            count += value[offset / 8].count_ones() as usize;
+            offset += 8;
+        } else {
+            count += get_bit(value, offset) as usize;
+            offset += 1;
+        }
+    }
+    count
+}
+
+/// Offset of the first bit set to `bit` in the inclusive range of bits
+pub fn find_bit(value: &[u8], bit: u8, start: usize, end: usize) -> Option<usize> {
+    let skipped = match bit {
+        0 => 0xff,
+        _ => 0,
+    };
+    let mut offset = start;
+    while offset <= end {
+        if offset.is_multiple_of(8) && offset + 7 <= end && value[offset / 8] == skipped {
+            offset += 8;
+            continue;
+        }
+        if get_bit(value, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+    None
+}
+
+/// Combine the values byte by byte, shorter values are padded with zeros
+pub fn bitop(kind: BitOpKind, values: &[&[u8]]) -> Vec<u8> {
+    let len = values.iter().map(|value| value.len()).max().unwrap_or(0);
+    (0..len)
+        .map(|i| {
+            let mut bytes = values
+                .iter()
+                .map(|value| value.get(i).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            match kind {
+                BitOpKind::And => bytes.fold(first, |acc, byte| acc & byte),
+                BitOpKind::Or => bytes.fold(first, |acc, byte| acc | byte),
+                BitOpKind::Xor => bytes.fold(first, |acc, byte| acc ^ byte),
+                BitOpKind::Not => !first,
+            }
+        })
+        .collect()
+}
+
+impl FieldType {
+    pub fn parse(s: &str) -> Option<FieldType> {
+        let signed = match s.as_bytes().first() {
+            Some(b'i' | b'I') => true,
+            Some(b'u' | b'U') => false,
+            _ => return None,
+        };
+        let bits = s[1..].parse::<u8>().ok()?;
+        let max = if signed { 64 } else { 63 };
+        (1..=max)
+            .contains(&bits)
+            .then_some(FieldType { signed, bits })
+    }
+
+    fn range(&self) -> (i128, i128) {
+        match self.signed {
+            true => (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1),
+            false => (0, (1 << self.bits) - 1),
+        }
+    }
+
+    /// Fit `value` in the type, as `overflow` says when out of range
+    pub fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = self.range();
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                let modulo = 1i128 << self.bits;
+                let wrapped = value.rem_euclid(modulo);
+                match self.signed && wrapped > max {
+                    true => Some((wrapped - modulo) as i64),
+                    false => Some(wrapped as i64),
+                }
+            }
+            Overflow::Sat => Some(value.clamp(min, max) as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.signed { 'i' } else { 'u' };
+        write!(f, "{}{}", sign, self.bits)
+    }
+}
+
+/// The integer of type `field` at bit `offset`, bits past the end of the
+/// value are zeros
+pub fn get_field(value: &[u8], offset: usize, field: FieldType) -> i64 {
+    let bits = field.bits as usize;
+    let mut unsigned = 0u64;
+    for i in 0..bits {
+        unsigned = (unsigned << 1) | get_bit(value, offset + i) as u64;
+    }
+    if field.signed && bits < 64 && unsigned & (1 << (bits - 1)) != 0 {
+        unsigned |= u64::MAX << bits;
+    }
+    unsigned as i64
+}
+
+/// Write the integer of type `field` at bit `offset`, growing the value
+/// with zeros as needed
+pub fn set_field(value: &mut Vec<u8>, offset: usize, field: FieldType, integer: i64) {
+    let bits = field.bits as usize;
+    let end = (offset + bits).div_ceil(8);
+    if value.len() < end {
+        value.resize(end, 0);
+    }
+    for i in 0..bits {
+        let bit = ((integer as u64) >> (bits - 1 - i)) & 1;
+        set_bit(value, offset + i, bit as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_test() {
+        let mut value = vec![];
+        assert_eq!(set_bit(&mut value, 7, 1), 0);
+        assert_eq!(value, vec![0x01]);
+        assert_eq!(set_bit(&mut value, 17, 1), 0);
+        assert_eq!(value, vec![0x01, 0x00, 0x40]);
+        assert_eq!(set_bit(&mut value, 7, 0), 1);
+        assert_eq!(get_bit(&value, 17), 1);
+        assert_eq!(get_bit(&value, 100), 0);
+
+        let value = b"foobar";
+        assert_eq!(count_bits(value, 0, 47), 26);
+        assert_eq!(bit_range(1, 1, BitUnit::Byte, 6), Some((8, 15)));
+        assert_eq!(bit_range(5, 30, BitUnit::Bit, 6), Some((5, 30)));
+        assert_eq!(bit_range(-100, -100, BitUnit::Byte, 6), Some((0, 7)));
+        assert_eq!(bit_range(3, 2, BitUnit::Byte, 6), None);
+        let (start, end) = bit_range(1, 1, BitUnit::Byte, 6).unwrap();
+        assert_eq!(count_bits(value, start, end), 6);
+        assert_eq!(count_bits(value, 5, 30), 17);
+
+        let value = [0xff, 0xf0, 0x00];
+        assert_eq!(find_bit(&value, 0, 0, 23), Some(12));
+        assert_eq!(find_bit(&value, 1, 16, 23), None);
+        assert_eq!(find_bit(&value, 1, 2, 23), Some(2));
+    }
+
+    #[test]
+    fn bitop_test() {
+        let values: [&[u8]; 2] = [b"\xff\x0f", b"\x0f"];
+        assert_eq!(bitop(BitOpKind::And, &values), vec![0x0f, 0x00]);
+        assert_eq!(bitop(BitOpKind::Or, &values), vec![0xff, 0x0f]);
+        assert_eq!(bitop(BitOpKind::Xor, &values), vec![0xf0, 0x0f]);
+        assert_eq!(bitop(BitOpKind::Not, &values[1..]), vec![0xf0]);
+    }
+
+    #[test]
+    fn field_test() {
+        let i8 = FieldType::parse("i8").unwrap();
+        let u4 = FieldType::parse("u4").unwrap();
+        assert_eq!(FieldType::parse("u64"), None);
+        assert_eq!(FieldType::parse("i0"), None);
+        assert_eq!(FieldType::parse("x8"), None);
+        assert_eq!(FieldType::parse("i64").unwrap().to_string(), "i64");
+
+        let mut value = vec![];
+        set_field(&mut value, 4, i8, -2);
+        assert_eq!(value, vec![0x0f, 0xe0]);
+        assert_eq!(get_field(&value, 4, i8), -2);
+        assert_eq!(get_field(&value, 4, u4), 15);
+        assert_eq!(get_field(&value, 100, i8), 0);
+
+        assert_eq!(u4.fit(17, Overflow::Wrap), Some(1));
+        assert_eq!(u4.fit(-1, Overflow::Wrap), Some(15));
+        assert_eq!(u4.fit(17, Overflow::Sat), Some(15));
+        assert_eq!(u4.fit(17, Overflow::Fail), None);
+        assert_eq!(i8.fit(128, Overflow::Wrap), Some(-128));
+        assert_eq!(i8.fit(-200, Overflow::Sat), Some(-128));
+        let i64 = FieldType::parse("i64").unwrap();
+        assert_eq!(
+            i64.fit(i64::MAX as i128 + 1, Overflow::Wrap),
+            Some(i64::MIN)
+        );
+    }
+}
@@ -1,7 +1,5 @@
-use bytes::Bytes;
 use bytes::{Buf, BytesMut};
 use tokio::io::AsyncReadExt;
-use tokio_util::io::StreamReader;
 
 pub struct BufferedStream<Stream> {
     stream: Stream,
@@ -16,10 +14,6 @@ impl<Stream: AsyncReadExt + Unpin> BufferedStream<Stream> {
         }
     }
 
-    pub async fn reset(&mut self) {
-        self.buffer.clear();
-    }
-
     pub async fn peek_u8(&mut self) -> Result<u8, std::io::Error> {
         if !self.buffer.has_remaining() {
             self.refill().await?
@@ -47,7 +41,7 @@ impl<Stream: AsyncReadExt + Unpin> BufferedStream<Stream> {
 
     pub async fn get_u8_not_consume(&mut self, not: u8) -> Result<Option<u8>, std::io::Error> {
         let e = self.get_u8().await?;
-        return Ok(if e == not { None } else { Some(e) });
+        Ok(if e == not { None } else { Some(e) })
     }
 
     pub async fn get_u8_until_consume(&mut self, not: u8) -> Result<Vec<u8>, std::io::Error> {
@@ -58,16 +52,16 @@ impl<Stream: AsyncReadExt + Unpin> BufferedStream<Stream> {
             }
             acc.push(e);
         }
-        return Ok(acc);
+        Ok(acc)
     }
 
     /// Read a new-line terminated decimal
-    pub async fn get_decimal(&mut self) -> Result<u64, std::io::Error> {
+    pub async fn get_decimal(&mut self) -> Result<i64, std::io::Error> {
         use atoi::atoi;
 
         let line = self.get_line().await?;
 
-        atoi::<u64>(&line).ok_or_else(|| {
+        atoi::<i64>(&line).ok_or_else(|| {
             std::io::Error::new(
                 std::io::ErrorKind::Interrupted,
                 "protocol error; invalid frame format".to_string(),
@@ -80,9 +74,9 @@ impl<Stream: AsyncReadExt + Unpin> BufferedStream<Stream> {
             self.refill().await?
         }
 
-        let extract = Vec::from(self.buffer.chunk()[..n].to_vec());
+        let extract = self.buffer.chunk()[..n].to_vec();
         self.skip(n).await?;
-        return Ok(extract);
+        Ok(extract)
     }
 
     /// Find a line
@@ -112,13 +106,15 @@ impl<Stream: AsyncReadExt + Unpin> BufferedStream<Stream> {
                 "protocol error; not enough bytes".to_string(),
             ));
         }
-        return Ok(());
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bytes::Bytes;
+    use tokio_util::io::StreamReader;
 
     #[tokio::test]
     async fn advance_test() {
@@ -129,7 +125,7 @@ mod tests {
         ]);
 
         // Convert it to an AsyncRead.
-        let mut read = StreamReader::new(stream);
+        let read = StreamReader::new(stream);
         let mut buffer = BufferedStream::new(read);
 
         println!("0");
@@ -143,7 +139,7 @@ mod tests {
         println!("4");
         assert_eq!(buffer.get_u8().await.unwrap(), 11);
         println!("5");
-        assert_eq!(buffer.get_u8().await.is_err(), true);
+        assert!(buffer.get_u8().await.is_err());
         println!("6");
     }
 }
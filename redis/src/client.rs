@@ -0,0 +1,260 @@
+use crate::command::{ClientKill, ClientType};
+use crate::frame::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Notify};
+
+/// State of a connection, as listed by `CLIENT LIST`
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: String,
+    /// Local address the client connected to
+    pub laddr: String,
+    pub name: String,
+    /// ACL user the client is authenticated as
+    pub user: String,
+    created: Instant,
+    last_interaction: Instant,
+    pub db: usize,
+    /// Name of the last command
+    pub command: &'static str,
+    pub subscriptions: usize,
+    pub patterns: usize,
+    /// Commands queued since `MULTI`, when in a transaction
+    pub multi: Option<usize>,
+    pub replica: bool,
+    pub monitor: bool,
+}
+
+impl ClientInfo {
+    pub fn new(id: u64, addr: String, laddr: String) -> Self {
+        Self {
+            id,
+            addr,
+            laddr,
+            name: String::new(),
+            user: "default".to_string(),
+            created: Instant::now(),
+            last_interaction: Instant::now(),
+            db: 0,
+            command: "NULL",
+            subscriptions: 0,
+            patterns: 0,
+            multi: None,
+            replica: false,
+            monitor: false,
+        }
+    }
+
+    /// Record a command received from the client
+    pub fn interact(&mut self, command: &'static str) {
+        self.command = command;
+        self.last_interaction = Instant::now();
+    }
+
+    fn kind(&self) -> ClientType {
+        if self.replica {
+            ClientType::Replica
+        } else if self.subscriptions + self.patterns > 0 {
+            ClientType::PubSub
+        } else {
+            ClientType::Normal
+        }
+    }
+
+    fn flags(&self) -> String {
+        let mut flags = String::new();
+        if self.replica {
+            flags.push('S');
+        }
+        if self.monitor {
+            flags.push('O');
+        }
+        if self.subscriptions + self.patterns > 0 {
+            flags.push('P');
+        }
+        if self.multi.is_some() {
+            flags.push('x');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        flags
+    }
+
+    /// Line of `CLIENT LIST` and `CLIENT INFO`
+    pub fn describe(&self) -> String {
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub={} psub={} multi={} cmd={} user={}\n",
+            self.id,
+            self.addr,
+            self.laddr,
+            self.name,
+            self.created.elapsed().as_secs(),
+            self.last_interaction.elapsed().as_secs(),
+            self.flags(),
+            self.db,
+            self.subscriptions,
+            self.patterns,
+            self.multi.map_or(-1, |queued| queued as i64),
+            self.command,
+            self.user
+        )
+    }
+}
+
+struct Client {
+    info: Arc<Mutex<ClientInfo>>,
+    /// Notified to close the connection
+    kill: Arc<Notify>,
+}
+
+/// Connected clients, by id
+#[derive(Default)]
+pub struct Clients {
+    clients: Mutex<BTreeMap<u64, Client>>,
+}
+
+impl Clients {
+    /// Register a connection, which closes once the returned `Notify` is
+    /// notified
+    pub fn register(&self, info: ClientInfo) -> (Arc<Mutex<ClientInfo>>, Arc<Notify>) {
+        let client = Client {
+            info: Arc::new(Mutex::new(info)),
+            kill: Arc::new(Notify::new()),
+        };
+        let registered = (client.info.clone(), client.kill.clone());
+        let id = client.info.lock().unwrap().id;
+        self.clients.lock().unwrap().insert(id, client);
+        registered
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.clients.lock().unwrap().remove(&id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// `CLIENT LIST`, filtered by type and ids
+    pub fn list(&self, kind: Option<ClientType>, ids: &[u64]) -> String {
+        let clients = self.clients.lock().unwrap();
+        clients
+            .values()
+            .map(|client| client.info.lock().unwrap())
+            .filter(|info| kind.is_none_or(|kind| info.kind() == kind))
+            .filter(|info| ids.is_empty() || ids.contains(&info.id))
+            .map(|info| info.describe())
+            .collect()
+    }
+
+    /// `CLIENT KILL`, returns the number of killed clients, the client
+    /// `myself` only being killed when not skipped
+    pub fn kill(&self, filter: &ClientKill, myself: u64) -> usize {
+        let clients = self.clients.lock().unwrap();
+        let mut killed = 0;
+        for client in clients.values() {
+            let info = client.info.lock().unwrap();
+            let matched = filter.id.is_none_or(|id| id == info.id)
+                && filter.addr.as_ref().is_none_or(|addr| *addr == info.addr)
+                && filter.laddr.as_ref().is_none_or(|addr| *addr == info.laddr)
+                && filter.kind.is_none_or(|kind| kind == info.kind())
+                && filter.user.as_ref().is_none_or(|user| *user == info.user)
+                && !(filter.skipme && info.id == myself);
+            if matched {
+                client.kill.notify_one();
+                killed += 1;
+            }
+        }
+        killed
+    }
+}
+
+/// Connections streaming the received commands, after `MONITOR`
+#[derive(Default)]
+pub struct Monitors {
+    senders: Mutex<Vec<mpsc::UnboundedSender<Frame>>>,
+    /// Whether there are monitors, checked without locking
+    active: AtomicBool,
+}
+
+impl Monitors {
+    pub fn add(&self, sender: mpsc::UnboundedSender<Frame>) {
+        self.senders.lock().unwrap().push(sender);
+        self.active.store(true, Ordering::Relaxed);
+    }
+
+    /// Stream a request received from the client, as
+    /// `+1339518083.107412 [0 127.0.0.1:60866] "keys" "*"`
+    pub fn feed(&self, request: &Frame, db: usize, addr: &str) {
+        if !self.active.load(Ordering::Relaxed) {
+            return;
+        }
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros();
+        let mut line = format!(
+            "{}.{:06} [{} {}]",
+            micros / 1_000_000,
+            micros % 1_000_000,
+            db,
+            addr
+        );
+        if let Frame::Array(args) = request {
+            for arg in args {
+                if let Frame::Bulk(arg) = arg {
+                    line.push(' ');
+                    line.push_str(&quote(arg));
+                }
+            }
+        }
+
+        let mut senders = self.senders.lock().unwrap();
+        senders.retain(|sender| sender.send(Frame::Simple(line.clone())).is_ok());
+        self.active.store(!senders.is_empty(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(clients: &Clients, id: u64) -> Arc<Mutex<ClientInfo>> {
+        let addr = format!("127.0.0.1:{}", 5000 + id);
+        let info = ClientInfo::new(id, addr, "127.0.0.1:6379".to_string());
+        clients.register(info).0
+    }
+
+    #[test]
+    fn clients_test() {
+        let clients = Clients::default();
+        let first = client(&clients, 1);
+        client(&clients, 2).lock().unwrap().subscriptions = 1;
+        first.lock().unwrap().name = "first".to_string();
+        first.lock().unwrap().interact("get");
+
+        let list = clients.list(None, &[]);
+        assert_eq!(list.lines().count(), 2);
+        assert!(list.starts_with("id=1 addr=127.0.0.1:5001 laddr=127.0.0.1:6379 name=first"));
+        assert!(list.contains("flags=N db=0 sub=0 psub=0 multi=-1 cmd=get"));
+        assert!(clients
+            .list(Some(ClientType::PubSub), &[])
+            .starts_with("id=2 "));
+        assert!(clients.list(None, &[3]).is_empty());
+
+        let mut filter = ClientKill {
+            skipme: true,
+            ..Default::default()
+        };
+        assert_eq!(clients.kill(&filter, 1), 1);
+        filter.addr = Some("127.0.0.1:5001".to_string());
+        filter.skipme = false;
+        assert_eq!(clients.kill(&filter, 1), 1);
+        clients.unregister(1);
+        assert_eq!(clients.len(), 1);
+    }
+}
@@ -0,0 +1,429 @@
+use crate::error::Error;
+use crate::frame::*;
+use bytes::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Number of hash slots the keys are spread over
+pub const SLOTS: usize = 16384;
+/// The cluster bus port of a node is its port plus this offset
+const BUS_PORT_OFFSET: u32 = 10000;
+
+/// Hash slot of a key, only the part between the first `{` and the next `}`
+/// is hashed when not empty, so that related keys share a slot
+pub fn key_slot(key: &[u8]) -> u16 {
+    let key = match key.iter().position(|&c| c == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|&c| c == b'}') {
+            Some(0) | None => key,
+            Some(len) => &key[open + 1..open + 1 + len],
+        },
+        None => key,
+    };
+    crc16(key) % SLOTS as u16
+}
+
+/// CRC16-CCITT (XMODEM), as used by Redis Cluster
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
+
+pub struct Node {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Node {
+    /// The id is derived from the address, so that every node of a static
+    /// cluster agrees on it
+    fn new(host: String, port: u16) -> Self {
+        let id = (0..3u8)
+            .map(|salt| {
+                let mut hasher = DefaultHasher::new();
+                (salt, &host, port).hash(&mut hasher);
+                format!("{:016x}", hasher.finish())
+            })
+            .collect::<String>();
+        Self {
+            id: id[..40].to_string(),
+            host,
+            port,
+        }
+    }
+
+    fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Way a command is served in cluster mode
+pub enum Route {
+    Local,
+    /// The slot is migrating to the node at the address, the command is
+    /// only served when its keys still exist on this node
+    Migrating(u16, String),
+    /// Error redirecting the client, as `MOVED`
+    Redirect(Frame),
+}
+
+/// `CLUSTER SETSLOT` states
+#[derive(Debug)]
+pub enum SlotState {
+    Migrating(String),
+    Importing(String),
+    Node(String),
+    Stable,
+}
+
+/// Nodes of the cluster and the slots they serve.
+///
+/// The nodes are listed by `cluster-nodes`, the slots being split in
+/// contiguous ranges in the order of the list. Slots are then moved with
+/// `CLUSTER SETSLOT`, sent to every node.
+pub struct Cluster {
+    nodes: Vec<Node>,
+    /// Index of this node
+    myself: usize,
+    /// Index of the node serving each slot
+    slots: Vec<usize>,
+    /// Slots moving from this node, with the node they move to
+    migrating: HashMap<u16, usize>,
+    /// Slots moving to this node, with the node they move from
+    importing: HashMap<u16, usize>,
+}
+
+impl Cluster {
+    /// A cluster of the nodes at the addresses, this node being the one at
+    /// `myself`, alone when there are no other nodes
+    pub fn new(addresses: &[(String, u16)], myself: (&[String], u16)) -> Result<Self, Error> {
+        let (bind, port) = myself;
+        let mut nodes: Vec<Node> = addresses
+            .iter()
+            .map(|(host, port)| Node::new(host.clone(), *port))
+            .collect();
+        let on_port: Vec<usize> = (0..nodes.len())
+            .filter(|&index| nodes[index].port == port)
+            .collect();
+        let myself = match on_port[..] {
+            [index] => index,
+            _ => match on_port
+                .iter()
+                .find(|&&index| bind.contains(&nodes[index].host))
+            {
+                Some(&index) => index,
+                None if nodes.is_empty() => {
+                    nodes.push(Node::new(bind[0].clone(), port));
+                    0
+                }
+                None => return Err("this node is not listed in cluster-nodes".into()),
+            },
+        };
+
+        let slots = (0..SLOTS).map(|slot| slot * nodes.len() / SLOTS).collect();
+        Ok(Self {
+            nodes,
+            myself,
+            slots,
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+        })
+    }
+
+    /// Route a command accessing the keys, `asking` when it follows `ASKING`
+    pub fn route(&self, keys: &[&str], asking: bool) -> Route {
+        let Some((first, others)) = keys.split_first() else {
+            return Route::Local;
+        };
+        let slot = key_slot(first.as_bytes());
+        if others.iter().any(|key| key_slot(key.as_bytes()) != slot) {
+            return Route::Redirect(Frame::Error(
+                "CROSSSLOT Keys in request don't hash to the same slot".to_string(),
+            ));
+        }
+
+        let owner = self.slots[slot as usize];
+        if owner == self.myself {
+            match self.migrating.get(&slot) {
+                Some(&target) => Route::Migrating(slot, self.nodes[target].address()),
+                None => Route::Local,
+            }
+        } else if asking && self.importing.contains_key(&slot) {
+            Route::Local
+        } else {
+            Route::Redirect(Frame::Error(format!(
+                "MOVED {} {}",
+                slot,
+                self.nodes[owner].address()
+            )))
+        }
+    }
+
+    /// `CLUSTER SETSLOT`
+    pub fn set_slot(&mut self, slot: u16, state: SlotState) -> Result<(), String> {
+        if slot as usize >= SLOTS {
+            return Err("ERR Invalid or out of range slot".to_string());
+        }
+        let node = |id: &str| {
+            self.nodes
+                .iter()
+                .position(|node| node.id == id)
+                .ok_or_else(|| format!("ERR I don't know about node {}", id))
+        };
+        let owner = self.slots[slot as usize];
+        match state {
+            SlotState::Migrating(id) => {
+                let target = node(&id)?;
+                if owner != self.myself {
+                    return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+                }
+                if target == self.myself {
+                    return Err("ERR Can't MIGRATE to myself".to_string());
+                }
+                self.migrating.insert(slot, target);
+            }
+            SlotState::Importing(id) => {
+                let source = node(&id)?;
+                if owner == self.myself {
+                    return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+                }
+                if source == self.myself {
+                    return Err("ERR Can't IMPORT from myself".to_string());
+                }
+                self.importing.insert(slot, source);
+            }
+            SlotState::Node(id) => {
+                self.slots[slot as usize] = node(&id)?;
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+            SlotState::Stable => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn myself(&self) -> &Node {
+        &self.nodes[self.myself]
+    }
+
+    /// Contiguous ranges of slots served by a node, as `(node, start, end)`
+    fn ranges(&self) -> Vec<(usize, u16, u16)> {
+        let mut ranges: Vec<(usize, u16, u16)> = vec![];
+        for (slot, &node) in self.slots.iter().enumerate() {
+            match ranges.last_mut() {
+                Some((last, _, end)) if *last == node => *end = slot as u16,
+                _ => ranges.push((node, slot as u16, slot as u16)),
+            }
+        }
+        ranges
+    }
+
+    /// `CLUSTER SLOTS`
+    pub fn slots(&self) -> Frame {
+        let ranges = self.ranges().into_iter().map(|(node, start, end)| {
+            let node = &self.nodes[node];
+            Frame::Array(vec![
+                Frame::Integer(start as i64),
+                Frame::Integer(end as i64),
+                Frame::Array(vec![
+                    Frame::Bulk(Bytes::from(node.host.clone())),
+                    Frame::Integer(node.port as i64),
+                    Frame::Bulk(Bytes::from(node.id.clone())),
+                ]),
+            ])
+        });
+        Frame::Array(ranges.collect())
+    }
+
+    /// `CLUSTER SHARDS`, a shard per node serving slots
+    pub fn shards(&self) -> Frame {
+        let ranges = self.ranges();
+        let field = |name: &'static str, value: Frame| {
+            (Frame::Bulk(Bytes::from_static(name.as_bytes())), value)
+        };
+        let shards = self.nodes.iter().enumerate().filter_map(|(index, node)| {
+            let slots: Vec<Frame> = ranges
+                .iter()
+                .filter(|(owner, _, _)| *owner == index)
+                .flat_map(|(_, start, end)| {
+                    [Frame::Integer(*start as i64), Frame::Integer(*end as i64)]
+                })
+                .collect();
+            if slots.is_empty() {
+                return None;
+            }
+            let node = Frame::Map(vec![
+                field("id", Frame::Bulk(Bytes::from(node.id.clone()))),
+                field("port", Frame::Integer(node.port as i64)),
+                field("ip", Frame::Bulk(Bytes::from(node.host.clone()))),
+                field("endpoint", Frame::Bulk(Bytes::from(node.host.clone()))),
+                field("role", Frame::Bulk(Bytes::from_static(b"master"))),
+                field("replication-offset", Frame::Integer(0)),
+                field("health", Frame::Bulk(Bytes::from_static(b"online"))),
+            ]);
+            Some(Frame::Map(vec![
+                field("slots", Frame::Array(slots)),
+                field("nodes", Frame::Array(vec![node])),
+            ]))
+        });
+        Frame::Array(shards.collect())
+    }
+
+    /// `CLUSTER NODES`, a line per node
+    pub fn nodes(&self) -> String {
+        let ranges = self.ranges();
+        let mut lines = String::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let flags = match index == self.myself {
+                true => "myself,master",
+                false => "master",
+            };
+            lines.push_str(&format!(
+                "{} {}@{} {} - 0 0 {} connected",
+                node.id,
+                node.address(),
+                node.port as u32 + BUS_PORT_OFFSET,
+                flags,
+                index + 1
+            ));
+            for (_, start, end) in ranges.iter().filter(|(owner, _, _)| *owner == index) {
+                match start == end {
+                    true => lines.push_str(&format!(" {}", start)),
+                    false => lines.push_str(&format!(" {}-{}", start, end)),
+                }
+            }
+            if index == self.myself {
+                let mut moving: Vec<_> = self
+                    .migrating
+                    .iter()
+                    .map(|(slot, node)| (slot, "->-", node))
+                    .chain(
+                        self.importing
+                            .iter()
+                            .map(|(slot, node)| (slot, "-<-", node)),
+                    )
+                    .collect();
+                moving.sort();
+                for (slot, direction, node) in moving {
+                    let id = &self.nodes[*node].id;
+                    lines.push_str(&format!(" [{}{}{}]", slot, direction, id));
+                }
+            }
+            lines.push('\n');
+        }
+        lines
+    }
+
+    /// `CLUSTER INFO`
+    pub fn info(&self) -> String {
+        // Nodes serving at least a slot
+        let mut owners = self.slots.clone();
+        owners.sort();
+        owners.dedup();
+        [
+            "cluster_state:ok".to_string(),
+            format!("cluster_slots_assigned:{}", SLOTS),
+            format!("cluster_slots_ok:{}", SLOTS),
+            "cluster_slots_pfail:0".to_string(),
+            "cluster_slots_fail:0".to_string(),
+            format!("cluster_known_nodes:{}", self.nodes.len()),
+            format!("cluster_size:{}", owners.len()),
+            format!("cluster_current_epoch:{}", self.nodes.len()),
+            format!("cluster_my_epoch:{}", self.myself + 1),
+        ]
+        .join("\r\n")
+            + "\r\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(myself: u16) -> Cluster {
+        let addresses: Vec<_> = (7001..=7003)
+            .map(|port| ("127.0.0.1".to_string(), port))
+            .collect();
+        Cluster::new(&addresses, (&["127.0.0.1".to_string()], myself)).unwrap()
+    }
+
+    #[test]
+    fn key_slot_test() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_eq!(key_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % SLOTS as u16);
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+
+    #[test]
+    fn route_test() {
+        let mut cluster = cluster(7001);
+        assert!(matches!(cluster.route(&["b"], false), Route::Local));
+        assert!(matches!(
+            cluster.route(&["foo"], false),
+            Route::Redirect(Frame::Error(err)) if err == "MOVED 12182 127.0.0.1:7003"
+        ));
+        assert!(matches!(
+            cluster.route(&["b", "foo"], false),
+            Route::Redirect(Frame::Error(err)) if err.starts_with("CROSSSLOT")
+        ));
+
+        let target = cluster.nodes[1].id.clone();
+        let slot = key_slot(b"b");
+        cluster
+            .set_slot(slot, SlotState::Migrating(target.clone()))
+            .unwrap();
+        assert!(matches!(
+            cluster.route(&["b"], false),
+            Route::Migrating(s, address) if s == slot && address == "127.0.0.1:7002"
+        ));
+        assert!(cluster
+            .nodes()
+            .contains(&format!("[{}->-{}]", slot, target)));
+        cluster.set_slot(slot, SlotState::Node(target)).unwrap();
+        assert!(matches!(cluster.route(&["b"], false), Route::Redirect(_)));
+
+        let mut cluster = cluster_importing(slot);
+        assert!(matches!(cluster.route(&["b"], true), Route::Local));
+        assert!(matches!(cluster.route(&["b"], false), Route::Redirect(_)));
+        assert!(cluster.set_slot(slot, SlotState::Stable).is_ok());
+        assert!(cluster.set_slot(16384, SlotState::Stable).is_err());
+    }
+
+    fn cluster_importing(slot: u16) -> Cluster {
+        let mut cluster = cluster(7002);
+        let source = cluster.nodes[0].id.clone();
+        cluster
+            .set_slot(slot, SlotState::Importing(source))
+            .unwrap();
+        cluster
+    }
+
+    #[test]
+    fn slots_test() {
+        let cluster = cluster(7002);
+        assert_eq!(cluster.myself().port, 7002);
+        assert_eq!(
+            cluster.ranges(),
+            vec![(0, 0, 5461), (1, 5462, 10922), (2, 10923, 16383)]
+        );
+        let nodes = cluster.nodes();
+        assert_eq!(nodes.lines().count(), 3);
+        assert!(nodes.lines().nth(1).unwrap().contains("myself,master"));
+        assert!(nodes.lines().nth(2).unwrap().ends_with(" 10923-16383"));
+    }
+}
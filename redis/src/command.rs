@@ -1,16 +1,135 @@
+use crate::bitmap::{BitOpKind, BitUnit, FieldType, Overflow};
+use crate::cluster::SlotState;
 use crate::command_parser::*;
+use crate::geo::{self, Shape};
+use crate::stream::{parse_range_bound, Fields, NewId, StreamId, Trim, INVALID_ID};
+use crate::zset::{LexBound, ScoreBound};
 use crate::Frame;
 use bytes::Bytes;
 use std::time::Duration;
 
+/// Maximum size of a string value
+pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
 #[derive(Debug)]
 pub enum Command {
+    Acl(Acl),
+    Append(Append),
+    Asking(Asking),
+    Auth(Auth),
+    BgRewriteAof(BgRewriteAof),
+    BgSave(BgSave),
+    BitCount(BitCount),
+    BitField(BitField),
+    BitOp(BitOp),
+    BitPos(BitPos),
+    BLMove(BLMove),
+    BPop(BPop),
+    Client(Client),
+    Cluster(Cluster),
     Config(Config),
+    DbSize(DbSize),
+    Del(Del),
+    Discard(Discard),
+    Exec(Exec),
+    Exists(Exists),
+    Expire(Expire),
+    Flush(Flush),
+    GeoAdd(GeoAdd),
+    GeoDist(GeoDist),
+    GeoHash(GeoMembers),
+    GeoPos(GeoMembers),
+    GeoSearch(GeoSearch),
+    GeoSearchStore(GeoSearchStore),
     Get(Get),
+    GetBit(GetBit),
+    GetDel(GetDel),
+    GetEx(GetEx),
+    GetRange(GetRange),
+    HDel(HDel),
+    HExists(HExists),
+    HGet(HGet),
+    HGetAll(HGetAll),
+    HIncrBy(HIncrBy),
+    HKeys(HKeys),
+    HLen(HLen),
+    HMGet(HMGet),
+    HScan(HScan),
+    HSet(HSet),
+    HVals(HVals),
+    Hello(Hello),
+    IncrBy(IncrBy),
+    IncrByFloat(IncrByFloat),
+    Info(Info),
+    Keys(Keys),
+    LastSave(LastSave),
+    Latency(Latency),
+    LIndex(LIndex),
+    LLen(LLen),
+    LMove(LMove),
+    LRange(LRange),
+    LRem(LRem),
+    LSet(LSet),
+    LTrim(LTrim),
+    MGet(MGet),
+    Monitor(Monitor),
+    Move(Move),
+    MSet(MSet),
+    Multi(Multi),
+    Persist(Persist),
+    PfAdd(PfAdd),
+    PfCount(PfCount),
+    PfMerge(PfMerge),
+    Pop(Pop),
+    PSubscribe(PSubscribe),
+    PUnsubscribe(PUnsubscribe),
+    PubSub(PubSub),
     Publish(Publish),
+    PSync(PSync),
+    Push(Push),
+    RandomKey(RandomKey),
+    Rename(Rename),
+    ReplConf(ReplConf),
+    ReplicaOf(ReplicaOf),
+    SAdd(SAdd),
+    Save(Save),
+    Scan(Scan),
+    Select(Select),
+    SCard(SCard),
     Set(Set),
+    SetBit(SetBit),
+    SetOp(SetOp),
+    SetRange(SetRange),
+    SIsMember(SIsMember),
+    SlowLog(SlowLog),
+    SMembers(SMembers),
+    SRem(SRem),
+    StrLen(StrLen),
     Subscribe(Subscribe),
+    SwapDb(SwapDb),
+    Ttl(Ttl),
+    Type(Type),
     Unsubscribe(Unsubscribe),
+    Unwatch(Unwatch),
+    Watch(Watch),
+    XAck(XAck),
+    XAdd(XAdd),
+    XAutoClaim(XAutoClaim),
+    XClaim(XClaim),
+    XDel(XDel),
+    XGroup(XGroup),
+    XLen(XLen),
+    XPending(XPending),
+    XRange(XRange),
+    XRead(XRead),
+    XSetId(XSetId),
+    ZAdd(ZAdd),
+    ZCard(ZCard),
+    ZIncrBy(ZIncrBy),
+    ZRange(ZRange),
+    ZRank(ZRank),
+    ZRem(ZRem),
+    ZScore(ZScore),
     Ping(Ping),
     Unknown(Unknown),
 }
@@ -21,222 +140,4365 @@ impl Command {
         let command_name = parse.next_string()?.to_lowercase();
         let command = match &command_name[..] {
             "get" => Command::Get(Get::parse_frames(&mut parse)?),
+            "del" => Command::Del(Del::parse_frames(&mut parse, false)?),
+            "unlink" => Command::Del(Del::parse_frames(&mut parse, true)?),
+            "exists" => Command::Exists(Exists::parse_frames(&mut parse)?),
+            "type" => Command::Type(Type::parse_frames(&mut parse)?),
+            "rename" => Command::Rename(Rename::parse_frames(&mut parse, false)?),
+            "renamenx" => Command::Rename(Rename::parse_frames(&mut parse, true)?),
+            "randomkey" => Command::RandomKey(RandomKey::parse_frames(&mut parse)?),
+            "dbsize" => Command::DbSize(DbSize::parse_frames(&mut parse)?),
+            "keys" => Command::Keys(Keys::parse_frames(&mut parse)?),
+            "scan" => Command::Scan(Scan::parse_frames(&mut parse)?),
+            "select" => Command::Select(Select::parse_frames(&mut parse)?),
+            "flushdb" => Command::Flush(Flush::parse_frames(&mut parse, false)?),
+            "flushall" => Command::Flush(Flush::parse_frames(&mut parse, true)?),
+            "swapdb" => Command::SwapDb(SwapDb::parse_frames(&mut parse)?),
+            "move" => Command::Move(Move::parse_frames(&mut parse)?),
+            "getdel" => Command::GetDel(GetDel::parse_frames(&mut parse)?),
+            "getex" => Command::GetEx(GetEx::parse_frames(&mut parse)?),
+            "getrange" => Command::GetRange(GetRange::parse_frames(&mut parse)?),
+            "setrange" => Command::SetRange(SetRange::parse_frames(&mut parse)?),
+            "setbit" => Command::SetBit(SetBit::parse_frames(&mut parse)?),
+            "getbit" => Command::GetBit(GetBit::parse_frames(&mut parse)?),
+            "bitcount" => Command::BitCount(BitCount::parse_frames(&mut parse)?),
+            "bitpos" => Command::BitPos(BitPos::parse_frames(&mut parse)?),
+            "bitop" => Command::BitOp(BitOp::parse_frames(&mut parse)?),
+            "bitfield" => Command::BitField(BitField::parse_frames(&mut parse)?),
+            "pfadd" => Command::PfAdd(PfAdd::parse_frames(&mut parse)?),
+            "pfcount" => Command::PfCount(PfCount::parse_frames(&mut parse)?),
+            "pfmerge" => Command::PfMerge(PfMerge::parse_frames(&mut parse)?),
+            "geoadd" => Command::GeoAdd(GeoAdd::parse_frames(&mut parse)?),
+            "geodist" => Command::GeoDist(GeoDist::parse_frames(&mut parse)?),
+            "geohash" => Command::GeoHash(GeoMembers::parse_frames(&mut parse)?),
+            "geopos" => Command::GeoPos(GeoMembers::parse_frames(&mut parse)?),
+            "geosearch" => Command::GeoSearch(GeoSearch::parse_frames(&mut parse)?),
+            "geosearchstore" => Command::GeoSearchStore(GeoSearchStore::parse_frames(&mut parse)?),
+            "strlen" => Command::StrLen(StrLen::parse_frames(&mut parse)?),
+            "append" => Command::Append(Append::parse_frames(&mut parse)?),
+            "incr" => Command::IncrBy(IncrBy::parse_frames(&mut parse, false, false)?),
+            "decr" => Command::IncrBy(IncrBy::parse_frames(&mut parse, false, true)?),
+            "incrby" => Command::IncrBy(IncrBy::parse_frames(&mut parse, true, false)?),
+            "decrby" => Command::IncrBy(IncrBy::parse_frames(&mut parse, true, true)?),
+            "incrbyfloat" => Command::IncrByFloat(IncrByFloat::parse_frames(&mut parse)?),
+            "multi" => Command::Multi(Multi::parse_frames(&mut parse)?),
+            "exec" => Command::Exec(Exec::parse_frames(&mut parse)?),
+            "discard" => Command::Discard(Discard::parse_frames(&mut parse)?),
+            "watch" => Command::Watch(Watch::parse_frames(&mut parse)?),
+            "unwatch" => Command::Unwatch(Unwatch::parse_frames(&mut parse)?),
+            "mget" => Command::MGet(MGet::parse_frames(&mut parse)?),
+            "mset" => Command::MSet(MSet::parse_frames(&mut parse, false)?),
+            "msetnx" => Command::MSet(MSet::parse_frames(&mut parse, true)?),
+            "hello" => Command::Hello(Hello::parse_frames(&mut parse)?),
+            "hset" => Command::HSet(HSet::parse_frames(&mut parse)?),
+            "hget" => Command::HGet(HGet::parse_frames(&mut parse)?),
+            "hmget" => Command::HMGet(HMGet::parse_frames(&mut parse)?),
+            "hdel" => Command::HDel(HDel::parse_frames(&mut parse)?),
+            "hexists" => Command::HExists(HExists::parse_frames(&mut parse)?),
+            "hgetall" => Command::HGetAll(HGetAll::parse_frames(&mut parse)?),
+            "hkeys" => Command::HKeys(HKeys::parse_frames(&mut parse)?),
+            "hvals" => Command::HVals(HVals::parse_frames(&mut parse)?),
+            "hlen" => Command::HLen(HLen::parse_frames(&mut parse)?),
+            "hincrby" => Command::HIncrBy(HIncrBy::parse_frames(&mut parse)?),
+            "hscan" => Command::HScan(HScan::parse_frames(&mut parse)?),
+            "expire" => {
+                Command::Expire(Expire::parse_frames(&mut parse, TimeUnit::Seconds, false)?)
+            }
+            "pexpire" => Command::Expire(Expire::parse_frames(
+                &mut parse,
+                TimeUnit::Milliseconds,
+                false,
+            )?),
+            "expireat" => {
+                Command::Expire(Expire::parse_frames(&mut parse, TimeUnit::Seconds, true)?)
+            }
+            "pexpireat" => Command::Expire(Expire::parse_frames(
+                &mut parse,
+                TimeUnit::Milliseconds,
+                true,
+            )?),
+            "ttl" => Command::Ttl(Ttl::parse_frames(&mut parse, TimeUnit::Seconds)?),
+            "pttl" => Command::Ttl(Ttl::parse_frames(&mut parse, TimeUnit::Milliseconds)?),
+            "persist" => Command::Persist(Persist::parse_frames(&mut parse)?),
+            "lpush" => Command::Push(Push::parse_frames(&mut parse, ListSide::Left)?),
+            "rpush" => Command::Push(Push::parse_frames(&mut parse, ListSide::Right)?),
+            "lpop" => Command::Pop(Pop::parse_frames(&mut parse, ListSide::Left)?),
+            "rpop" => Command::Pop(Pop::parse_frames(&mut parse, ListSide::Right)?),
+            "lrange" => Command::LRange(LRange::parse_frames(&mut parse)?),
+            "llen" => Command::LLen(LLen::parse_frames(&mut parse)?),
+            "lindex" => Command::LIndex(LIndex::parse_frames(&mut parse)?),
+            "lset" => Command::LSet(LSet::parse_frames(&mut parse)?),
+            "lrem" => Command::LRem(LRem::parse_frames(&mut parse)?),
+            "ltrim" => Command::LTrim(LTrim::parse_frames(&mut parse)?),
+            "lmove" => Command::LMove(LMove::parse_frames(&mut parse)?),
+            "blpop" => Command::BPop(BPop::parse_frames(&mut parse, ListSide::Left)?),
+            "brpop" => Command::BPop(BPop::parse_frames(&mut parse, ListSide::Right)?),
+            "blmove" => Command::BLMove(BLMove::parse_frames(&mut parse)?),
             "publish" => Command::Publish(Publish::parse_frames(&mut parse)?),
             "set" => Command::Set(Set::parse_frames(&mut parse)?),
+            "setnx" => Command::Set(Set::parse_nx_frames(&mut parse)?),
+            "setex" => Command::Set(Set::parse_ex_frames(&mut parse, TimeUnit::Seconds)?),
+            "psetex" => Command::Set(Set::parse_ex_frames(&mut parse, TimeUnit::Milliseconds)?),
+            "sadd" => Command::SAdd(SAdd::parse_frames(&mut parse)?),
+            "srem" => Command::SRem(SRem::parse_frames(&mut parse)?),
+            "sismember" => Command::SIsMember(SIsMember::parse_frames(&mut parse)?),
+            "smembers" => Command::SMembers(SMembers::parse_frames(&mut parse)?),
+            "scard" => Command::SCard(SCard::parse_frames(&mut parse)?),
+            "sinter" => Command::SetOp(SetOp::parse_frames(&mut parse, SetOpKind::Inter)?),
+            "sunion" => Command::SetOp(SetOp::parse_frames(&mut parse, SetOpKind::Union)?),
+            "sdiff" => Command::SetOp(SetOp::parse_frames(&mut parse, SetOpKind::Diff)?),
+            "zadd" => Command::ZAdd(ZAdd::parse_frames(&mut parse)?),
+            "zcard" => Command::ZCard(ZCard::parse_frames(&mut parse)?),
+            "zscore" => Command::ZScore(ZScore::parse_frames(&mut parse)?),
+            "zincrby" => Command::ZIncrBy(ZIncrBy::parse_frames(&mut parse)?),
+            "zrange" => Command::ZRange(ZRange::parse_frames(&mut parse)?),
+            "zrank" => Command::ZRank(ZRank::parse_frames(&mut parse, false)?),
+            "zrevrank" => Command::ZRank(ZRank::parse_frames(&mut parse, true)?),
+            "zrem" => Command::ZRem(ZRem::parse_frames(&mut parse)?),
+            "xadd" => Command::XAdd(XAdd::parse_frames(&mut parse)?),
+            "xrange" => Command::XRange(XRange::parse_frames(&mut parse, false)?),
+            "xrevrange" => Command::XRange(XRange::parse_frames(&mut parse, true)?),
+            "xlen" => Command::XLen(XLen::parse_frames(&mut parse)?),
+            "xdel" => Command::XDel(XDel::parse_frames(&mut parse)?),
+            "xsetid" => Command::XSetId(XSetId::parse_frames(&mut parse)?),
+            "xread" => Command::XRead(XRead::parse_frames(&mut parse, false)?),
+            "xreadgroup" => Command::XRead(XRead::parse_frames(&mut parse, true)?),
+            "xgroup" => Command::XGroup(XGroup::parse_frames(&mut parse)?),
+            "xack" => Command::XAck(XAck::parse_frames(&mut parse)?),
+            "xpending" => Command::XPending(XPending::parse_frames(&mut parse)?),
+            "xclaim" => Command::XClaim(XClaim::parse_frames(&mut parse)?),
+            "xautoclaim" => Command::XAutoClaim(XAutoClaim::parse_frames(&mut parse)?),
             "subscribe" => Command::Subscribe(Subscribe::parse_frames(&mut parse)?),
             "unsubscribe" => Command::Unsubscribe(Unsubscribe::parse_frames(&mut parse)?),
+            "psubscribe" => Command::PSubscribe(PSubscribe::parse_frames(&mut parse)?),
+            "punsubscribe" => Command::PUnsubscribe(PUnsubscribe::parse_frames(&mut parse)?),
+            "pubsub" => Command::PubSub(PubSub::parse_frames(&mut parse)?),
             "ping" => Command::Ping(Ping::parse_frames(&mut parse)?),
             "config" => Command::Config(Config::parse_frames(&mut parse)?),
-            _ => Command::Unknown(Unknown::new(command_name)),
+            "save" => Command::Save(Save::parse_frames(&mut parse)?),
+            "bgsave" => Command::BgSave(BgSave::parse_frames(&mut parse)?),
+            "bgrewriteaof" => Command::BgRewriteAof(BgRewriteAof::parse_frames(&mut parse)?),
+            "lastsave" => Command::LastSave(LastSave::parse_frames(&mut parse)?),
+            "replicaof" | "slaveof" => Command::ReplicaOf(ReplicaOf::parse_frames(&mut parse)?),
+            "replconf" => Command::ReplConf(ReplConf::parse_frames(&mut parse)?),
+            "psync" => Command::PSync(PSync::parse_frames(&mut parse)?),
+            "cluster" => Command::Cluster(Cluster::parse_frames(&mut parse)?),
+            "asking" => Command::Asking(Asking::parse_frames(&mut parse)?),
+            "client" => Command::Client(Client::parse_frames(&mut parse)?),
+            "info" => Command::Info(Info::parse_frames(&mut parse)?),
+            "latency" => Command::Latency(Latency::parse_frames(&mut parse)?),
+            "monitor" => Command::Monitor(Monitor::parse_frames(&mut parse)?),
+            "slowlog" => Command::SlowLog(SlowLog::parse_frames(&mut parse)?),
+            "auth" => Command::Auth(Auth::parse_frames(&mut parse)?),
+            "acl" => Command::Acl(Acl::parse_frames(&mut parse)?),
+            _ => return Ok(Command::Unknown(Unknown::new(command_name))),
         };
         parse.finish()?;
         Ok(command)
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Acl(_) => "acl",
+            Command::Append(_) => "append",
+            Command::Asking(_) => "asking",
+            Command::Auth(_) => "auth",
+            Command::BgRewriteAof(_) => "bgrewriteaof",
+            Command::BgSave(_) => "bgsave",
+            Command::BitCount(_) => "bitcount",
+            Command::BitField(_) => "bitfield",
+            Command::BitOp(_) => "bitop",
+            Command::BitPos(_) => "bitpos",
+            Command::BLMove(_) => "blmove",
+            Command::BPop(cmd) => match cmd.side {
+                ListSide::Left => "blpop",
+                ListSide::Right => "brpop",
+            },
+            Command::Client(_) => "client",
+            Command::Cluster(_) => "cluster",
+            Command::Config(_) => "config",
+            Command::DbSize(_) => "dbsize",
+            Command::Del(cmd) => match cmd.unlink {
+                false => "del",
+                true => "unlink",
+            },
+            Command::Discard(_) => "discard",
+            Command::Exec(_) => "exec",
+            Command::Exists(_) => "exists",
+            Command::Expire(cmd) => match (cmd.unit, cmd.absolute) {
+                (TimeUnit::Seconds, false) => "expire",
+                (TimeUnit::Milliseconds, false) => "pexpire",
+                (TimeUnit::Seconds, true) => "expireat",
+                (TimeUnit::Milliseconds, true) => "pexpireat",
+            },
+            Command::Flush(cmd) => match cmd.all {
+                false => "flushdb",
+                true => "flushall",
+            },
+            Command::GeoAdd(_) => "geoadd",
+            Command::GeoDist(_) => "geodist",
+            Command::GeoHash(_) => "geohash",
+            Command::GeoPos(_) => "geopos",
+            Command::GeoSearch(_) => "geosearch",
+            Command::GeoSearchStore(_) => "geosearchstore",
+            Command::Get(_) => "get",
+            Command::GetBit(_) => "getbit",
+            Command::GetDel(_) => "getdel",
+            Command::GetEx(_) => "getex",
+            Command::GetRange(_) => "getrange",
+            Command::HDel(_) => "hdel",
+            Command::HExists(_) => "hexists",
+            Command::HGet(_) => "hget",
+            Command::HGetAll(_) => "hgetall",
+            Command::HIncrBy(_) => "hincrby",
+            Command::HKeys(_) => "hkeys",
+            Command::HLen(_) => "hlen",
+            Command::HMGet(_) => "hmget",
+            Command::HScan(_) => "hscan",
+            Command::HSet(_) => "hset",
+            Command::HVals(_) => "hvals",
+            Command::Hello(_) => "hello",
+            Command::IncrBy(cmd) => match (cmd.by, cmd.decrement) {
+                (false, false) => "incr",
+                (false, true) => "decr",
+                (true, false) => "incrby",
+                (true, true) => "decrby",
+            },
+            Command::IncrByFloat(_) => "incrbyfloat",
+            Command::Info(_) => "info",
+            Command::Keys(_) => "keys",
+            Command::LastSave(_) => "lastsave",
+            Command::Latency(_) => "latency",
+            Command::LIndex(_) => "lindex",
+            Command::LLen(_) => "llen",
+            Command::LMove(_) => "lmove",
+            Command::LRange(_) => "lrange",
+            Command::LRem(_) => "lrem",
+            Command::LSet(_) => "lset",
+            Command::LTrim(_) => "ltrim",
+            Command::MGet(_) => "mget",
+            Command::Monitor(_) => "monitor",
+            Command::Move(_) => "move",
+            Command::Multi(_) => "multi",
+            Command::MSet(cmd) => match cmd.nx {
+                false => "mset",
+                true => "msetnx",
+            },
+            Command::Persist(_) => "persist",
+            Command::PfAdd(_) => "pfadd",
+            Command::PfCount(_) => "pfcount",
+            Command::PfMerge(_) => "pfmerge",
+            Command::Pop(cmd) => match cmd.side {
+                ListSide::Left => "lpop",
+                ListSide::Right => "rpop",
+            },
+            Command::PSubscribe(_) => "psubscribe",
+            Command::PUnsubscribe(_) => "punsubscribe",
+            Command::PubSub(_) => "pubsub",
+            Command::Publish(_) => "publish",
+            Command::Push(cmd) => match cmd.side {
+                ListSide::Left => "lpush",
+                ListSide::Right => "rpush",
+            },
+            Command::PSync(_) => "psync",
+            Command::RandomKey(_) => "randomkey",
+            Command::Rename(cmd) => match cmd.nx {
+                false => "rename",
+                true => "renamenx",
+            },
+            Command::ReplConf(_) => "replconf",
+            Command::ReplicaOf(_) => "replicaof",
+            Command::SAdd(_) => "sadd",
+            Command::Save(_) => "save",
+            Command::Scan(_) => "scan",
+            Command::Select(_) => "select",
+            Command::SCard(_) => "scard",
+            Command::Set(cmd) => match cmd.nx {
+                false => "set",
+                true => "setnx",
+            },
+            Command::SetBit(_) => "setbit",
+            Command::SetOp(cmd) => match cmd.kind {
+                SetOpKind::Inter => "sinter",
+                SetOpKind::Union => "sunion",
+                SetOpKind::Diff => "sdiff",
+            },
+            Command::SetRange(_) => "setrange",
+            Command::SIsMember(_) => "sismember",
+            Command::SlowLog(_) => "slowlog",
+            Command::SMembers(_) => "smembers",
+            Command::SRem(_) => "srem",
+            Command::StrLen(_) => "strlen",
+            Command::Subscribe(_) => "subscribe",
+            Command::SwapDb(_) => "swapdb",
+            Command::Ttl(cmd) => match cmd.unit {
+                TimeUnit::Seconds => "ttl",
+                TimeUnit::Milliseconds => "pttl",
+            },
+            Command::Type(_) => "type",
+            Command::Unsubscribe(_) => "unsubscribe",
+            Command::Unwatch(_) => "unwatch",
+            Command::Watch(_) => "watch",
+            Command::XAck(_) => "xack",
+            Command::XAdd(_) => "xadd",
+            Command::XAutoClaim(_) => "xautoclaim",
+            Command::XClaim(_) => "xclaim",
+            Command::XDel(_) => "xdel",
+            Command::XGroup(_) => "xgroup",
+            Command::XLen(_) => "xlen",
+            Command::XPending(_) => "xpending",
+            Command::XRange(cmd) => match cmd.rev {
+                false => "xrange",
+                true => "xrevrange",
+            },
+            Command::XRead(cmd) => match cmd.group {
+                None => "xread",
+                Some(_) => "xreadgroup",
+            },
+            Command::XSetId(_) => "xsetid",
+            Command::ZAdd(_) => "zadd",
+            Command::ZCard(_) => "zcard",
+            Command::ZIncrBy(_) => "zincrby",
+            Command::ZRange(_) => "zrange",
+            Command::ZRank(cmd) => match cmd.rev {
+                false => "zrank",
+                true => "zrevrank",
+            },
+            Command::ZRem(_) => "zrem",
+            Command::ZScore(_) => "zscore",
+            Command::Ping(_) => "ping",
+            Command::Unknown(_) => "unknown",
+        }
+    }
+
+    /// Name of the command in ACL rules, as `container|subcommand` for the
+    /// subcommands of container commands
+    pub fn acl_name(&self) -> &'static str {
+        match self {
+            Command::Acl(cmd) => match cmd {
+                Acl::Cat(_) => "acl|cat",
+                Acl::DelUser(_) => "acl|deluser",
+                Acl::GetUser(_) => "acl|getuser",
+                Acl::List => "acl|list",
+                Acl::Load => "acl|load",
+                Acl::Save => "acl|save",
+                Acl::SetUser(..) => "acl|setuser",
+                Acl::Users => "acl|users",
+                Acl::WhoAmI => "acl|whoami",
+            },
+            Command::Client(cmd) => match cmd {
+                Client::GetName => "client|getname",
+                Client::Id => "client|id",
+                Client::Info => "client|info",
+                Client::Kill(_) | Client::KillAddr(_) => "client|kill",
+                Client::List(..) => "client|list",
+                Client::SetName(_) => "client|setname",
+            },
+            Command::Cluster(cmd) => match cmd {
+                Cluster::Info => "cluster|info",
+                Cluster::KeySlot(_) => "cluster|keyslot",
+                Cluster::MyId => "cluster|myid",
+                Cluster::Nodes => "cluster|nodes",
+                Cluster::SetSlot(..) => "cluster|setslot",
+                Cluster::Shards => "cluster|shards",
+                Cluster::Slots => "cluster|slots",
+            },
+            Command::Config(cmd) => match cmd {
+                Config::Get(_) => "config|get",
+                Config::Set(_) => "config|set",
+                Config::Rewrite => "config|rewrite",
+            },
+            Command::Latency(Latency::Histogram(_)) => "latency|histogram",
+            Command::PubSub(cmd) => match cmd {
+                PubSub::Channels(_) => "pubsub|channels",
+                PubSub::NumSub(_) => "pubsub|numsub",
+                PubSub::NumPat => "pubsub|numpat",
+            },
+            Command::SlowLog(cmd) => match cmd {
+                SlowLog::Get(_) => "slowlog|get",
+                SlowLog::Len => "slowlog|len",
+                SlowLog::Reset => "slowlog|reset",
+            },
+            Command::XGroup(cmd) => match cmd.action {
+                XGroupAction::Create { .. } => "xgroup|create",
+                XGroupAction::CreateConsumer(_) => "xgroup|createconsumer",
+                XGroupAction::DelConsumer(_) => "xgroup|delconsumer",
+                XGroupAction::Destroy => "xgroup|destroy",
+                XGroupAction::SetId(_) => "xgroup|setid",
+            },
+            cmd => cmd.name(),
+        }
+    }
+
+    /// The command accesses every shard, not only the shards of its keys
+    pub fn all_shards(&self) -> bool {
+        matches!(
+            self,
+            Command::DbSize(_)
+                | Command::Flush(_)
+                | Command::Keys(_)
+                | Command::RandomKey(_)
+                | Command::Scan(_)
+                | Command::SwapDb(_)
+        )
+    }
+
+    /// The command may wait for long before being served
+    pub fn is_blocking(&self) -> bool {
+        match self {
+            Command::BLMove(_) | Command::BPop(_) => true,
+            Command::XRead(cmd) => cmd.block.is_some(),
+            _ => false,
+        }
+    }
+
+    /// The command modifies the key space, which replicas refuse
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Append(_)
+                | Command::BitField(_)
+                | Command::BitOp(_)
+                | Command::BLMove(_)
+                | Command::BPop(_)
+                | Command::Del(_)
+                | Command::Expire(_)
+                | Command::Flush(_)
+                | Command::GeoAdd(_)
+                | Command::GeoSearchStore(_)
+                | Command::GetDel(_)
+                | Command::GetEx(_)
+                | Command::HDel(_)
+                | Command::HIncrBy(_)
+                | Command::HSet(_)
+                | Command::IncrBy(_)
+                | Command::IncrByFloat(_)
+                | Command::LMove(_)
+                | Command::LRem(_)
+                | Command::LSet(_)
+                | Command::LTrim(_)
+                | Command::Move(_)
+                | Command::MSet(_)
+                | Command::Persist(_)
+                | Command::PfAdd(_)
+                | Command::PfMerge(_)
+                | Command::Pop(_)
+                | Command::Push(_)
+                | Command::Rename(_)
+                | Command::SAdd(_)
+                | Command::Set(_)
+                | Command::SetBit(_)
+                | Command::SetRange(_)
+                | Command::SRem(_)
+                | Command::SwapDb(_)
+                | Command::XAck(_)
+                | Command::XAdd(_)
+                | Command::XAutoClaim(_)
+                | Command::XClaim(_)
+                | Command::XDel(_)
+                | Command::XGroup(_)
+                | Command::XRead(XRead { group: Some(_), .. })
+                | Command::XSetId(_)
+                | Command::ZAdd(_)
+                | Command::ZIncrBy(_)
+                | Command::ZRem(_)
+        )
+    }
+
+    /// Keys accessed by the command
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::BitOp(cmd) => std::iter::once(&cmd.destination)
+                .chain(&cmd.keys)
+                .map(|key| &key[..])
+                .collect(),
+            Command::BLMove(cmd) => vec![&cmd.lmove.source, &cmd.lmove.destination],
+            Command::BPop(cmd) => cmd.keys.iter().map(|key| &key[..]).collect(),
+            Command::Del(cmd) => cmd.keys.iter().map(|key| &key[..]).collect(),
+            Command::Exists(cmd) => cmd.keys.iter().map(|key| &key[..]).collect(),
+            Command::GeoSearchStore(cmd) => vec![&cmd.destination, cmd.search.key()],
+            Command::LMove(cmd) => vec![&cmd.source, &cmd.destination],
+            Command::MGet(cmd) => cmd.keys.iter().map(|key| &key[..]).collect(),
+            Command::MSet(cmd) => cmd.pairs.iter().map(|(key, _)| &key[..]).collect(),
+            Command::PfCount(cmd) => cmd.keys.iter().map(|key| &key[..]).collect(),
+            Command::PfMerge(cmd) => std::iter::once(&cmd.destination)
+                .chain(&cmd.keys)
+                .map(|key| &key[..])
+                .collect(),
+            Command::Rename(cmd) => vec![&cmd.source, &cmd.destination],
+            Command::SetOp(cmd) => cmd.keys.iter().map(|key| &key[..]).collect(),
+            Command::Watch(cmd) => cmd.keys.iter().map(|key| &key[..]).collect(),
+            Command::XRead(cmd) => cmd.streams.iter().map(|(key, _)| &key[..]).collect(),
+            Command::Append(cmd) => vec![cmd.key()],
+            Command::BitCount(cmd) => vec![cmd.key()],
+            Command::BitField(cmd) => vec![cmd.key()],
+            Command::BitPos(cmd) => vec![cmd.key()],
+            Command::Expire(cmd) => vec![cmd.key()],
+            Command::GeoAdd(cmd) => vec![cmd.key()],
+            Command::GeoDist(cmd) => vec![cmd.key()],
+            Command::GeoHash(cmd) => vec![cmd.key()],
+            Command::GeoPos(cmd) => vec![cmd.key()],
+            Command::GeoSearch(cmd) => vec![cmd.key()],
+            Command::Get(cmd) => vec![cmd.key()],
+            Command::GetBit(cmd) => vec![cmd.key()],
+            Command::GetDel(cmd) => vec![cmd.key()],
+            Command::GetEx(cmd) => vec![cmd.key()],
+            Command::GetRange(cmd) => vec![cmd.key()],
+            Command::HDel(cmd) => vec![cmd.key()],
+            Command::HExists(cmd) => vec![cmd.key()],
+            Command::HGet(cmd) => vec![cmd.key()],
+            Command::HGetAll(cmd) => vec![cmd.key()],
+            Command::HIncrBy(cmd) => vec![cmd.key()],
+            Command::HKeys(cmd) => vec![cmd.key()],
+            Command::HLen(cmd) => vec![cmd.key()],
+            Command::HMGet(cmd) => vec![cmd.key()],
+            Command::HScan(cmd) => vec![cmd.key()],
+            Command::HSet(cmd) => vec![cmd.key()],
+            Command::HVals(cmd) => vec![cmd.key()],
+            Command::IncrBy(cmd) => vec![cmd.key()],
+            Command::IncrByFloat(cmd) => vec![cmd.key()],
+            Command::LIndex(cmd) => vec![cmd.key()],
+            Command::LLen(cmd) => vec![cmd.key()],
+            Command::LRange(cmd) => vec![cmd.key()],
+            Command::LRem(cmd) => vec![cmd.key()],
+            Command::LSet(cmd) => vec![cmd.key()],
+            Command::LTrim(cmd) => vec![cmd.key()],
+            Command::Move(cmd) => vec![cmd.key()],
+            Command::Persist(cmd) => vec![cmd.key()],
+            Command::PfAdd(cmd) => vec![cmd.key()],
+            Command::Pop(cmd) => vec![cmd.key()],
+            Command::Push(cmd) => vec![cmd.key()],
+            Command::SAdd(cmd) => vec![cmd.key()],
+            Command::SCard(cmd) => vec![cmd.key()],
+            Command::Set(cmd) => vec![cmd.key()],
+            Command::SetBit(cmd) => vec![cmd.key()],
+            Command::SetRange(cmd) => vec![cmd.key()],
+            Command::SIsMember(cmd) => vec![cmd.key()],
+            Command::SMembers(cmd) => vec![cmd.key()],
+            Command::SRem(cmd) => vec![cmd.key()],
+            Command::StrLen(cmd) => vec![cmd.key()],
+            Command::Ttl(cmd) => vec![cmd.key()],
+            Command::Type(cmd) => vec![cmd.key()],
+            Command::XAck(cmd) => vec![cmd.key()],
+            Command::XAdd(cmd) => vec![cmd.key()],
+            Command::XAutoClaim(cmd) => vec![cmd.key()],
+            Command::XClaim(cmd) => vec![cmd.key()],
+            Command::XDel(cmd) => vec![cmd.key()],
+            Command::XGroup(cmd) => vec![cmd.key()],
+            Command::XLen(cmd) => vec![cmd.key()],
+            Command::XPending(cmd) => vec![cmd.key()],
+            Command::XRange(cmd) => vec![cmd.key()],
+            Command::XSetId(cmd) => vec![cmd.key()],
+            Command::ZAdd(cmd) => vec![cmd.key()],
+            Command::ZCard(cmd) => vec![cmd.key()],
+            Command::ZIncrBy(cmd) => vec![cmd.key()],
+            Command::ZRange(cmd) => vec![cmd.key()],
+            Command::ZRank(cmd) => vec![cmd.key()],
+            Command::ZRem(cmd) => vec![cmd.key()],
+            Command::ZScore(cmd) => vec![cmd.key()],
+            _ => vec![],
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct Get {
+pub struct Append {
     pub key: String,
+    pub value: Bytes,
 }
 
-impl Get {
-    pub fn new(key: impl ToString) -> Get {
-        Get {
-            key: key.to_string(),
-        }
-    }
-
+impl Append {
     pub fn key(&self) -> &str {
         &self.key
     }
 
-    pub fn parse_frames(parse: &mut CommandParser) -> Result<Get, CommandParseError> {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Append, CommandParseError> {
         let key = parse.next_string()?;
-        Ok(Get { key })
+        let value = parse.next_bytes()?;
+        Ok(Append { key, value })
     }
 }
 
-#[derive(Debug, Default)]
-pub struct Ping {
-    pub msg: Option<Bytes>,
-}
+#[derive(Debug)]
+pub struct BgRewriteAof;
 
-impl Ping {
-    pub fn new(msg: Option<Bytes>) -> Ping {
-        Ping { msg }
+impl BgRewriteAof {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<BgRewriteAof, CommandParseError> {
+        Ok(BgRewriteAof)
     }
+}
 
-    pub fn parse_frames(parse: &mut CommandParser) -> Result<Ping, CommandParseError> {
-        match parse.next_bytes() {
-            Ok(msg) => Ok(Ping::new(Some(msg))),
-            Err(CommandParseError::EndOfStream) => Ok(Ping::default()),
-            Err(e) => Err(e.into()),
-        }
+#[derive(Debug)]
+pub struct BgSave;
+
+impl BgSave {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<BgSave, CommandParseError> {
+        Ok(BgSave)
     }
 }
 
 #[derive(Debug)]
-pub struct Publish {
-    pub channel: String,
-    pub message: Bytes,
+pub struct BitCount {
+    pub key: String,
+    /// All the value when missing
+    pub range: Option<(i64, i64, BitUnit)>,
 }
 
-impl Publish {
-    pub fn new(channel: impl ToString, message: Bytes) -> Publish {
-        Publish {
-            channel: channel.to_string(),
-            message,
-        }
+impl BitCount {
+    pub fn key(&self) -> &str {
+        &self.key
     }
 
-    pub fn parse_frames(parse: &mut CommandParser) -> Result<Publish, CommandParseError> {
-        let channel = parse.next_string()?;
-        let message = parse.next_bytes()?;
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<BitCount, CommandParseError> {
+        const MSG: &str = "value is not an integer or out of range";
 
-        Ok(Publish { channel, message })
+        let key = parse.next_string()?;
+        let start = match parse.next_signed_int() {
+            Ok(start) => start,
+            Err(CommandParseError::EndOfStream) => return Ok(BitCount { key, range: None }),
+            Err(_) => return Err(MSG.into()),
+        };
+        let end = match parse.next_signed_int() {
+            Ok(end) => end,
+            Err(CommandParseError::EndOfStream) => return Err("syntax error".into()),
+            Err(_) => return Err(MSG.into()),
+        };
+        let unit = parse_bit_unit(parse)?;
+        Ok(BitCount {
+            key,
+            range: Some((start, end, unit)),
+        })
     }
 }
 
+/// Operation of `BITFIELD`, offsets are in bits
 #[derive(Debug)]
-pub struct Set {
+pub enum BitFieldOp {
+    Get(FieldType, usize),
+    Set(FieldType, usize, i64),
+    IncrBy(FieldType, usize, i64),
+    /// Applies to the following `SET` and `INCRBY`
+    Overflow(Overflow),
+}
+
+#[derive(Debug)]
+pub struct BitField {
     pub key: String,
-    pub value: Bytes,
-    pub expire: Option<Duration>,
+    pub ops: Vec<BitFieldOp>,
 }
 
-impl Set {
-    pub fn new(key: impl ToString, value: Bytes, expire: Option<Duration>) -> Set {
-        Set {
-            key: key.to_string(),
-            value,
-            expire,
+impl BitField {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Some operation may write to the value
+    pub fn writes(&self) -> bool {
+        self.ops
+            .iter()
+            .any(|op| matches!(op, BitFieldOp::Set(..) | BitFieldOp::IncrBy(..)))
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<BitField, CommandParseError> {
+        let key = parse.next_string()?;
+        let mut ops = vec![];
+        loop {
+            let op = match parse.next_string() {
+                Ok(op) => op.to_uppercase(),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            let op = match &op[..] {
+                "GET" => {
+                    let (field, offset) = parse_field(parse)?;
+                    BitFieldOp::Get(field, offset)
+                }
+                "SET" | "INCRBY" => {
+                    let (field, offset) = parse_field(parse)?;
+                    let value = parse
+                        .next_signed_int()
+                        .map_err(|_| "value is not an integer or out of range")?;
+                    match &op[..] {
+                        "SET" => BitFieldOp::Set(field, offset, value),
+                        _ => BitFieldOp::IncrBy(field, offset, value),
+                    }
+                }
+                "OVERFLOW" => match &parse.next_string()?.to_uppercase()[..] {
+                    "WRAP" => BitFieldOp::Overflow(Overflow::Wrap),
+                    "SAT" => BitFieldOp::Overflow(Overflow::Sat),
+                    "FAIL" => BitFieldOp::Overflow(Overflow::Fail),
+                    _ => return Err("Invalid OVERFLOW type specified".into()),
+                },
+                _ => return Err("syntax error".into()),
+            };
+            ops.push(op);
+        }
+        Ok(BitField { key, ops })
+    }
+}
+
+#[derive(Debug)]
+pub struct BitOp {
+    pub kind: BitOpKind,
+    pub destination: String,
+    pub keys: Vec<String>,
+}
+
+impl BitOp {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<BitOp, CommandParseError> {
+        let kind = match &parse.next_string()?.to_uppercase()[..] {
+            "AND" => BitOpKind::And,
+            "OR" => BitOpKind::Or,
+            "XOR" => BitOpKind::Xor,
+            "NOT" => BitOpKind::Not,
+            _ => return Err("syntax error".into()),
+        };
+        let destination = parse.next_string()?;
+        let keys = parse_keys(parse)?;
+        if kind == BitOpKind::Not && keys.len() > 1 {
+            return Err("BITOP NOT must be called with a single source key.".into());
         }
+        Ok(BitOp {
+            kind,
+            destination,
+            keys,
+        })
     }
+}
+
+#[derive(Debug)]
+pub struct BitPos {
+    pub key: String,
+    pub bit: u8,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    pub unit: BitUnit,
+}
 
+impl BitPos {
     pub fn key(&self) -> &str {
         &self.key
     }
 
-    pub fn value(&self) -> &Bytes {
-        &self.value
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<BitPos, CommandParseError> {
+        const MSG: &str = "value is not an integer or out of range";
+
+        let key = parse.next_string()?;
+        let bit = match parse.next_signed_int() {
+            Ok(bit @ (0 | 1)) => bit as u8,
+            Ok(_) => return Err("The bit argument must be 1 or 0.".into()),
+            Err(_) => return Err(MSG.into()),
+        };
+        let mut bounds = [None, None];
+        for bound in &mut bounds {
+            match parse.next_signed_int() {
+                Ok(index) => *bound = Some(index),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(_) => return Err(MSG.into()),
+            }
+        }
+        let unit = match bounds[1] {
+            Some(_) => parse_bit_unit(parse)?,
+            None => BitUnit::Byte,
+        };
+        Ok(BitPos {
+            key,
+            bit,
+            start: bounds[0],
+            end: bounds[1],
+            unit,
+        })
     }
+}
 
-    pub fn expire(&self) -> Option<Duration> {
-        self.expire
+#[derive(Debug)]
+pub struct BLMove {
+    pub lmove: LMove,
+    pub timeout: Duration,
+}
+
+impl BLMove {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<BLMove, CommandParseError> {
+        let lmove = LMove::parse_frames(parse)?;
+        let timeout = parse_timeout(parse)?;
+        Ok(BLMove { lmove, timeout })
     }
+}
 
-    pub fn parse_frames(parse: &mut CommandParser) -> Result<Set, CommandParseError> {
-        let key = parse.next_string()?;
-        let value = parse.next_bytes()?;
-        let mut expire = None;
+/// `BLPOP` and `BRPOP`
+#[derive(Debug)]
+pub struct BPop {
+    pub keys: Vec<String>,
+    pub side: ListSide,
+    /// Zero blocks forever
+    pub timeout: Duration,
+}
 
-        match parse.next_string() {
-            Ok(s) if s.to_uppercase() == "EX" => {
-                let secs = parse.next_int()?;
-                expire = Some(Duration::from_secs(secs));
-            }
-            Ok(s) if s.to_uppercase() == "PX" => {
-                let ms = parse.next_int()?;
-                expire = Some(Duration::from_millis(ms));
+impl BPop {
+    pub fn parse_frames(
+        parse: &mut CommandParser,
+        side: ListSide,
+    ) -> Result<BPop, CommandParseError> {
+        let mut args = vec![parse.next_string()?, parse.next_string()?];
+        loop {
+            match parse.next_string() {
+                Ok(s) => args.push(s),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
             }
-            Ok(_) => return Err("currently `SET` only supports the expiration option".into()),
-            Err(CommandParseError::EndOfStream) => {}
-            Err(err) => return Err(err.into()),
         }
 
-        Ok(Set { key, value, expire })
+        let timeout = args.pop().unwrap();
+        let timeout = parse_timeout_str(&timeout)?;
+        Ok(BPop {
+            keys: args,
+            side,
+            timeout,
+        })
+    }
+}
+
+fn parse_timeout(parse: &mut CommandParser) -> Result<Duration, CommandParseError> {
+    parse_timeout_str(&parse.next_string()?)
+}
+
+/// Blocking timeouts are in seconds, with a decimal part
+fn parse_timeout_str(timeout: &str) -> Result<Duration, CommandParseError> {
+    let secs = parse_float(timeout.as_bytes())
+        .filter(|secs| secs.is_finite())
+        .ok_or("timeout is not a float or out of range")?;
+    if secs < 0.0 {
+        return Err("timeout is negative".into());
+    }
+    Duration::try_from_secs_f64(secs).map_err(|_| "timeout is out of range".into())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+impl TimeUnit {
+    pub fn to_millis(self, time: i64) -> Option<i64> {
+        match self {
+            TimeUnit::Seconds => time.checked_mul(1000),
+            TimeUnit::Milliseconds => Some(time),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpireCondition {
+    /// Only when the key has no expiry
+    Nx,
+    /// Only when the key already has an expiry
+    Xx,
+    /// Only when the new expiry is greater than the current one
+    Gt,
+    /// Only when the new expiry is less than the current one
+    Lt,
+}
+
+impl ExpireCondition {
+    /// Check the condition against the current expiry, a key without expiry
+    /// behaving as if it had an infinite TTL.
+    pub fn allows(self, current: Option<u64>, expire_at: i64) -> bool {
+        match (self, current) {
+            (ExpireCondition::Nx, current) => current.is_none(),
+            (ExpireCondition::Xx, current) => current.is_some(),
+            (ExpireCondition::Gt, None) => false,
+            (ExpireCondition::Gt, Some(current)) => expire_at > current as i64,
+            (ExpireCondition::Lt, None) => true,
+            (ExpireCondition::Lt, Some(current)) => expire_at < current as i64,
+        }
     }
 }
 
 #[derive(Debug)]
-pub struct Subscribe {
-    pub channels: Vec<String>,
+pub struct Expire {
+    pub key: String,
+    /// Expiration in milliseconds, either relative or a unix timestamp
+    pub time: i64,
+    pub unit: TimeUnit,
+    pub absolute: bool,
+    pub conditions: Vec<ExpireCondition>,
 }
 
-impl Subscribe {
-    pub fn new(channels: Vec<String>) -> Subscribe {
-        Subscribe { channels }
+impl Expire {
+    pub fn key(&self) -> &str {
+        &self.key
     }
 
-    pub fn parse_frames(parse: &mut CommandParser) -> Result<Subscribe, CommandParseError> {
-        let mut channels = vec![parse.next_string()?];
+    /// Resolve the expiration to a unix timestamp in milliseconds
+    pub fn expire_at(&self, now: u64) -> i64 {
+        if self.absolute {
+            self.time
+        } else {
+            self.time.saturating_add(now as i64)
+        }
+    }
+
+    pub fn parse_frames(
+        parse: &mut CommandParser,
+        unit: TimeUnit,
+        absolute: bool,
+    ) -> Result<Expire, CommandParseError> {
+        let key = parse.next_string()?;
+        let time = unit
+            .to_millis(parse.next_signed_int()?)
+            .ok_or("invalid expire time")?;
+        let mut conditions = vec![];
 
         loop {
-            match parse.next_string() {
-                Ok(s) => channels.push(s),
+            let option = match parse.next_string() {
+                Ok(s) => s.to_uppercase(),
                 Err(CommandParseError::EndOfStream) => break,
-                Err(err) => return Err(err.into()),
+                Err(err) => return Err(err),
+            };
+            let parsed = match &option[..] {
+                "NX" => ExpireCondition::Nx,
+                "XX" => ExpireCondition::Xx,
+                "GT" => ExpireCondition::Gt,
+                "LT" => ExpireCondition::Lt,
+                _ => return Err(format!("Unsupported option {}", option).into()),
+            };
+            if !conditions.contains(&parsed) {
+                conditions.push(parsed);
             }
         }
 
-        Ok(Subscribe { channels })
+        if conditions.contains(&ExpireCondition::Nx) && conditions.len() > 1 {
+            return Err("NX and XX, GT or LT options at the same time are not compatible".into());
+        }
+        if conditions.contains(&ExpireCondition::Gt) && conditions.contains(&ExpireCondition::Lt) {
+            return Err("GT and LT options at the same time are not compatible".into());
+        }
+
+        Ok(Expire {
+            key,
+            time,
+            unit,
+            absolute,
+            conditions,
+        })
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Unsubscribe {
-    pub channels: Vec<String>,
+#[derive(Debug)]
+pub struct DbSize;
+
+impl DbSize {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<DbSize, CommandParseError> {
+        Ok(DbSize)
+    }
 }
 
-impl Unsubscribe {
-    pub fn new(channels: &[String]) -> Unsubscribe {
-        Unsubscribe {
-            channels: channels.to_vec(),
+/// `DEL` and `UNLINK`, the keys may belong to different shards
+#[derive(Debug)]
+pub struct Del {
+    pub keys: Vec<String>,
+    pub unlink: bool,
+}
+
+impl Del {
+    pub fn parse_frames(parse: &mut CommandParser, unlink: bool) -> Result<Del, CommandParseError> {
+        let keys = parse_keys(parse)?;
+        Ok(Del { keys, unlink })
+    }
+}
+
+#[derive(Debug)]
+pub struct Exec;
+
+impl Exec {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<Exec, CommandParseError> {
+        Ok(Exec)
+    }
+}
+
+/// `EXISTS`, the keys may belong to different shards, a key given twice is
+/// counted twice
+#[derive(Debug)]
+pub struct Exists {
+    pub keys: Vec<String>,
+}
+
+impl Exists {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Exists, CommandParseError> {
+        let keys = parse_keys(parse)?;
+        Ok(Exists { keys })
+    }
+}
+
+/// `FLUSHDB` and `FLUSHALL`
+#[derive(Debug)]
+pub struct Flush {
+    pub all: bool,
+    /// `ASYNC`, the entries are dropped in the background
+    pub lazy: bool,
+}
+
+impl Flush {
+    pub fn parse_frames(parse: &mut CommandParser, all: bool) -> Result<Flush, CommandParseError> {
+        let lazy = match parse.next_string() {
+            Ok(mode) => match &mode.to_uppercase()[..] {
+                "ASYNC" => true,
+                "SYNC" => false,
+                _ => return Err("syntax error".into()),
+            },
+            Err(CommandParseError::EndOfStream) => false,
+            Err(err) => return Err(err),
+        };
+        Ok(Flush { all, lazy })
+    }
+}
+
+/// Expiration option of `GETEX`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expiration {
+    /// `EX` and `PX`, in milliseconds
+    In(u64),
+    /// `EXAT` and `PXAT`, as a unix timestamp in milliseconds
+    At(u64),
+    Persist,
+}
+
+impl Expiration {
+    /// Parse the expiration `option` and its time, `None` when the option is
+    /// not an expiration
+    fn parse(
+        option: &str,
+        parse: &mut CommandParser,
+        command: &str,
+    ) -> Result<Option<Expiration>, CommandParseError> {
+        let (unit, absolute) = match option {
+            "EX" => (TimeUnit::Seconds, false),
+            "PX" => (TimeUnit::Milliseconds, false),
+            "EXAT" => (TimeUnit::Seconds, true),
+            "PXAT" => (TimeUnit::Milliseconds, true),
+            "PERSIST" => return Ok(Some(Expiration::Persist)),
+            _ => return Ok(None),
+        };
+        let time = parse
+            .next_signed_int()
+            .map_err(|_| "value is not an integer or out of range")?;
+        let time = match unit.to_millis(time) {
+            Some(time) if time > 0 => time as u64,
+            _ => return Err(format!("invalid expire time in '{}' command", command).into()),
+        };
+        Ok(Some(if absolute {
+            Expiration::At(time)
+        } else {
+            Expiration::In(time)
+        }))
+    }
+
+    /// Resolve the expiration to a unix timestamp in milliseconds
+    pub fn expire_at(self, now: u64) -> Option<u64> {
+        match self {
+            Expiration::In(ms) => Some(now.saturating_add(ms)),
+            Expiration::At(at) => Some(at),
+            Expiration::Persist => None,
         }
     }
+}
 
-    pub fn parse_frames(parse: &mut CommandParser) -> Result<Unsubscribe, CommandParseError> {
-        let mut channels = vec![];
+/// `GEOADD`, executed as a `ZADD` of the geohashes of the coordinates
+#[derive(Debug)]
+pub struct GeoAdd {
+    pub zadd: ZAdd,
+}
+
+impl GeoAdd {
+    pub fn key(&self) -> &str {
+        &self.zadd.key
+    }
 
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<GeoAdd, CommandParseError> {
+        const MSG: &str = "syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ";
+
+        let key = parse.next_string()?;
+        let (mut nx, mut xx, mut changed) = (false, false, false);
+        let mut lon = loop {
+            let arg = parse.next_bytes()?;
+            match &arg.to_ascii_uppercase()[..] {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                b"CH" => changed = true,
+                _ => break arg,
+            }
+        };
+        let mut pairs = vec![];
         loop {
-            match parse.next_string() {
-                Ok(s) => channels.push(s),
+            let (lat, member) = match (parse.next_bytes(), parse.next_bytes()) {
+                (Ok(lat), Ok(member)) => (lat, member),
+                (Err(CommandParseError::EndOfStream), _)
+                | (_, Err(CommandParseError::EndOfStream)) => return Err(MSG.into()),
+                (Err(err), _) | (_, Err(err)) => return Err(err),
+            };
+            let (x, y) = coordinates(&lon, &lat)?;
+            pairs.push((geo::encode(x, y) as f64, member));
+            lon = match parse.next_bytes() {
+                Ok(lon) => lon,
                 Err(CommandParseError::EndOfStream) => break,
                 Err(err) => return Err(err),
-            }
+            };
         }
 
-        Ok(Unsubscribe { channels })
+        if nx && xx {
+            return Err("XX and NX options at the same time are not compatible".into());
+        }
+        let existence = match (nx, xx) {
+            (true, _) => Some(Existence::Missing),
+            (_, true) => Some(Existence::Present),
+            _ => None,
+        };
+        Ok(GeoAdd {
+            zadd: ZAdd {
+                key,
+                existence,
+                comparison: None,
+                changed,
+                incr: false,
+                pairs,
+            },
+        })
     }
 }
 
 #[derive(Debug)]
-pub struct Config {
+pub struct GeoDist {
     pub key: String,
-    pub value: String,
+    pub first: Bytes,
+    pub second: Bytes,
+    /// Meters in the unit of the distance
+    pub unit: f64,
 }
 
-impl Config {
-    pub fn new(key: impl ToString, value: String) -> Config {
-        Config {
-            key: key.to_string(),
-            value,
+impl GeoDist {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<GeoDist, CommandParseError> {
+        let key = parse.next_string()?;
+        let first = parse.next_bytes()?;
+        let second = parse.next_bytes()?;
+        let unit = match parse_unit(parse) {
+            Err(CommandParseError::EndOfStream) => 1.0,
+            unit => unit?,
+        };
+        if parse.finish().is_err() {
+            return Err("syntax error".into());
         }
+        Ok(GeoDist {
+            key,
+            first,
+            second,
+            unit,
+        })
     }
+}
+
+/// `GEOHASH` and `GEOPOS`
+#[derive(Debug)]
+pub struct GeoMembers {
+    pub key: String,
+    pub members: Vec<Bytes>,
+}
 
+impl GeoMembers {
     pub fn key(&self) -> &str {
         &self.key
     }
 
-    pub fn value(&self) -> &String {
-        &self.value
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<GeoMembers, CommandParseError> {
+        let key = parse.next_string()?;
+        let mut members = vec![];
+        loop {
+            match parse.next_bytes() {
+                Ok(member) => members.push(member),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(GeoMembers { key, members })
     }
+}
 
-    pub fn parse_frames(parse: &mut CommandParser) -> Result<Config, CommandParseError> {
+/// Center of a `GEOSEARCH`
+#[derive(Debug)]
+pub enum GeoOrigin {
+    Member(Bytes),
+    /// Longitude and latitude
+    Coordinates(f64, f64),
+}
+
+#[derive(Debug)]
+pub struct GeoSearch {
+    pub key: String,
+    pub origin: GeoOrigin,
+    pub shape: Shape,
+    /// Meters in the unit of the shape, used for the replied distances
+    pub unit: f64,
+    /// By increasing distance, or decreasing when false
+    pub ascending: Option<bool>,
+    pub count: Option<usize>,
+    /// `ANY`, the first `count` matches found are kept, before sorting
+    pub any: bool,
+    pub with_coord: bool,
+    pub with_dist: bool,
+    pub with_hash: bool,
+}
+
+impl GeoSearch {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<GeoSearch, CommandParseError> {
         let key = parse.next_string()?;
-        let value = parse.next_string()?;
+        let (search, _) = GeoSearch::parse_options(parse, key, false)?;
+        Ok(search)
+    }
 
-        Ok(Config { key, value })
+    /// Options following the key, `STOREDIST` being allowed instead of the
+    /// `WITH` options when storing
+    fn parse_options(
+        parse: &mut CommandParser,
+        key: String,
+        store: bool,
+    ) -> Result<(GeoSearch, bool), CommandParseError> {
+        let name = if store { "GEOSEARCHSTORE" } else { "GEOSEARCH" };
+        let float = |parse: &mut CommandParser, msg: &'static str| {
+            parse_float(&parse.next_bytes()?).ok_or(CommandParseError::from(msg))
+        };
+
+        let (mut origin, mut shape, mut unit) = (None, None, 1.0);
+        let (mut ascending, mut count, mut any) = (None, None, false);
+        let (mut with_coord, mut with_dist, mut with_hash, mut store_dist) =
+            (false, false, false, false);
+        loop {
+            let option = match parse.next_string() {
+                Ok(option) => option.to_uppercase(),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            match &option[..] {
+                "FROMMEMBER" | "FROMLONLAT" if origin.is_some() => {
+                    return Err(format!(
+                        "exactly one of FROMMEMBER or FROMLONLAT can be specified for {}",
+                        name.to_lowercase()
+                    )
+                    .into())
+                }
+                "FROMMEMBER" => origin = Some(GeoOrigin::Member(parse.next_bytes()?)),
+                "FROMLONLAT" => {
+                    let (lon, lat) = (parse.next_bytes()?, parse.next_bytes()?);
+                    let (lon, lat) = coordinates(&lon, &lat)?;
+                    origin = Some(GeoOrigin::Coordinates(lon, lat));
+                }
+                "BYRADIUS" | "BYBOX" if shape.is_some() => {
+                    return Err(format!(
+                        "exactly one of BYRADIUS and BYBOX can be specified for {}",
+                        name.to_lowercase()
+                    )
+                    .into())
+                }
+                "BYRADIUS" => {
+                    let radius = float(parse, "need numeric radius")?;
+                    if radius < 0.0 {
+                        return Err("radius cannot be negative".into());
+                    }
+                    unit = parse_unit(parse)?;
+                    shape = Some(Shape::Radius(radius * unit));
+                }
+                "BYBOX" => {
+                    let width = float(parse, "need numeric width")?;
+                    let height = float(parse, "need numeric height")?;
+                    if width < 0.0 || height < 0.0 {
+                        return Err("height or width cannot be negative".into());
+                    }
+                    unit = parse_unit(parse)?;
+                    shape = Some(Shape::Box(width * unit, height * unit));
+                }
+                "ASC" => ascending = Some(true),
+                "DESC" => ascending = Some(false),
+                "COUNT" => match parse.next_signed_int() {
+                    Ok(n) if n > 0 => count = Some(n as usize),
+                    Ok(_) => return Err("COUNT must be > 0".into()),
+                    Err(_) => return Err("value is not an integer or out of range".into()),
+                },
+                "ANY" => any = true,
+                "WITHCOORD" if !store => with_coord = true,
+                "WITHDIST" if !store => with_dist = true,
+                "WITHHASH" if !store => with_hash = true,
+                "STOREDIST" if store => store_dist = true,
+                _ => return Err("syntax error".into()),
+            }
+        }
+
+        let Some(origin) = origin else {
+            return Err(format!(
+                "exactly one of FROMMEMBER or FROMLONLAT can be specified for {}",
+                name.to_lowercase()
+            )
+            .into());
+        };
+        let Some(shape) = shape else {
+            return Err(format!(
+                "exactly one of BYRADIUS and BYBOX can be specified for {}",
+                name.to_lowercase()
+            )
+            .into());
+        };
+        if any && count.is_none() {
+            return Err("the ANY argument requires COUNT argument".into());
+        }
+        let search = GeoSearch {
+            key,
+            origin,
+            shape,
+            unit,
+            ascending,
+            count,
+            any,
+            with_coord,
+            with_dist,
+            with_hash,
+        };
+        Ok((search, store_dist))
     }
 }
 
 #[derive(Debug)]
-pub struct Unknown {
-    pub command_name: String,
+pub struct GeoSearchStore {
+    pub destination: String,
+    pub search: GeoSearch,
+    /// `STOREDIST`, the scores are the distances instead of the geohashes
+    pub store_dist: bool,
 }
 
-impl Unknown {
-    pub fn new(key: impl ToString) -> Unknown {
+impl GeoSearchStore {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<GeoSearchStore, CommandParseError> {
+        let destination = parse.next_string()?;
+        let key = parse.next_string()?;
+        let (search, store_dist) = GeoSearch::parse_options(parse, key, true)?;
+        Ok(GeoSearchStore {
+            destination,
+            search,
+            store_dist,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Get {
+    pub key: String,
+}
+
+impl Get {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Get, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(Get { key })
+    }
+}
+
+#[derive(Debug)]
+pub struct GetBit {
+    pub key: String,
+    pub offset: usize,
+}
+
+impl GetBit {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<GetBit, CommandParseError> {
+        let key = parse.next_string()?;
+        let offset = parse_bit_offset(parse)?;
+        Ok(GetBit { key, offset })
+    }
+}
+
+#[derive(Debug)]
+pub struct GetDel {
+    pub key: String,
+}
+
+impl GetDel {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<GetDel, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(GetDel { key })
+    }
+}
+
+#[derive(Debug)]
+pub struct GetEx {
+    pub key: String,
+    pub expiration: Option<Expiration>,
+}
+
+impl GetEx {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<GetEx, CommandParseError> {
+        let key = parse.next_string()?;
+        let expiration = match parse.next_string() {
+            Ok(option) => match Expiration::parse(&option.to_uppercase(), parse, "getex")? {
+                Some(expiration) => Some(expiration),
+                None => return Err("syntax error".into()),
+            },
+            Err(CommandParseError::EndOfStream) => None,
+            Err(err) => return Err(err),
+        };
+        if parse.finish().is_err() {
+            return Err("syntax error".into());
+        }
+        Ok(GetEx { key, expiration })
+    }
+}
+
+#[derive(Debug)]
+pub struct GetRange {
+    pub key: String,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl GetRange {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<GetRange, CommandParseError> {
+        const MSG: &str = "value is not an integer or out of range";
+
+        let key = parse.next_string()?;
+        let start = parse.next_signed_int().map_err(|_| MSG)?;
+        let end = parse.next_signed_int().map_err(|_| MSG)?;
+        Ok(GetRange { key, start, end })
+    }
+}
+
+#[derive(Debug)]
+pub struct HDel {
+    pub key: String,
+    pub fields: Vec<Bytes>,
+}
+
+impl HDel {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HDel, CommandParseError> {
+        let key = parse.next_string()?;
+        let fields = parse_fields(parse)?;
+        Ok(HDel { key, fields })
+    }
+}
+
+#[derive(Debug)]
+pub struct HExists {
+    pub key: String,
+    pub field: Bytes,
+}
+
+impl HExists {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HExists, CommandParseError> {
+        let key = parse.next_string()?;
+        let field = parse.next_bytes()?;
+        Ok(HExists { key, field })
+    }
+}
+
+#[derive(Debug)]
+pub struct HGet {
+    pub key: String,
+    pub field: Bytes,
+}
+
+impl HGet {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HGet, CommandParseError> {
+        let key = parse.next_string()?;
+        let field = parse.next_bytes()?;
+        Ok(HGet { key, field })
+    }
+}
+
+#[derive(Debug)]
+pub struct HGetAll {
+    pub key: String,
+}
+
+impl HGetAll {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HGetAll, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(HGetAll { key })
+    }
+}
+
+#[derive(Debug)]
+pub struct HIncrBy {
+    pub key: String,
+    pub field: Bytes,
+    pub increment: i64,
+}
+
+impl HIncrBy {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HIncrBy, CommandParseError> {
+        let key = parse.next_string()?;
+        let field = parse.next_bytes()?;
+        let increment = parse
+            .next_signed_int()
+            .map_err(|_| "value is not an integer or out of range")?;
+        Ok(HIncrBy {
+            key,
+            field,
+            increment,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct HKeys {
+    pub key: String,
+}
+
+impl HKeys {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HKeys, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(HKeys { key })
+    }
+}
+
+#[derive(Debug)]
+pub struct HLen {
+    pub key: String,
+}
+
+impl HLen {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HLen, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(HLen { key })
+    }
+}
+
+#[derive(Debug)]
+pub struct HMGet {
+    pub key: String,
+    pub fields: Vec<Bytes>,
+}
+
+impl HMGet {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HMGet, CommandParseError> {
+        let key = parse.next_string()?;
+        let fields = parse_fields(parse)?;
+        Ok(HMGet { key, fields })
+    }
+}
+
+/// One or more fields
+fn parse_fields(parse: &mut CommandParser) -> Result<Vec<Bytes>, CommandParseError> {
+    let mut fields = vec![parse.next_bytes()?];
+    loop {
+        match parse.next_bytes() {
+            Ok(field) => fields.push(field),
+            Err(CommandParseError::EndOfStream) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(fields)
+}
+
+/// Offset of a bit in a string value, within the maximum size of strings
+fn parse_bit_offset(parse: &mut CommandParser) -> Result<usize, CommandParseError> {
+    match parse.next_signed_int() {
+        Ok(offset) if (0..MAX_STRING_LEN as i64 * 8).contains(&offset) => Ok(offset as usize),
+        _ => Err("bit offset is not an integer or out of range".into()),
+    }
+}
+
+/// The optional `BYTE` or `BIT` unit ending the range of `BITCOUNT` and
+/// `BITPOS`
+fn parse_bit_unit(parse: &mut CommandParser) -> Result<BitUnit, CommandParseError> {
+    let unit = match parse.next_string() {
+        Ok(unit) => match &unit.to_uppercase()[..] {
+            "BYTE" => BitUnit::Byte,
+            "BIT" => BitUnit::Bit,
+            _ => return Err("syntax error".into()),
+        },
+        Err(CommandParseError::EndOfStream) => return Ok(BitUnit::Byte),
+        Err(err) => return Err(err),
+    };
+    if parse.finish().is_err() {
+        return Err("syntax error".into());
+    }
+    Ok(unit)
+}
+
+/// Type and offset of a `BITFIELD` integer, the offset is multiplied by the
+/// size of the type when prefixed with `#`
+fn parse_field(parse: &mut CommandParser) -> Result<(FieldType, usize), CommandParseError> {
+    let field = FieldType::parse(&parse.next_string()?).ok_or(
+        "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.",
+    )?;
+    let offset = parse.next_string()?;
+    let offset = match offset.strip_prefix('#') {
+        Some(index) => index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| index.checked_mul(field.bits as usize)),
+        None => offset.parse::<usize>().ok(),
+    };
+    match offset {
+        Some(offset)
+            if offset
+                .checked_add(field.bits as usize)
+                .is_some_and(|end| end <= MAX_STRING_LEN * 8) =>
+        {
+            Ok((field, offset))
+        }
+        _ => Err("bit offset is not an integer or out of range".into()),
+    }
+}
+
+/// Longitude and latitude, within the limits of the geohashes
+fn coordinates(lon: &[u8], lat: &[u8]) -> Result<(f64, f64), CommandParseError> {
+    const MSG: &str = "value is not a valid float";
+
+    let lon = parse_float(lon).ok_or(MSG)?;
+    let lat = parse_float(lat).ok_or(MSG)?;
+    if !geo::valid(lon, lat) {
+        return Err(format!("invalid longitude,latitude pair {:.6},{:.6}", lon, lat).into());
+    }
+    Ok((lon, lat))
+}
+
+/// Meters in a distance unit
+fn parse_unit(parse: &mut CommandParser) -> Result<f64, CommandParseError> {
+    match &parse.next_string()?.to_lowercase()[..] {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err("unsupported unit provided. please use M, KM, FT, MI".into()),
+    }
+}
+
+/// One or more keys
+fn parse_keys(parse: &mut CommandParser) -> Result<Vec<String>, CommandParseError> {
+    let mut keys = vec![parse.next_string()?];
+    loop {
+        match parse.next_string() {
+            Ok(key) => keys.push(key),
+            Err(CommandParseError::EndOfStream) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(keys)
+}
+
+#[derive(Debug)]
+pub struct HScan {
+    pub key: String,
+    pub cursor: u64,
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+    pub novalues: bool,
+}
+
+impl HScan {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HScan, CommandParseError> {
+        let key = parse.next_string()?;
+        let cursor = parse.next_int().map_err(|_| "invalid cursor")?;
+        let mut scan = HScan {
+            key,
+            cursor,
+            pattern: None,
+            count: 10,
+            novalues: false,
+        };
+
+        loop {
+            let option = match parse.next_string() {
+                Ok(s) => s.to_uppercase(),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            match &option[..] {
+                "MATCH" => scan.pattern = Some(parse.next_bytes()?),
+                "COUNT" => {
+                    scan.count = match parse.next_int() {
+                        Ok(count) if count > 0 => count as usize,
+                        _ => return Err("value is out of range, must be positive".into()),
+                    }
+                }
+                "NOVALUES" => scan.novalues = true,
+                _ => return Err("syntax error".into()),
+            }
+        }
+
+        Ok(scan)
+    }
+}
+
+#[derive(Debug)]
+pub struct HSet {
+    pub key: String,
+    pub pairs: Vec<(Bytes, Bytes)>,
+}
+
+impl HSet {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HSet, CommandParseError> {
+        const MSG: &str = "wrong number of arguments for 'hset' command";
+
+        let key = parse.next_string()?;
+        let mut pairs = vec![];
+        loop {
+            let field = match parse.next_bytes() {
+                Ok(field) => field,
+                Err(CommandParseError::EndOfStream) if !pairs.is_empty() => break,
+                Err(CommandParseError::EndOfStream) => return Err(MSG.into()),
+                Err(err) => return Err(err),
+            };
+            let value = match parse.next_bytes() {
+                Err(CommandParseError::EndOfStream) => return Err(MSG.into()),
+                value => value?,
+            };
+            pairs.push((field, value));
+        }
+        Ok(HSet { key, pairs })
+    }
+}
+
+#[derive(Debug)]
+pub struct HVals {
+    pub key: String,
+}
+
+impl HVals {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<HVals, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(HVals { key })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Hello {
+    pub protocol: Option<i64>,
+    /// Username and password
+    pub auth: Option<(String, String)>,
+    pub setname: Option<String>,
+}
+
+impl Hello {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Hello, CommandParseError> {
+        let mut hello = Hello::default();
+
+        match parse.next_signed_int() {
+            Ok(protocol) => hello.protocol = Some(protocol),
+            Err(CommandParseError::EndOfStream) => return Ok(hello),
+            Err(_) => return Err("Protocol version is not an integer or out of range".into()),
+        }
+
+        loop {
+            let option = match parse.next_string() {
+                Ok(s) => s.to_uppercase(),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            match &option[..] {
+                "AUTH" => hello.auth = Some((parse.next_string()?, parse.next_string()?)),
+                "SETNAME" => hello.setname = Some(parse.next_string()?),
+                _ => return Err(format!("Syntax error in HELLO option '{}'", option).into()),
+            }
+        }
+
+        Ok(hello)
+    }
+}
+
+/// `INCR`, `DECR`, `INCRBY` and `DECRBY`
+#[derive(Debug)]
+pub struct IncrBy {
+    pub key: String,
+    pub increment: i64,
+    /// The increment is given, as `INCRBY` and `DECRBY`
+    pub by: bool,
+    pub decrement: bool,
+}
+
+impl IncrBy {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(
+        parse: &mut CommandParser,
+        by: bool,
+        decrement: bool,
+    ) -> Result<IncrBy, CommandParseError> {
+        let key = parse.next_string()?;
+        let mut increment = match by {
+            true => parse
+                .next_signed_int()
+                .map_err(|_| "value is not an integer or out of range")?,
+            false => 1,
+        };
+        if decrement {
+            increment = increment.checked_neg().ok_or("decrement would overflow")?;
+        }
+        Ok(IncrBy {
+            key,
+            increment,
+            by,
+            decrement,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct IncrByFloat {
+    pub key: String,
+    pub increment: f64,
+}
+
+impl IncrByFloat {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<IncrByFloat, CommandParseError> {
+        let key = parse.next_string()?;
+        let increment = parse_float(&parse.next_bytes()?).ok_or("value is not a valid float")?;
+        Ok(IncrByFloat { key, increment })
+    }
+}
+
+#[derive(Debug)]
+pub struct Keys {
+    pub pattern: Bytes,
+}
+
+impl Keys {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Keys, CommandParseError> {
+        let pattern = parse.next_bytes()?;
+        Ok(Keys { pattern })
+    }
+}
+
+#[derive(Debug)]
+pub struct LastSave;
+
+impl LastSave {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<LastSave, CommandParseError> {
+        Ok(LastSave)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListSide {
+    Left,
+    Right,
+}
+
+impl ListSide {
+    pub fn parse(parse: &mut CommandParser) -> Result<ListSide, CommandParseError> {
+        match &parse.next_string()?.to_uppercase()[..] {
+            "LEFT" => Ok(ListSide::Left),
+            "RIGHT" => Ok(ListSide::Right),
+            _ => Err("syntax error".into()),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ListSide::Left => "LEFT",
+            ListSide::Right => "RIGHT",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LIndex {
+    pub key: String,
+    pub index: i64,
+}
+
+impl LIndex {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<LIndex, CommandParseError> {
+        let key = parse.next_string()?;
+        let index = parse.next_signed_int()?;
+        Ok(LIndex { key, index })
+    }
+}
+
+#[derive(Debug)]
+pub struct LLen {
+    pub key: String,
+}
+
+impl LLen {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<LLen, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(LLen { key })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LMove {
+    pub source: String,
+    pub destination: String,
+    pub from: ListSide,
+    pub to: ListSide,
+}
+
+impl LMove {
+    pub fn key(&self) -> &str {
+        &self.source
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<LMove, CommandParseError> {
+        let source = parse.next_string()?;
+        let destination = parse.next_string()?;
+        let from = ListSide::parse(parse)?;
+        let to = ListSide::parse(parse)?;
+        Ok(LMove {
+            source,
+            destination,
+            from,
+            to,
+        })
+    }
+}
+
+/// `LRANGE` and `LTRIM` bounds, both inclusive, negative values count from
+/// the end of the list
+#[derive(Debug)]
+pub struct LRange {
+    pub key: String,
+    pub start: i64,
+    pub stop: i64,
+}
+
+impl LRange {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<LRange, CommandParseError> {
+        let key = parse.next_string()?;
+        let start = parse.next_signed_int()?;
+        let stop = parse.next_signed_int()?;
+        Ok(LRange { key, start, stop })
+    }
+}
+
+#[derive(Debug)]
+pub struct LRem {
+    pub key: String,
+    /// Removes from the tail when negative, every occurrence when zero
+    pub count: i64,
+    pub element: Bytes,
+}
+
+impl LRem {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<LRem, CommandParseError> {
+        let key = parse.next_string()?;
+        let count = parse.next_signed_int()?;
+        let element = parse.next_bytes()?;
+        Ok(LRem {
+            key,
+            count,
+            element,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct LSet {
+    pub key: String,
+    pub index: i64,
+    pub element: Bytes,
+}
+
+impl LSet {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<LSet, CommandParseError> {
+        let key = parse.next_string()?;
+        let index = parse.next_signed_int()?;
+        let element = parse.next_bytes()?;
+        Ok(LSet {
+            key,
+            index,
+            element,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct LTrim {
+    pub range: LRange,
+}
+
+impl LTrim {
+    pub fn key(&self) -> &str {
+        self.range.key()
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<LTrim, CommandParseError> {
+        Ok(LTrim {
+            range: LRange::parse_frames(parse)?,
+        })
+    }
+}
+
+/// Spans several shards
+#[derive(Debug)]
+pub struct MGet {
+    pub keys: Vec<String>,
+}
+
+impl MGet {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<MGet, CommandParseError> {
+        Ok(MGet {
+            keys: parse_keys(parse)?,
+        })
+    }
+}
+
+/// `MOVE`, the key stays on its shard
+#[derive(Debug)]
+pub struct Move {
+    pub key: String,
+    pub db: usize,
+}
+
+impl Move {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Move, CommandParseError> {
+        let key = parse.next_string()?;
+        let db = parse
+            .next_int()
+            .map_err(|_| "value is not an integer or out of range")?;
+        Ok(Move {
+            key,
+            db: db as usize,
+        })
+    }
+}
+
+/// `MSET` and `MSETNX`, spans several shards
+#[derive(Debug)]
+pub struct MSet {
+    pub pairs: Vec<(String, Bytes)>,
+    /// Only set the keys if none exists
+    pub nx: bool,
+}
+
+impl MSet {
+    pub fn parse_frames(parse: &mut CommandParser, nx: bool) -> Result<MSet, CommandParseError> {
+        let msg = match nx {
+            true => "wrong number of arguments for 'msetnx' command",
+            false => "wrong number of arguments for 'mset' command",
+        };
+
+        let mut pairs = vec![];
+        loop {
+            let key = match parse.next_string() {
+                Ok(key) => key,
+                Err(CommandParseError::EndOfStream) if !pairs.is_empty() => break,
+                Err(CommandParseError::EndOfStream) => return Err(msg.into()),
+                Err(err) => return Err(err),
+            };
+            let value = match parse.next_bytes() {
+                Err(CommandParseError::EndOfStream) => return Err(msg.into()),
+                value => value?,
+            };
+            pairs.push((key, value));
+        }
+        Ok(MSet { pairs, nx })
+    }
+}
+
+#[derive(Debug)]
+pub struct Multi;
+
+impl Multi {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<Multi, CommandParseError> {
+        Ok(Multi)
+    }
+}
+
+#[derive(Debug)]
+pub struct Persist {
+    pub key: String,
+}
+
+impl Persist {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Persist, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(Persist { key })
+    }
+}
+
+#[derive(Debug)]
+pub struct PfAdd {
+    pub key: String,
+    pub elements: Vec<Bytes>,
+}
+
+impl PfAdd {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<PfAdd, CommandParseError> {
+        let key = parse.next_string()?;
+        let mut elements = vec![];
+        loop {
+            match parse.next_bytes() {
+                Ok(element) => elements.push(element),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(PfAdd { key, elements })
+    }
+}
+
+#[derive(Debug)]
+pub struct PfCount {
+    pub keys: Vec<String>,
+}
+
+impl PfCount {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<PfCount, CommandParseError> {
+        let keys = parse_keys(parse)?;
+        Ok(PfCount { keys })
+    }
+}
+
+#[derive(Debug)]
+pub struct PfMerge {
+    pub destination: String,
+    /// Merged with the destination
+    pub keys: Vec<String>,
+}
+
+impl PfMerge {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<PfMerge, CommandParseError> {
+        let destination = parse.next_string()?;
+        let mut keys = vec![];
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(PfMerge { destination, keys })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Ping {
+    pub msg: Option<Bytes>,
+}
+
+impl Ping {
+    pub fn new(msg: Option<Bytes>) -> Ping {
+        Ping { msg }
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Ping, CommandParseError> {
+        match parse.next_bytes() {
+            Ok(msg) => Ok(Ping::new(Some(msg))),
+            Err(CommandParseError::EndOfStream) => Ok(Ping::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// `LPOP` and `RPOP`
+#[derive(Debug)]
+pub struct Pop {
+    pub key: String,
+    /// Replies with an array, instead of a single element, when set
+    pub count: Option<u64>,
+    pub side: ListSide,
+}
+
+impl Pop {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(
+        parse: &mut CommandParser,
+        side: ListSide,
+    ) -> Result<Pop, CommandParseError> {
+        let key = parse.next_string()?;
+        let count = match parse.next_int() {
+            Ok(count) => Some(count),
+            Err(CommandParseError::EndOfStream) => None,
+            Err(_) => return Err("value is out of range, must be positive".into()),
+        };
+        Ok(Pop { key, count, side })
+    }
+}
+
+#[derive(Debug)]
+pub struct PSubscribe {
+    pub patterns: Vec<String>,
+}
+
+impl PSubscribe {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<PSubscribe, CommandParseError> {
+        let mut patterns = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(s) => patterns.push(s),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(PSubscribe { patterns })
+    }
+}
+
+#[derive(Debug)]
+pub struct PUnsubscribe {
+    pub patterns: Vec<String>,
+}
+
+impl PUnsubscribe {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<PUnsubscribe, CommandParseError> {
+        let mut patterns = vec![];
+
+        loop {
+            match parse.next_string() {
+                Ok(s) => patterns.push(s),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(PUnsubscribe { patterns })
+    }
+}
+
+#[derive(Debug)]
+pub enum PubSub {
+    Channels(Option<String>),
+    NumSub(Vec<String>),
+    NumPat,
+}
+
+impl PubSub {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<PubSub, CommandParseError> {
+        let subcommand = parse.next_string()?.to_uppercase();
+        match &subcommand[..] {
+            "CHANNELS" => match parse.next_string() {
+                Ok(pattern) => Ok(PubSub::Channels(Some(pattern))),
+                Err(CommandParseError::EndOfStream) => Ok(PubSub::Channels(None)),
+                Err(err) => Err(err),
+            },
+            "NUMSUB" => {
+                let mut channels = vec![];
+                loop {
+                    match parse.next_string() {
+                        Ok(s) => channels.push(s),
+                        Err(CommandParseError::EndOfStream) => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(PubSub::NumSub(channels))
+            }
+            "NUMPAT" => Ok(PubSub::NumPat),
+            _ => Err(format!("unknown subcommand '{}'", subcommand).into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Publish {
+    pub channel: String,
+    pub message: Bytes,
+}
+
+impl Publish {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Publish, CommandParseError> {
+        let channel = parse.next_string()?;
+        let message = parse.next_bytes()?;
+
+        Ok(Publish { channel, message })
+    }
+}
+
+/// `LPUSH` and `RPUSH`
+#[derive(Debug)]
+pub struct Push {
+    pub key: String,
+    pub elements: Vec<Bytes>,
+    pub side: ListSide,
+}
+
+impl Push {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(
+        parse: &mut CommandParser,
+        side: ListSide,
+    ) -> Result<Push, CommandParseError> {
+        let key = parse.next_string()?;
+        let mut elements = vec![parse.next_bytes()?];
+        loop {
+            match parse.next_bytes() {
+                Ok(element) => elements.push(element),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(Push {
+            key,
+            elements,
+            side,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct RandomKey;
+
+impl RandomKey {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<RandomKey, CommandParseError> {
+        Ok(RandomKey)
+    }
+}
+
+/// `RENAME` and `RENAMENX`, the keys may belong to different shards
+#[derive(Debug)]
+pub struct Rename {
+    pub source: String,
+    pub destination: String,
+    pub nx: bool,
+}
+
+impl Rename {
+    pub fn parse_frames(parse: &mut CommandParser, nx: bool) -> Result<Rename, CommandParseError> {
+        let source = parse.next_string()?;
+        let destination = parse.next_string()?;
+        Ok(Rename {
+            source,
+            destination,
+            nx,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SAdd {
+    pub key: String,
+    pub members: Vec<Bytes>,
+}
+
+impl SAdd {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<SAdd, CommandParseError> {
+        let key = parse.next_string()?;
+        let members = parse_fields(parse)?;
+        Ok(SAdd { key, members })
+    }
+}
+
+#[derive(Debug)]
+pub struct Save;
+
+impl Save {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<Save, CommandParseError> {
+        Ok(Save)
+    }
+}
+
+#[derive(Debug)]
+pub struct Select {
+    pub index: usize,
+}
+
+impl Select {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Select, CommandParseError> {
+        let index = parse
+            .next_int()
+            .map_err(|_| "value is not an integer or out of range")?;
+        Ok(Select {
+            index: index as usize,
+        })
+    }
+}
+
+/// `REPLICAOF host port`, or `REPLICAOF NO ONE` to stop replicating
+#[derive(Debug)]
+pub struct ReplicaOf {
+    pub primary: Option<(String, u16)>,
+}
+
+impl ReplicaOf {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<ReplicaOf, CommandParseError> {
+        let host = parse.next_string()?;
+        let port = parse.next_string()?;
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            return Ok(ReplicaOf { primary: None });
+        }
+        let port = port.parse().map_err(|_| "Invalid master port")?;
+        Ok(ReplicaOf {
+            primary: Some((host, port)),
+        })
+    }
+}
+
+/// `CLUSTER` subcommands
+#[derive(Debug)]
+pub enum Cluster {
+    Info,
+    KeySlot(Bytes),
+    MyId,
+    Nodes,
+    SetSlot(u16, SlotState),
+    Shards,
+    Slots,
+}
+
+impl Cluster {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Cluster, CommandParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let msg = format!(
+            "wrong number of arguments for 'cluster|{}' command",
+            subcommand
+        );
+        let missing = |err| match err {
+            CommandParseError::EndOfStream => msg.clone().into(),
+            err => err,
+        };
+        match &subcommand[..] {
+            "info" => Ok(Cluster::Info),
+            "keyslot" => Ok(Cluster::KeySlot(parse.next_bytes().map_err(missing)?)),
+            "myid" => Ok(Cluster::MyId),
+            "nodes" => Ok(Cluster::Nodes),
+            "setslot" => {
+                let slot = parse.next_string().map_err(missing)?;
+                let slot = slot.parse().map_err(|_| "Invalid or out of range slot")?;
+                let state = parse.next_string().map_err(missing)?.to_lowercase();
+                let state = match &state[..] {
+                    "migrating" => SlotState::Migrating(parse.next_string().map_err(missing)?),
+                    "importing" => SlotState::Importing(parse.next_string().map_err(missing)?),
+                    "node" => SlotState::Node(parse.next_string().map_err(missing)?),
+                    "stable" => SlotState::Stable,
+                    _ => return Err(
+                        "Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP"
+                            .into(),
+                    ),
+                };
+                Ok(Cluster::SetSlot(slot, state))
+            }
+            "shards" => Ok(Cluster::Shards),
+            "slots" => Ok(Cluster::Slots),
+            _ => Err(format!("unknown subcommand '{}'. Try CLUSTER HELP.", subcommand).into()),
+        }
+    }
+}
+
+/// `CLIENT` subcommands
+#[derive(Debug)]
+pub enum Client {
+    GetName,
+    Id,
+    Info,
+    Kill(ClientKill),
+    /// `CLIENT KILL <addr>`, an error when no client is killed
+    KillAddr(String),
+    List(Option<ClientType>, Vec<u64>),
+    SetName(String),
+}
+
+/// Clients matched by `CLIENT KILL`, by every given filter
+#[derive(Debug, Default)]
+pub struct ClientKill {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    pub laddr: Option<String>,
+    pub kind: Option<ClientType>,
+    pub user: Option<String>,
+    /// The client killing the others is not killed
+    pub skipme: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClientType {
+    Normal,
+    Replica,
+    PubSub,
+    Master,
+}
+
+impl ClientType {
+    fn parse(name: &str) -> Result<ClientType, CommandParseError> {
+        match &name.to_lowercase()[..] {
+            "normal" => Ok(ClientType::Normal),
+            "replica" | "slave" => Ok(ClientType::Replica),
+            "pubsub" => Ok(ClientType::PubSub),
+            "master" => Ok(ClientType::Master),
+            _ => Err(format!("Unknown client type '{}'", name).into()),
+        }
+    }
+}
+
+impl Client {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Client, CommandParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let msg = format!(
+            "wrong number of arguments for 'client|{}' command",
+            subcommand
+        );
+        let missing = |err| match err {
+            CommandParseError::EndOfStream => msg.clone().into(),
+            err => err,
+        };
+        match &subcommand[..] {
+            "getname" => Ok(Client::GetName),
+            "id" => Ok(Client::Id),
+            "info" => Ok(Client::Info),
+            "setname" => Ok(Client::SetName(parse.next_string().map_err(missing)?)),
+            "list" => {
+                let (mut kind, mut ids) = (None, vec![]);
+                loop {
+                    match parse.next_string() {
+                        Ok(filter) if filter.eq_ignore_ascii_case("type") => {
+                            kind = Some(ClientType::parse(&parse.next_string()?)?)
+                        }
+                        Ok(filter) if filter.eq_ignore_ascii_case("id") => loop {
+                            match parse.next_int() {
+                                Ok(id) => ids.push(id),
+                                Err(CommandParseError::EndOfStream) if !ids.is_empty() => break,
+                                Err(_) => return Err("Invalid client ID".into()),
+                            }
+                        },
+                        Ok(_) => return Err("syntax error".into()),
+                        Err(CommandParseError::EndOfStream) => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(Client::List(kind, ids))
+            }
+            "kill" => {
+                let mut option = parse.next_string().map_err(missing)?;
+                let mut value = match parse.next_string() {
+                    Ok(value) => value,
+                    // The legacy form, with the address alone
+                    Err(CommandParseError::EndOfStream) => return Ok(Client::KillAddr(option)),
+                    Err(err) => return Err(err),
+                };
+                let mut filter = ClientKill {
+                    skipme: true,
+                    ..Default::default()
+                };
+                loop {
+                    match &option.to_lowercase()[..] {
+                        "id" => {
+                            let id = value
+                                .parse()
+                                .map_err(|_| "client-id should be greater than 0")?;
+                            filter.id = Some(id);
+                        }
+                        "addr" => filter.addr = Some(value),
+                        "laddr" => filter.laddr = Some(value),
+                        "type" => filter.kind = Some(ClientType::parse(&value)?),
+                        "user" => filter.user = Some(value),
+                        "skipme" => {
+                            filter.skipme = match &value.to_lowercase()[..] {
+                                "yes" => true,
+                                "no" => false,
+                                _ => return Err("syntax error".into()),
+                            }
+                        }
+                        _ => return Err("syntax error".into()),
+                    }
+                    option = match parse.next_string() {
+                        Ok(option) => option,
+                        Err(CommandParseError::EndOfStream) => break,
+                        Err(err) => return Err(err),
+                    };
+                    value = match parse.next_string() {
+                        Err(CommandParseError::EndOfStream) => return Err("syntax error".into()),
+                        value => value?,
+                    };
+                }
+                Ok(Client::Kill(filter))
+            }
+            _ => Err(format!("unknown subcommand '{}'. Try CLIENT HELP.", subcommand).into()),
+        }
+    }
+}
+
+/// `INFO [section ...]`, the default sections when none is given
+#[derive(Debug)]
+pub struct Info {
+    pub sections: Vec<String>,
+}
+
+impl Info {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Info, CommandParseError> {
+        let mut sections = vec![];
+        loop {
+            match parse.next_string() {
+                Ok(section) => sections.push(section.to_lowercase()),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(Info { sections })
+    }
+}
+
+/// `SLOWLOG` subcommands
+#[derive(Debug)]
+pub enum SlowLog {
+    /// The last entries, all of them when `None`
+    Get(Option<usize>),
+    Len,
+    Reset,
+}
+
+impl SlowLog {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<SlowLog, CommandParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        match &subcommand[..] {
+            "get" => match parse.next_signed_int() {
+                Ok(-1) => Ok(SlowLog::Get(None)),
+                Ok(count) if count >= 0 => Ok(SlowLog::Get(Some(count as usize))),
+                Ok(_) => Err("count should be greater than or equal to -1".into()),
+                Err(CommandParseError::EndOfStream) => Ok(SlowLog::Get(Some(10))),
+                Err(_) => Err("value is not an integer or out of range".into()),
+            },
+            "len" => Ok(SlowLog::Len),
+            "reset" => Ok(SlowLog::Reset),
+            _ => Err(format!("unknown subcommand '{}'. Try SLOWLOG HELP.", subcommand).into()),
+        }
+    }
+}
+
+/// `LATENCY HISTOGRAM [command ...]`, of every command when none is given
+#[derive(Debug)]
+pub enum Latency {
+    Histogram(Vec<String>),
+}
+
+impl Latency {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Latency, CommandParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        match &subcommand[..] {
+            "histogram" => {
+                let mut commands = vec![];
+                loop {
+                    match parse.next_string() {
+                        Ok(command) => commands.push(command.to_lowercase()),
+                        Err(CommandParseError::EndOfStream) => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(Latency::Histogram(commands))
+            }
+            _ => Err(format!("unknown subcommand '{}'. Try LATENCY HELP.", subcommand).into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Monitor;
+
+impl Monitor {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<Monitor, CommandParseError> {
+        Ok(Monitor)
+    }
+}
+
+/// Serve the next command for a slot being imported, see `Route`
+#[derive(Debug)]
+pub struct Asking;
+
+impl Asking {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<Asking, CommandParseError> {
+        Ok(Asking)
+    }
+}
+
+/// `AUTH [username] password`, as the default user without a username
+#[derive(Debug)]
+pub struct Auth {
+    pub username: Option<String>,
+    pub password: String,
+}
+
+impl Auth {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Auth, CommandParseError> {
+        let first = parse.next_string()?;
+        match parse.next_string() {
+            Ok(password) => Ok(Auth {
+                username: Some(first),
+                password,
+            }),
+            Err(CommandParseError::EndOfStream) => Ok(Auth {
+                username: None,
+                password: first,
+            }),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// `ACL` subcommands
+#[derive(Debug)]
+pub enum Acl {
+    /// The categories, or the commands of a category
+    Cat(Option<String>),
+    DelUser(Vec<String>),
+    GetUser(String),
+    List,
+    Load,
+    Save,
+    /// A user with the rules to apply
+    SetUser(String, Vec<String>),
+    Users,
+    WhoAmI,
+}
+
+impl Acl {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Acl, CommandParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        let msg = format!("wrong number of arguments for 'acl|{}' command", subcommand);
+        let missing = |err| match err {
+            CommandParseError::EndOfStream => msg.clone().into(),
+            err => err,
+        };
+        match &subcommand[..] {
+            "cat" => match parse.next_string() {
+                Ok(category) => Ok(Acl::Cat(Some(category))),
+                Err(CommandParseError::EndOfStream) => Ok(Acl::Cat(None)),
+                Err(err) => Err(err),
+            },
+            "deluser" => Ok(Acl::DelUser(parse_keys(parse).map_err(missing)?)),
+            "getuser" => Ok(Acl::GetUser(parse.next_string().map_err(missing)?)),
+            "list" => Ok(Acl::List),
+            "load" => Ok(Acl::Load),
+            "save" => Ok(Acl::Save),
+            "setuser" => {
+                let name = parse.next_string().map_err(missing)?;
+                let mut rules = vec![];
+                loop {
+                    match parse.next_string() {
+                        Ok(rule) => rules.push(rule),
+                        Err(CommandParseError::EndOfStream) => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(Acl::SetUser(name, rules))
+            }
+            "users" => Ok(Acl::Users),
+            "whoami" => Ok(Acl::WhoAmI),
+            _ => Err(format!("unknown subcommand '{}'. Try ACL HELP.", subcommand).into()),
+        }
+    }
+}
+
+/// Options sent by a replica to its primary, as `REPLCONF ACK <offset>`
+#[derive(Debug)]
+pub struct ReplConf {
+    pub options: Vec<(String, String)>,
+}
+
+impl ReplConf {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<ReplConf, CommandParseError> {
+        let mut options = vec![];
+        loop {
+            let name = match parse.next_string() {
+                Ok(name) => name.to_lowercase(),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            let value = match parse.next_string() {
+                Err(CommandParseError::EndOfStream) => return Err("syntax error".into()),
+                value => value?,
+            };
+            options.push((name, value));
+        }
+        Ok(ReplConf { options })
+    }
+}
+
+/// `PSYNC <replication id> <offset>`, `PSYNC ? -1` for a full
+/// synchronization
+#[derive(Debug)]
+pub struct PSync {
+    pub id: String,
+    /// Offset of the first byte of the stream wanted by the replica
+    pub offset: i64,
+}
+
+impl PSync {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<PSync, CommandParseError> {
+        let id = parse.next_string()?;
+        let offset = parse
+            .next_signed_int()
+            .map_err(|_| "value is not an integer or out of range")?;
+        Ok(PSync { id, offset })
+    }
+}
+
+/// `SCAN`, the cursor is made of the index of a shard and of a position in
+/// that shard, see `scan_cursor`
+#[derive(Debug)]
+pub struct Scan {
+    pub cursor: u64,
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+    /// Only the keys holding this type, as named by `TYPE`
+    pub value_type: Option<String>,
+}
+
+impl Scan {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Scan, CommandParseError> {
+        let cursor = parse.next_int().map_err(|_| "invalid cursor")?;
+        let mut scan = Scan {
+            cursor,
+            pattern: None,
+            count: 10,
+            value_type: None,
+        };
+
+        loop {
+            let option = match parse.next_string() {
+                Ok(s) => s.to_uppercase(),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            match &option[..] {
+                "MATCH" => scan.pattern = Some(parse.next_bytes()?),
+                "COUNT" => {
+                    scan.count = match parse.next_int() {
+                        Ok(count) if count > 0 => count as usize,
+                        _ => return Err("value is out of range, must be positive".into()),
+                    }
+                }
+                "TYPE" => scan.value_type = Some(parse.next_string()?.to_lowercase()),
+                _ => return Err("syntax error".into()),
+            }
+        }
+
+        Ok(scan)
+    }
+}
+
+/// Bits of a `SCAN` cursor holding the position in a shard, the index of the
+/// shard is held by the bits above
+const SCAN_POSITION_BITS: u32 = 48;
+
+/// Make a `SCAN` cursor, the position is truncated to `SCAN_POSITION_BITS`
+pub fn scan_cursor(shard: usize, position: u64) -> u64 {
+    ((shard as u64) << SCAN_POSITION_BITS) | (position >> (64 - SCAN_POSITION_BITS))
+}
+
+/// Split a `SCAN` cursor into the index of a shard and a position in it
+pub fn split_scan_cursor(cursor: u64) -> (usize, u64) {
+    let position = cursor & ((1 << SCAN_POSITION_BITS) - 1);
+    (
+        (cursor >> SCAN_POSITION_BITS) as usize,
+        position << (64 - SCAN_POSITION_BITS),
+    )
+}
+
+/// `SET`, and `SETNX`, `SETEX` and `PSETEX` which are special cases of it
+#[derive(Debug)]
+pub struct Set {
+    pub key: String,
+    pub value: Bytes,
+    pub expiration: Option<Expiration>,
+    /// `KEEPTTL`, the expiry of the previous value is retained
+    pub keep_ttl: bool,
+    pub existence: Option<Existence>,
+    /// `GET`, replies with the previous value
+    pub get: bool,
+    /// `SETNX`, replies with an integer
+    pub nx: bool,
+}
+
+impl Set {
+    pub fn new(key: String, value: Bytes) -> Set {
+        Set {
+            key,
+            value,
+            expiration: None,
+            keep_ttl: false,
+            existence: None,
+            get: false,
+            nx: false,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn value(&self) -> &Bytes {
+        &self.value
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Set, CommandParseError> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+        let mut set = Set::new(key, value);
+
+        loop {
+            let option = match parse.next_string() {
+                Ok(option) => option.to_uppercase(),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            match &option[..] {
+                "NX" | "XX" if set.existence.is_none() => {
+                    set.existence = Some(match &option[..] {
+                        "NX" => Existence::Missing,
+                        _ => Existence::Present,
+                    });
+                }
+                "GET" => set.get = true,
+                "KEEPTTL" if set.expiration.is_none() => set.keep_ttl = true,
+                "PERSIST" => return Err("syntax error".into()),
+                _ if set.expiration.is_none() && !set.keep_ttl => {
+                    match Expiration::parse(&option, parse, "set")? {
+                        Some(expiration) => set.expiration = Some(expiration),
+                        None => return Err("syntax error".into()),
+                    }
+                }
+                _ => return Err("syntax error".into()),
+            }
+        }
+
+        Ok(set)
+    }
+
+    /// `SETNX key value`
+    pub fn parse_nx_frames(parse: &mut CommandParser) -> Result<Set, CommandParseError> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+        let mut set = Set::new(key, value);
+        set.existence = Some(Existence::Missing);
+        set.nx = true;
+        Ok(set)
+    }
+
+    /// `SETEX key seconds value` and `PSETEX key milliseconds value`
+    pub fn parse_ex_frames(
+        parse: &mut CommandParser,
+        unit: TimeUnit,
+    ) -> Result<Set, CommandParseError> {
+        let key = parse.next_string()?;
+        let (option, command) = match unit {
+            TimeUnit::Seconds => ("EX", "setex"),
+            TimeUnit::Milliseconds => ("PX", "psetex"),
+        };
+        let expiration = Expiration::parse(option, parse, command)?;
+        let value = parse.next_bytes()?;
+        let mut set = Set::new(key, value);
+        set.expiration = expiration;
+        Ok(set)
+    }
+}
+
+#[derive(Debug)]
+pub struct SCard {
+    pub key: String,
+}
+
+impl SCard {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<SCard, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(SCard { key })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SetOpKind {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// `SINTER`, `SUNION` and `SDIFF`, the keys may belong to different shards
+#[derive(Debug)]
+pub struct SetBit {
+    pub key: String,
+    pub offset: usize,
+    pub bit: u8,
+}
+
+impl SetBit {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<SetBit, CommandParseError> {
+        let key = parse.next_string()?;
+        let offset = parse_bit_offset(parse)?;
+        let bit = match parse.next_signed_int() {
+            Ok(bit @ (0 | 1)) => bit as u8,
+            _ => return Err("bit is not an integer or out of range".into()),
+        };
+        Ok(SetBit { key, offset, bit })
+    }
+}
+
+#[derive(Debug)]
+pub struct SetOp {
+    pub keys: Vec<String>,
+    pub kind: SetOpKind,
+}
+
+impl SetOp {
+    pub fn parse_frames(
+        parse: &mut CommandParser,
+        kind: SetOpKind,
+    ) -> Result<SetOp, CommandParseError> {
+        let keys = parse_keys(parse)?;
+        Ok(SetOp { keys, kind })
+    }
+}
+
+#[derive(Debug)]
+pub struct SetRange {
+    pub key: String,
+    pub offset: usize,
+    pub value: Bytes,
+}
+
+impl SetRange {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<SetRange, CommandParseError> {
+        let key = parse.next_string()?;
+        let offset = match parse.next_signed_int() {
+            Ok(offset) if (0..MAX_STRING_LEN as i64).contains(&offset) => offset as usize,
+            Ok(_) => return Err("offset is out of range".into()),
+            Err(_) => return Err("value is not an integer or out of range".into()),
+        };
+        let value = parse.next_bytes()?;
+        if offset + value.len() > MAX_STRING_LEN {
+            return Err("string exceeds maximum allowed size (proto-max-bulk-len)".into());
+        }
+        Ok(SetRange { key, offset, value })
+    }
+}
+
+#[derive(Debug)]
+pub struct SIsMember {
+    pub key: String,
+    pub member: Bytes,
+}
+
+impl SIsMember {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<SIsMember, CommandParseError> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+        Ok(SIsMember { key, member })
+    }
+}
+
+#[derive(Debug)]
+pub struct SMembers {
+    pub key: String,
+}
+
+impl SMembers {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<SMembers, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(SMembers { key })
+    }
+}
+
+#[derive(Debug)]
+pub struct SRem {
+    pub key: String,
+    pub members: Vec<Bytes>,
+}
+
+impl SRem {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<SRem, CommandParseError> {
+        let key = parse.next_string()?;
+        let members = parse_fields(parse)?;
+        Ok(SRem { key, members })
+    }
+}
+
+#[derive(Debug)]
+pub struct StrLen {
+    pub key: String,
+}
+
+impl StrLen {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<StrLen, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(StrLen { key })
+    }
+}
+
+#[derive(Debug)]
+pub struct Subscribe {
+    pub channels: Vec<String>,
+}
+
+impl Subscribe {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Subscribe, CommandParseError> {
+        let mut channels = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(s) => channels.push(s),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Subscribe { channels })
+    }
+}
+
+#[derive(Debug)]
+pub struct Ttl {
+    pub key: String,
+    pub unit: TimeUnit,
+}
+
+impl Ttl {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(
+        parse: &mut CommandParser,
+        unit: TimeUnit,
+    ) -> Result<Ttl, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(Ttl { key, unit })
+    }
+}
+
+#[derive(Debug)]
+pub struct SwapDb {
+    pub first: usize,
+    pub second: usize,
+}
+
+impl SwapDb {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<SwapDb, CommandParseError> {
+        let first = parse.next_int().map_err(|_| "invalid first DB index")?;
+        let second = parse.next_int().map_err(|_| "invalid second DB index")?;
+        Ok(SwapDb {
+            first: first as usize,
+            second: second as usize,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Type {
+    pub key: String,
+}
+
+impl Type {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Type, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(Type { key })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Unsubscribe {
+    pub channels: Vec<String>,
+}
+
+impl Unsubscribe {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Unsubscribe, CommandParseError> {
+        let mut channels = vec![];
+
+        loop {
+            match parse.next_string() {
+                Ok(s) => channels.push(s),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Unsubscribe { channels })
+    }
+}
+
+/// Ids following the arguments, up to the end of the command
+fn parse_stream_ids(parse: &mut CommandParser) -> Result<Vec<StreamId>, CommandParseError> {
+    let mut ids = vec![];
+    loop {
+        match parse.next_bytes() {
+            Ok(id) => ids.push(StreamId::parse(&id, 0).ok_or(INVALID_ID)?),
+            Err(CommandParseError::EndOfStream) if !ids.is_empty() => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(ids)
+}
+
+/// Time in milliseconds, negative times being zero
+fn parse_millis(parse: &mut CommandParser) -> Result<u64, CommandParseError> {
+    parse
+        .next_signed_int()
+        .map(|ms| ms.max(0) as u64)
+        .map_err(|_| "value is not an integer or out of range".into())
+}
+
+#[derive(Debug)]
+pub struct XAck {
+    pub key: String,
+    pub group: String,
+    pub ids: Vec<StreamId>,
+}
+
+impl XAck {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<XAck, CommandParseError> {
+        let key = parse.next_string()?;
+        let group = parse.next_string()?;
+        let ids = parse_stream_ids(parse)?;
+        Ok(XAck { key, group, ids })
+    }
+}
+
+/// Trimming of the stream by `XADD`
+#[derive(Clone, Copy, Debug)]
+pub struct XTrim {
+    pub trim: Trim,
+    /// `~`, which trims as exactly as `=` since the entries are not stored
+    /// by nodes to evict at once
+    pub approximate: bool,
+    /// Maximal number of evicted entries, zero for no limit
+    pub limit: u64,
+}
+
+#[derive(Debug)]
+pub struct XAdd {
+    pub key: String,
+    /// The stream is not created when missing
+    pub nomkstream: bool,
+    pub trim: Option<XTrim>,
+    pub id: NewId,
+    pub fields: Fields,
+}
+
+impl XAdd {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<XAdd, CommandParseError> {
+        let key = parse.next_string()?;
+        let (mut nomkstream, mut trim, mut approximate, mut limit) = (false, None, false, None);
+
+        // Options come first, up to the id
+        let id = loop {
+            let arg = parse.next_bytes()?;
+            match &arg.to_ascii_uppercase()[..] {
+                b"NOMKSTREAM" => nomkstream = true,
+                option @ (b"MAXLEN" | b"MINID") => {
+                    let mut threshold = parse.next_bytes()?;
+                    if &threshold[..] == b"~" || &threshold[..] == b"=" {
+                        approximate = &threshold[..] == b"~";
+                        threshold = parse.next_bytes()?;
+                    }
+                    trim = Some(if option == b"MAXLEN" {
+                        match atoi::atoi::<i64>(&threshold) {
+                            Some(len) if len >= 0 => Trim::MaxLen(len as u64),
+                            Some(_) => return Err("The MAXLEN argument must be >= 0.".into()),
+                            None => return Err("value is not an integer or out of range".into()),
+                        }
+                    } else {
+                        Trim::MinId(StreamId::parse(&threshold, 0).ok_or(INVALID_ID)?)
+                    });
+                }
+                b"LIMIT" => match parse.next_signed_int() {
+                    Ok(count) if count >= 0 => limit = Some(count as u64),
+                    Ok(_) => return Err("The LIMIT argument must be >= 0.".into()),
+                    Err(_) => return Err("value is not an integer or out of range".into()),
+                },
+                _ => break NewId::parse(&arg).ok_or(INVALID_ID)?,
+            }
+        };
+
+        let mut fields = vec![];
+        loop {
+            let field = match parse.next_bytes() {
+                Ok(field) => field,
+                Err(CommandParseError::EndOfStream) if !fields.is_empty() => break,
+                Err(CommandParseError::EndOfStream) => {
+                    return Err("wrong number of arguments for 'xadd' command".into())
+                }
+                Err(err) => return Err(err),
+            };
+            let value = match parse.next_bytes() {
+                Err(CommandParseError::EndOfStream) => {
+                    return Err("wrong number of arguments for 'xadd' command".into())
+                }
+                value => value?,
+            };
+            fields.push((field, value));
+        }
+
+        if limit.is_some() && !approximate {
+            return Err("syntax error, LIMIT cannot be used without the special ~ option".into());
+        }
+        let trim = trim.map(|trim| XTrim {
+            trim,
+            approximate,
+            limit: limit.unwrap_or(0),
+        });
+        Ok(XAdd {
+            key,
+            nomkstream,
+            trim,
+            id,
+            fields,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct XAutoClaim {
+    pub key: String,
+    pub group: String,
+    pub consumer: String,
+    /// Only the entries delivered for at least this time in milliseconds are
+    /// claimed
+    pub min_idle: u64,
+    pub start: StreamId,
+    pub count: usize,
+    pub justid: bool,
+}
+
+impl XAutoClaim {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<XAutoClaim, CommandParseError> {
+        let key = parse.next_string()?;
+        let group = parse.next_string()?;
+        let consumer = parse.next_string()?;
+        let min_idle = parse_millis(parse)?;
+        let start = parse_range_bound(&parse.next_bytes()?, true)?;
+        let mut xautoclaim = XAutoClaim {
+            key,
+            group,
+            consumer,
+            min_idle,
+            start,
+            count: 100,
+            justid: false,
+        };
+        loop {
+            let option = match parse.next_string() {
+                Ok(s) => s.to_uppercase(),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            match &option[..] {
+                "COUNT" => match parse.next_signed_int() {
+                    Ok(count) if count > 0 && count <= i64::MAX / 10 => {
+                        xautoclaim.count = count as usize
+                    }
+                    _ => return Err("COUNT must be > 0".into()),
+                },
+                "JUSTID" => xautoclaim.justid = true,
+                _ => return Err("syntax error".into()),
+            }
+        }
+        Ok(xautoclaim)
+    }
+}
+
+#[derive(Debug)]
+pub struct XClaim {
+    pub key: String,
+    pub group: String,
+    pub consumer: String,
+    /// Only the entries delivered for at least this time in milliseconds are
+    /// claimed
+    pub min_idle: u64,
+    pub ids: Vec<StreamId>,
+    /// Time of the delivery, in milliseconds since now (`IDLE`) or since the
+    /// epoch (`TIME`)
+    pub idle: Option<u64>,
+    pub time: Option<u64>,
+    /// Number of deliveries, instead of incrementing it
+    pub retry_count: Option<u64>,
+    /// Also claim the entries of the stream which are not pending
+    pub force: bool,
+    /// Reply with the ids, the number of deliveries is not incremented
+    pub justid: bool,
+    /// Last delivered id of the group, when greater
+    pub last_id: Option<StreamId>,
+}
+
+impl XClaim {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<XClaim, CommandParseError> {
+        let key = parse.next_string()?;
+        let group = parse.next_string()?;
+        let consumer = parse.next_string()?;
+        let min_idle = parse_millis(parse)?;
+        let mut xclaim = XClaim {
+            key,
+            group,
+            consumer,
+            min_idle,
+            ids: vec![],
+            idle: None,
+            time: None,
+            retry_count: None,
+            force: false,
+            justid: false,
+            last_id: None,
+        };
+
+        // Ids come first, up to the first option
+        let mut option = loop {
+            match parse.next_bytes() {
+                Ok(arg) => match StreamId::parse(&arg, 0) {
+                    Some(id) => xclaim.ids.push(id),
+                    None if xclaim.ids.is_empty() => return Err(INVALID_ID.into()),
+                    None => break Some(arg),
+                },
+                Err(CommandParseError::EndOfStream) if !xclaim.ids.is_empty() => break None,
+                Err(err) => return Err(err),
+            }
+        };
+        while let Some(arg) = option {
+            match &arg.to_ascii_uppercase()[..] {
+                b"IDLE" => xclaim.idle = Some(parse_millis(parse)?),
+                b"TIME" => xclaim.time = Some(parse_millis(parse)?),
+                b"RETRYCOUNT" => xclaim.retry_count = Some(parse_millis(parse)?),
+                b"FORCE" => xclaim.force = true,
+                b"JUSTID" => xclaim.justid = true,
+                b"LASTID" => {
+                    let id = StreamId::parse(&parse.next_bytes()?, 0).ok_or(INVALID_ID)?;
+                    xclaim.last_id = Some(id);
+                }
+                _ => return Err("Unrecognized XCLAIM option".into()),
+            }
+            option = match parse.next_bytes() {
+                Ok(arg) => Some(arg),
+                Err(CommandParseError::EndOfStream) => None,
+                Err(err) => return Err(err),
+            };
+        }
+        Ok(xclaim)
+    }
+}
+
+#[derive(Debug)]
+pub struct XDel {
+    pub key: String,
+    pub ids: Vec<StreamId>,
+}
+
+impl XDel {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<XDel, CommandParseError> {
+        let key = parse.next_string()?;
+        let ids = parse_stream_ids(parse)?;
+        Ok(XDel { key, ids })
+    }
+}
+
+/// Subcommands of `XGROUP`, ids being `None` for `$`, the last id of the
+/// stream
+#[derive(Debug)]
+pub enum XGroupAction {
+    Create {
+        id: Option<StreamId>,
+        mkstream: bool,
+    },
+    CreateConsumer(String),
+    DelConsumer(String),
+    Destroy,
+    SetId(Option<StreamId>),
+}
+
+#[derive(Debug)]
+pub struct XGroup {
+    pub key: String,
+    pub group: String,
+    pub action: XGroupAction,
+}
+
+impl XGroup {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<XGroup, CommandParseError> {
+        let subcommand = parse.next_string()?.to_lowercase();
+        if ![
+            "create",
+            "createconsumer",
+            "delconsumer",
+            "destroy",
+            "setid",
+        ]
+        .contains(&&subcommand[..])
+        {
+            return Err(format!("unknown subcommand '{}'. Try XGROUP HELP.", subcommand).into());
+        }
+        let msg = format!(
+            "wrong number of arguments for 'xgroup|{}' command",
+            subcommand
+        );
+        let missing = |err| match err {
+            CommandParseError::EndOfStream => msg.clone().into(),
+            err => err,
+        };
+        let key = parse.next_string().map_err(missing)?;
+        let group = parse.next_string().map_err(missing)?;
+        let mut parse_id = || -> Result<Option<StreamId>, CommandParseError> {
+            match &parse.next_bytes().map_err(missing)?[..] {
+                b"$" => Ok(None),
+                id => Ok(Some(StreamId::parse(id, 0).ok_or(INVALID_ID)?)),
+            }
+        };
+        let action = match &subcommand[..] {
+            "create" => {
+                let id = parse_id()?;
+                let mkstream = match parse.next_string() {
+                    Ok(option) if option.eq_ignore_ascii_case("mkstream") => true,
+                    Ok(_) => return Err("syntax error".into()),
+                    Err(CommandParseError::EndOfStream) => false,
+                    Err(err) => return Err(err),
+                };
+                XGroupAction::Create { id, mkstream }
+            }
+            "createconsumer" => XGroupAction::CreateConsumer(parse.next_string().map_err(missing)?),
+            "delconsumer" => XGroupAction::DelConsumer(parse.next_string().map_err(missing)?),
+            "destroy" => XGroupAction::Destroy,
+            _ => XGroupAction::SetId(parse_id()?),
+        };
+        Ok(XGroup { key, group, action })
+    }
+}
+
+#[derive(Debug)]
+pub struct XLen {
+    pub key: String,
+}
+
+impl XLen {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<XLen, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(XLen { key })
+    }
+}
+
+/// Pending entries listed by `XPENDING`, rather than their summary
+#[derive(Debug)]
+pub struct XPendingRange {
+    pub min_idle: u64,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: usize,
+    pub consumer: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct XPending {
+    pub key: String,
+    pub group: String,
+    pub range: Option<XPendingRange>,
+}
+
+impl XPending {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<XPending, CommandParseError> {
+        let key = parse.next_string()?;
+        let group = parse.next_string()?;
+        let mut start = match parse.next_bytes() {
+            Ok(start) => start,
+            Err(CommandParseError::EndOfStream) => {
+                return Ok(XPending {
+                    key,
+                    group,
+                    range: None,
+                })
+            }
+            Err(err) => return Err(err),
+        };
+        let mut min_idle = 0;
+        if start.eq_ignore_ascii_case(b"IDLE") {
+            min_idle = parse_millis(parse)?;
+            start = parse.next_bytes()?;
+        }
+        let start = parse_range_bound(&start, true)?;
+        let end = parse_range_bound(&parse.next_bytes()?, false)?;
+        let count = parse
+            .next_signed_int()
+            .map_err(|_| "value is not an integer or out of range")?;
+        let consumer = match parse.next_string() {
+            Ok(consumer) => Some(consumer),
+            Err(CommandParseError::EndOfStream) => None,
+            Err(err) => return Err(err),
+        };
+        let range = XPendingRange {
+            min_idle,
+            start,
+            end,
+            count: count.max(0) as usize,
+            consumer,
+        };
+        Ok(XPending {
+            key,
+            group,
+            range: Some(range),
+        })
+    }
+}
+
+/// `XRANGE` and `XREVRANGE`, bounds are always given from the lowest id
+#[derive(Debug)]
+pub struct XRange {
+    pub key: String,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: Option<usize>,
+    pub rev: bool,
+}
+
+impl XRange {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser, rev: bool) -> Result<XRange, CommandParseError> {
+        let key = parse.next_string()?;
+        let (first, second) = (parse.next_bytes()?, parse.next_bytes()?);
+        let (start, end) = match rev {
+            true => (second, first),
+            false => (first, second),
+        };
+        let start = parse_range_bound(&start, true)?;
+        let end = parse_range_bound(&end, false)?;
+        let count = match parse.next_string() {
+            Ok(option) if option.eq_ignore_ascii_case("count") => match parse.next_signed_int() {
+                Ok(count) => Some(count.max(0) as usize),
+                Err(_) => return Err("value is not an integer or out of range".into()),
+            },
+            Ok(_) => return Err("syntax error".into()),
+            Err(CommandParseError::EndOfStream) => None,
+            Err(err) => return Err(err),
+        };
+        Ok(XRange {
+            key,
+            start,
+            end,
+            count,
+            rev,
+        })
+    }
+}
+
+/// Consumer reading through a group, with `XREADGROUP`
+#[derive(Clone, Debug)]
+pub struct GroupRead {
+    pub group: String,
+    pub consumer: String,
+    /// The entries are acknowledged once delivered
+    pub noack: bool,
+}
+
+/// Read of a single stream by `XREAD` or `XREADGROUP`, see `XRead::reads`
+#[derive(Clone, Debug)]
+pub struct StreamRead {
+    pub key: String,
+    /// Entries are read after this id, `None` for the entries not read yet:
+    /// the ones added from now on (`$`) or the ones not delivered to the
+    /// group (`>`). The pending entries of the consumer are read instead of
+    /// the stream when reading through a group after an id.
+    pub after: Option<StreamId>,
+    pub count: Option<usize>,
+    pub group: Option<GroupRead>,
+}
+
+/// `XREAD` and `XREADGROUP`
+#[derive(Debug)]
+pub struct XRead {
+    pub count: Option<usize>,
+    /// Zero blocks forever
+    pub block: Option<Duration>,
+    pub group: Option<GroupRead>,
+    /// Keys with the id entries are read after, see `StreamRead::after`
+    pub streams: Vec<(String, Option<StreamId>)>,
+}
+
+impl XRead {
+    pub fn parse_frames(
+        parse: &mut CommandParser,
+        with_group: bool,
+    ) -> Result<XRead, CommandParseError> {
+        let name = if with_group { "xreadgroup" } else { "xread" };
+        let (mut count, mut block, mut group, mut noack) = (None, None, None, false);
+        loop {
+            let option = parse.next_string()?.to_uppercase();
+            match &option[..] {
+                "COUNT" => match parse.next_signed_int() {
+                    Ok(n) if n > 0 => count = Some(n as usize),
+                    Ok(_) => count = None,
+                    Err(_) => return Err("value is not an integer or out of range".into()),
+                },
+                "BLOCK" => match parse.next_signed_int() {
+                    Ok(ms) if ms >= 0 => block = Some(Duration::from_millis(ms as u64)),
+                    Ok(_) => return Err("timeout is negative".into()),
+                    Err(_) => return Err("timeout is not an integer or out of range".into()),
+                },
+                "GROUP" if with_group => {
+                    group = Some((parse.next_string()?, parse.next_string()?));
+                }
+                "GROUP" => return Err(
+                    "The GROUP option is only supported by XREADGROUP. You called XREAD instead."
+                        .into(),
+                ),
+                "NOACK" if with_group => noack = true,
+                "STREAMS" => break,
+                _ => return Err("syntax error".into()),
+            }
+        }
+
+        let mut args = vec![];
+        loop {
+            match parse.next_bytes() {
+                Ok(arg) => args.push(arg),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        if args.is_empty() || args.len() % 2 != 0 {
+            return Err(format!("Unbalanced '{}' list of streams: for each stream key an ID or '{}' must be specified.", name, if with_group { ">" } else { "$" }).into());
+        }
+        let ids = args.split_off(args.len() / 2);
+        let mut streams = Vec::with_capacity(args.len());
+        for (key, id) in args.into_iter().zip(ids) {
+            let key =
+                String::from_utf8(key.to_vec()).map_err(|_| "protocol error; invalid string")?;
+            let after = match (&id[..], with_group) {
+                (b"$", false) | (b">", true) => None,
+                (b"$", true) => {
+                    return Err("The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.".into())
+                }
+                (b">", false) => {
+                    return Err("The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option.".into())
+                }
+                (id, _) => Some(StreamId::parse(id, 0).ok_or(INVALID_ID)?),
+            };
+            streams.push((key, after));
+        }
+
+        let group = match group {
+            Some((group, consumer)) => Some(GroupRead {
+                group,
+                consumer,
+                noack,
+            }),
+            None if with_group => return Err("Missing GROUP option for XREADGROUP".into()),
+            None => None,
+        };
+        Ok(XRead {
+            count,
+            block,
+            group,
+            streams,
+        })
+    }
+
+    /// The read of each stream
+    pub fn reads(&self) -> Vec<StreamRead> {
+        self.streams
+            .iter()
+            .map(|(key, after)| StreamRead {
+                key: key.clone(),
+                after: *after,
+                count: self.count,
+                group: self.group.clone(),
+            })
+            .collect()
+    }
+}
+
+impl StreamRead {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+#[derive(Debug)]
+pub struct XSetId {
+    pub key: String,
+    pub id: StreamId,
+}
+
+impl XSetId {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<XSetId, CommandParseError> {
+        let key = parse.next_string()?;
+        let id = StreamId::parse(&parse.next_bytes()?, 0).ok_or(INVALID_ID)?;
+        Ok(XSetId { key, id })
+    }
+}
+
+/// `NX` and `XX` options
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Existence {
+    /// Only when missing
+    Missing,
+    /// Only when present
+    Present,
+}
+
+/// `GT` and `LT` options of `ZADD`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreComparison {
+    Greater,
+    Less,
+}
+
+impl ScoreComparison {
+    pub fn allows(self, current: f64, score: f64) -> bool {
+        match self {
+            ScoreComparison::Greater => score > current,
+            ScoreComparison::Less => score < current,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ZAdd {
+    pub key: String,
+    pub existence: Option<Existence>,
+    pub comparison: Option<ScoreComparison>,
+    /// Count the updated members along the added ones
+    pub changed: bool,
+    /// Increment the score of a single member, as `ZINCRBY`
+    pub incr: bool,
+    pub pairs: Vec<(f64, Bytes)>,
+}
+
+impl ZAdd {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<ZAdd, CommandParseError> {
+        let key = parse.next_string()?;
+        let mut zadd = ZAdd {
+            key,
+            existence: None,
+            comparison: None,
+            changed: false,
+            incr: false,
+            pairs: vec![],
+        };
+
+        // Options come first, up to the first score
+        let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+        let mut score = loop {
+            let arg = parse.next_bytes()?;
+            match &arg.to_ascii_uppercase()[..] {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                b"GT" => gt = true,
+                b"LT" => lt = true,
+                b"CH" => zadd.changed = true,
+                b"INCR" => zadd.incr = true,
+                _ => break arg,
+            }
+        };
+        loop {
+            let member = match parse.next_bytes() {
+                Err(CommandParseError::EndOfStream) => return Err("syntax error".into()),
+                member => member?,
+            };
+            let value = parse_float(&score).ok_or("value is not a valid float")?;
+            zadd.pairs.push((value, member));
+            score = match parse.next_bytes() {
+                Ok(score) => score,
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+        }
+
+        if nx && xx {
+            return Err("XX and NX options at the same time are not compatible".into());
+        }
+        if [nx, gt, lt].iter().filter(|set| **set).count() > 1 {
+            return Err("GT, LT, and/or NX options at the same time are not compatible".into());
+        }
+        zadd.existence = match (nx, xx) {
+            (true, _) => Some(Existence::Missing),
+            (_, true) => Some(Existence::Present),
+            _ => None,
+        };
+        zadd.comparison = match (gt, lt) {
+            (true, _) => Some(ScoreComparison::Greater),
+            (_, true) => Some(ScoreComparison::Less),
+            _ => None,
+        };
+        if zadd.incr && zadd.pairs.len() > 1 {
+            return Err("INCR option supports a single increment-element pair".into());
+        }
+        Ok(zadd)
+    }
+}
+
+#[derive(Debug)]
+pub struct ZCard {
+    pub key: String,
+}
+
+impl ZCard {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<ZCard, CommandParseError> {
+        let key = parse.next_string()?;
+        Ok(ZCard { key })
+    }
+}
+
+#[derive(Debug)]
+pub struct ZIncrBy {
+    pub key: String,
+    pub increment: f64,
+    pub member: Bytes,
+}
+
+impl ZIncrBy {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<ZIncrBy, CommandParseError> {
+        let key = parse.next_string()?;
+        let increment = parse_float(&parse.next_bytes()?).ok_or("value is not a valid float")?;
+        let member = parse.next_bytes()?;
+        Ok(ZIncrBy {
+            key,
+            increment,
+            member,
+        })
+    }
+}
+
+/// Selection of the members of `ZRANGE`, bounds are always given from the
+/// lowest to the highest
+#[derive(Debug)]
+pub enum ZRangeBy {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+#[derive(Debug)]
+pub struct ZRange {
+    pub key: String,
+    pub by: ZRangeBy,
+    pub rev: bool,
+    /// Offset and count, a negative count meaning all the remaining members
+    pub limit: Option<(i64, i64)>,
+    pub withscores: bool,
+}
+
+impl ZRange {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<ZRange, CommandParseError> {
+        let key = parse.next_string()?;
+        let start = parse.next_bytes()?;
+        let stop = parse.next_bytes()?;
+        let (mut by_score, mut by_lex, mut rev, mut limit, mut withscores) =
+            (false, false, false, None, false);
+
+        loop {
+            let option = match parse.next_string() {
+                Ok(s) => s.to_uppercase(),
+                Err(CommandParseError::EndOfStream) => break,
+                Err(err) => return Err(err),
+            };
+            match &option[..] {
+                "BYSCORE" => by_score = true,
+                "BYLEX" => by_lex = true,
+                "REV" => rev = true,
+                "LIMIT" => {
+                    let offset = parse.next_signed_int();
+                    let count = parse.next_signed_int();
+                    match (offset, count) {
+                        (Ok(offset), Ok(count)) => limit = Some((offset, count)),
+                        _ => return Err("value is not an integer or out of range".into()),
+                    }
+                }
+                "WITHSCORES" => withscores = true,
+                _ => return Err("syntax error".into()),
+            }
+        }
+
+        if by_score && by_lex {
+            return Err("syntax error".into());
+        }
+        if limit.is_some() && !by_score && !by_lex {
+            return Err(
+                "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                    .into(),
+            );
+        }
+        if withscores && by_lex {
+            return Err("syntax error, WITHSCORES not supported in combination with BYLEX".into());
+        }
+
+        // The bounds of a reversed range by score or lex are given from the
+        // highest
+        let (min, max) = if rev && (by_score || by_lex) {
+            (stop, start)
+        } else {
+            (start, stop)
+        };
+        let by = if by_score {
+            match (ScoreBound::parse(&min), ScoreBound::parse(&max)) {
+                (Some(min), Some(max)) => ZRangeBy::Score(min, max),
+                _ => return Err("min or max is not a float".into()),
+            }
+        } else if by_lex {
+            match (LexBound::parse(&min), LexBound::parse(&max)) {
+                (Some(min), Some(max)) => ZRangeBy::Lex(min, max),
+                _ => return Err("min or max not valid string range item".into()),
+            }
+        } else {
+            match (atoi::atoi::<i64>(&min), atoi::atoi::<i64>(&max)) {
+                (Some(start), Some(stop)) => ZRangeBy::Rank(start, stop),
+                _ => return Err("value is not an integer or out of range".into()),
+            }
+        };
+
+        Ok(ZRange {
+            key,
+            by,
+            rev,
+            limit,
+            withscores,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ZRank {
+    pub key: String,
+    pub member: Bytes,
+    /// By decreasing score, as `ZREVRANK`
+    pub rev: bool,
+    pub withscore: bool,
+}
+
+impl ZRank {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser, rev: bool) -> Result<ZRank, CommandParseError> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+        let withscore = match parse.next_string() {
+            Ok(s) if s.to_uppercase() == "WITHSCORE" => true,
+            Ok(_) => return Err("syntax error".into()),
+            Err(CommandParseError::EndOfStream) => false,
+            Err(err) => return Err(err),
+        };
+        Ok(ZRank {
+            key,
+            member,
+            rev,
+            withscore,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ZRem {
+    pub key: String,
+    pub members: Vec<Bytes>,
+}
+
+impl ZRem {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<ZRem, CommandParseError> {
+        let key = parse.next_string()?;
+        let members = parse_fields(parse)?;
+        Ok(ZRem { key, members })
+    }
+}
+
+#[derive(Debug)]
+pub struct ZScore {
+    pub key: String,
+    pub member: Bytes,
+}
+
+impl ZScore {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<ZScore, CommandParseError> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+        Ok(ZScore { key, member })
+    }
+}
+
+#[derive(Debug)]
+pub struct Unwatch;
+
+impl Unwatch {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<Unwatch, CommandParseError> {
+        Ok(Unwatch)
+    }
+}
+
+#[derive(Debug)]
+pub struct Watch {
+    pub keys: Vec<String>,
+}
+
+impl Watch {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Watch, CommandParseError> {
+        Ok(Watch {
+            keys: parse_keys(parse)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Config {
+    /// Options matching any of the glob patterns
+    Get(Vec<Bytes>),
+    /// Options with their new value, all set or none
+    Set(Vec<(String, String)>),
+    Rewrite,
+}
+
+impl Config {
+    pub fn parse_frames(parse: &mut CommandParser) -> Result<Config, CommandParseError> {
+        let subcommand = parse.next_string()?.to_uppercase();
+        let msg = format!(
+            "wrong number of arguments for 'config|{}' command",
+            subcommand.to_lowercase()
+        );
+        match &subcommand[..] {
+            "GET" => {
+                let mut patterns = vec![];
+                loop {
+                    match parse.next_bytes() {
+                        Ok(pattern) => patterns.push(pattern),
+                        Err(CommandParseError::EndOfStream) if !patterns.is_empty() => break,
+                        Err(CommandParseError::EndOfStream) => return Err(msg.into()),
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(Config::Get(patterns))
+            }
+            "SET" => {
+                let mut pairs = vec![];
+                loop {
+                    let name = match parse.next_string() {
+                        Ok(name) => name.to_lowercase(),
+                        Err(CommandParseError::EndOfStream) if !pairs.is_empty() => break,
+                        Err(CommandParseError::EndOfStream) => return Err(msg.into()),
+                        Err(err) => return Err(err),
+                    };
+                    let value = match parse.next_string() {
+                        Err(CommandParseError::EndOfStream) => return Err(msg.into()),
+                        value => value?,
+                    };
+                    pairs.push((name, value));
+                }
+                Ok(Config::Set(pairs))
+            }
+            "REWRITE" => Ok(Config::Rewrite),
+            _ => Err(format!(
+                "unknown subcommand '{}'. Try CONFIG HELP.",
+                subcommand.to_lowercase()
+            )
+            .into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Discard;
+
+impl Discard {
+    pub fn parse_frames(_parse: &mut CommandParser) -> Result<Discard, CommandParseError> {
+        Ok(Discard)
+    }
+}
+
+#[derive(Debug)]
+pub struct Unknown {
+    pub command_name: String,
+}
+
+impl Unknown {
+    pub fn new(key: impl ToString) -> Unknown {
         Unknown {
             command_name: key.to_string(),
         }
@@ -246,3 +4508,39 @@ impl Unknown {
         &self.command_name
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::request;
+
+    #[test]
+    fn parse_timeout_test() {
+        let parse = |timeout| parse_timeout_str(timeout).map_err(|err| err.to_string());
+        assert_eq!(parse("0"), Ok(Duration::ZERO));
+        assert_eq!(parse("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse("-1"), Err("timeout is negative".to_string()));
+        for timeout in ["1e20", "18446744073709551616"] {
+            assert_eq!(parse(timeout), Err("timeout is out of range".to_string()));
+        }
+        assert!(parse("inf").is_err());
+        assert!(parse("one").is_err());
+    }
+
+    #[test]
+    fn bitfield_offset_test() {
+        let parse = |args: &[&str]| Command::from_frame(request(args)).map(|_| ());
+        assert!(parse(&["bitfield", "bf", "INCRBY", "i8", "#2", "1"]).is_ok());
+        for offset in [
+            "18446744073709551615",
+            "#18446744073709551615",
+            "4294967289",
+        ] {
+            let err = parse(&["bitfield", "bf", "INCRBY", "i8", offset, "1"]).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "bit offset is not an integer or out of range"
+            );
+        }
+    }
+}
@@ -1,10 +1,10 @@
-use crate::{Error, Frame, FrameSimple};
+use crate::{Error, Frame};
 use bytes::Bytes;
 use std::{fmt, str, vec};
 
 #[derive(Debug)]
 pub struct CommandParser {
-    parts: vec::IntoIter<FrameSimple>,
+    parts: vec::IntoIter<Frame>,
 }
 
 #[derive(Debug)]
@@ -25,14 +25,14 @@ impl CommandParser {
         })
     }
 
-    fn next(&mut self) -> Result<FrameSimple, CommandParseError> {
+    fn next(&mut self) -> Result<Frame, CommandParseError> {
         self.parts.next().ok_or(CommandParseError::EndOfStream)
     }
 
     pub fn next_string(&mut self) -> Result<String, CommandParseError> {
         match self.next()? {
-            FrameSimple::Simple(s) => Ok(s),
-            FrameSimple::Bulk(data) => str::from_utf8(&data[..])
+            Frame::Simple(s) => Ok(s),
+            Frame::Bulk(data) => str::from_utf8(&data[..])
                 .map(|s| s.to_string())
                 .map_err(|_| "protocol error; invalid string".into()),
             frame => Err(format!(
@@ -45,8 +45,8 @@ impl CommandParser {
 
     pub fn next_bytes(&mut self) -> Result<Bytes, CommandParseError> {
         match self.next()? {
-            FrameSimple::Simple(s) => Ok(Bytes::from(s.into_bytes())),
-            FrameSimple::Bulk(data) => Ok(data),
+            Frame::Simple(s) => Ok(Bytes::from(s.into_bytes())),
+            Frame::Bulk(data) => Ok(data),
             frame => Err(format!(
                 "protocol error; expected simple frame or bulk frame, got {:?}",
                 frame
@@ -61,9 +61,22 @@ impl CommandParser {
         const MSG: &str = "protocol error; invalid number";
 
         match self.next()? {
-            FrameSimple::Integer(v) => Ok(v),
-            FrameSimple::Simple(data) => atoi::<u64>(data.as_bytes()).ok_or_else(|| MSG.into()),
-            FrameSimple::Bulk(data) => atoi::<u64>(&data).ok_or_else(|| MSG.into()),
+            Frame::Integer(v) => v.try_into().map_err(|_| MSG.into()),
+            Frame::Simple(data) => atoi::<u64>(data.as_bytes()).ok_or_else(|| MSG.into()),
+            Frame::Bulk(data) => atoi::<u64>(&data).ok_or_else(|| MSG.into()),
+            frame => Err(format!("protocol error; expected int frame but got {:?}", frame).into()),
+        }
+    }
+
+    pub fn next_signed_int(&mut self) -> Result<i64, CommandParseError> {
+        use atoi::atoi;
+
+        const MSG: &str = "protocol error; invalid number";
+
+        match self.next()? {
+            Frame::Integer(v) => Ok(v),
+            Frame::Simple(data) => atoi::<i64>(data.as_bytes()).ok_or_else(|| MSG.into()),
+            Frame::Bulk(data) => atoi::<i64>(&data).ok_or_else(|| MSG.into()),
             frame => Err(format!("protocol error; expected int frame but got {:?}", frame).into()),
         }
     }
@@ -77,6 +90,14 @@ impl CommandParser {
     }
 }
 
+/// Parse a float as redis does, `inf` is valid but `nan` is not
+pub fn parse_float(data: &[u8]) -> Option<f64> {
+    str::from_utf8(data)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|v| !v.is_nan())
+}
+
 impl From<String> for CommandParseError {
     fn from(src: String) -> CommandParseError {
         CommandParseError::Other(src.into())
@@ -0,0 +1,615 @@
+use crate::aof::FsyncPolicy;
+use crate::args::split_args;
+use crate::error::Error;
+use crate::eviction::{parse_memory, EvictionPolicy, MaxMemory};
+use crate::glob::glob_match;
+use crate::log::LogLevel;
+use crate::pubsub::NotifyEvents;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Options in the order of `CONFIG GET *`, with whether `CONFIG SET` can
+/// change them at runtime
+const OPTIONS: &[(&str, bool)] = &[
+    ("bind", false),
+    ("port", false),
+    ("loglevel", true),
+    ("dir", true),
+    ("dbfilename", true),
+    ("save", true),
+    ("appendonly", false),
+    ("appendfilename", false),
+    ("appendfsync", false),
+    ("databases", false),
+    ("shards", false),
+    ("maxmemory", true),
+    ("maxmemory-policy", true),
+    ("maxmemory-samples", true),
+    ("replicaof", false),
+    ("replica-read-only", true),
+    ("repl-backlog-size", false),
+    ("slowlog-log-slower-than", true),
+    ("slowlog-max-len", true),
+    ("notify-keyspace-events", true),
+    ("cluster-enabled", false),
+    ("cluster-nodes", false),
+    ("requirepass", true),
+    ("aclfile", false),
+];
+
+/// Server settings, named after their `redis.conf` counterpart.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// File the configuration was loaded from, for `CONFIG REWRITE`
+    pub config_file: Option<PathBuf>,
+    /// Addresses to listen on
+    pub bind: Vec<String>,
+    pub port: u16,
+    /// Messages less severe are not logged
+    pub loglevel: LogLevel,
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    /// Snapshot after `seconds` if at least `changes` writes were performed
+    pub save: Vec<SavePoint>,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: FsyncPolicy,
+    /// Number of logical databases
+    pub databases: usize,
+    /// Number of tasks the keys are spread over
+    pub shards: usize,
+    /// Memory limit in bytes, 0 when there is none
+    pub maxmemory: u64,
+    pub maxmemory_policy: EvictionPolicy,
+    pub maxmemory_samples: usize,
+    /// Host and port of the primary, when a replica
+    pub replicaof: Option<(String, u16)>,
+    /// Replicas refuse the writes of their clients
+    pub replica_read_only: bool,
+    /// Bytes of the last writes kept for the replicas to resume from
+    pub repl_backlog_size: u64,
+    /// Commands slower than this many microseconds are logged, none when
+    /// negative
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: usize,
+    /// Classes of keyspace events published by the shards
+    pub notify_keyspace_events: NotifyEvents,
+    pub cluster_enabled: bool,
+    /// Address of every node of the cluster, as `host:port`
+    pub cluster_nodes: Vec<(String, u16)>,
+    /// Password of the default user, none when empty
+    pub requirepass: String,
+    /// File the ACL users are loaded from
+    pub aclfile: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            config_file: None,
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            loglevel: LogLevel::Notice,
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            save: vec![
+                SavePoint {
+                    seconds: 3600,
+                    changes: 1,
+                },
+                SavePoint {
+                    seconds: 300,
+                    changes: 100,
+                },
+                SavePoint {
+                    seconds: 60,
+                    changes: 10000,
+                },
+            ],
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            databases: 16,
+            shards: 128,
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            maxmemory_samples: 5,
+            replicaof: None,
+            replica_read_only: true,
+            repl_backlog_size: 1024 * 1024,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            notify_keyspace_events: NotifyEvents::default(),
+            cluster_enabled: false,
+            cluster_nodes: vec![],
+            requirepass: String::new(),
+            aclfile: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Build the configuration from command line arguments, the path of a
+    /// config file followed by `--name value` overrides
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, Error> {
+        let mut args = args.peekable();
+        let mut config = Self::default();
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            let path = PathBuf::from(path);
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|err| format!("unable to read '{}': {}", path.display(), err))?;
+            config
+                .load(&contents)
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            config.config_file = Some(path);
+        }
+
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("unexpected argument '{}'", arg))?;
+            // As in redis, the values up to the next option are joined
+            let mut values = vec![];
+            while let Some(value) = args.next_if(|value| !value.starts_with("--")) {
+                values.push(value);
+            }
+            if values.is_empty() {
+                return Err(format!("missing value for '{}'", arg).into());
+            }
+            config.set(name, &values.join(" "))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Apply the directives of a `redis.conf` file, one per line
+    fn load(&mut self, contents: &str) -> Result<(), Error> {
+        let mut saves = 0;
+        for (index, line) in contents.lines().enumerate() {
+            let args =
+                split_directive(line).map_err(|err| format!("line {}: {}", index + 1, err))?;
+            let Some((name, values)) = args.split_first() else {
+                continue;
+            };
+            if name.starts_with('#') {
+                continue;
+            }
+            let value = values.join(" ");
+            let name = name.to_lowercase();
+            // Save points accumulate over lines, replacing the defaults
+            let value = match &name[..] {
+                "save" if !value.is_empty() && saves > 0 => {
+                    format!("{} {}", self.get("save").unwrap(), value)
+                }
+                _ => value,
+            };
+            saves += (name == "save") as usize;
+            self.set(&name, &value)
+                .map_err(|err| format!("line {}: {}", index + 1, err))?;
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        match &name.to_lowercase()[..] {
+            "bind" => {
+                self.bind = value.split_whitespace().map(str::to_string).collect();
+                if self.bind.is_empty() {
+                    return Err("at least one bind address is required".into());
+                }
+            }
+            "port" => {
+                self.port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port '{}'", value))?
+            }
+            "loglevel" => self.loglevel = LogLevel::parse(value)?,
+            "dir" => self.dir = PathBuf::from(value),
+            "dbfilename" => self.dbfilename = value.to_string(),
+            "save" => self.save = parse_save_points(value)?,
+            "appendonly" => self.appendonly = parse_bool(value)?,
+            "appendfilename" => self.appendfilename = value.to_string(),
+            "appendfsync" => self.appendfsync = FsyncPolicy::parse(value)?,
+            "databases" => {
+                self.databases = match value.parse() {
+                    Ok(databases) if databases > 0 => databases,
+                    _ => return Err("invalid number of databases".into()),
+                }
+            }
+            "shards" => {
+                self.shards = match value.parse() {
+                    Ok(shards) if shards > 0 => shards,
+                    _ => return Err("invalid number of shards".into()),
+                }
+            }
+            "maxmemory" => self.maxmemory = parse_memory(value)?,
+            "maxmemory-policy" => self.maxmemory_policy = EvictionPolicy::parse(value)?,
+            "maxmemory-samples" => {
+                self.maxmemory_samples = match value.parse() {
+                    Ok(samples) if samples > 0 => samples,
+                    _ => return Err("invalid number of maxmemory samples".into()),
+                }
+            }
+            "replicaof" | "slaveof" => {
+                self.replicaof = match value.split_whitespace().collect::<Vec<_>>()[..] {
+                    [] => None,
+                    [host, port] => match port.parse() {
+                        Ok(port) => Some((host.to_string(), port)),
+                        Err(_) => return Err(format!("invalid port '{}'", port).into()),
+                    },
+                    _ => return Err("replicaof needs a host and a port".into()),
+                }
+            }
+            "replica-read-only" | "slave-read-only" => self.replica_read_only = parse_bool(value)?,
+            "repl-backlog-size" => {
+                self.repl_backlog_size = match parse_memory(value)? {
+                    0 => return Err("invalid backlog size".into()),
+                    size => size,
+                }
+            }
+            "slowlog-log-slower-than" => {
+                self.slowlog_log_slower_than = value
+                    .parse()
+                    .map_err(|_| format!("invalid slowlog threshold '{}'", value))?
+            }
+            "slowlog-max-len" => {
+                self.slowlog_max_len = value
+                    .parse()
+                    .map_err(|_| format!("invalid slowlog length '{}'", value))?
+            }
+            "notify-keyspace-events" => self.notify_keyspace_events = NotifyEvents::parse(value)?,
+            "cluster-enabled" => self.cluster_enabled = parse_bool(value)?,
+            "cluster-nodes" => {
+                self.cluster_nodes = value
+                    .split_whitespace()
+                    .map(|node| {
+                        node.rsplit_once(':')
+                            .and_then(|(host, port)| Some((host.to_string(), port.parse().ok()?)))
+                            .ok_or_else(|| format!("invalid node address '{}'", node).into())
+                    })
+                    .collect::<Result<_, Error>>()?
+            }
+            "requirepass" => self.requirepass = value.to_string(),
+            "aclfile" => {
+                self.aclfile = match value {
+                    "" => None,
+                    path => Some(PathBuf::from(path)),
+                }
+            }
+            _ => return Err(format!("unknown option '{}'", name).into()),
+        }
+        Ok(())
+    }
+
+    /// Value of an option, as replied to `CONFIG GET`
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "bind" => self.bind.join(" "),
+            "port" => self.port.to_string(),
+            "loglevel" => self.loglevel.name().to_string(),
+            "dir" => self.dir.display().to_string(),
+            "dbfilename" => self.dbfilename.clone(),
+            "save" => self
+                .save
+                .iter()
+                .map(|point| format!("{} {}", point.seconds, point.changes))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "appendonly" => format_bool(self.appendonly),
+            "appendfilename" => self.appendfilename.clone(),
+            "appendfsync" => self.appendfsync.name().to_string(),
+            "databases" => self.databases.to_string(),
+            "shards" => self.shards.to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.name().to_string(),
+            "maxmemory-samples" => self.maxmemory_samples.to_string(),
+            "replicaof" => match &self.replicaof {
+                Some((host, port)) => format!("{} {}", host, port),
+                None => String::new(),
+            },
+            "replica-read-only" => format_bool(self.replica_read_only),
+            "repl-backlog-size" => self.repl_backlog_size.to_string(),
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than.to_string(),
+            "slowlog-max-len" => self.slowlog_max_len.to_string(),
+            "notify-keyspace-events" => self.notify_keyspace_events.to_string(),
+            "cluster-enabled" => format_bool(self.cluster_enabled),
+            "cluster-nodes" => self
+                .cluster_nodes
+                .iter()
+                .map(|(host, port)| format!("{}:{}", host, port))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "requirepass" => self.requirepass.clone(),
+            "aclfile" => self
+                .aclfile
+                .as_ref()
+                .map_or(String::new(), |path| path.display().to_string()),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Options matching the glob pattern, with their value
+    pub fn matching(&self, pattern: &[u8]) -> Vec<(&'static str, String)> {
+        let pattern = pattern.to_ascii_lowercase();
+        OPTIONS
+            .iter()
+            .filter(|(name, _)| glob_match(&pattern, name.as_bytes()))
+            .map(|(name, _)| (*name, self.get(name).unwrap()))
+            .collect()
+    }
+
+    /// `CONFIG SET`, the options are only changed when all the values are
+    /// valid
+    pub fn set_at_runtime(&mut self, pairs: &[(String, String)]) -> Result<(), String> {
+        let mut config = self.clone();
+        for (name, value) in pairs {
+            match OPTIONS.iter().find(|(option, _)| option == name) {
+                Some((_, true)) => {}
+                Some((_, false)) => {
+                    return Err(format!(
+                        "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                        name
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                        name
+                    ))
+                }
+            }
+            if let Err(err) = config.set(name, value) {
+                return Err(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                    name, err
+                ));
+            }
+        }
+        *self = config;
+        Ok(())
+    }
+
+    /// `CONFIG REWRITE`, the directives of the config file are replaced by
+    /// the current values, and the changed options missing from the file
+    /// are appended
+    pub fn rewrite(&self) -> Result<(), Error> {
+        let path = self
+            .config_file
+            .as_ref()
+            .ok_or("The server is running without a config file")?;
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut rewritten = HashSet::new();
+        let mut lines = vec![];
+        for line in contents.lines() {
+            let name = split_directive(line)
+                .ok()
+                .and_then(|args| args.first().map(|name| name.to_lowercase()));
+            match OPTIONS
+                .iter()
+                .find(|(option, _)| Some(*option) == name.as_deref())
+            {
+                // Only the first directive of an option is kept
+                Some((name, _)) => {
+                    if rewritten.insert(*name) {
+                        lines.extend(self.directives(name));
+                    }
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+
+        let default = Self::default();
+        let mut generated = OPTIONS
+            .iter()
+            .filter(|(name, _)| !rewritten.contains(name) && self.get(name) != default.get(name))
+            .flat_map(|(name, _)| self.directives(name))
+            .peekable();
+        if generated.peek().is_some() {
+            lines.push("# Generated by CONFIG REWRITE".to_string());
+            lines.extend(generated);
+        }
+
+        let temp = path.with_extension(format!("rewrite-{}", std::process::id()));
+        std::fs::write(&temp, lines.join("\n") + "\n")?;
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    /// Lines setting an option in a config file
+    fn directives(&self, name: &str) -> Vec<String> {
+        match name {
+            "save" if self.save.is_empty() => vec!["save \"\"".to_string()],
+            "save" => self
+                .save
+                .iter()
+                .map(|point| format!("save {} {}", point.seconds, point.changes))
+                .collect(),
+            "bind" => vec![format!("bind {}", self.bind.join(" "))],
+            "replicaof" => match &self.replicaof {
+                Some((host, port)) => vec![format!("replicaof {} {}", quote(host), port)],
+                None => vec![],
+            },
+            name => vec![format!("{} {}", name, quote(&self.get(name).unwrap()))],
+        }
+    }
+
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.appendfilename)
+    }
+
+    pub fn max_memory(&self) -> MaxMemory {
+        MaxMemory {
+            limit: self.maxmemory,
+            policy: self.maxmemory_policy,
+            samples: self.maxmemory_samples,
+        }
+    }
+}
+
+fn format_bool(value: bool) -> String {
+    match value {
+        true => "yes".to_string(),
+        false => "no".to_string(),
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, Error> {
+    match &value.to_lowercase()[..] {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".into()),
+    }
+}
+
+fn parse_save_points(value: &str) -> Result<Vec<SavePoint>, Error> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if !parts.len().is_multiple_of(2) {
+        return Err("invalid save parameters".into());
+    }
+
+    parts
+        .chunks(2)
+        .map(|pair| match (pair[0].parse(), pair[1].parse()) {
+            (Ok(seconds), Ok(changes)) => Ok(SavePoint { seconds, changes }),
+            _ => Err("invalid save parameters".into()),
+        })
+        .collect()
+}
+
+/// Split a directive of a config or ACL file, see `args::split_args`
+pub fn split_directive(line: &str) -> Result<Vec<String>, Error> {
+    split_args(line)?
+        .into_iter()
+        .map(|arg| String::from_utf8(arg).map_err(|_| "invalid UTF-8 argument".into()))
+        .collect()
+}
+
+/// Quote an argument for `split_directive` when needed
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+        return arg.to_string();
+    }
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_args_test() {
+        let args = ["--save", "900 1 300 10", "--dbfilename", "other.rdb"];
+        let config = ServerConfig::from_args(args.iter().map(|a| a.to_string())).unwrap();
+
+        assert_eq!(
+            config.save,
+            vec![
+                SavePoint {
+                    seconds: 900,
+                    changes: 1
+                },
+                SavePoint {
+                    seconds: 300,
+                    changes: 10
+                }
+            ]
+        );
+        assert_eq!(config.rdb_path(), PathBuf::from("./other.rdb"));
+
+        let config = ServerConfig::from_args(["--save", ""].iter().map(|a| a.to_string()));
+        assert!(config.unwrap().save.is_empty());
+        assert!(ServerConfig::from_args(["--save", "1"].iter().map(|a| a.to_string())).is_err());
+
+        let args = ["--save", "900", "1", "300", "10", "--port", "7002"];
+        let config = ServerConfig::from_args(args.iter().map(|a| a.to_string())).unwrap();
+        assert_eq!(config.get("save").unwrap(), "900 1 300 10");
+        assert_eq!(config.port, 7002);
+        assert!(ServerConfig::from_args(["--port"].iter().map(|a| a.to_string())).is_err());
+
+        let args = ["--replicaof", "127.0.0.1", "7001"];
+        let config = ServerConfig::from_args(args.iter().map(|a| a.to_string())).unwrap();
+        assert_eq!(config.replicaof, Some(("127.0.0.1".to_string(), 7001)));
+    }
+
+    #[test]
+    fn load_test() {
+        let mut config = ServerConfig::default();
+        let contents = "# comment\n\nport 7000\nsave 900 1\nsave 300 10\ndir \"/tmp/with space\"\nMAXMEMORY 1mb\n";
+        config.load(contents).unwrap();
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.get("save").unwrap(), "900 1 300 10");
+        assert_eq!(config.dir, PathBuf::from("/tmp/with space"));
+        assert_eq!(config.maxmemory, 1024 * 1024);
+        assert!(config.load("port abc").is_err());
+        assert!(config.load("unknown 1").is_err());
+
+        let names: Vec<_> = config
+            .matching(b"max*")
+            .into_iter()
+            .map(|(n, _)| n)
+            .collect();
+        assert_eq!(
+            names,
+            ["maxmemory", "maxmemory-policy", "maxmemory-samples"]
+        );
+
+        assert!(config
+            .set_at_runtime(&[("port".to_string(), "1".to_string())])
+            .is_err());
+        let pairs = [
+            ("maxmemory".to_string(), "2mb".to_string()),
+            ("maxmemory-policy".to_string(), "invalid".to_string()),
+        ];
+        assert!(config.set_at_runtime(&pairs).is_err());
+        assert_eq!(config.maxmemory, 1024 * 1024);
+    }
+
+    #[test]
+    fn quote_test() {
+        for arg in ["a", "", "a \"b\"\n", "it's"] {
+            assert_eq!(split_directive(&quote(arg)).unwrap(), [arg]);
+        }
+    }
+
+    #[test]
+    fn rewrite_test() {
+        let path = std::env::temp_dir().join(format!("rewrite-test-{}.conf", std::process::id()));
+        std::fs::write(&path, "# kept\nsave 900 1\nsave 300 10\nport 7000\n").unwrap();
+        let args = [
+            path.display().to_string(),
+            "--port".to_string(),
+            "7001".to_string(),
+        ];
+        let mut config = ServerConfig::from_args(args.into_iter()).unwrap();
+        config.maxmemory = 100;
+        config.save.clear();
+        config.rewrite().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "# kept\nsave \"\"\nport 7001\n# Generated by CONFIG REWRITE\nmaxmemory 100\n"
+        );
+    }
+}
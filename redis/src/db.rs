@@ -0,0 +1,457 @@
+use crate::eviction::{Access, EvictionPolicy};
+use crate::stream::Stream;
+use crate::zset::SortedSet;
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current unix time in milliseconds
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Random number, seeded differently on each call
+pub fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Estimated memory of a key beyond its name and value, in bytes
+const ENTRY_OVERHEAD: usize = 64;
+/// Estimated memory of an element of a collection beyond its content
+const ELEMENT_OVERHEAD: usize = 16;
+/// Elements of a collection measured to estimate its memory, the same
+/// default as `MEMORY USAGE`
+const MEMORY_SAMPLES: usize = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(Vec<u8>),
+    List(VecDeque<Bytes>),
+    Hash(HashMap<Bytes, Bytes>),
+    Set(HashSet<Bytes>),
+    ZSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// Name of the type, as replied to `TYPE`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Estimated memory in bytes, extrapolated from a few elements for the
+    /// collections
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            Value::String(value) => value.len(),
+            Value::List(list) => sampled(list.len(), list.iter().map(|e| e.len())),
+            Value::Hash(hash) => sampled(
+                hash.len(),
+                hash.iter()
+                    .map(|(field, value)| field.len() + value.len() + ELEMENT_OVERHEAD),
+            ),
+            Value::Set(set) => sampled(set.len(), set.iter().map(|m| m.len())),
+            // Members are both indexed and ordered
+            Value::ZSet(zset) => sampled(
+                zset.len(),
+                zset.iter().map(|(m, _)| 2 * m.len() + ELEMENT_OVERHEAD + 8),
+            ),
+            Value::Stream(stream) => {
+                sampled(
+                    stream.len(),
+                    stream.entries().map(|(_, fields)| {
+                        fields
+                            .iter()
+                            .map(|(field, value)| field.len() + value.len() + ELEMENT_OVERHEAD)
+                            .sum()
+                    }),
+                ) + stream.pending_len() * ELEMENT_OVERHEAD
+            }
+        }
+    }
+}
+
+/// Memory of `len` elements from the size of the first ones
+fn sampled(len: usize, sizes: impl Iterator<Item = usize>) -> usize {
+    let (count, total) = sizes
+        .take(MEMORY_SAMPLES)
+        .fold((0, 0), |(count, total), size| (count + 1, total + size));
+    match count {
+        0 => 0,
+        count => (total / count + ELEMENT_OVERHEAD) * len,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub value: Value,
+    /// Unix timestamp in milliseconds after which the key is gone
+    pub expire_at: Option<u64>,
+}
+
+/// An entry with what is known about its use
+struct Slot {
+    entry: Entry,
+    access: Access,
+    /// Estimated memory, accounted in `Db::used`
+    size: usize,
+}
+
+impl Slot {
+    fn new(key: &str, entry: Entry, now: u64) -> Self {
+        Self {
+            size: ENTRY_OVERHEAD + key.len() + entry.value.memory_usage(),
+            entry,
+            access: Access::new(now),
+        }
+    }
+}
+
+/// Key space of a shard.
+///
+/// Expired keys are removed lazily when they are accessed, and actively by
+/// `active_expire` which walks the keys ordered by expiration time.
+pub struct Db {
+    entries: HashMap<String, Slot>,
+    expires: BTreeSet<(u64, String)>,
+    /// Keys removed since they expired, until taken
+    expired: Vec<String>,
+    /// Estimated memory of the entries, in bytes
+    used: usize,
+}
+
+impl Default for Db {
+    /// An empty key space, without any preallocated capacity
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            expires: BTreeSet::new(),
+            expired: vec![],
+            used: 0,
+        }
+    }
+}
+
+impl Db {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::with_capacity(2048),
+            ..Self::default()
+        }
+    }
+
+    /// The entry at `key`, which counts as an access
+    pub fn get(&mut self, key: &str) -> Option<&Entry> {
+        self.get_mut(key).map(|entry| &*entry)
+    }
+
+    /// The entry at `key`, which counts as an access. Once modified the
+    /// entry has to be measured again with `resize`
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        let now = now_ms();
+        self.expire_if_needed(key, now);
+        let slot = self.entries.get_mut(key)?;
+        slot.access.touch(now);
+        Some(&mut slot.entry)
+    }
+
+    pub fn set(&mut self, key: String, value: Value, expire_at: Option<u64>) {
+        self.remove(&key);
+        if let Some(at) = expire_at {
+            self.expires.insert((at, key.clone()));
+        }
+        let slot = Slot::new(&key, Entry { value, expire_at }, now_ms());
+        self.used += slot.size;
+        self.entries.insert(key, slot);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.unlink(key)?;
+        if let Some(at) = entry.expire_at {
+            self.expires.remove(&(at, key.to_string()));
+        }
+        Some(entry)
+    }
+
+    /// Remove the entry, leaving its expiration
+    fn unlink(&mut self, key: &str) -> Option<Entry> {
+        let slot = self.entries.remove(key)?;
+        self.used -= slot.size;
+        Some(slot.entry)
+    }
+
+    /// Measure again the memory of the entry at `key`, once modified
+    pub fn resize(&mut self, key: &str) {
+        if let Some(slot) = self.entries.get_mut(key) {
+            let size = ENTRY_OVERHEAD + key.len() + slot.entry.value.memory_usage();
+            self.used = self.used - slot.size + size;
+            slot.size = size;
+        }
+    }
+
+    /// Estimated memory of the entries, in bytes
+    pub fn used_memory(&self) -> usize {
+        self.used
+    }
+
+    /// Up to `count` keys which could be evicted under `policy`, with their
+    /// score, the higher the better to evict
+    pub fn sample(&self, policy: EvictionPolicy, count: usize, now: u64) -> Vec<(u64, String)> {
+        let score = |slot: &Slot| match policy {
+            EvictionPolicy::AllKeysLfu => (u8::MAX - slot.access.decayed_frequency(now)) as u64,
+            EvictionPolicy::VolatileTtl => u64::MAX - slot.entry.expire_at.unwrap_or(u64::MAX),
+            EvictionPolicy::AllKeysRandom => random(),
+            EvictionPolicy::NoEviction => 0,
+            EvictionPolicy::AllKeysLru | EvictionPolicy::VolatileLru => {
+                now.saturating_sub(slot.access.at)
+            }
+        };
+
+        match policy {
+            EvictionPolicy::NoEviction => vec![],
+            // The keys expiring first are known
+            EvictionPolicy::VolatileTtl => self
+                .expires
+                .iter()
+                .take(count)
+                .filter_map(|(_, key)| Some((score(self.entries.get(key)?), key.clone())))
+                .collect(),
+            // Contiguous keys from a random position, the order of the keys
+            // being unrelated to their use
+            EvictionPolicy::VolatileLru => {
+                let start = random() as usize % self.expires.len().max(1);
+                self.expires
+                    .iter()
+                    .skip(start)
+                    .chain(self.expires.iter())
+                    .take(count.min(self.expires.len()))
+                    .filter_map(|(_, key)| Some((score(self.entries.get(key)?), key.clone())))
+                    .collect()
+            }
+            _ => {
+                let start = random() as usize % self.entries.len().max(1);
+                self.entries
+                    .iter()
+                    .skip(start)
+                    .chain(self.entries.iter())
+                    .take(count.min(self.entries.len()))
+                    .map(|(key, slot)| (score(slot), key.clone()))
+                    .collect()
+            }
+        }
+    }
+
+    /// Change the expiration of an existing key, returns `false` when the key
+    /// does not exist.
+    pub fn set_expire(&mut self, key: &str, expire_at: Option<u64>) -> bool {
+        self.expire_if_needed(key, now_ms());
+        let entry = match self.entries.get_mut(key) {
+            Some(slot) => &mut slot.entry,
+            None => return false,
+        };
+
+        if let Some(at) = entry.expire_at {
+            self.expires.remove(&(at, key.to_string()));
+        }
+        if let Some(at) = expire_at {
+            self.expires.insert((at, key.to_string()));
+        }
+        entry.expire_at = expire_at;
+        true
+    }
+
+    /// Number of keys, including the expired keys not removed yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of keys with an expiration time
+    pub fn expires_len(&self) -> usize {
+        self.expires.len()
+    }
+
+    /// Entries which are not expired
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        let now = now_ms();
+        self.entries
+            .iter()
+            .map(|(key, slot)| (key, &slot.entry))
+            .filter(move |(_, entry)| entry.expire_at.is_none_or(|at| at > now))
+    }
+
+    /// A key picked at random, expired keys met on the way are removed
+    pub fn random_key(&mut self) -> Option<String> {
+        while !self.is_empty() {
+            let index = random() as usize % self.entries.len();
+            let key = self.entries.keys().nth(index).cloned()?;
+            self.expire_if_needed(&key, now_ms());
+            if self.entries.contains_key(&key) {
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    /// Copy of the entries which are not expired
+    pub fn dump(&self) -> Vec<(String, Entry)> {
+        self.iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Remove up to `limit` expired keys, returns the number of removed keys.
+    pub fn active_expire(&mut self, now: u64, limit: usize) -> usize {
+        let mut removed = 0;
+        while removed < limit {
+            match self.expires.first() {
+                Some((at, _)) if *at <= now => {}
+                _ => break,
+            }
+            if let Some((_, key)) = self.expires.pop_first() {
+                self.unlink(&key);
+                self.expired.push(key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    fn expire_if_needed(&mut self, key: &str, now: u64) {
+        if let Some(Slot {
+            entry: Entry {
+                expire_at: Some(at),
+                ..
+            },
+            ..
+        }) = self.entries.get(key)
+        {
+            if *at <= now {
+                self.remove(key);
+                self.expired.push(key.to_string());
+            }
+        }
+    }
+
+    /// Take the keys removed since they expired
+    pub fn take_expired(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lazy_expire_test() {
+        let mut db = Db::new();
+        let now = now_ms();
+
+        db.set(
+            "past".to_string(),
+            Value::String(b"a".to_vec()),
+            Some(now - 1),
+        );
+        db.set(
+            "future".to_string(),
+            Value::String(b"b".to_vec()),
+            Some(now + 60_000),
+        );
+        db.set("forever".to_string(), Value::String(b"c".to_vec()), None);
+
+        assert!(db.get("past").is_none());
+        assert_eq!(db.get("future").unwrap().expire_at, Some(now + 60_000));
+        assert_eq!(db.get("forever").unwrap().expire_at, None);
+        assert_eq!(db.take_expired(), vec!["past".to_string()]);
+        assert!(db.take_expired().is_empty());
+    }
+
+    #[test]
+    fn active_expire_test() {
+        let mut db = Db::new();
+        let now = now_ms();
+
+        for i in 0..10 {
+            db.set(
+                format!("key{}", i),
+                Value::String(vec![]),
+                Some(now - 10 + i),
+            );
+        }
+        db.set("key5".to_string(), Value::String(vec![]), None);
+
+        assert_eq!(db.active_expire(now, 3), 3);
+        assert_eq!(db.active_expire(now, 100), 6);
+        assert_eq!(db.active_expire(now, 100), 0);
+        assert!(db.get("key5").is_some());
+    }
+
+    #[test]
+    fn set_expire_test() {
+        let mut db = Db::new();
+        let now = now_ms();
+
+        assert!(!db.set_expire("missing", Some(now + 1000)));
+
+        db.set("key".to_string(), Value::String(vec![]), Some(now + 1000));
+        assert!(db.set_expire("key", None));
+        assert_eq!(db.active_expire(now + 2000, 100), 0);
+        assert!(db.get("key").is_some());
+
+        assert!(db.set_expire("key", Some(now + 1000)));
+        assert_eq!(db.active_expire(now + 2000, 100), 1);
+        assert!(db.get("key").is_none());
+    }
+
+    #[test]
+    fn memory_test() {
+        let mut db = Db::new();
+        let now = now_ms();
+
+        db.set("a".to_string(), Value::String(vec![0; 100]), None);
+        let used = db.used_memory();
+        assert!(used >= 100);
+        db.set(
+            "b".to_string(),
+            Value::List(VecDeque::new()),
+            Some(now + 1000),
+        );
+        if let Some(Entry {
+            value: Value::List(list),
+            ..
+        }) = db.get_mut("b")
+        {
+            list.extend((0..1000).map(|_| Bytes::from(vec![0; 10])));
+        }
+        db.resize("b");
+        assert!(db.used_memory() >= used + 10_000);
+
+        assert_eq!(db.sample(EvictionPolicy::VolatileTtl, 5, now).len(), 1);
+        assert_eq!(db.sample(EvictionPolicy::AllKeysLru, 5, now).len(), 2);
+        assert!(db.sample(EvictionPolicy::NoEviction, 5, now).is_empty());
+
+        db.remove("b");
+        assert_eq!(db.used_memory(), used);
+        assert_eq!(db.active_expire(now, 10), 0);
+        db.remove("a");
+        assert_eq!(db.used_memory(), 0);
+    }
+}
@@ -19,7 +19,7 @@ impl fmt::Display for Error {
 
 impl From<String> for Error {
     fn from(src: String) -> Error {
-        Error::Other(src.into())
+        Error::Other(src)
     }
 }
 
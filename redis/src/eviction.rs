@@ -0,0 +1,195 @@
+use crate::db::random;
+use crate::error::Error;
+
+/// Initial frequency counter of a new key, so that it is not evicted before
+/// getting a chance to be accessed
+const LFU_INIT_VAL: u8 = 5;
+/// The higher, the more accesses are needed to increase the counter
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// Minutes after which the counter of an idle key is decremented
+const LFU_DECAY_TIME_MINUTES: u64 = 1;
+/// Best candidates kept between two evictions
+const EVICTION_POOL_SIZE: usize = 16;
+
+pub const OOM: &str = "OOM command not allowed when used memory > 'maxmemory'.";
+
+/// Keys removed once `maxmemory` is reached, named as in `redis.conf`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EvictionPolicy {
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    VolatileLru,
+    VolatileTtl,
+}
+
+impl EvictionPolicy {
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        match &value.to_lowercase()[..] {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+            "allkeys-random" => Ok(EvictionPolicy::AllKeysRandom),
+            "volatile-lru" => Ok(EvictionPolicy::VolatileLru),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            _ => Err(format!("invalid maxmemory policy '{}'", value).into()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        }
+    }
+}
+
+/// Memory limit of the server, `limit` being 0 when there is none
+#[derive(Clone, Copy, Debug)]
+pub struct MaxMemory {
+    pub limit: u64,
+    pub policy: EvictionPolicy,
+    /// Keys sampled for each eviction
+    pub samples: usize,
+}
+
+impl Default for MaxMemory {
+    fn default() -> Self {
+        Self {
+            limit: 0,
+            policy: EvictionPolicy::NoEviction,
+            samples: 5,
+        }
+    }
+}
+
+/// Access statistics of a key, to pick the keys to evict
+#[derive(Clone, Copy, Debug)]
+pub struct Access {
+    /// Unix time of the last access in milliseconds
+    pub at: u64,
+    /// Logarithmic access frequency, decayed over time
+    pub frequency: u8,
+}
+
+impl Access {
+    pub fn new(now: u64) -> Self {
+        Self {
+            at: now,
+            frequency: LFU_INIT_VAL,
+        }
+    }
+
+    pub fn touch(&mut self, now: u64) {
+        self.frequency = self.decayed_frequency(now);
+        if self.frequency < u8::MAX {
+            let base = self.frequency.saturating_sub(LFU_INIT_VAL) as f64;
+            let probability = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+            if (random() as f64 / u64::MAX as f64) < probability {
+                self.frequency += 1;
+            }
+        }
+        self.at = now;
+    }
+
+    /// Frequency counter, decremented for every period the key was idle
+    pub fn decayed_frequency(&self, now: u64) -> u8 {
+        let periods = now.saturating_sub(self.at) / (LFU_DECAY_TIME_MINUTES * 60_000);
+        self.frequency
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+}
+
+/// Keys to evict from the databases of a shard, the higher the score the
+/// better the candidate.
+///
+/// Sampled keys enter the pool when they are better than its worst
+/// candidate, as done by upstream Redis to approximate LRU with few samples.
+#[derive(Default)]
+pub struct EvictionPool {
+    /// Candidates by increasing score, with the index of their database
+    candidates: Vec<(u64, usize, String)>,
+}
+
+impl EvictionPool {
+    pub fn insert(&mut self, score: u64, db: usize, key: String) {
+        if self
+            .candidates
+            .iter()
+            .any(|(_, d, k)| *d == db && *k == key)
+        {
+            return;
+        }
+        if self.candidates.len() == EVICTION_POOL_SIZE {
+            if score <= self.candidates[0].0 {
+                return;
+            }
+            self.candidates.remove(0);
+        }
+        let index = self.candidates.partition_point(|(s, _, _)| *s < score);
+        self.candidates.insert(index, (score, db, key));
+    }
+
+    /// The best candidate, which may have been removed or accessed since
+    /// it was sampled
+    pub fn pop(&mut self) -> Option<(usize, String)> {
+        self.candidates.pop().map(|(_, db, key)| (db, key))
+    }
+}
+
+/// Parse a memory amount with an optional unit, as `100mb`
+pub fn parse_memory(value: &str) -> Result<u64, Error> {
+    let value = value.to_lowercase();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let unit = match &value[split..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid memory amount '{}'", value).into()),
+    };
+    value[..split]
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(unit))
+        .ok_or_else(|| format!("invalid memory amount '{}'", value).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eviction_pool_test() {
+        let mut pool = EvictionPool::default();
+        for score in 0..20 {
+            pool.insert(score, 0, format!("key{}", score));
+        }
+        pool.insert(19, 0, "key19".to_string());
+        pool.insert(2, 1, "low".to_string());
+        assert_eq!(pool.pop(), Some((0, "key19".to_string())));
+        assert_eq!(pool.pop(), Some((0, "key18".to_string())));
+        assert_eq!(pool.candidates.len(), 14);
+        assert_eq!(pool.candidates[0].2, "key4");
+    }
+
+    #[test]
+    fn parse_memory_test() {
+        assert_eq!(parse_memory("100").unwrap(), 100);
+        assert_eq!(parse_memory("1kb").unwrap(), 1024);
+        assert_eq!(parse_memory("2MB").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_memory("1g").unwrap(), 1_000_000_000);
+        assert!(parse_memory("1tb").is_err());
+        assert!(parse_memory("mb").is_err());
+    }
+}
@@ -1,30 +1,62 @@
 use bytes::Bytes;
 use std::convert::TryInto;
-use std::io;
+use std::io::{self, Write};
 use std::num::TryFromIntError;
 use std::string::FromUtf8Error;
 use std::{fmt, str};
-use tokio::io::{AsyncReadExt, AsyncWrite};
-use tokio::{
-    io::{AsyncWriteExt, BufWriter},
-    net::TcpStream,
-};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use crate::buffer::BufferedStream;
 
-#[derive(Clone, Debug)]
+/// A RESP value, RESP3 types are written with their RESP2 counterpart to
+/// RESP2 clients.
+///
+/// * https://redis.io/docs/latest/develop/reference/protocol-spec/
+#[derive(Clone, Debug, PartialEq)]
 pub enum Frame {
-    Simple(FrameSimple),
-    Array(Vec<FrameSimple>),
-}
-
-#[derive(Clone, Debug)]
-pub enum FrameSimple {
     Simple(String),
     Error(String),
-    Integer(u64),
+    Integer(i64),
     Bulk(Bytes),
     Null,
+    /// `*-1`, the null reply of the commands returning arrays (RESP2)
+    NullArray,
+    Array(Vec<Frame>),
+    Map(Vec<(Frame, Frame)>),
+    Set(Vec<Frame>),
+    Double(f64),
+    Boolean(bool),
+    /// Integer out of the range of `Integer`, kept as its decimal digits
+    BigNumber(String),
+    /// Text with its three letters format, such as `txt` or `mkd`
+    Verbatim(String, Bytes),
+    /// Out of band data, such as pub/sub messages
+    Push(Vec<Frame>),
+}
+
+/// Version of the protocol spoken by a connection, negotiated by `HELLO`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn from_version(version: i64) -> Option<Protocol> {
+        match version {
+            2 => Some(Protocol::Resp2),
+            3 => Some(Protocol::Resp3),
+            _ => None,
+        }
+    }
+
+    pub fn version(self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -35,7 +67,7 @@ pub enum FrameParseError {
 
 impl From<String> for FrameParseError {
     fn from(src: String) -> FrameParseError {
-        FrameParseError::Other(src.into())
+        FrameParseError::Other(src)
     }
 }
 
@@ -58,7 +90,7 @@ impl From<TryFromIntError> for FrameParseError {
 }
 
 impl From<std::io::Error> for FrameParseError {
-    fn from(src: std::io::Error) -> FrameParseError {
+    fn from(_src: std::io::Error) -> FrameParseError {
         FrameParseError::Incomplete
     }
 }
@@ -75,70 +107,75 @@ impl fmt::Display for FrameParseError {
 }
 
 impl Frame {
-    fn push(&mut self, simple: FrameSimple) {
-        match self {
-            Frame::Array(vec) => {
-                vec.push(simple);
-            }
-            _ => panic!("not an array frame"),
-        }
+    pub async fn parse<Stream: AsyncReadExt + Unpin>(
+        buffer: &mut BufferedStream<Stream>,
+    ) -> Result<Frame, FrameParseError> {
+        let t = buffer.get_u8().await?;
+        Frame::parse_typed(t, buffer).await
     }
 
-    pub async fn parse<Stream: AsyncReadExt + Unpin>(
+    async fn parse_typed<Stream: AsyncReadExt + Unpin>(
+        t: u8,
         buffer: &mut BufferedStream<Stream>,
     ) -> Result<Frame, FrameParseError> {
-        match buffer.get_u8().await? {
-            b'*' => {
-                let len = buffer.get_decimal().await?.try_into()?;
-                let mut out = Vec::with_capacity(len);
+        match t {
+            b'*' | b'~' | b'>' | b'%' => {
+                let len = buffer.get_decimal().await?;
+                if len == -1 && t == b'*' {
+                    return Ok(Frame::NullArray);
+                }
+                let len: usize = len.try_into()?;
+                let count = if t == b'%' { len * 2 } else { len };
 
-                for _ in 0..len {
+                let mut out = Vec::with_capacity(count);
+                for _ in 0..count {
                     let t = buffer.get_u8().await?;
-                    out.push(FrameSimple::parse(t, buffer).await?);
+                    // Nested aggregates are boxed, as the future would be
+                    // infinitely sized otherwise
+                    let item = if matches!(t, b'*' | b'~' | b'>' | b'%') {
+                        Box::pin(Frame::parse_typed(t, buffer)).await?
+                    } else {
+                        Frame::parse_typed_simple(t, buffer).await?
+                    };
+                    out.push(item);
                 }
 
-                Ok(Frame::Array(out))
-            }
-            simple => FrameSimple::parse(simple, buffer).await.map(Frame::Simple),
-        }
-    }
-
-    pub async fn write(&self, stream: &mut BufWriter<TcpStream>) -> io::Result<()> {
-        match self {
-            Frame::Array(val) => {
-                stream.write_u8(b'*').await?;
-                write_decimal(stream, val.len() as u64).await?;
-
-                for entry in &**val {
-                    entry.write(stream).await?;
-                }
+                Ok(match t {
+                    b'~' => Frame::Set(out),
+                    b'>' => Frame::Push(out),
+                    b'%' => {
+                        let mut pairs = Vec::with_capacity(len);
+                        let mut items = out.into_iter();
+                        while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                            pairs.push((k, v));
+                        }
+                        Frame::Map(pairs)
+                    }
+                    _ => Frame::Array(out),
+                })
             }
-            Frame::Simple(val) => val.write(stream).await?,
+            t => Frame::parse_typed_simple(t, buffer).await,
         }
-
-        stream.flush().await
     }
-}
 
-impl FrameSimple {
-    pub async fn parse<Stream: AsyncReadExt + Unpin>(
+    async fn parse_typed_simple<Stream: AsyncReadExt + Unpin>(
         t: u8,
         buffer: &mut BufferedStream<Stream>,
-    ) -> Result<FrameSimple, FrameParseError> {
+    ) -> Result<Frame, FrameParseError> {
         match t {
             b'+' => {
                 let line = buffer.get_line().await?.to_vec();
                 let string = String::from_utf8(line)?;
-                Ok(FrameSimple::Simple(string))
+                Ok(Frame::Simple(string))
             }
             b'-' => {
                 let line = buffer.get_line().await?.to_vec();
                 let string = String::from_utf8(line)?;
-                Ok(FrameSimple::Error(string))
+                Ok(Frame::Error(string))
             }
             b':' => {
                 let n = buffer.get_decimal().await?;
-                Ok(FrameSimple::Integer(n))
+                Ok(Frame::Integer(n))
             }
             b'$' => {
                 if b'-' == buffer.peek_u8().await? {
@@ -148,69 +185,285 @@ impl FrameSimple {
                         return Err("protocol error; invalid frame format".into());
                     }
 
-                    Ok(FrameSimple::Null)
+                    Ok(Frame::Null)
                 } else {
-                    // Read the bulk string
-                    let len = buffer.get_decimal().await?.try_into()?;
-                    let data = Bytes::copy_from_slice(buffer.take(len).await?.as_slice());
-
-                    // skip that number of bytes + 2 (\r\n).
-                    buffer.skip(2).await?;
-
-                    Ok(FrameSimple::Bulk(data))
+                    Ok(Frame::Bulk(Frame::parse_blob(buffer).await?))
                 }
             }
+            b'_' => {
+                buffer.skip(2).await?;
+                Ok(Frame::Null)
+            }
+            b',' => {
+                let line = String::from_utf8(buffer.get_line().await?)?;
+                let value = match &line.to_lowercase()[..] {
+                    "inf" => f64::INFINITY,
+                    "-inf" => f64::NEG_INFINITY,
+                    "nan" => f64::NAN,
+                    _ => line.parse().map_err(|_| "protocol error; invalid double")?,
+                };
+                Ok(Frame::Double(value))
+            }
+            b'#' => match &buffer.get_line().await?[..] {
+                b"t" => Ok(Frame::Boolean(true)),
+                b"f" => Ok(Frame::Boolean(false)),
+                _ => Err("protocol error; invalid boolean".into()),
+            },
+            b'(' => {
+                let line = String::from_utf8(buffer.get_line().await?)?;
+                Ok(Frame::BigNumber(line))
+            }
+            b'!' => {
+                let data = Frame::parse_blob(buffer).await?;
+                Ok(Frame::Error(String::from_utf8(data.to_vec())?))
+            }
+            b'=' => {
+                let data = Frame::parse_blob(buffer).await?;
+                if data.len() < 4 || data[3] != b':' {
+                    return Err("protocol error; invalid verbatim string".into());
+                }
+                let format = String::from_utf8(data[..3].to_vec())?;
+                Ok(Frame::Verbatim(format, data.slice(4..)))
+            }
             actual => Err(format!("protocol error; invalid frame type byte `{}`", actual).into()),
         }
     }
 
-    pub async fn write<T: AsyncWrite + Unpin>(&self, stream: &mut T) -> io::Result<()> {
+    /// Read a length-prefixed payload, the type byte being already consumed
+    async fn parse_blob<Stream: AsyncReadExt + Unpin>(
+        buffer: &mut BufferedStream<Stream>,
+    ) -> Result<Bytes, FrameParseError> {
+        let len = buffer.get_decimal().await?.try_into()?;
+        let data = Bytes::copy_from_slice(buffer.take(len).await?.as_slice());
+
+        // skip that number of bytes + 2 (\r\n).
+        buffer.skip(2).await?;
+
+        Ok(data)
+    }
+
+    pub async fn write<T: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut T,
+        protocol: Protocol,
+    ) -> io::Result<()> {
+        let mut out = Vec::with_capacity(64);
+        self.encode(&mut out, protocol);
+        stream.write_all(&out).await
+    }
+
+    /// Serialize the frame, RESP3 types being degraded for RESP2
+    pub fn encode(&self, out: &mut Vec<u8>, protocol: Protocol) {
+        let resp3 = protocol == Protocol::Resp3;
+
         match self {
-            FrameSimple::Simple(val) => {
-                stream.write_u8(b'+').await?;
-                stream.write_all(val.as_bytes()).await?;
-                stream.write_all(b"\r\n").await?;
+            Frame::Simple(val) => {
+                out.push(b'+');
+                out.extend_from_slice(val.as_bytes());
+                out.extend_from_slice(b"\r\n");
             }
-            FrameSimple::Error(val) => {
-                stream.write_u8(b'-').await?;
-                stream.write_all(val.as_bytes()).await?;
-                stream.write_all(b"\r\n").await?;
+            Frame::Error(val) => {
+                out.push(b'-');
+                out.extend_from_slice(val.as_bytes());
+                out.extend_from_slice(b"\r\n");
             }
-            FrameSimple::Integer(val) => {
-                stream.write_u8(b':').await?;
-                write_decimal(stream, *val).await?;
+            Frame::Integer(val) => {
+                out.push(b':');
+                encode_decimal(out, *val);
             }
-            FrameSimple::Null => {
-                stream.write_all(b"$-1\r\n").await?;
+            Frame::Bulk(val) => encode_blob(out, b'$', val),
+            Frame::Null if resp3 => out.extend_from_slice(b"_\r\n"),
+            Frame::Null => out.extend_from_slice(b"$-1\r\n"),
+            Frame::NullArray if resp3 => out.extend_from_slice(b"_\r\n"),
+            Frame::NullArray => out.extend_from_slice(b"*-1\r\n"),
+            Frame::Array(val) => encode_aggregate(out, b'*', val, protocol),
+            Frame::Set(val) => {
+                encode_aggregate(out, if resp3 { b'~' } else { b'*' }, val, protocol)
             }
-            FrameSimple::Bulk(val) => {
-                let len = val.len();
-
-                stream.write_u8(b'$').await?;
-                write_decimal(stream, len as u64).await?;
-                stream.write_all(val).await?;
-                stream.write_all(b"\r\n").await?;
+            Frame::Push(val) => {
+                encode_aggregate(out, if resp3 { b'>' } else { b'*' }, val, protocol)
+            }
+            Frame::Map(val) => {
+                if resp3 {
+                    out.push(b'%');
+                    encode_decimal(out, val.len() as i64);
+                } else {
+                    out.push(b'*');
+                    encode_decimal(out, val.len() as i64 * 2);
+                }
+                for (key, value) in val {
+                    key.encode(out, protocol);
+                    value.encode(out, protocol);
+                }
             }
+            Frame::Double(val) => {
+                let repr = format_double(*val);
+                if resp3 {
+                    out.push(b',');
+                    out.extend_from_slice(repr.as_bytes());
+                    out.extend_from_slice(b"\r\n");
+                } else {
+                    encode_blob(out, b'$', repr.as_bytes());
+                }
+            }
+            Frame::Boolean(val) if resp3 => {
+                out.extend_from_slice(if *val { b"#t\r\n" } else { b"#f\r\n" })
+            }
+            Frame::Boolean(val) => {
+                out.push(b':');
+                encode_decimal(out, *val as i64);
+            }
+            Frame::BigNumber(val) if resp3 => {
+                out.push(b'(');
+                out.extend_from_slice(val.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            Frame::BigNumber(val) => encode_blob(out, b'$', val.as_bytes()),
+            Frame::Verbatim(format, val) if resp3 => {
+                let mut data = Vec::with_capacity(4 + val.len());
+                data.extend_from_slice(format.as_bytes());
+                data.push(b':');
+                data.extend_from_slice(val);
+                encode_blob(out, b'=', &data);
+            }
+            Frame::Verbatim(_, val) => encode_blob(out, b'$', val),
+        }
+    }
+}
+
+/// Doubles as written by redis, integral values have no decimal part
+pub fn format_double(val: f64) -> String {
+    if val.is_nan() {
+        "nan".to_string()
+    } else if val.is_infinite() {
+        if val > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        val.to_string()
+    }
+}
+
+/// Quote a string as redis does, escaping the quotes and the non printable
+/// bytes
+pub fn quote(data: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &c in data {
+        match c {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            c if c.is_ascii_graphic() || c == b' ' => quoted.push(c as char),
+            c => quoted.push_str(&format!("\\x{:02x}", c)),
         }
+    }
+    quoted.push('"');
+    quoted
+}
 
-        Ok(())
+fn encode_aggregate(out: &mut Vec<u8>, t: u8, items: &[Frame], protocol: Protocol) {
+    out.push(t);
+    encode_decimal(out, items.len() as i64);
+    for item in items {
+        item.encode(out, protocol);
     }
 }
 
-pub async fn write_decimal<Stream: AsyncWrite + Unpin>(
-    stream: &mut Stream,
-    val: u64,
-) -> io::Result<()> {
-    use std::io::Write;
+fn encode_blob(out: &mut Vec<u8>, t: u8, data: &[u8]) {
+    out.push(t);
+    encode_decimal(out, data.len() as i64);
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn encode_decimal(out: &mut Vec<u8>, val: i64) {
+    // Writing to a `Vec` cannot fail
+    let _ = write!(out, "{}\r\n", val);
+}
+
+/// A command as sent by a client
+pub fn request(args: &[&str]) -> Frame {
+    Frame::Array(
+        args.iter()
+            .map(|arg| Frame::Bulk(Bytes::from(arg.to_string())))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_util::io::StreamReader;
 
-    // Convert the value to a string
-    let mut buf = [0u8; 20];
-    let mut buf = io::Cursor::new(&mut buf[..]);
-    write!(&mut buf, "{}", val)?;
+    async fn roundtrip(frame: &Frame, protocol: Protocol) -> (Vec<u8>, Frame) {
+        let mut out = vec![];
+        frame.encode(&mut out, protocol);
 
-    let pos = buf.position() as usize;
-    stream.write_all(&buf.get_ref()[..pos]).await?;
-    stream.write_all(b"\r\n").await?;
+        let stream = tokio_stream::iter(vec![Result::<Bytes, std::io::Error>::Ok(Bytes::from(
+            out.clone(),
+        ))]);
+        let mut buffer = BufferedStream::new(StreamReader::new(stream));
+        (out, Frame::parse(&mut buffer).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn nested_test() {
+        let frame = Frame::Array(vec![
+            Frame::Bulk(Bytes::from("0")),
+            Frame::Array(vec![Frame::Integer(-2), Frame::Null, Frame::Array(vec![])]),
+        ]);
+
+        let (out, parsed) = roundtrip(&frame, Protocol::Resp2).await;
+        assert_eq!(out, b"*2\r\n$1\r\n0\r\n*3\r\n:-2\r\n$-1\r\n*0\r\n");
+        assert_eq!(parsed, frame);
+    }
+
+    #[tokio::test]
+    async fn resp3_test() {
+        let frame = Frame::Map(vec![
+            (
+                Frame::Bulk(Bytes::from("set")),
+                Frame::Set(vec![Frame::Boolean(true)]),
+            ),
+            (Frame::Bulk(Bytes::from("double")), Frame::Double(1.5)),
+            (
+                Frame::Bulk(Bytes::from("big")),
+                Frame::BigNumber("1234567890123456789012".into()),
+            ),
+            (
+                Frame::Bulk(Bytes::from("txt")),
+                Frame::Verbatim("txt".into(), Bytes::from("hi")),
+            ),
+            (
+                Frame::Bulk(Bytes::from("push")),
+                Frame::Push(vec![Frame::Null]),
+            ),
+        ]);
 
-    Ok(())
+        let (_, parsed) = roundtrip(&frame, Protocol::Resp3).await;
+        assert_eq!(parsed, frame);
+    }
+
+    #[tokio::test]
+    async fn resp3_to_resp2_test() {
+        let frame = Frame::Map(vec![(
+            Frame::Bulk(Bytes::from("k")),
+            Frame::Array(vec![
+                Frame::Double(2.0),
+                Frame::Boolean(false),
+                Frame::Verbatim("txt".into(), Bytes::from("hi")),
+            ]),
+        )]);
+
+        let (out, _) = roundtrip(&frame, Protocol::Resp2).await;
+        assert_eq!(out, b"*2\r\n$1\r\nk\r\n*3\r\n$1\r\n2\r\n:0\r\n$2\r\nhi\r\n");
+    }
+
+    #[test]
+    fn quote_test() {
+        assert_eq!(quote(b"a b"), "\"a b\"");
+        assert_eq!(quote(b"\"\\\n\x01"), "\"\\\"\\\\\\n\\x01\"");
+    }
 }
@@ -0,0 +1,331 @@
+use crate::zset::{ScoreBound, SortedSet};
+use bytes::Bytes;
+
+/// Bits of each coordinate in the score of a member
+const STEP_MAX: u32 = 26;
+const LON_MIN: f64 = -180.0;
+const LON_MAX: f64 = 180.0;
+/// Latitude limits of the Web Mercator projection
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+const EARTH_RADIUS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+const ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+/// Cells searched at most, the precision is lowered until the searched area
+/// fits in them
+const CELLS_MAX: usize = 16;
+
+/// Area searched around a center, sizes in meters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Radius(f64),
+    /// Width and height
+    Box(f64, f64),
+}
+
+/// Member found by a search, the distance is in meters
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeoMatch {
+    pub member: Bytes,
+    pub distance: f64,
+    pub hash: u64,
+    pub lon: f64,
+    pub lat: f64,
+}
+
+/// The coordinates can be indexed
+pub fn valid(lon: f64, lat: f64) -> bool {
+    (LON_MIN..=LON_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat)
+}
+
+/// Score of a member at the coordinates, their bits interleaved starting
+/// with the longitude
+pub fn encode(lon: f64, lat: f64) -> u64 {
+    let lat = cell(lat, LAT_MIN, LAT_MAX, STEP_MAX);
+    let lon = cell(lon, LON_MIN, LON_MAX, STEP_MAX);
+    interleave(lat, lon)
+}
+
+/// Coordinates of the center of the cell of a score
+pub fn decode(hash: u64) -> (f64, f64) {
+    let cells = (1u64 << STEP_MAX) as f64;
+    let center = |index: u32, min: f64, max: f64| {
+        let low = min + index as f64 / cells * (max - min);
+        let high = min + (index as f64 + 1.0) / cells * (max - min);
+        ((low + high) / 2.0).clamp(min, max)
+    };
+    let lon = center(squash(hash >> 1), LON_MIN, LON_MAX);
+    let lat = center(squash(hash), LAT_MIN, LAT_MAX);
+    (lon, lat)
+}
+
+/// Standard 11 characters geohash of the coordinates of a score
+pub fn hash_string(hash: u64) -> String {
+    let (lon, lat) = decode(hash);
+    let hash = interleave(
+        cell(lat, -90.0, 90.0, STEP_MAX),
+        cell(lon, LON_MIN, LON_MAX, STEP_MAX),
+    );
+    (0..11)
+        .map(|i| match i {
+            // Only 52 bits for 55 in 11 characters
+            10 => ALPHABET[0] as char,
+            _ => ALPHABET[((hash >> (52 - (i + 1) * 5)) & 0x1f) as usize] as char,
+        })
+        .collect()
+}
+
+/// Great circle distance in meters
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS * (lat2.to_radians() - lat1.to_radians()).abs()
+}
+
+impl Shape {
+    /// Distance from the center to the point, if within the shape
+    fn distance(&self, center: (f64, f64), lon: f64, lat: f64) -> Option<f64> {
+        match *self {
+            Shape::Radius(radius) => {
+                let distance = distance(center.0, center.1, lon, lat);
+                (distance <= radius).then_some(distance)
+            }
+            Shape::Box(width, height) => {
+                if lat_distance(lat, center.1) > height / 2.0
+                    || distance(lon, lat, center.0, lat) > width / 2.0
+                {
+                    return None;
+                }
+                Some(distance(center.0, center.1, lon, lat))
+            }
+        }
+    }
+
+    /// Half of the width and of the height of the bounding box
+    fn half_sizes(&self) -> (f64, f64) {
+        match *self {
+            Shape::Radius(radius) => (radius, radius),
+            Shape::Box(width, height) => (width / 2.0, height / 2.0),
+        }
+    }
+}
+
+/// Members of the sorted set within the shape around the center, stopping
+/// at `limit` matches
+pub fn search(
+    zset: &SortedSet,
+    center: (f64, f64),
+    shape: Shape,
+    limit: Option<usize>,
+) -> Vec<GeoMatch> {
+    let mut matches = vec![];
+    for (min, max) in score_ranges(center, shape) {
+        let min = ScoreBound::Inclusive(min as f64);
+        let max = ScoreBound::Exclusive(max as f64);
+        for (member, score) in zset.range_by_score(min, max, false) {
+            let hash = score as u64;
+            let (lon, lat) = decode(hash);
+            let Some(distance) = shape.distance(center, lon, lat) else {
+                continue;
+            };
+            matches.push(GeoMatch {
+                member: member.clone(),
+                distance,
+                hash,
+                lon,
+                lat,
+            });
+            if limit.is_some_and(|limit| matches.len() >= limit) {
+                return matches;
+            }
+        }
+    }
+    matches
+}
+
+/// Ranges of scores, max excluded, of the cells covering the bounding box
+/// of the shape
+fn score_ranges(center: (f64, f64), shape: Shape) -> Vec<(u64, u64)> {
+    let (lon, lat) = center;
+    let (half_width, half_height) = shape.half_sizes();
+    let lat_delta = (half_height / EARTH_RADIUS).to_degrees();
+    let lat_range = (
+        (lat - lat_delta).max(LAT_MIN),
+        (lat + lat_delta).min(LAT_MAX),
+    );
+    // Meridians are the farthest apart at the edge closest to the equator,
+    // the longitudes to cover are the widest at the other edge
+    let polar = (lat.abs() + lat_delta).to_radians().cos();
+    let lon_delta = match polar > 0.0 {
+        true => (half_width / EARTH_RADIUS / polar).to_degrees(),
+        false => LON_MAX,
+    };
+    let lon_ranges = if lon_delta >= LON_MAX {
+        vec![(LON_MIN, LON_MAX)]
+    } else if lon - lon_delta < LON_MIN {
+        vec![
+            (lon - lon_delta + 360.0, LON_MAX),
+            (LON_MIN, lon + lon_delta),
+        ]
+    } else if lon + lon_delta > LON_MAX {
+        vec![
+            (lon - lon_delta, LON_MAX),
+            (LON_MIN, lon + lon_delta - 360.0),
+        ]
+    } else {
+        vec![(lon - lon_delta, lon + lon_delta)]
+    };
+
+    let mut step = estimate_step(half_width.hypot(half_height), lat);
+    let cells = |step: u32| {
+        let lats =
+            cell(lat_range.0, LAT_MIN, LAT_MAX, step)..=cell(lat_range.1, LAT_MIN, LAT_MAX, step);
+        lon_ranges
+            .iter()
+            .flat_map(move |&(min, max)| {
+                let lats = lats.clone();
+                (cell(min, LON_MIN, LON_MAX, step)..=cell(max, LON_MIN, LON_MAX, step))
+                    .flat_map(move |lon| lats.clone().map(move |lat| interleave(lat, lon)))
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut hashes = cells(step);
+    while hashes.len() > CELLS_MAX && step > 1 {
+        step -= 1;
+        hashes = cells(step);
+    }
+
+    let shift = 2 * (STEP_MAX - step);
+    hashes.sort_unstable();
+    hashes.dedup();
+    let mut ranges: Vec<(u64, u64)> = vec![];
+    for hash in hashes {
+        let (min, max) = (hash << shift, (hash + 1) << shift);
+        match ranges.last_mut() {
+            Some(last) if last.1 == min => last.1 = max,
+            _ => ranges.push((min, max)),
+        }
+    }
+    ranges
+}
+
+/// Precision of the cells at least as large as the radius
+fn estimate_step(mut radius: f64, lat: f64) -> u32 {
+    if radius == 0.0 {
+        return STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    // The cells are narrower towards the poles
+    step -= 2;
+    if lat.abs() > 66.0 {
+        step -= 1;
+    }
+    if lat.abs() > 80.0 {
+        step -= 1;
+    }
+    step.clamp(1, STEP_MAX as i32) as u32
+}
+
+/// Index of the cell of a coordinate, with 2^step cells between the limits
+fn cell(value: f64, min: f64, max: f64, step: u32) -> u32 {
+    let cells = 1u64 << step;
+    let index = ((value - min) / (max - min) * cells as f64) as u64;
+    index.min(cells - 1) as u32
+}
+
+/// The bits of the latitude in even positions, the bits of the longitude in
+/// odd positions
+fn interleave(lat: u32, lon: u32) -> u64 {
+    spread(lat) | spread(lon) << 1
+}
+
+fn spread(value: u32) -> u64 {
+    let mut value = value as u64;
+    value = (value | value << 16) & 0x0000ffff0000ffff;
+    value = (value | value << 8) & 0x00ff00ff00ff00ff;
+    value = (value | value << 4) & 0x0f0f0f0f0f0f0f0f;
+    value = (value | value << 2) & 0x3333333333333333;
+    (value | value << 1) & 0x5555555555555555
+}
+
+fn squash(value: u64) -> u32 {
+    let mut value = value & 0x5555555555555555;
+    value = (value | value >> 1) & 0x3333333333333333;
+    value = (value | value >> 2) & 0x0f0f0f0f0f0f0f0f;
+    value = (value | value >> 4) & 0x00ff00ff00ff00ff;
+    value = (value | value >> 8) & 0x0000ffff0000ffff;
+    ((value | value >> 16) & 0x00000000ffffffff) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALERMO: (f64, f64) = (13.361389, 38.115556);
+    const CATANIA: (f64, f64) = (15.087269, 37.502669);
+
+    #[test]
+    fn encoding_test() {
+        assert_eq!(encode(PALERMO.0, PALERMO.1), 3479099956230698);
+        assert_eq!(encode(CATANIA.0, CATANIA.1), 3479447370796909);
+        let (lon, lat) = decode(3479099956230698);
+        assert!((lon - 13.361389338970184).abs() < 1e-12);
+        assert!((lat - 38.1155563954963).abs() < 1e-12);
+        assert_eq!(hash_string(3479099956230698), "sqc8b49rny0");
+        assert_eq!(hash_string(3479447370796909), "sqdtr74hyu0");
+        assert!(valid(180.0, 85.0));
+        assert!(!valid(0.0, 86.0));
+    }
+
+    #[test]
+    fn distance_test() {
+        let (lon1, lat1) = decode(encode(PALERMO.0, PALERMO.1));
+        let (lon2, lat2) = decode(encode(CATANIA.0, CATANIA.1));
+        let distance = distance(lon1, lat1, lon2, lat2);
+        assert_eq!(format!("{:.4}", distance), "166274.1516");
+    }
+
+    #[test]
+    fn search_test() {
+        let mut zset = SortedSet::new();
+        for (member, (lon, lat)) in [
+            ("Palermo", PALERMO),
+            ("Catania", CATANIA),
+            ("edge1", (12.758489, 38.788135)),
+            ("edge2", (17.241510, 38.788135)),
+            ("west", (-179.9, 0.0)),
+        ] {
+            zset.insert(Bytes::from(member), encode(lon, lat) as f64);
+        }
+        let names = |matches: Vec<GeoMatch>| {
+            let mut names: Vec<_> = matches.into_iter().map(|m| m.member).collect();
+            names.sort();
+            names
+        };
+
+        let found = search(&zset, (15.0, 37.0), Shape::Radius(200_000.0), None);
+        assert_eq!(names(found), vec!["Catania", "Palermo"]);
+        let found = search(&zset, (15.0, 37.0), Shape::Box(400_000.0, 400_000.0), None);
+        assert_eq!(names(found), vec!["Catania", "Palermo", "edge1", "edge2"]);
+        let found = search(&zset, (15.0, 37.0), Shape::Radius(200_000.0), Some(1));
+        assert_eq!(found.len(), 1);
+        // Across the antimeridian
+        let found = search(&zset, (179.9, 0.0), Shape::Radius(50_000.0), None);
+        assert_eq!(names(found), vec!["west"]);
+        let found = search(&zset, (0.0, 0.0), Shape::Radius(30_000_000.0), None);
+        assert_eq!(found.len(), 5);
+    }
+}
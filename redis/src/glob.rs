@@ -0,0 +1,103 @@
+/// Glob-style matching as done by redis (`KEYS`, `PSUBSCRIBE`, ...).
+///
+/// Supports `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to escape the next
+/// character.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                while p + 1 < pattern.len() && pattern[p + 1] == b'*' {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                return (s..=string.len())
+                    .any(|start| glob_match(&pattern[p + 1..], &string[start..]));
+            }
+            b'?' => {
+                if s >= string.len() {
+                    return false;
+                }
+                s += 1;
+            }
+            b'[' => {
+                if s >= string.len() {
+                    return false;
+                }
+                p += 1;
+                let negate = p < pattern.len() && pattern[p] == b'^';
+                if negate {
+                    p += 1;
+                }
+
+                let mut matched = false;
+                while p < pattern.len() && pattern[p] != b']' {
+                    if pattern[p] == b'\\' && p + 1 < pattern.len() {
+                        p += 1;
+                        matched |= pattern[p] == string[s];
+                    } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+                        let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+                        matched |= start <= string[s] && string[s] <= end;
+                        p += 2;
+                    } else {
+                        matched |= pattern[p] == string[s];
+                    }
+                    p += 1;
+                }
+
+                if matched == negate {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                let c = if c == b'\\' && p + 1 < pattern.len() {
+                    p += 1;
+                    pattern[p]
+                } else {
+                    c
+                };
+                if s >= string.len() || string[s] != c {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+
+    s == string.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_test() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"*", b"anything"));
+        assert!(glob_match(b"news.*", b"news.tech"));
+        assert!(!glob_match(b"news.*", b"sport.tech"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(glob_match(b"h*llo", b"heeeello"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[ae]llo", b"hillo"));
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo"));
+        assert!(!glob_match(b"h[a-b]llo", b"hcllo"));
+        assert!(glob_match(b"h\\*llo", b"h*llo"));
+        assert!(!glob_match(b"h\\*llo", b"hello"));
+        assert!(glob_match(b"a*b*c", b"a123b456c"));
+        assert!(!glob_match(b"a*b*c", b"a123b456"));
+    }
+}
@@ -0,0 +1,340 @@
+/// Bits of the hash selecting the register
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+/// Bits of the hash counted for the rank
+const Q: usize = 64 - P as usize;
+const REGISTER_BITS: usize = 6;
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * REGISTER_BITS).div_ceil(8);
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+/// Largest value of a register in the sparse encoding
+const SPARSE_VALUE_MAX: u8 = 32;
+/// Sparse values are promoted to the dense encoding past this length
+const SPARSE_MAX_LEN: usize = 3000;
+/// Set in the last byte of the cached cardinality when it is stale
+const STALE: u8 = 0x80;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+pub const INVALID: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+pub const CORRUPTED: &str = "INVALIDOBJ Corrupted HLL object detected";
+
+/// Registers of a HyperLogLog, encoded in string values with the layout of
+/// redis: a `HYLL` header with the encoding and the cached cardinality,
+/// followed by the registers, either packed as 6 bits each (dense) or run
+/// length encoded (sparse).
+#[derive(Clone, Debug, PartialEq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+    sparse: bool,
+    cached: Option<u64>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS],
+            sparse: true,
+            cached: Some(0),
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn decode(data: &[u8]) -> Result<HyperLogLog, &'static str> {
+        let cached = cached_count(data)?;
+        let mut registers = vec![0; REGISTERS];
+        let sparse = data[4] == SPARSE;
+        if sparse {
+            let mut index = 0;
+            let mut at = HEADER_LEN;
+            while at < data.len() {
+                let opcode = data[at];
+                if opcode & 0xc0 == 0 {
+                    // ZERO: 00xxxxxx
+                    index += (opcode & 0x3f) as usize + 1;
+                    at += 1;
+                } else if opcode & 0xc0 == 0x40 {
+                    // XZERO: 01xxxxxx yyyyyyyy
+                    let Some(&low) = data.get(at + 1) else {
+                        return Err(CORRUPTED);
+                    };
+                    index += (((opcode & 0x3f) as usize) << 8 | low as usize) + 1;
+                    at += 2;
+                } else {
+                    // VAL: 1vvvvvxx
+                    let value = ((opcode >> 2) & 0x1f) + 1;
+                    let run = (opcode & 0x03) as usize + 1;
+                    if index + run > REGISTERS {
+                        return Err(CORRUPTED);
+                    }
+                    registers[index..index + run].fill(value);
+                    index += run;
+                    at += 1;
+                }
+                if index > REGISTERS {
+                    return Err(CORRUPTED);
+                }
+            }
+            if index != REGISTERS {
+                return Err(CORRUPTED);
+            }
+        } else {
+            for (i, register) in registers.iter_mut().enumerate() {
+                let byte = HEADER_LEN + i * REGISTER_BITS / 8;
+                let shift = i * REGISTER_BITS % 8;
+                let next = data.get(byte + 1).copied().unwrap_or(0);
+                let bits = (data[byte] as u16 | (next as u16) << 8) >> shift;
+                *register = (bits & 0x3f) as u8;
+            }
+        }
+        Ok(HyperLogLog {
+            registers,
+            sparse,
+            cached,
+        })
+    }
+
+    /// Encoded as sparse unless the registers do not fit
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = b"HYLL".to_vec();
+        data.extend_from_slice(&[SPARSE, 0, 0, 0]);
+        match self.cached {
+            Some(count) => data.extend_from_slice(&count.to_le_bytes()),
+            None => data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, STALE]),
+        }
+        if self.sparse && self.encode_sparse(&mut data) {
+            return data;
+        }
+        data.truncate(HEADER_LEN);
+        data[4] = DENSE;
+        data.resize(DENSE_LEN, 0);
+        for (i, &register) in self.registers.iter().enumerate() {
+            let byte = HEADER_LEN + i * REGISTER_BITS / 8;
+            let bits = (register as u16) << (i * REGISTER_BITS % 8);
+            data[byte] |= bits as u8;
+            if let Some(next) = data.get_mut(byte + 1) {
+                *next |= (bits >> 8) as u8;
+            }
+        }
+        data
+    }
+
+    fn encode_sparse(&self, data: &mut Vec<u8>) -> bool {
+        let mut index = 0;
+        while index < REGISTERS {
+            let value = self.registers[index];
+            let run = self.registers[index..]
+                .iter()
+                .take_while(|&&register| register == value)
+                .count();
+            index += run;
+            let mut left = run;
+            while left > 0 {
+                if value == 0 && left > 64 {
+                    let len = left - 1;
+                    data.push(0x40 | (len >> 8) as u8);
+                    data.push(len as u8);
+                    left = 0;
+                } else if value == 0 {
+                    data.push((left - 1) as u8);
+                    left = 0;
+                } else if value <= SPARSE_VALUE_MAX {
+                    let len = left.min(4);
+                    data.push(0x80 | (value - 1) << 2 | (len - 1) as u8);
+                    left -= len;
+                } else {
+                    return false;
+                }
+            }
+            if data.len() > SPARSE_MAX_LEN {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Count an element, true if a register changed
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash = murmur_hash64a(element, 0xadc83b19);
+        let index = hash as usize & (REGISTERS - 1);
+        let rank = ((hash >> P) | 1 << Q).trailing_zeros() as u8 + 1;
+        if self.registers[index] >= rank {
+            return false;
+        }
+        self.registers[index] = rank;
+        self.cached = None;
+        true
+    }
+
+    /// Keep the largest registers of both
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, &value) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(value);
+        }
+        self.sparse &= other.sparse;
+        self.cached = None;
+    }
+
+    /// Estimated cardinality, with the estimator of Otmar Ertl used by redis
+    pub fn count(&self) -> u64 {
+        let mut histogram = [0u32; 64];
+        for &register in &self.registers {
+            histogram[register as usize] += 1;
+        }
+        let m = REGISTERS as f64;
+        let mut z = m * tau((m - histogram[Q + 1] as f64) / m);
+        for &count in histogram[1..=Q].iter().rev() {
+            z += count as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+        (ALPHA_INF * m * m / z).round() as u64
+    }
+}
+
+/// The cardinality cached in an encoded HyperLogLog, unless stale
+pub fn cached_count(data: &[u8]) -> Result<Option<u64>, &'static str> {
+    if data.len() < HEADER_LEN || &data[..4] != b"HYLL" {
+        return Err(INVALID);
+    }
+    match data[4] {
+        SPARSE => {}
+        DENSE if data.len() == DENSE_LEN => {}
+        _ => return Err(INVALID),
+    }
+    match data[15] & STALE {
+        0 => Ok(Some(u64::from_le_bytes(data[8..16].try_into().unwrap()))),
+        _ => Ok(None),
+    }
+}
+
+/// Store the cardinality in the header of an encoded HyperLogLog
+pub fn cache_count(data: &mut [u8], count: u64) {
+    data[8..16].copy_from_slice(&count.to_le_bytes());
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+/// MurmurHash64A, reading blocks as little endian like redis does on
+/// little endian machines
+fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let mut blocks = key.chunks_exact(8);
+    for block in &mut blocks {
+        let mut k = u64::from_le_bytes(block.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_test() {
+        let empty = HyperLogLog::default().encode();
+        assert_eq!(&empty[..5], b"HYLL\x01");
+        assert_eq!(&empty[HEADER_LEN..], &[0x7f, 0xff]);
+        assert_eq!(cached_count(&empty), Ok(Some(0)));
+        assert_eq!(HyperLogLog::decode(&empty).unwrap(), HyperLogLog::default());
+
+        let mut hll = HyperLogLog::default();
+        for element in ["a", "b", "c", "d", "e", "f", "g"] {
+            assert!(hll.add(element.as_bytes()));
+        }
+        assert!(!hll.add(b"a"));
+        assert_eq!(hll.count(), 7);
+        let data = hll.encode();
+        assert_eq!(data[4], SPARSE);
+        assert_eq!(cached_count(&data), Ok(None));
+        assert_eq!(HyperLogLog::decode(&data).unwrap(), hll);
+
+        for i in 0..5000 {
+            hll.add(format!("element:{}", i).as_bytes());
+        }
+        let data = hll.encode();
+        assert_eq!(data[4], DENSE);
+        assert_eq!(data.len(), DENSE_LEN);
+        let decoded = HyperLogLog::decode(&data).unwrap();
+        assert_eq!(decoded.registers, hll.registers);
+        let count = decoded.count();
+        assert!((4900..5100).contains(&count), "{}", count);
+
+        assert_eq!(HyperLogLog::decode(b"HYLL"), Err(INVALID));
+        assert_eq!(HyperLogLog::decode(&data[..100]), Err(INVALID));
+        assert_eq!(HyperLogLog::decode(&empty[..17]), Err(CORRUPTED));
+    }
+
+    #[test]
+    fn merge_test() {
+        let mut first = HyperLogLog::default();
+        let mut second = HyperLogLog::default();
+        for i in 0..1000 {
+            first.add(format!("{}", i).as_bytes());
+            second.add(format!("{}", i + 500).as_bytes());
+        }
+        first.merge(&second);
+        let count = first.count();
+        assert!((1470..1530).contains(&count), "{}", count);
+    }
+
+    #[test]
+    fn murmur_hash_test() {
+        assert_eq!(murmur_hash64a(b"", 0), 0);
+        assert_ne!(
+            murmur_hash64a(b"abcdefgh", 0),
+            murmur_hash64a(b"abcdefg", 0)
+        );
+    }
+}
@@ -0,0 +1,3949 @@
+use crate::aof::{claim_args, rewrite_entry, Feed, FeedMessage};
+use crate::bitmap::{
+    bit_range, count_bits, find_bit, get_bit, get_field, set_bit, set_field, BitUnit, Overflow,
+};
+use crate::command::*;
+use crate::command_parser::parse_float;
+use crate::db::{now_ms, Db, Entry, Value, WRONGTYPE};
+use crate::eviction::{EvictionPolicy, EvictionPool, MaxMemory, OOM};
+use crate::frame::*;
+use crate::geo::{self, GeoMatch};
+use crate::glob::glob_match;
+use crate::hyperloglog::{self, HyperLogLog};
+use crate::pubsub::{Broker, BrokerCommand, NotifyEvents};
+use crate::stats::{ExecutionTime, KeyspaceStats};
+use crate::stream::{ConsumerGroup, Fields, PendingEntry, Stream, StreamId, Trim};
+use crate::zset::SortedSet;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, MissedTickBehavior};
+
+/// Delay between two active expiration cycles
+const ACTIVE_EXPIRE_PERIOD: Duration = Duration::from_millis(100);
+/// Maximum number of keys removed by a single active expiration cycle
+const ACTIVE_EXPIRE_KEYS_PER_CYCLE: usize = 200;
+
+pub type KVStore = mpsc::UnboundedSender<KVStoreMessage>;
+
+/// A shard lent by its task, with the way to give it back
+pub type LentShard = (Shard, oneshot::Sender<Shard>);
+
+/// Messages to a shard, the index of the database they apply to comes first
+pub enum KVStoreMessage {
+    /// A command to execute, its execution time accounted when measured
+    Command(
+        usize,
+        KVStoreCommand,
+        mpsc::UnboundedSender<Frame>,
+        Option<ExecutionTime>,
+    ),
+    /// Lend the shard for exclusive access until it is given back, see
+    /// `LockedShards`
+    Lock(oneshot::Sender<LentShard>),
+    /// Set the flag once the key is touched, for `WATCH`
+    Watch(usize, String, Arc<AtomicBool>),
+    /// New memory limit, from `CONFIG SET`
+    MaxMemory(MaxMemory),
+    /// New classes of keyspace events, from `CONFIG SET`
+    NotifyEvents(NotifyEvents),
+}
+
+/// A client blocked on lists or streams, registered on the shard of every
+/// key it waits for.
+///
+/// The first shard able to serve it, or its timeout, takes the claim, the
+/// other registrations are then ignored.
+pub struct Waiter {
+    pub claim: Arc<AtomicBool>,
+    pub wait: Wait,
+    pub respond: mpsc::UnboundedSender<Frame>,
+}
+
+/// What a blocked client waits for
+#[derive(Clone)]
+pub enum Wait {
+    /// An element to pop, replied with its key as by `BLPOP`
+    Pop(ListSide),
+    /// The list to be ready, the waiter is notified with a null reply and
+    /// moves the element itself, `BLMOVE` needing the shard of the
+    /// destination too.
+    ///
+    /// Every ready waiter is notified, the ones served late block again.
+    Ready,
+    /// Entries to read, replied as by `XREAD`
+    Read(StreamRead),
+}
+
+impl Waiter {
+    fn claim(&self) -> bool {
+        !self.respond.is_closed()
+            && self
+                .claim
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+    }
+
+    fn is_claimed(&self) -> bool {
+        self.claim.load(Ordering::SeqCst)
+    }
+}
+
+/// A blocked client, served by the first of its waiters taking the claim
+pub struct Blocked {
+    claim: Arc<AtomicBool>,
+    served_tx: mpsc::UnboundedSender<Frame>,
+    served_rx: mpsc::UnboundedReceiver<Frame>,
+}
+
+impl Default for Blocked {
+    fn default() -> Blocked {
+        let (served_tx, served_rx) = mpsc::unbounded_channel();
+        Blocked {
+            claim: Arc::new(AtomicBool::new(false)),
+            served_tx,
+            served_rx,
+        }
+    }
+}
+
+impl Blocked {
+    /// A waiter to register on the shard of one of the keys
+    pub fn waiter(&self, wait: Wait) -> Waiter {
+        Waiter {
+            claim: self.claim.clone(),
+            wait,
+            respond: self.served_tx.clone(),
+        }
+    }
+
+    /// Wait up to `timeout` (forever when zero) to be served.
+    ///
+    /// Returns `Some(None)` on timeout, and `None` when the client is gone.
+    pub async fn wait(
+        mut self,
+        timeout: Duration,
+        respond: &mpsc::UnboundedSender<Frame>,
+    ) -> Option<Option<Frame>> {
+        let expired = async {
+            if timeout.is_zero() {
+                std::future::pending::<()>().await
+            } else {
+                time::sleep(timeout).await
+            }
+        };
+        let take_claim = || {
+            self.claim
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        };
+
+        tokio::select! {
+            served = self.served_rx.recv() => Some(served),
+            _ = expired => {
+                if take_claim() {
+                    Some(None)
+                } else {
+                    Some(self.served_rx.recv().await)
+                }
+            }
+            _ = respond.closed() => {
+                take_claim();
+                None
+            }
+        }
+    }
+}
+
+pub enum KVStoreCommand {
+    Append(Append),
+    BitCount(BitCount),
+    BitField(BitField),
+    BitPos(BitPos),
+    /// The keys belong to the shard
+    Del(Del),
+    /// The keys belong to the shard
+    Exists(Exists),
+    Expire(Expire),
+    GeoDist(GeoDist),
+    GeoHash(GeoMembers),
+    GeoPos(GeoMembers),
+    GeoSearch(GeoSearch),
+    Get(Get),
+    GetBit(GetBit),
+    GetDel(GetDel),
+    GetEx(GetEx),
+    GetRange(GetRange),
+    HDel(HDel),
+    HExists(HExists),
+    HGet(HGet),
+    HGetAll(HGetAll),
+    HIncrBy(HIncrBy),
+    HKeys(HKeys),
+    HLen(HLen),
+    HMGet(HMGet),
+    HScan(HScan),
+    HSet(HSet),
+    HVals(HVals),
+    IncrBy(IncrBy),
+    IncrByFloat(IncrByFloat),
+    LIndex(LIndex),
+    LLen(LLen),
+    /// Only when both keys belong to the shard
+    LMove(LMove),
+    LRange(LRange),
+    LRem(LRem),
+    LSet(LSet),
+    LTrim(LTrim),
+    Move(Move),
+    Persist(Persist),
+    PfAdd(PfAdd),
+    /// Only of a single key
+    PfCount(PfCount),
+    Pop(Pop),
+    Push(Push),
+    SAdd(SAdd),
+    SCard(SCard),
+    Set(Set),
+    SetBit(SetBit),
+    SetRange(SetRange),
+    SIsMember(SIsMember),
+    SMembers(SMembers),
+    SRem(SRem),
+    StrLen(StrLen),
+    Ttl(Ttl),
+    Type(Type),
+    XAck(XAck),
+    XAdd(XAdd),
+    XAutoClaim(XAutoClaim),
+    XClaim(XClaim),
+    XDel(XDel),
+    XGroup(XGroup),
+    XLen(XLen),
+    XPending(XPending),
+    XRange(XRange),
+    /// A single stream of `XREAD` or `XREADGROUP`, see `Backend::read_streams`
+    XRead(StreamRead),
+    XSetId(XSetId),
+    ZAdd(ZAdd),
+    ZCard(ZCard),
+    ZIncrBy(ZIncrBy),
+    ZRange(ZRange),
+    ZRank(ZRank),
+    ZRem(ZRem),
+    ZScore(ZScore),
+}
+
+impl KVStoreCommand {
+    pub fn key(&self) -> &str {
+        match self {
+            KVStoreCommand::Append(cmd) => cmd.key(),
+            KVStoreCommand::BitCount(cmd) => cmd.key(),
+            KVStoreCommand::BitField(cmd) => cmd.key(),
+            KVStoreCommand::BitPos(cmd) => cmd.key(),
+            KVStoreCommand::Del(cmd) => &cmd.keys[0],
+            KVStoreCommand::Exists(cmd) => &cmd.keys[0],
+            KVStoreCommand::Expire(cmd) => cmd.key(),
+            KVStoreCommand::GeoDist(cmd) => cmd.key(),
+            KVStoreCommand::GeoHash(cmd) => cmd.key(),
+            KVStoreCommand::GeoPos(cmd) => cmd.key(),
+            KVStoreCommand::GeoSearch(cmd) => cmd.key(),
+            KVStoreCommand::Get(cmd) => cmd.key(),
+            KVStoreCommand::GetBit(cmd) => cmd.key(),
+            KVStoreCommand::GetDel(cmd) => cmd.key(),
+            KVStoreCommand::GetEx(cmd) => cmd.key(),
+            KVStoreCommand::GetRange(cmd) => cmd.key(),
+            KVStoreCommand::HDel(cmd) => cmd.key(),
+            KVStoreCommand::HExists(cmd) => cmd.key(),
+            KVStoreCommand::HGet(cmd) => cmd.key(),
+            KVStoreCommand::HGetAll(cmd) => cmd.key(),
+            KVStoreCommand::HIncrBy(cmd) => cmd.key(),
+            KVStoreCommand::HKeys(cmd) => cmd.key(),
+            KVStoreCommand::HLen(cmd) => cmd.key(),
+            KVStoreCommand::HMGet(cmd) => cmd.key(),
+            KVStoreCommand::HScan(cmd) => cmd.key(),
+            KVStoreCommand::HSet(cmd) => cmd.key(),
+            KVStoreCommand::HVals(cmd) => cmd.key(),
+            KVStoreCommand::IncrBy(cmd) => cmd.key(),
+            KVStoreCommand::IncrByFloat(cmd) => cmd.key(),
+            KVStoreCommand::LIndex(cmd) => cmd.key(),
+            KVStoreCommand::LLen(cmd) => cmd.key(),
+            KVStoreCommand::LMove(cmd) => cmd.key(),
+            KVStoreCommand::LRange(cmd) => cmd.key(),
+            KVStoreCommand::LRem(cmd) => cmd.key(),
+            KVStoreCommand::LSet(cmd) => cmd.key(),
+            KVStoreCommand::LTrim(cmd) => cmd.key(),
+            KVStoreCommand::Move(cmd) => cmd.key(),
+            KVStoreCommand::Persist(cmd) => cmd.key(),
+            KVStoreCommand::PfAdd(cmd) => cmd.key(),
+            KVStoreCommand::PfCount(cmd) => &cmd.keys[0],
+            KVStoreCommand::Pop(cmd) => cmd.key(),
+            KVStoreCommand::Push(cmd) => cmd.key(),
+            KVStoreCommand::SAdd(cmd) => cmd.key(),
+            KVStoreCommand::SCard(cmd) => cmd.key(),
+            KVStoreCommand::Set(cmd) => cmd.key(),
+            KVStoreCommand::SetBit(cmd) => cmd.key(),
+            KVStoreCommand::SetRange(cmd) => cmd.key(),
+            KVStoreCommand::SIsMember(cmd) => cmd.key(),
+            KVStoreCommand::SMembers(cmd) => cmd.key(),
+            KVStoreCommand::SRem(cmd) => cmd.key(),
+            KVStoreCommand::StrLen(cmd) => cmd.key(),
+            KVStoreCommand::Ttl(cmd) => cmd.key(),
+            KVStoreCommand::Type(cmd) => cmd.key(),
+            KVStoreCommand::XAck(cmd) => cmd.key(),
+            KVStoreCommand::XAdd(cmd) => cmd.key(),
+            KVStoreCommand::XAutoClaim(cmd) => cmd.key(),
+            KVStoreCommand::XClaim(cmd) => cmd.key(),
+            KVStoreCommand::XDel(cmd) => cmd.key(),
+            KVStoreCommand::XGroup(cmd) => cmd.key(),
+            KVStoreCommand::XLen(cmd) => cmd.key(),
+            KVStoreCommand::XPending(cmd) => cmd.key(),
+            KVStoreCommand::XRange(cmd) => cmd.key(),
+            KVStoreCommand::XRead(cmd) => cmd.key(),
+            KVStoreCommand::XSetId(cmd) => cmd.key(),
+            KVStoreCommand::ZAdd(cmd) => cmd.key(),
+            KVStoreCommand::ZCard(cmd) => cmd.key(),
+            KVStoreCommand::ZIncrBy(cmd) => cmd.key(),
+            KVStoreCommand::ZRange(cmd) => cmd.key(),
+            KVStoreCommand::ZRank(cmd) => cmd.key(),
+            KVStoreCommand::ZRem(cmd) => cmd.key(),
+            KVStoreCommand::ZScore(cmd) => cmd.key(),
+        }
+    }
+
+    /// Keys the command may write to
+    fn keys(&self) -> Vec<String> {
+        match self {
+            KVStoreCommand::Del(cmd) => cmd.keys.clone(),
+            KVStoreCommand::LMove(cmd) => vec![cmd.source.clone(), cmd.destination.clone()],
+            cmd => vec![cmd.key().to_string()],
+        }
+    }
+
+    /// The command may use more memory, it is refused once `maxmemory` is
+    /// reached and no key can be evicted
+    fn denies_oom(&self) -> bool {
+        matches!(
+            self,
+            KVStoreCommand::Append(_)
+                | KVStoreCommand::BitField(_)
+                | KVStoreCommand::HIncrBy(_)
+                | KVStoreCommand::HSet(_)
+                | KVStoreCommand::IncrBy(_)
+                | KVStoreCommand::IncrByFloat(_)
+                | KVStoreCommand::LMove(_)
+                | KVStoreCommand::LSet(_)
+                | KVStoreCommand::PfAdd(_)
+                | KVStoreCommand::Push(_)
+                | KVStoreCommand::SAdd(_)
+                | KVStoreCommand::Set(_)
+                | KVStoreCommand::SetBit(_)
+                | KVStoreCommand::SetRange(_)
+                | KVStoreCommand::XAdd(_)
+                | KVStoreCommand::XGroup(XGroup {
+                    action: XGroupAction::Create { .. } | XGroupAction::CreateConsumer(_),
+                    ..
+                })
+                | KVStoreCommand::XSetId(_)
+                | KVStoreCommand::ZAdd(_)
+                | KVStoreCommand::ZIncrBy(_)
+        )
+    }
+}
+
+impl TryFrom<Command> for KVStoreCommand {
+    type Error = Command;
+
+    fn try_from(cmd: Command) -> Result<KVStoreCommand, Command> {
+        match cmd {
+            Command::Append(cmd) => Ok(KVStoreCommand::Append(cmd)),
+            Command::BitCount(cmd) => Ok(KVStoreCommand::BitCount(cmd)),
+            Command::BitField(cmd) => Ok(KVStoreCommand::BitField(cmd)),
+            Command::BitPos(cmd) => Ok(KVStoreCommand::BitPos(cmd)),
+            // Commands of a single key, see `Backend::process`
+            Command::Del(cmd) if cmd.keys.len() == 1 => Ok(KVStoreCommand::Del(cmd)),
+            Command::Exists(cmd) if cmd.keys.len() == 1 => Ok(KVStoreCommand::Exists(cmd)),
+            Command::Expire(cmd) => Ok(KVStoreCommand::Expire(cmd)),
+            Command::GeoAdd(cmd) => Ok(KVStoreCommand::ZAdd(cmd.zadd)),
+            Command::GeoDist(cmd) => Ok(KVStoreCommand::GeoDist(cmd)),
+            Command::GeoHash(cmd) => Ok(KVStoreCommand::GeoHash(cmd)),
+            Command::GeoPos(cmd) => Ok(KVStoreCommand::GeoPos(cmd)),
+            Command::GeoSearch(cmd) => Ok(KVStoreCommand::GeoSearch(cmd)),
+            Command::Get(cmd) => Ok(KVStoreCommand::Get(cmd)),
+            Command::GetBit(cmd) => Ok(KVStoreCommand::GetBit(cmd)),
+            Command::GetDel(cmd) => Ok(KVStoreCommand::GetDel(cmd)),
+            Command::GetEx(cmd) => Ok(KVStoreCommand::GetEx(cmd)),
+            Command::GetRange(cmd) => Ok(KVStoreCommand::GetRange(cmd)),
+            Command::HDel(cmd) => Ok(KVStoreCommand::HDel(cmd)),
+            Command::HExists(cmd) => Ok(KVStoreCommand::HExists(cmd)),
+            Command::HGet(cmd) => Ok(KVStoreCommand::HGet(cmd)),
+            Command::HGetAll(cmd) => Ok(KVStoreCommand::HGetAll(cmd)),
+            Command::HIncrBy(cmd) => Ok(KVStoreCommand::HIncrBy(cmd)),
+            Command::HKeys(cmd) => Ok(KVStoreCommand::HKeys(cmd)),
+            Command::HLen(cmd) => Ok(KVStoreCommand::HLen(cmd)),
+            Command::HMGet(cmd) => Ok(KVStoreCommand::HMGet(cmd)),
+            Command::HScan(cmd) => Ok(KVStoreCommand::HScan(cmd)),
+            Command::HSet(cmd) => Ok(KVStoreCommand::HSet(cmd)),
+            Command::HVals(cmd) => Ok(KVStoreCommand::HVals(cmd)),
+            Command::IncrBy(cmd) => Ok(KVStoreCommand::IncrBy(cmd)),
+            Command::IncrByFloat(cmd) => Ok(KVStoreCommand::IncrByFloat(cmd)),
+            Command::LIndex(cmd) => Ok(KVStoreCommand::LIndex(cmd)),
+            Command::LLen(cmd) => Ok(KVStoreCommand::LLen(cmd)),
+            Command::LMove(cmd) => Ok(KVStoreCommand::LMove(cmd)),
+            Command::LRange(cmd) => Ok(KVStoreCommand::LRange(cmd)),
+            Command::LRem(cmd) => Ok(KVStoreCommand::LRem(cmd)),
+            Command::LSet(cmd) => Ok(KVStoreCommand::LSet(cmd)),
+            Command::LTrim(cmd) => Ok(KVStoreCommand::LTrim(cmd)),
+            Command::Move(cmd) => Ok(KVStoreCommand::Move(cmd)),
+            Command::Persist(cmd) => Ok(KVStoreCommand::Persist(cmd)),
+            Command::PfAdd(cmd) => Ok(KVStoreCommand::PfAdd(cmd)),
+            Command::PfCount(cmd) if cmd.keys.len() == 1 => Ok(KVStoreCommand::PfCount(cmd)),
+            Command::Pop(cmd) => Ok(KVStoreCommand::Pop(cmd)),
+            Command::Push(cmd) => Ok(KVStoreCommand::Push(cmd)),
+            Command::SAdd(cmd) => Ok(KVStoreCommand::SAdd(cmd)),
+            Command::SCard(cmd) => Ok(KVStoreCommand::SCard(cmd)),
+            Command::Set(cmd) => Ok(KVStoreCommand::Set(cmd)),
+            Command::SetBit(cmd) => Ok(KVStoreCommand::SetBit(cmd)),
+            Command::SetRange(cmd) => Ok(KVStoreCommand::SetRange(cmd)),
+            Command::SIsMember(cmd) => Ok(KVStoreCommand::SIsMember(cmd)),
+            Command::SMembers(cmd) => Ok(KVStoreCommand::SMembers(cmd)),
+            Command::SRem(cmd) => Ok(KVStoreCommand::SRem(cmd)),
+            Command::StrLen(cmd) => Ok(KVStoreCommand::StrLen(cmd)),
+            Command::Ttl(cmd) => Ok(KVStoreCommand::Ttl(cmd)),
+            Command::Type(cmd) => Ok(KVStoreCommand::Type(cmd)),
+            Command::XAck(cmd) => Ok(KVStoreCommand::XAck(cmd)),
+            Command::XAdd(cmd) => Ok(KVStoreCommand::XAdd(cmd)),
+            Command::XAutoClaim(cmd) => Ok(KVStoreCommand::XAutoClaim(cmd)),
+            Command::XClaim(cmd) => Ok(KVStoreCommand::XClaim(cmd)),
+            Command::XDel(cmd) => Ok(KVStoreCommand::XDel(cmd)),
+            Command::XGroup(cmd) => Ok(KVStoreCommand::XGroup(cmd)),
+            Command::XLen(cmd) => Ok(KVStoreCommand::XLen(cmd)),
+            Command::XPending(cmd) => Ok(KVStoreCommand::XPending(cmd)),
+            Command::XRange(cmd) => Ok(KVStoreCommand::XRange(cmd)),
+            Command::XSetId(cmd) => Ok(KVStoreCommand::XSetId(cmd)),
+            Command::ZAdd(cmd) => Ok(KVStoreCommand::ZAdd(cmd)),
+            Command::ZCard(cmd) => Ok(KVStoreCommand::ZCard(cmd)),
+            Command::ZIncrBy(cmd) => Ok(KVStoreCommand::ZIncrBy(cmd)),
+            Command::ZRange(cmd) => Ok(KVStoreCommand::ZRange(cmd)),
+            Command::ZRank(cmd) => Ok(KVStoreCommand::ZRank(cmd)),
+            Command::ZRem(cmd) => Ok(KVStoreCommand::ZRem(cmd)),
+            Command::ZScore(cmd) => Ok(KVStoreCommand::ZScore(cmd)),
+            cmd => Err(cmd),
+        }
+    }
+}
+
+pub struct Shard {
+    /// Key space of the selected database, see `select`
+    db: Db,
+    /// Writes since the last snapshot, shared by all the shards
+    dirty: Arc<AtomicU64>,
+    /// Writes of the last executed command, as commands to replay on the
+    /// database at the given index
+    writes: Vec<(usize, Frame)>,
+    /// Clients waiting for an element to be pushed to a list, by key
+    blocked: HashMap<String, VecDeque<Waiter>>,
+    /// Flags of the connections watching a key, set once it is touched
+    watched: HashMap<String, Vec<Arc<AtomicBool>>>,
+    selected: usize,
+    /// The databases which are not selected, the selected one being moved
+    /// out to the fields above
+    databases: Vec<Database>,
+    maxmemory: MaxMemory,
+    /// Shards sharing `maxmemory`
+    shards: u64,
+    /// Estimated memory used by all the shards
+    used_memory: Arc<AtomicU64>,
+    /// Memory of the shard accounted in `used_memory`
+    accounted_memory: u64,
+    eviction_pool: EvictionPool,
+    /// Expired and evicted keys, counted for all the shards
+    stats: Arc<KeyspaceStats>,
+    /// Where keyspace events are published, see `notify`
+    broker: Option<Broker>,
+    notify_events: NotifyEvents,
+}
+
+/// A logical database, with the clients blocked on or watching its keys
+#[derive(Default)]
+struct Database {
+    db: Db,
+    blocked: HashMap<String, VecDeque<Waiter>>,
+    watched: HashMap<String, Vec<Arc<AtomicBool>>>,
+}
+
+impl Shard {
+    pub fn new(dirty: Arc<AtomicU64>, databases: usize) -> Self {
+        Self {
+            db: Db::new(),
+            dirty,
+            writes: vec![],
+            blocked: HashMap::new(),
+            watched: HashMap::new(),
+            selected: 0,
+            databases: (0..databases).map(|_| Database::default()).collect(),
+            maxmemory: MaxMemory::default(),
+            shards: 1,
+            used_memory: Arc::new(AtomicU64::new(0)),
+            accounted_memory: 0,
+            eviction_pool: EvictionPool::default(),
+            stats: Arc::new(KeyspaceStats::default()),
+            broker: None,
+            notify_events: NotifyEvents::default(),
+        }
+    }
+
+    /// Count the expired and evicted keys in `stats`, shared with the other
+    /// shards
+    pub fn count_in(&mut self, stats: Arc<KeyspaceStats>) {
+        self.stats = stats;
+    }
+
+    /// Publish the keyspace events of the enabled classes to the broker
+    pub fn notify_to(&mut self, broker: Broker, events: NotifyEvents) {
+        self.broker = Some(broker);
+        self.notify_events = events;
+    }
+
+    /// Account the memory of the shard in `used`, shared with the other
+    /// `shards`, and evict keys once it exceeds `maxmemory`
+    pub fn limit_memory(&mut self, used: Arc<AtomicU64>, maxmemory: MaxMemory, shards: usize) {
+        self.used_memory = used;
+        self.shards = shards as u64;
+        self.accounted_memory = 0;
+        self.maxmemory = maxmemory;
+        self.account_memory();
+    }
+
+    /// The selected database
+    pub fn db(&mut self) -> &mut Db {
+        &mut self.db
+    }
+
+    /// Select the database the commands apply to, by swapping it with the
+    /// fields of the previously selected one
+    pub fn select(&mut self, index: usize) {
+        if index == self.selected {
+            return;
+        }
+        for index in [self.selected, index] {
+            let database = &mut self.databases[index];
+            std::mem::swap(&mut self.db, &mut database.db);
+            std::mem::swap(&mut self.blocked, &mut database.blocked);
+            std::mem::swap(&mut self.watched, &mut database.watched);
+        }
+        self.selected = index;
+    }
+
+    pub fn databases(&self) -> usize {
+        self.databases.len()
+    }
+
+    /// Copy of the entries of every database, by index
+    pub fn dump(&mut self) -> Vec<Vec<(String, Entry)>> {
+        let selected = self.selected;
+        let dbs = (0..self.databases())
+            .map(|index| {
+                self.select(index);
+                self.db.dump()
+            })
+            .collect();
+        self.select(selected);
+        dbs
+    }
+
+    /// Empty the selected database, the entries are dropped in the
+    /// background when `lazy` is set
+    pub fn flush(&mut self, lazy: bool) {
+        let db = std::mem::take(&mut self.db);
+        self.touch_all();
+        self.account_memory();
+        if lazy {
+            tokio::task::spawn_blocking(move || drop(db));
+        }
+    }
+
+    /// Swap the key spaces of two databases, the clients blocked on or
+    /// watching a key stay with their database
+    pub fn swap_dbs(&mut self, first: usize, second: usize) {
+        if first == second {
+            return;
+        }
+        let selected = self.selected;
+        self.select(first);
+        let db = std::mem::take(&mut self.db);
+        self.select(second);
+        let db = std::mem::replace(&mut self.db, db);
+        self.touch_all();
+        self.serve_all_blocked();
+        self.select(first);
+        self.db = db;
+        self.touch_all();
+        self.serve_all_blocked();
+        self.select(selected);
+    }
+
+    /// `MOVE`, between two databases of the shard
+    fn move_key(&mut self, cmd: Move) -> Frame {
+        if cmd.db >= self.databases() {
+            return Frame::Error("ERR DB index is out of range".to_string());
+        }
+        if cmd.db == self.selected {
+            return Frame::Error("ERR source and destination objects are the same".to_string());
+        }
+        if self.db.get(cmd.key()).is_none() {
+            return Frame::Integer(0);
+        }
+        let source = self.selected;
+        self.select(cmd.db);
+        let exists = self.db.get(cmd.key()).is_some();
+        self.select(source);
+        if exists {
+            return Frame::Integer(0);
+        }
+
+        let entry = self.take(cmd.key()).unwrap();
+        self.notify(NotifyEvents::GENERIC, "move_from", &cmd.key);
+        self.select(cmd.db);
+        self.insert(cmd.key.clone(), entry);
+        self.notify(NotifyEvents::GENERIC, "move_to", &cmd.key);
+        self.select(source);
+        Frame::Integer(1)
+    }
+
+    /// `XGROUP`, all subcommands but `CREATE` need the group
+    fn xgroup(&mut self, cmd: XGroup) -> Frame {
+        let create = matches!(cmd.action, XGroupAction::Create { mkstream: true, .. });
+        let stream =
+            match self.stream_mut(cmd.key(), create) {
+                Ok(Some(stream)) => stream,
+                Ok(None) => return Frame::Error(
+                    "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE \
+                     you may want to use the MKSTREAM option to create an empty stream \
+                     automatically."
+                        .to_string(),
+                ),
+                Err(err) => return err,
+            };
+        let last_id = stream.last_id();
+        let (response, args) = match cmd.action {
+            XGroupAction::Create { id, mkstream } => {
+                let id = id.unwrap_or(last_id);
+                if !stream.create_group(cmd.group.clone(), ConsumerGroup::new(id)) {
+                    return Frame::Error(
+                        "BUSYGROUP Consumer Group name already exists".to_string(),
+                    );
+                }
+                let mut args = vec![Bytes::from_static(b"CREATE"), id.to_bytes()];
+                if mkstream {
+                    args.push(Bytes::from_static(b"MKSTREAM"));
+                }
+                (Frame::Simple("OK".to_string()), Some(args))
+            }
+            XGroupAction::Destroy => {
+                let destroyed = stream.destroy_group(&cmd.group);
+                let args = destroyed.then(|| vec![Bytes::from_static(b"DESTROY")]);
+                (Frame::Integer(destroyed as i64), args)
+            }
+            action => {
+                let Some(group) = stream.group_mut(&cmd.group) else {
+                    return Frame::Error(format!(
+                        "NOGROUP No such consumer group '{}' for key name '{}'",
+                        cmd.group, cmd.key
+                    ));
+                };
+                match action {
+                    XGroupAction::CreateConsumer(name) => {
+                        let created = group.create_consumer(&name, now_ms());
+                        let args = created.then(|| {
+                            vec![Bytes::from_static(b"CREATECONSUMER"), Bytes::from(name)]
+                        });
+                        (Frame::Integer(created as i64), args)
+                    }
+                    XGroupAction::DelConsumer(name) => match group.delete_consumer(&name) {
+                        Some(pending) => (
+                            Frame::Integer(pending as i64),
+                            Some(vec![Bytes::from_static(b"DELCONSUMER"), Bytes::from(name)]),
+                        ),
+                        None => (Frame::Integer(0), None),
+                    },
+                    XGroupAction::SetId(id) => {
+                        group.last_id = id.unwrap_or(last_id);
+                        let args = vec![Bytes::from_static(b"SETID"), group.last_id.to_bytes()];
+                        (Frame::Simple("OK".to_string()), Some(args))
+                    }
+                    XGroupAction::Create { .. } | XGroupAction::Destroy => unreachable!(),
+                }
+            }
+        };
+
+        if let Some(mut args) = args {
+            let subcommand = args.remove(0);
+            let event = format!(
+                "xgroup-{}",
+                String::from_utf8_lossy(&subcommand).to_lowercase()
+            );
+            self.notify(NotifyEvents::STREAM, &event, &cmd.key);
+            let mut write = vec![
+                Bytes::from_static(b"XGROUP"),
+                subcommand,
+                Bytes::from(cmd.key),
+                Bytes::from(cmd.group),
+            ];
+            write.extend(args);
+            self.propagate(write);
+        }
+        response
+    }
+
+    /// `XPENDING`, summarized by consumer without a range
+    fn xpending(&mut self, cmd: XPending) -> Frame {
+        let group = match self.stream_mut(cmd.key(), false) {
+            Ok(Some(stream)) => stream.group(&cmd.group),
+            Ok(None) => None,
+            Err(err) => return err,
+        };
+        let Some(group) = group else {
+            return no_group(&cmd.key, &cmd.group);
+        };
+
+        let Some(range) = cmd.range else {
+            let (Some((first, _)), Some((last, _))) = (
+                group.pending.first_key_value(),
+                group.pending.last_key_value(),
+            ) else {
+                return Frame::Array(vec![
+                    Frame::Integer(0),
+                    Frame::Null,
+                    Frame::Null,
+                    Frame::NullArray,
+                ]);
+            };
+            let consumers = group
+                .consumers
+                .iter()
+                .filter(|(_, consumer)| !consumer.pending.is_empty())
+                .map(|(name, consumer)| {
+                    Frame::Array(vec![
+                        Frame::Bulk(Bytes::from(name.clone())),
+                        Frame::Bulk(Bytes::from(consumer.pending.len().to_string())),
+                    ])
+                })
+                .collect();
+            return Frame::Array(vec![
+                Frame::Integer(group.pending.len() as i64),
+                Frame::Bulk(first.to_bytes()),
+                Frame::Bulk(last.to_bytes()),
+                Frame::Array(consumers),
+            ]);
+        };
+
+        if range.start > range.end {
+            return Frame::Array(vec![]);
+        }
+        let now = now_ms();
+        let entries = group
+            .pending
+            .range(range.start..=range.end)
+            .filter(|(_, entry)| {
+                range
+                    .consumer
+                    .as_ref()
+                    .is_none_or(|consumer| *consumer == entry.consumer)
+            })
+            .filter(|(_, entry)| now.saturating_sub(entry.delivered_at) >= range.min_idle)
+            .take(range.count)
+            .map(|(id, entry)| {
+                Frame::Array(vec![
+                    Frame::Bulk(id.to_bytes()),
+                    Frame::Bulk(Bytes::from(entry.consumer.clone())),
+                    Frame::Integer(now.saturating_sub(entry.delivered_at) as i64),
+                    Frame::Integer(entry.deliveries as i64),
+                ])
+            })
+            .collect();
+        Frame::Array(entries)
+    }
+
+    /// `XCLAIM`, pending entries deleted from the stream are acknowledged
+    fn xclaim(&mut self, cmd: XClaim) -> Frame {
+        let now = now_ms();
+        let delivered_at = match (cmd.time, cmd.idle) {
+            (Some(time), _) => time.min(now),
+            (None, Some(idle)) => now.saturating_sub(idle),
+            (None, None) => now,
+        };
+        let stream = match self.stream_mut(cmd.key(), false) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => return no_group(&cmd.key, &cmd.group),
+            Err(err) => return err,
+        };
+        let Some((group, entries)) = stream.group_with_entries(&cmd.group) else {
+            return no_group(&cmd.key, &cmd.group);
+        };
+
+        let created = group.seen(&cmd.consumer, now);
+        let mut claimed = vec![];
+        let mut deleted = vec![];
+        for id in &cmd.ids {
+            let pending = group.pending.get(id);
+            let previous = pending.map(|entry| (entry.delivered_at, entry.deliveries));
+            let Some(fields) = entries.get(id) else {
+                if previous.is_some() {
+                    group.ack(id);
+                    deleted.push(*id);
+                }
+                continue;
+            };
+            let deliveries = match previous {
+                Some((at, _)) if now.saturating_sub(at) < cmd.min_idle => continue,
+                Some((_, deliveries)) => deliveries,
+                None if cmd.force => 0,
+                None => continue,
+            };
+            let deliveries = cmd
+                .retry_count
+                .unwrap_or(deliveries + u64::from(!cmd.justid));
+            group.deliver(*id, &cmd.consumer, delivered_at, deliveries);
+            claimed.push((*id, fields));
+        }
+        let moved = cmd.last_id.filter(|last_id| *last_id > group.last_id);
+        if let Some(last_id) = moved {
+            group.last_id = last_id;
+        }
+
+        let response = claimed
+            .iter()
+            .map(|(id, fields)| match cmd.justid {
+                true => Frame::Bulk(id.to_bytes()),
+                false => entry_frame(*id, fields),
+            })
+            .collect();
+        let claims = claimed
+            .iter()
+            .map(|(id, _)| (*id, group.pending[id].clone()))
+            .collect();
+        let last_id = group.last_id;
+        let set_id = moved.is_some() && claimed.is_empty();
+
+        if created {
+            self.propagate_group(&cmd.key, &cmd.group, "CREATECONSUMER", &cmd.consumer);
+            self.notify(NotifyEvents::STREAM, "xgroup-createconsumer", &cmd.key);
+        }
+        self.propagate_claims(&cmd.key, &cmd.group, claims, last_id);
+        if set_id {
+            self.propagate_group(&cmd.key, &cmd.group, "SETID", &last_id.to_string());
+        }
+        self.propagate_ack(&cmd.key, &cmd.group, &deleted);
+        Frame::Array(response)
+    }
+
+    /// `XAUTOCLAIM`, scans up to ten times `COUNT` pending entries
+    fn xautoclaim(&mut self, cmd: XAutoClaim) -> Frame {
+        let now = now_ms();
+        let stream = match self.stream_mut(cmd.key(), false) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => return no_group(&cmd.key, &cmd.group),
+            Err(err) => return err,
+        };
+        let Some((group, entries)) = stream.group_with_entries(&cmd.group) else {
+            return no_group(&cmd.key, &cmd.group);
+        };
+
+        let created = group.seen(&cmd.consumer, now);
+        let attempts = cmd.count * 10;
+        let scanned: Vec<StreamId> = group
+            .pending
+            .range(cmd.start..)
+            .map(|(id, _)| *id)
+            .take(attempts + 1)
+            .collect();
+        let mut cursor = StreamId::MIN;
+        let mut claimed = vec![];
+        let mut deleted = vec![];
+        for (scans, id) in scanned.into_iter().enumerate() {
+            if scans == attempts || claimed.len() == cmd.count {
+                cursor = id;
+                break;
+            }
+            let Some(fields) = entries.get(&id) else {
+                group.ack(&id);
+                deleted.push(id);
+                continue;
+            };
+            let entry = &group.pending[&id];
+            if now.saturating_sub(entry.delivered_at) < cmd.min_idle {
+                continue;
+            }
+            let deliveries = entry.deliveries + u64::from(!cmd.justid);
+            group.deliver(id, &cmd.consumer, now, deliveries);
+            claimed.push((id, fields));
+        }
+
+        let response = Frame::Array(vec![
+            Frame::Bulk(cursor.to_bytes()),
+            Frame::Array(
+                claimed
+                    .iter()
+                    .map(|(id, fields)| match cmd.justid {
+                        true => Frame::Bulk(id.to_bytes()),
+                        false => entry_frame(*id, fields),
+                    })
+                    .collect(),
+            ),
+            Frame::Array(
+                deleted
+                    .iter()
+                    .map(|id| Frame::Bulk(id.to_bytes()))
+                    .collect(),
+            ),
+        ]);
+        let claims = claimed
+            .iter()
+            .map(|(id, _)| (*id, group.pending[id].clone()))
+            .collect();
+        let last_id = group.last_id;
+
+        if created {
+            self.propagate_group(&cmd.key, &cmd.group, "CREATECONSUMER", &cmd.consumer);
+            self.notify(NotifyEvents::STREAM, "xgroup-createconsumer", &cmd.key);
+        }
+        self.propagate_claims(&cmd.key, &cmd.group, claims, last_id);
+        self.propagate_ack(&cmd.key, &cmd.group, &deleted);
+        response
+    }
+
+    /// Read a stream for `XREAD` or `XREADGROUP`, replies the key with the
+    /// entries read, or null when there are none
+    fn read_stream(&mut self, read: StreamRead) -> Frame {
+        let count = read.count.unwrap_or(usize::MAX);
+        let Some(group_read) = read.group else {
+            let entries: Vec<Frame> = match (self.stream_mut(&read.key, false), read.after) {
+                (Ok(Some(stream)), Some(after)) => stream
+                    .after(after)
+                    .take(count)
+                    .map(|(id, fields)| entry_frame(*id, fields))
+                    .collect(),
+                (Ok(_), _) => vec![],
+                (Err(err), _) => return err,
+            };
+            if entries.is_empty() {
+                return Frame::Null;
+            }
+            return Frame::Array(vec![
+                Frame::Bulk(Bytes::from(read.key)),
+                Frame::Array(entries),
+            ]);
+        };
+
+        let now = now_ms();
+        let group = match self.stream_mut(&read.key, false) {
+            Ok(Some(stream)) => stream.group_with_entries(&group_read.group),
+            Ok(None) => None,
+            Err(err) => return err,
+        };
+        let Some((group, entries)) = group else {
+            return Frame::Error(format!(
+                "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                read.key, group_read.group
+            ));
+        };
+
+        let created = group.seen(&group_read.consumer, now);
+        let mut response = vec![];
+        let mut claims = vec![];
+        match read.after {
+            // `>`, entries never delivered to the group
+            None => {
+                let new = entries.range((Bound::Excluded(group.last_id), Bound::Unbounded));
+                for (id, fields) in new.take(count) {
+                    group.last_id = *id;
+                    if !group_read.noack {
+                        group.deliver(*id, &group_read.consumer, now, 1);
+                        claims.push((*id, group.pending[id].clone()));
+                    }
+                    response.push(entry_frame(*id, fields));
+                }
+            }
+            // The history of the consumer, deleted entries have no fields
+            Some(after) => {
+                let pending = &group.consumers[&group_read.consumer].pending;
+                let ids: Vec<StreamId> = pending
+                    .range((Bound::Excluded(after), Bound::Unbounded))
+                    .take(count)
+                    .copied()
+                    .collect();
+                for id in ids {
+                    let Some(fields) = entries.get(&id) else {
+                        response.push(Frame::Array(vec![
+                            Frame::Bulk(id.to_bytes()),
+                            Frame::NullArray,
+                        ]));
+                        continue;
+                    };
+                    let entry = group.pending.get_mut(&id).unwrap();
+                    entry.delivered_at = now;
+                    entry.deliveries += 1;
+                    claims.push((id, entry.clone()));
+                    response.push(entry_frame(id, fields));
+                }
+            }
+        }
+        let last_id = group.last_id;
+
+        if created {
+            self.propagate_group(
+                &read.key,
+                &group_read.group,
+                "CREATECONSUMER",
+                &group_read.consumer,
+            );
+            self.notify(NotifyEvents::STREAM, "xgroup-createconsumer", &read.key);
+        }
+        self.propagate_claims(&read.key, &group_read.group, claims, last_id);
+        if read.after.is_none() && group_read.noack && !response.is_empty() {
+            self.propagate_group(&read.key, &group_read.group, "SETID", &last_id.to_string());
+        }
+        if read.after.is_none() && response.is_empty() {
+            return Frame::Null;
+        }
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from(read.key)),
+            Frame::Array(response),
+        ])
+    }
+
+    /// Execute a command, writes are recorded to be fed to the AOF and touch
+    /// the keys of the command
+    pub fn execute(&mut self, cmd: KVStoreCommand) -> Frame {
+        if cmd.denies_oom() {
+            if let Err(err) = self.free_memory() {
+                return err;
+            }
+        }
+
+        let keys = cmd.keys();
+        let writes = self.writes.len();
+        let response = self.apply(cmd);
+        if self.writes.len() > writes {
+            for key in keys {
+                self.db.resize(&key);
+                self.touch(&key);
+            }
+            self.account_memory();
+        }
+        response
+    }
+
+    /// Update the memory of the shard in the memory used by the server
+    fn account_memory(&mut self) {
+        let used = self.db.used_memory()
+            + self
+                .databases
+                .iter()
+                .map(|database| database.db.used_memory())
+                .sum::<usize>();
+        let used = used as u64;
+        if used > self.accounted_memory {
+            self.used_memory
+                .fetch_add(used - self.accounted_memory, Ordering::Relaxed);
+        } else {
+            self.used_memory
+                .fetch_sub(self.accounted_memory - used, Ordering::Relaxed);
+        }
+        self.accounted_memory = used;
+    }
+
+    /// Evict keys of the shard until the memory used by the server is under
+    /// `maxmemory`, fails with an `OOM` error when no key can be evicted.
+    ///
+    /// A shard only evicts its keys while it uses more than its share of
+    /// `maxmemory`, the other shards evict theirs on their next write.
+    pub fn free_memory(&mut self) -> Result<(), Frame> {
+        if self.maxmemory.limit == 0 {
+            return Ok(());
+        }
+        self.account_memory();
+        let share = self.maxmemory.limit / self.shards;
+        while self.used_memory.load(Ordering::Relaxed) > self.maxmemory.limit {
+            if self.maxmemory.policy == EvictionPolicy::NoEviction {
+                return Err(Frame::Error(OOM.to_string()));
+            }
+            if self.accounted_memory <= share {
+                break;
+            }
+            if !self.evict() {
+                return Err(Frame::Error(OOM.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Evict the best candidate among the keys sampled from every database,
+    /// returns `false` when there is none
+    fn evict(&mut self) -> bool {
+        let selected = self.selected;
+        let now = now_ms();
+        let MaxMemory {
+            policy, samples, ..
+        } = self.maxmemory;
+        for db in 0..self.databases() {
+            self.select(db);
+            for (score, key) in self.db.sample(policy, samples, now) {
+                self.eviction_pool.insert(score, db, key);
+            }
+        }
+
+        let mut evicted = false;
+        while let Some((db, key)) = self.eviction_pool.pop() {
+            // The candidate may be gone since it was sampled
+            self.select(db);
+            if self.db.remove(&key).is_some() {
+                self.propagate(vec![Bytes::from_static(b"DEL"), Bytes::from(key.clone())]);
+                self.notify(NotifyEvents::EVICTED, "evicted", &key);
+                self.touch(&key);
+                self.stats.evicted_keys.fetch_add(1, Ordering::Relaxed);
+                evicted = true;
+                break;
+            }
+        }
+        self.select(selected);
+        self.account_memory();
+        evicted
+    }
+
+    fn apply(&mut self, cmd: KVStoreCommand) -> Frame {
+        match cmd {
+            KVStoreCommand::Set(cmd) => {
+                let current = self.db.get(cmd.key());
+                let previous = match current {
+                    Some(Entry {
+                        value: Value::String(value),
+                        ..
+                    }) if cmd.get => Frame::Bulk(Bytes::from(value.clone())),
+                    Some(_) if cmd.get => return Frame::Error(WRONGTYPE.to_string()),
+                    _ => Frame::Null,
+                };
+                let allowed = match cmd.existence {
+                    Some(Existence::Missing) => current.is_none(),
+                    Some(Existence::Present) => current.is_some(),
+                    None => true,
+                };
+                if !allowed {
+                    return match cmd.nx {
+                        true => Frame::Integer(0),
+                        false => previous,
+                    };
+                }
+
+                let expire_at = match cmd.expiration {
+                    Some(expiration) => expiration.expire_at(now_ms()),
+                    None if cmd.keep_ttl => current.and_then(|entry| entry.expire_at),
+                    None => None,
+                };
+                self.db.set(
+                    cmd.key().to_string(),
+                    Value::String(cmd.value().to_vec()),
+                    expire_at,
+                );
+                self.propagate(vec![
+                    Bytes::from_static(b"SET"),
+                    Bytes::from(cmd.key.clone()),
+                    cmd.value.clone(),
+                ]);
+                self.notify(NotifyEvents::STRING, "set", &cmd.key);
+                if let Some(at) = expire_at {
+                    self.propagate_expire_at(&cmd.key, at as i64);
+                    self.notify(NotifyEvents::GENERIC, "expire", &cmd.key);
+                }
+
+                if cmd.nx {
+                    Frame::Integer(1)
+                } else if cmd.get {
+                    previous
+                } else {
+                    Frame::Simple("OK".to_string())
+                }
+            }
+            KVStoreCommand::Get(cmd) => match self.db.get(cmd.key()) {
+                Some(Entry {
+                    value: Value::String(value),
+                    ..
+                }) => Frame::Bulk(value.clone().into()),
+                Some(_) => Frame::Error(WRONGTYPE.to_string()),
+                None => Frame::Null,
+            },
+            KVStoreCommand::GetDel(cmd) => {
+                let value = match self.string_mut(cmd.key(), false) {
+                    Ok(Some(value)) => Bytes::from(std::mem::take(value)),
+                    Ok(None) => return Frame::Null,
+                    Err(err) => return err,
+                };
+                self.db.remove(cmd.key());
+                self.propagate(vec![
+                    Bytes::from_static(b"GETDEL"),
+                    Bytes::from(cmd.key.clone()),
+                ]);
+                self.notify(NotifyEvents::GENERIC, "del", &cmd.key);
+                Frame::Bulk(value)
+            }
+            KVStoreCommand::GetEx(cmd) => {
+                let value = match self.string_mut(cmd.key(), false) {
+                    Ok(Some(value)) => Bytes::from(value.clone()),
+                    Ok(None) => return Frame::Null,
+                    Err(err) => return err,
+                };
+                match cmd.expiration {
+                    Some(Expiration::Persist)
+                        if self
+                            .db
+                            .get(cmd.key())
+                            .is_some_and(|e| e.expire_at.is_some()) =>
+                    {
+                        self.db.set_expire(cmd.key(), None);
+                        self.propagate(vec![
+                            Bytes::from_static(b"PERSIST"),
+                            Bytes::from(cmd.key.clone()),
+                        ]);
+                        self.notify(NotifyEvents::GENERIC, "persist", &cmd.key);
+                    }
+                    Some(Expiration::Persist) | None => {}
+                    Some(expiration) => {
+                        let now = now_ms();
+                        let expire_at = expiration.expire_at(now).unwrap();
+                        let event = if expire_at <= now {
+                            self.db.remove(cmd.key());
+                            "del"
+                        } else {
+                            self.db.set_expire(cmd.key(), Some(expire_at));
+                            "expire"
+                        };
+                        self.propagate_expire_at(&cmd.key, expire_at as i64);
+                        self.notify(NotifyEvents::GENERIC, event, &cmd.key);
+                    }
+                }
+                Frame::Bulk(value)
+            }
+            KVStoreCommand::GetRange(cmd) => match self.string_mut(cmd.key(), false) {
+                Ok(Some(value)) => match list_range(cmd.start, cmd.end, value.len()) {
+                    Some((start, end)) => Frame::Bulk(Bytes::copy_from_slice(&value[start..=end])),
+                    None => Frame::Bulk(Bytes::new()),
+                },
+                Ok(None) => Frame::Bulk(Bytes::new()),
+                Err(err) => err,
+            },
+            KVStoreCommand::SetRange(cmd) => {
+                if cmd.value.is_empty() {
+                    return match self.string_mut(cmd.key(), false) {
+                        Ok(value) => Frame::Integer(value.map_or(0, |value| value.len()) as i64),
+                        Err(err) => err,
+                    };
+                }
+                let len = match self.string_mut(cmd.key(), true) {
+                    Ok(Some(value)) => {
+                        let end = cmd.offset + cmd.value.len();
+                        if value.len() < end {
+                            value.resize(end, 0);
+                        }
+                        value[cmd.offset..end].copy_from_slice(&cmd.value);
+                        value.len()
+                    }
+                    Ok(None) => unreachable!(),
+                    Err(err) => return err,
+                };
+                self.propagate(vec![
+                    Bytes::from_static(b"SETRANGE"),
+                    Bytes::from(cmd.key.clone()),
+                    Bytes::from(cmd.offset.to_string()),
+                    cmd.value,
+                ]);
+                self.notify(NotifyEvents::STRING, "setrange", &cmd.key);
+                Frame::Integer(len as i64)
+            }
+            KVStoreCommand::SetBit(cmd) => {
+                let previous = match self.string_mut(cmd.key(), true) {
+                    Ok(Some(value)) => set_bit(value, cmd.offset, cmd.bit),
+                    Ok(None) => unreachable!(),
+                    Err(err) => return err,
+                };
+                self.propagate(vec![
+                    Bytes::from_static(b"SETBIT"),
+                    Bytes::from(cmd.key.clone()),
+                    Bytes::from(cmd.offset.to_string()),
+                    Bytes::from(cmd.bit.to_string()),
+                ]);
+                self.notify(NotifyEvents::STRING, "setbit", &cmd.key);
+                Frame::Integer(previous as i64)
+            }
+            KVStoreCommand::GetBit(cmd) => match self.string_mut(cmd.key(), false) {
+                Ok(value) => {
+                    Frame::Integer(value.map_or(0, |value| get_bit(value, cmd.offset)) as i64)
+                }
+                Err(err) => err,
+            },
+            KVStoreCommand::BitCount(cmd) => match self.string_mut(cmd.key(), false) {
+                Ok(Some(value)) => {
+                    let range = match cmd.range {
+                        Some((start, end, unit)) => bit_range(start, end, unit, value.len()),
+                        None => bit_range(0, -1, BitUnit::Byte, value.len()),
+                    };
+                    let count = range.map_or(0, |(start, end)| count_bits(value, start, end));
+                    Frame::Integer(count as i64)
+                }
+                Ok(None) => Frame::Integer(0),
+                Err(err) => err,
+            },
+            KVStoreCommand::BitPos(cmd) => match self.string_mut(cmd.key(), false) {
+                Ok(Some(value)) => {
+                    let start = cmd.start.unwrap_or(0);
+                    let end = cmd.end.unwrap_or(-1);
+                    let Some((start, end)) = bit_range(start, end, cmd.unit, value.len()) else {
+                        return Frame::Integer(-1);
+                    };
+                    match find_bit(value, cmd.bit, start, end) {
+                        Some(offset) => Frame::Integer(offset as i64),
+                        // Without an end, the value is padded with clear bits
+                        None if cmd.bit == 0 && cmd.end.is_none() => Frame::Integer(end as i64 + 1),
+                        None => Frame::Integer(-1),
+                    }
+                }
+                Ok(None) => Frame::Integer(if cmd.bit == 0 { 0 } else { -1 }),
+                Err(err) => err,
+            },
+            KVStoreCommand::BitField(cmd) => {
+                let writes = cmd.writes();
+                let created = writes && self.db.get(cmd.key()).is_none();
+                let mut value = match self.string_mut(cmd.key(), writes) {
+                    Ok(value) => value,
+                    Err(err) => return err,
+                };
+                // Writes are replayed as `SET` of the resulting integers, whatever
+                // the overflow behavior
+                let mut written = vec![
+                    Bytes::from_static(b"BITFIELD"),
+                    Bytes::from(cmd.key.clone()),
+                ];
+                let mut overflow = Overflow::Wrap;
+                let mut replies = Vec::with_capacity(cmd.ops.len());
+                for op in &cmd.ops {
+                    let (field, offset, integer) = match *op {
+                        BitFieldOp::Get(field, offset) => {
+                            let integer = value
+                                .as_deref()
+                                .map_or(0, |value| get_field(value, offset, field));
+                            replies.push(Frame::Integer(integer));
+                            continue;
+                        }
+                        BitFieldOp::Overflow(behavior) => {
+                            overflow = behavior;
+                            continue;
+                        }
+                        BitFieldOp::Set(field, offset, integer) => (field, offset, integer),
+                        BitFieldOp::IncrBy(field, offset, integer) => (field, offset, integer),
+                    };
+                    let value = value.as_deref_mut().unwrap();
+                    let current = get_field(value, offset, field);
+                    let target = match op {
+                        BitFieldOp::Set(..) if !field.signed => integer as u64 as i128,
+                        BitFieldOp::Set(..) => integer as i128,
+                        _ => current as i128 + integer as i128,
+                    };
+                    let Some(fitted) = field.fit(target, overflow) else {
+                        replies.push(Frame::Null);
+                        continue;
+                    };
+                    set_field(value, offset, field, fitted);
+                    written.extend([
+                        Bytes::from_static(b"SET"),
+                        Bytes::from(field.to_string()),
+                        Bytes::from(offset.to_string()),
+                        Bytes::from(fitted.to_string()),
+                    ]);
+                    replies.push(Frame::Integer(match op {
+                        BitFieldOp::Set(..) => current,
+                        _ => fitted,
+                    }));
+                }
+                if written.len() > 2 {
+                    self.propagate(written);
+                    self.notify(NotifyEvents::STRING, "setbit", &cmd.key);
+                } else if created {
+                    self.db.remove(cmd.key());
+                }
+                Frame::Array(replies)
+            }
+            KVStoreCommand::PfAdd(cmd) => {
+                let hll = match self.string_mut(cmd.key(), false) {
+                    Ok(Some(value)) => match HyperLogLog::decode(value) {
+                        Ok(hll) => Some(hll),
+                        Err(err) => return Frame::Error(err.to_string()),
+                    },
+                    Ok(None) => None,
+                    Err(err) => return err,
+                };
+                let mut changed = hll.is_none();
+                let mut hll = hll.unwrap_or_default();
+                for element in &cmd.elements {
+                    changed |= hll.add(element);
+                }
+                if !changed {
+                    return Frame::Integer(0);
+                }
+                match self.string_mut(cmd.key(), true) {
+                    Ok(Some(value)) => *value = hll.encode(),
+                    _ => unreachable!(),
+                }
+                let mut args = vec![Bytes::from_static(b"PFADD"), Bytes::from(cmd.key.clone())];
+                args.extend(cmd.elements);
+                self.propagate(args);
+                self.notify(NotifyEvents::STRING, "pfadd", &cmd.key);
+                Frame::Integer(1)
+            }
+            // Refreshes the cached cardinality, which is not propagated
+            KVStoreCommand::PfCount(cmd) => match self.string_mut(&cmd.keys[0], false) {
+                Ok(Some(value)) => match hyperloglog::cached_count(value) {
+                    Ok(Some(count)) => Frame::Integer(count as i64),
+                    Ok(None) => match HyperLogLog::decode(value) {
+                        Ok(hll) => {
+                            let count = hll.count();
+                            hyperloglog::cache_count(value, count);
+                            Frame::Integer(count as i64)
+                        }
+                        Err(err) => Frame::Error(err.to_string()),
+                    },
+                    Err(err) => Frame::Error(err.to_string()),
+                },
+                Ok(None) => Frame::Integer(0),
+                Err(err) => err,
+            },
+            KVStoreCommand::StrLen(cmd) => match self.string_mut(cmd.key(), false) {
+                Ok(value) => Frame::Integer(value.map_or(0, |value| value.len()) as i64),
+                Err(err) => err,
+            },
+            KVStoreCommand::Append(cmd) => {
+                let len = match self.string_mut(cmd.key(), true) {
+                    Ok(Some(value)) => {
+                        if value.len() + cmd.value.len() > MAX_STRING_LEN {
+                            return Frame::Error(
+                                "ERR string exceeds maximum allowed size (proto-max-bulk-len)"
+                                    .to_string(),
+                            );
+                        }
+                        value.extend_from_slice(&cmd.value);
+                        value.len()
+                    }
+                    Ok(None) => unreachable!(),
+                    Err(err) => return err,
+                };
+                self.propagate(vec![
+                    Bytes::from_static(b"APPEND"),
+                    Bytes::from(cmd.key.clone()),
+                    cmd.value,
+                ]);
+                self.notify(NotifyEvents::STRING, "append", &cmd.key);
+                Frame::Integer(len as i64)
+            }
+            KVStoreCommand::IncrBy(cmd) => {
+                let current = match self.string_mut(cmd.key(), false) {
+                    Ok(Some(value)) => match std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|v| v.parse::<i64>().ok())
+                    {
+                        Some(current) => current,
+                        None => {
+                            return Frame::Error(
+                                "ERR value is not an integer or out of range".to_string(),
+                            )
+                        }
+                    },
+                    Ok(None) => 0,
+                    Err(err) => return err,
+                };
+                let Some(value) = current.checked_add(cmd.increment) else {
+                    return Frame::Error("ERR increment or decrement would overflow".to_string());
+                };
+                match self.string_mut(cmd.key(), true) {
+                    Ok(Some(current)) => *current = value.to_string().into_bytes(),
+                    _ => unreachable!(),
+                }
+                self.propagate(vec![
+                    Bytes::from_static(b"INCRBY"),
+                    Bytes::from(cmd.key.clone()),
+                    Bytes::from(cmd.increment.to_string()),
+                ]);
+                self.notify(NotifyEvents::STRING, "incrby", &cmd.key);
+                Frame::Integer(value)
+            }
+            KVStoreCommand::IncrByFloat(cmd) => {
+                let current = match self.string_mut(cmd.key(), false) {
+                    Ok(Some(value)) => match parse_float(value) {
+                        Some(current) => current,
+                        None => return Frame::Error("ERR value is not a valid float".to_string()),
+                    },
+                    Ok(None) => 0.0,
+                    Err(err) => return err,
+                };
+                let incremented = current + cmd.increment;
+                if !incremented.is_finite() {
+                    return Frame::Error("ERR increment would produce NaN or Infinity".to_string());
+                }
+                let value = Bytes::from(format_double(incremented));
+                match self.string_mut(cmd.key(), true) {
+                    Ok(Some(current)) => *current = value.to_vec(),
+                    _ => unreachable!(),
+                }
+                // Replayed as a `SET` so that float rounding does not depend
+                // on the replaying server
+                self.propagate(vec![
+                    Bytes::from_static(b"SET"),
+                    Bytes::from(cmd.key.clone()),
+                    value.clone(),
+                ]);
+                if let Some(at) = self.db.get(cmd.key()).and_then(|e| e.expire_at) {
+                    self.propagate_expire_at(&cmd.key, at as i64);
+                }
+                self.notify(NotifyEvents::STRING, "incrbyfloat", &cmd.key);
+                Frame::Bulk(value)
+            }
+            KVStoreCommand::Expire(cmd) => {
+                let current = match self.db.get(cmd.key()) {
+                    Some(entry) => entry.expire_at,
+                    None => return Frame::Integer(0),
+                };
+
+                let now = now_ms();
+                let expire_at = cmd.expire_at(now);
+                if !cmd.conditions.iter().all(|c| c.allows(current, expire_at)) {
+                    return Frame::Integer(0);
+                }
+
+                let event = if expire_at <= now as i64 {
+                    self.db.remove(cmd.key());
+                    "del"
+                } else {
+                    self.db.set_expire(cmd.key(), Some(expire_at as u64));
+                    "expire"
+                };
+                self.propagate_expire_at(&cmd.key, expire_at);
+                self.notify(NotifyEvents::GENERIC, event, &cmd.key);
+                Frame::Integer(1)
+            }
+            KVStoreCommand::Persist(cmd) => {
+                let persisted = match self.db.get(cmd.key()) {
+                    Some(entry) if entry.expire_at.is_some() => self.db.set_expire(cmd.key(), None),
+                    _ => false,
+                };
+                if persisted {
+                    self.propagate(vec![
+                        Bytes::from_static(b"PERSIST"),
+                        Bytes::from(cmd.key.clone()),
+                    ]);
+                    self.notify(NotifyEvents::GENERIC, "persist", &cmd.key);
+                }
+                Frame::Integer(persisted as i64)
+            }
+            KVStoreCommand::HSet(cmd) => {
+                let added = match self.hash_mut(cmd.key(), true) {
+                    Ok(Some(hash)) => cmd
+                        .pairs
+                        .iter()
+                        .filter(|(field, value)| {
+                            hash.insert(field.clone(), value.clone()).is_none()
+                        })
+                        .count(),
+                    Ok(None) => unreachable!(),
+                    Err(err) => return err,
+                };
+                let mut args = vec![Bytes::from_static(b"HSET"), Bytes::from(cmd.key.clone())];
+                for (field, value) in cmd.pairs {
+                    args.push(field);
+                    args.push(value);
+                }
+                self.propagate(args);
+                self.notify(NotifyEvents::HASH, "hset", &cmd.key);
+                Frame::Integer(added as i64)
+            }
+            KVStoreCommand::HGet(cmd) => match self.hash_mut(cmd.key(), false) {
+                Ok(hash) => match hash.and_then(|hash| hash.get(&cmd.field)) {
+                    Some(value) => Frame::Bulk(value.clone()),
+                    None => Frame::Null,
+                },
+                Err(err) => err,
+            },
+            KVStoreCommand::HMGet(cmd) => match self.hash_mut(cmd.key(), false) {
+                Ok(hash) => Frame::Array(
+                    cmd.fields
+                        .iter()
+                        .map(
+                            |field| match hash.as_ref().and_then(|hash| hash.get(field)) {
+                                Some(value) => Frame::Bulk(value.clone()),
+                                None => Frame::Null,
+                            },
+                        )
+                        .collect(),
+                ),
+                Err(err) => err,
+            },
+            KVStoreCommand::HDel(cmd) => {
+                let removed = match self.hash_mut(cmd.key(), false) {
+                    Ok(Some(hash)) => cmd
+                        .fields
+                        .iter()
+                        .filter(|field| hash.remove(*field).is_some())
+                        .count(),
+                    Ok(None) => 0,
+                    Err(err) => return err,
+                };
+                if removed > 0 {
+                    self.notify_removal(NotifyEvents::HASH, "hdel", &cmd.key);
+                    let mut args = vec![Bytes::from_static(b"HDEL"), Bytes::from(cmd.key.clone())];
+                    args.extend(cmd.fields);
+                    self.propagate(args);
+                }
+                Frame::Integer(removed as i64)
+            }
+            KVStoreCommand::HExists(cmd) => match self.hash_mut(cmd.key(), false) {
+                Ok(hash) => {
+                    Frame::Integer(hash.is_some_and(|hash| hash.contains_key(&cmd.field)) as i64)
+                }
+                Err(err) => err,
+            },
+            KVStoreCommand::HGetAll(cmd) => match self.hash_mut(cmd.key(), false) {
+                Ok(hash) => Frame::Map(
+                    hash.into_iter()
+                        .flatten()
+                        .map(|(field, value)| {
+                            (Frame::Bulk(field.clone()), Frame::Bulk(value.clone()))
+                        })
+                        .collect(),
+                ),
+                Err(err) => err,
+            },
+            KVStoreCommand::HKeys(cmd) => match self.hash_mut(cmd.key(), false) {
+                Ok(hash) => Frame::Array(
+                    hash.into_iter()
+                        .flat_map(|hash| hash.keys())
+                        .map(|field| Frame::Bulk(field.clone()))
+                        .collect(),
+                ),
+                Err(err) => err,
+            },
+            KVStoreCommand::HVals(cmd) => match self.hash_mut(cmd.key(), false) {
+                Ok(hash) => Frame::Array(
+                    hash.into_iter()
+                        .flat_map(|hash| hash.values())
+                        .map(|value| Frame::Bulk(value.clone()))
+                        .collect(),
+                ),
+                Err(err) => err,
+            },
+            KVStoreCommand::HLen(cmd) => match self.hash_mut(cmd.key(), false) {
+                Ok(hash) => Frame::Integer(hash.map_or(0, |hash| hash.len()) as i64),
+                Err(err) => err,
+            },
+            KVStoreCommand::HIncrBy(cmd) => {
+                let value = match self.hash_mut(cmd.key(), true) {
+                    Ok(Some(hash)) => {
+                        let current = match hash.get(&cmd.field) {
+                            Some(value) => match std::str::from_utf8(value)
+                                .ok()
+                                .and_then(|v| v.parse::<i64>().ok())
+                            {
+                                Some(current) => current,
+                                None => {
+                                    self.remove_if_empty(cmd.key());
+                                    return Frame::Error(
+                                        "ERR hash value is not an integer".to_string(),
+                                    );
+                                }
+                            },
+                            None => 0,
+                        };
+                        let Some(value) = current.checked_add(cmd.increment) else {
+                            self.remove_if_empty(cmd.key());
+                            return Frame::Error(
+                                "ERR increment or decrement would overflow".to_string(),
+                            );
+                        };
+                        hash.insert(cmd.field.clone(), Bytes::from(value.to_string()));
+                        value
+                    }
+                    Ok(None) => unreachable!(),
+                    Err(err) => return err,
+                };
+                self.propagate(vec![
+                    Bytes::from_static(b"HSET"),
+                    Bytes::from(cmd.key.clone()),
+                    cmd.field,
+                    Bytes::from(value.to_string()),
+                ]);
+                self.notify(NotifyEvents::HASH, "hincrby", &cmd.key);
+                Frame::Integer(value)
+            }
+            KVStoreCommand::HScan(cmd) => {
+                let hash = match self.hash_mut(cmd.key(), false) {
+                    Ok(Some(hash)) => hash,
+                    Ok(None) => {
+                        return Frame::Array(vec![
+                            Frame::Bulk(Bytes::from_static(b"0")),
+                            Frame::Array(vec![]),
+                        ])
+                    }
+                    Err(err) => return err,
+                };
+
+                // Fields are visited by increasing hash, the cursor being the
+                // hash of the next field to visit, so that the fields present
+                // during the whole iteration are all returned
+                let mut fields: Vec<(u64, &Bytes, &Bytes)> = hash
+                    .iter()
+                    .map(|(field, value)| (field_hash(field), field, value))
+                    .filter(|(h, _, _)| *h >= cmd.cursor)
+                    .collect();
+                fields.sort_unstable_by_key(|(h, _, _)| *h);
+                let mut end = cmd.count.min(fields.len());
+                while end > 0 && end < fields.len() && fields[end].0 == fields[end - 1].0 {
+                    end += 1;
+                }
+                let next = fields.get(end).map_or(0, |(h, _, _)| *h);
+
+                let mut items = vec![];
+                for (_, field, value) in &fields[..end] {
+                    if cmd
+                        .pattern
+                        .as_ref()
+                        .is_some_and(|pattern| !glob_match(pattern, field))
+                    {
+                        continue;
+                    }
+                    items.push(Frame::Bulk((*field).clone()));
+                    if !cmd.novalues {
+                        items.push(Frame::Bulk((*value).clone()));
+                    }
+                }
+                Frame::Array(vec![
+                    Frame::Bulk(Bytes::from(next.to_string())),
+                    Frame::Array(items),
+                ])
+            }
+            KVStoreCommand::SAdd(cmd) => {
+                let added = match self.set_mut(cmd.key(), true) {
+                    Ok(Some(set)) => cmd
+                        .members
+                        .iter()
+                        .filter(|member| set.insert((*member).clone()))
+                        .count(),
+                    Ok(None) => unreachable!(),
+                    Err(err) => return err,
+                };
+                if added > 0 {
+                    let mut args = vec![Bytes::from_static(b"SADD"), Bytes::from(cmd.key.clone())];
+                    args.extend(cmd.members);
+                    self.propagate(args);
+                    self.notify(NotifyEvents::SET, "sadd", &cmd.key);
+                }
+                Frame::Integer(added as i64)
+            }
+            KVStoreCommand::SRem(cmd) => {
+                let removed = match self.set_mut(cmd.key(), false) {
+                    Ok(Some(set)) => cmd
+                        .members
+                        .iter()
+                        .filter(|member| set.remove(*member))
+                        .count(),
+                    Ok(None) => 0,
+                    Err(err) => return err,
+                };
+                if removed > 0 {
+                    self.notify_removal(NotifyEvents::SET, "srem", &cmd.key);
+                    let mut args = vec![Bytes::from_static(b"SREM"), Bytes::from(cmd.key.clone())];
+                    args.extend(cmd.members);
+                    self.propagate(args);
+                }
+                Frame::Integer(removed as i64)
+            }
+            KVStoreCommand::SIsMember(cmd) => match self.set_mut(cmd.key(), false) {
+                Ok(set) => Frame::Integer(set.is_some_and(|set| set.contains(&cmd.member)) as i64),
+                Err(err) => err,
+            },
+            KVStoreCommand::SMembers(cmd) => match self.set_mut(cmd.key(), false) {
+                Ok(set) => Frame::Set(
+                    set.into_iter()
+                        .flat_map(|set| set.iter())
+                        .map(|member| Frame::Bulk(member.clone()))
+                        .collect(),
+                ),
+                Err(err) => err,
+            },
+            KVStoreCommand::SCard(cmd) => match self.set_mut(cmd.key(), false) {
+                Ok(set) => Frame::Integer(set.map_or(0, |set| set.len()) as i64),
+                Err(err) => err,
+            },
+            KVStoreCommand::ZAdd(cmd) => {
+                let create = cmd.existence != Some(Existence::Present);
+                let zset = match self.zset_mut(cmd.key(), create) {
+                    Ok(Some(zset)) => zset,
+                    Ok(None) if cmd.incr => return Frame::Null,
+                    Ok(None) => return Frame::Integer(0),
+                    Err(err) => return err,
+                };
+
+                let mut counted = 0;
+                let mut written = vec![];
+                let mut incremented = None;
+                let mut result = Ok(());
+                for (score, member) in &cmd.pairs {
+                    let current = zset.score(member);
+                    let score = match current {
+                        Some(current) if cmd.incr => current + score,
+                        _ => *score,
+                    };
+                    if score.is_nan() {
+                        result = Err(Frame::Error(
+                            "ERR resulting score is not a number (NaN)".to_string(),
+                        ));
+                        break;
+                    }
+                    match current {
+                        None if cmd.existence == Some(Existence::Present) => continue,
+                        None => counted += 1,
+                        Some(_) if cmd.existence == Some(Existence::Missing) => continue,
+                        Some(current) => {
+                            if cmd.comparison.is_some_and(|c| !c.allows(current, score)) {
+                                continue;
+                            }
+                            incremented = Some(score);
+                            if current == score {
+                                continue;
+                            }
+                            if cmd.changed {
+                                counted += 1;
+                            }
+                        }
+                    }
+                    incremented = Some(score);
+                    zset.insert(member.clone(), score);
+                    written.push((score, member.clone()));
+                }
+
+                // A sorted set created for nothing is removed
+                self.remove_if_empty(cmd.key());
+                if let Err(err) = result {
+                    return err;
+                }
+                if !written.is_empty() {
+                    let mut args = vec![Bytes::from_static(b"ZADD"), Bytes::from(cmd.key.clone())];
+                    for (score, member) in written {
+                        args.push(Bytes::from(format_double(score)));
+                        args.push(member);
+                    }
+                    self.propagate(args);
+                    let event = if cmd.incr { "zincr" } else { "zadd" };
+                    self.notify(NotifyEvents::ZSET, event, &cmd.key);
+                }
+                match (cmd.incr, incremented) {
+                    (true, Some(score)) => Frame::Double(score),
+                    (true, None) => Frame::Null,
+                    (false, _) => Frame::Integer(counted),
+                }
+            }
+            KVStoreCommand::ZIncrBy(cmd) => {
+                let score = match self.zset_mut(cmd.key(), true) {
+                    Ok(Some(zset)) => {
+                        let score = zset.score(&cmd.member).unwrap_or(0.0) + cmd.increment;
+                        if !score.is_nan() {
+                            zset.insert(cmd.member.clone(), score);
+                        }
+                        score
+                    }
+                    Ok(None) => unreachable!(),
+                    Err(err) => return err,
+                };
+                if score.is_nan() {
+                    self.remove_if_empty(cmd.key());
+                    return Frame::Error("ERR resulting score is not a number (NaN)".to_string());
+                }
+                self.propagate(vec![
+                    Bytes::from_static(b"ZADD"),
+                    Bytes::from(cmd.key.clone()),
+                    Bytes::from(format_double(score)),
+                    cmd.member,
+                ]);
+                self.notify(NotifyEvents::ZSET, "zincr", &cmd.key);
+                Frame::Double(score)
+            }
+            KVStoreCommand::GeoPos(cmd) => {
+                let zset = match self.zset_mut(cmd.key(), false) {
+                    Ok(zset) => zset,
+                    Err(err) => return err,
+                };
+                let positions = cmd.members.iter().map(|member| {
+                    match zset.as_ref().and_then(|zset| zset.score(member)) {
+                        Some(score) => {
+                            let (lon, lat) = geo::decode(score as u64);
+                            Frame::Array(vec![Frame::Double(lon), Frame::Double(lat)])
+                        }
+                        None => Frame::NullArray,
+                    }
+                });
+                Frame::Array(positions.collect())
+            }
+            KVStoreCommand::GeoHash(cmd) => {
+                let zset = match self.zset_mut(cmd.key(), false) {
+                    Ok(zset) => zset,
+                    Err(err) => return err,
+                };
+                let hashes = cmd.members.iter().map(|member| {
+                    match zset.as_ref().and_then(|zset| zset.score(member)) {
+                        Some(score) => Frame::Bulk(Bytes::from(geo::hash_string(score as u64))),
+                        None => Frame::Null,
+                    }
+                });
+                Frame::Array(hashes.collect())
+            }
+            KVStoreCommand::GeoDist(cmd) => {
+                let zset = match self.zset_mut(cmd.key(), false) {
+                    Ok(Some(zset)) => zset,
+                    Ok(None) => return Frame::Null,
+                    Err(err) => return err,
+                };
+                let (Some(first), Some(second)) = (zset.score(&cmd.first), zset.score(&cmd.second))
+                else {
+                    return Frame::Null;
+                };
+                let (lon1, lat1) = geo::decode(first as u64);
+                let (lon2, lat2) = geo::decode(second as u64);
+                let distance = geo::distance(lon1, lat1, lon2, lat2) / cmd.unit;
+                Frame::Bulk(Bytes::from(format!("{:.4}", distance)))
+            }
+            KVStoreCommand::GeoSearch(cmd) => {
+                let zset = match self.zset_mut(cmd.key(), false) {
+                    Ok(zset) => zset,
+                    Err(err) => return err,
+                };
+                match geo_search(zset.as_deref(), &cmd) {
+                    Ok(matches) => geo_reply(matches, &cmd),
+                    Err(err) => err,
+                }
+            }
+            KVStoreCommand::ZScore(cmd) => match self.zset_mut(cmd.key(), false) {
+                Ok(zset) => match zset.and_then(|zset| zset.score(&cmd.member)) {
+                    Some(score) => Frame::Double(score),
+                    None => Frame::Null,
+                },
+                Err(err) => err,
+            },
+            KVStoreCommand::ZCard(cmd) => match self.zset_mut(cmd.key(), false) {
+                Ok(zset) => Frame::Integer(zset.map_or(0, |zset| zset.len()) as i64),
+                Err(err) => err,
+            },
+            KVStoreCommand::ZRank(cmd) => {
+                let zset = match self.zset_mut(cmd.key(), false) {
+                    Ok(zset) => zset,
+                    Err(err) => return err,
+                };
+                let ranked = zset.and_then(|zset| {
+                    let rank = zset.rank(&cmd.member, cmd.rev)?;
+                    Some((rank, zset.score(&cmd.member)?))
+                });
+                match (ranked, cmd.withscore) {
+                    (Some((rank, _)), false) => Frame::Integer(rank as i64),
+                    (Some((rank, score)), true) => {
+                        Frame::Array(vec![Frame::Integer(rank as i64), Frame::Double(score)])
+                    }
+                    (None, false) => Frame::Null,
+                    (None, true) => Frame::NullArray,
+                }
+            }
+            KVStoreCommand::ZRange(cmd) => {
+                let zset = match self.zset_mut(cmd.key(), false) {
+                    Ok(Some(zset)) => &*zset,
+                    Ok(None) => return Frame::Array(vec![]),
+                    Err(err) => return err,
+                };
+                let members: Box<dyn Iterator<Item = (&Bytes, f64)>> = match &cmd.by {
+                    ZRangeBy::Rank(start, stop) => match list_range(*start, *stop, zset.len()) {
+                        Some((start, stop)) if cmd.rev => {
+                            Box::new(zset.iter().rev().skip(start).take(stop - start + 1))
+                        }
+                        Some((start, stop)) => {
+                            Box::new(zset.iter().skip(start).take(stop - start + 1))
+                        }
+                        None => Box::new(std::iter::empty()),
+                    },
+                    ZRangeBy::Score(min, max) => zset.range_by_score(*min, *max, cmd.rev),
+                    ZRangeBy::Lex(min, max) => zset.range_by_lex(min, max, cmd.rev),
+                };
+                let members: Box<dyn Iterator<Item = (&Bytes, f64)>> = match cmd.limit {
+                    Some((offset, _)) if offset < 0 => Box::new(std::iter::empty()),
+                    Some((offset, count)) => Box::new(
+                        members
+                            .skip(offset as usize)
+                            .take(usize::try_from(count).unwrap_or(usize::MAX)),
+                    ),
+                    None => members,
+                };
+
+                let mut items = vec![];
+                for (member, score) in members {
+                    items.push(Frame::Bulk(member.clone()));
+                    if cmd.withscores {
+                        items.push(Frame::Double(score));
+                    }
+                }
+                Frame::Array(items)
+            }
+            KVStoreCommand::ZRem(cmd) => {
+                let removed = match self.zset_mut(cmd.key(), false) {
+                    Ok(Some(zset)) => cmd
+                        .members
+                        .iter()
+                        .filter(|member| zset.remove(member))
+                        .count(),
+                    Ok(None) => 0,
+                    Err(err) => return err,
+                };
+                if removed > 0 {
+                    self.notify_removal(NotifyEvents::ZSET, "zrem", &cmd.key);
+                    let mut args = vec![Bytes::from_static(b"ZREM"), Bytes::from(cmd.key.clone())];
+                    args.extend(cmd.members);
+                    self.propagate(args);
+                }
+                Frame::Integer(removed as i64)
+            }
+            KVStoreCommand::Push(cmd) => {
+                let len = match self.list_mut(cmd.key(), true) {
+                    Ok(Some(list)) => {
+                        for element in &cmd.elements {
+                            match cmd.side {
+                                ListSide::Left => list.push_front(element.clone()),
+                                ListSide::Right => list.push_back(element.clone()),
+                            }
+                        }
+                        list.len()
+                    }
+                    Ok(None) => unreachable!(),
+                    Err(err) => return err,
+                };
+                let name = match cmd.side {
+                    ListSide::Left => "LPUSH",
+                    ListSide::Right => "RPUSH",
+                };
+                let mut args = vec![
+                    Bytes::from_static(name.as_bytes()),
+                    Bytes::from(cmd.key.clone()),
+                ];
+                args.extend(cmd.elements);
+                self.propagate(args);
+                self.notify(NotifyEvents::LIST, &name.to_lowercase(), &cmd.key);
+                self.serve_blocked(&cmd.key);
+                Frame::Integer(len as i64)
+            }
+            KVStoreCommand::Pop(cmd) => {
+                let popped: Vec<Bytes> = match self.list_mut(cmd.key(), false) {
+                    Ok(Some(list)) => {
+                        let count = cmd.count.unwrap_or(1).min(list.len() as u64) as usize;
+                        match cmd.side {
+                            ListSide::Left => list.drain(..count).collect(),
+                            ListSide::Right => list.drain(list.len() - count..).rev().collect(),
+                        }
+                    }
+                    Ok(None) => vec![],
+                    Err(err) => return err,
+                };
+                if popped.is_empty() {
+                    return match cmd.count {
+                        Some(_) => Frame::NullArray,
+                        None => Frame::Null,
+                    };
+                }
+                self.notify_pop(&cmd.key, cmd.side);
+                self.propagate_pop(&cmd.key, cmd.side, popped.len());
+                match cmd.count {
+                    Some(_) => Frame::Array(popped.into_iter().map(Frame::Bulk).collect()),
+                    None => Frame::Bulk(popped.into_iter().next().unwrap()),
+                }
+            }
+            KVStoreCommand::LLen(cmd) => match self.list_mut(cmd.key(), false) {
+                Ok(list) => Frame::Integer(list.map_or(0, |list| list.len()) as i64),
+                Err(err) => err,
+            },
+            KVStoreCommand::LIndex(cmd) => match self.list_mut(cmd.key(), false) {
+                Ok(Some(list)) => match list_index(cmd.index, list.len()) {
+                    Some(index) => Frame::Bulk(list[index].clone()),
+                    None => Frame::Null,
+                },
+                Ok(None) => Frame::Null,
+                Err(err) => err,
+            },
+            KVStoreCommand::LRange(cmd) => match self.list_mut(cmd.key(), false) {
+                Ok(Some(list)) => match list_range(cmd.start, cmd.stop, list.len()) {
+                    Some((start, stop)) => {
+                        Frame::Array(list.range(start..=stop).cloned().map(Frame::Bulk).collect())
+                    }
+                    None => Frame::Array(vec![]),
+                },
+                Ok(None) => Frame::Array(vec![]),
+                Err(err) => err,
+            },
+            KVStoreCommand::LSet(cmd) => {
+                match self.list_mut(cmd.key(), false) {
+                    Ok(Some(list)) => match list_index(cmd.index, list.len()) {
+                        Some(index) => list[index] = cmd.element.clone(),
+                        None => return Frame::Error("ERR index out of range".to_string()),
+                    },
+                    Ok(None) => return Frame::Error("ERR no such key".to_string()),
+                    Err(err) => return err,
+                }
+                self.propagate(vec![
+                    Bytes::from_static(b"LSET"),
+                    Bytes::from(cmd.key.clone()),
+                    Bytes::from(cmd.index.to_string()),
+                    cmd.element,
+                ]);
+                self.notify(NotifyEvents::LIST, "lset", &cmd.key);
+                Frame::Simple("OK".to_string())
+            }
+            KVStoreCommand::LRem(cmd) => {
+                let removed = match self.list_mut(cmd.key(), false) {
+                    Ok(Some(list)) => {
+                        let limit = match cmd.count {
+                            0 => usize::MAX,
+                            count => count.unsigned_abs() as usize,
+                        };
+                        let mut removed = 0;
+                        if cmd.count < 0 {
+                            let mut index = list.len();
+                            while index > 0 && removed < limit {
+                                index -= 1;
+                                if list[index] == cmd.element {
+                                    list.remove(index);
+                                    removed += 1;
+                                }
+                            }
+                        } else {
+                            let mut index = 0;
+                            while index < list.len() && removed < limit {
+                                if list[index] == cmd.element {
+                                    list.remove(index);
+                                    removed += 1;
+                                } else {
+                                    index += 1;
+                                }
+                            }
+                        }
+                        removed
+                    }
+                    Ok(None) => 0,
+                    Err(err) => return err,
+                };
+                if removed > 0 {
+                    self.notify_removal(NotifyEvents::LIST, "lrem", &cmd.key);
+                    self.propagate(vec![
+                        Bytes::from_static(b"LREM"),
+                        Bytes::from(cmd.key.clone()),
+                        Bytes::from(cmd.count.to_string()),
+                        cmd.element,
+                    ]);
+                }
+                Frame::Integer(removed as i64)
+            }
+            KVStoreCommand::LTrim(cmd) => {
+                let range = &cmd.range;
+                match self.list_mut(range.key(), false) {
+                    Ok(Some(list)) => match list_range(range.start, range.stop, list.len()) {
+                        Some((start, stop)) => {
+                            list.truncate(stop + 1);
+                            list.drain(..start);
+                        }
+                        None => list.clear(),
+                    },
+                    Ok(None) => return Frame::Simple("OK".to_string()),
+                    Err(err) => return err,
+                }
+                self.notify_removal(NotifyEvents::LIST, "ltrim", &range.key);
+                self.propagate(vec![
+                    Bytes::from_static(b"LTRIM"),
+                    Bytes::from(range.key.clone()),
+                    Bytes::from(range.start.to_string()),
+                    Bytes::from(range.stop.to_string()),
+                ]);
+                Frame::Simple("OK".to_string())
+            }
+            KVStoreCommand::LMove(cmd) => {
+                if let Err(err) = self.list_mut(&cmd.destination, false) {
+                    return err;
+                }
+                let element = match self.list_mut(&cmd.source, false) {
+                    Ok(Some(list)) => match cmd.from {
+                        ListSide::Left => list.pop_front().unwrap(),
+                        ListSide::Right => list.pop_back().unwrap(),
+                    },
+                    Ok(None) => return Frame::Null,
+                    Err(err) => return err,
+                };
+                self.notify_pop(&cmd.source, cmd.from);
+                match self.list_mut(&cmd.destination, true) {
+                    Ok(Some(list)) => match cmd.to {
+                        ListSide::Left => list.push_front(element.clone()),
+                        ListSide::Right => list.push_back(element.clone()),
+                    },
+                    _ => unreachable!(),
+                }
+                self.propagate(vec![
+                    Bytes::from_static(b"LMOVE"),
+                    Bytes::from(cmd.source.clone()),
+                    Bytes::from(cmd.destination.clone()),
+                    Bytes::from_static(cmd.from.name().as_bytes()),
+                    Bytes::from_static(cmd.to.name().as_bytes()),
+                ]);
+                let event = match cmd.to {
+                    ListSide::Left => "lpush",
+                    ListSide::Right => "rpush",
+                };
+                self.notify(NotifyEvents::LIST, event, &cmd.destination);
+                self.serve_blocked(&cmd.destination);
+                Frame::Bulk(element)
+            }
+            KVStoreCommand::XAdd(cmd) => {
+                let id = match self.stream_mut(cmd.key(), false) {
+                    Ok(Some(stream)) => stream.next_id(cmd.id, now_ms()),
+                    Ok(None) if cmd.nomkstream => return Frame::Null,
+                    Ok(None) => Stream::new().next_id(cmd.id, now_ms()),
+                    Err(err) => return err,
+                };
+                let id = match id {
+                    Ok(id) => id,
+                    Err(err) => return Frame::Error(err.to_string()),
+                };
+                let Ok(Some(stream)) = self.stream_mut(cmd.key(), true) else {
+                    unreachable!()
+                };
+                stream.add(id, cmd.fields.clone());
+                let trimmed = match cmd.trim {
+                    Some(trim) => stream.trim(trim.trim, trim.limit) > 0,
+                    None => false,
+                };
+
+                let mut args = vec![Bytes::from_static(b"XADD"), Bytes::from(cmd.key.clone())];
+                if let Some(trim) = cmd.trim {
+                    let (name, threshold) = match trim.trim {
+                        Trim::MaxLen(len) => ("MAXLEN", len.to_string()),
+                        Trim::MinId(id) => ("MINID", id.to_string()),
+                    };
+                    args.push(Bytes::from_static(name.as_bytes()));
+                    args.push(Bytes::from_static(match trim.approximate {
+                        true => b"~",
+                        false => b"=",
+                    }));
+                    args.push(Bytes::from(threshold));
+                    if trim.limit > 0 {
+                        args.push(Bytes::from_static(b"LIMIT"));
+                        args.push(Bytes::from(trim.limit.to_string()));
+                    }
+                }
+                args.push(id.to_bytes());
+                for (field, value) in cmd.fields {
+                    args.push(field);
+                    args.push(value);
+                }
+                self.propagate(args);
+                self.notify(NotifyEvents::STREAM, "xadd", &cmd.key);
+                if trimmed {
+                    self.notify(NotifyEvents::STREAM, "xtrim", &cmd.key);
+                }
+                self.serve_blocked(&cmd.key);
+                Frame::Bulk(id.to_bytes())
+            }
+            KVStoreCommand::XLen(cmd) => match self.stream_mut(cmd.key(), false) {
+                Ok(stream) => Frame::Integer(stream.map_or(0, |stream| stream.len()) as i64),
+                Err(err) => err,
+            },
+            KVStoreCommand::XRange(cmd) => match self.stream_mut(cmd.key(), false) {
+                Ok(Some(stream)) => Frame::Array(
+                    stream
+                        .range(cmd.start, cmd.end, cmd.rev)
+                        .take(cmd.count.unwrap_or(usize::MAX))
+                        .map(|(id, fields)| entry_frame(*id, fields))
+                        .collect(),
+                ),
+                Ok(None) => Frame::Array(vec![]),
+                Err(err) => err,
+            },
+            KVStoreCommand::XDel(cmd) => {
+                let removed = match self.stream_mut(cmd.key(), false) {
+                    Ok(Some(stream)) => cmd.ids.iter().filter(|id| stream.remove(id)).count(),
+                    Ok(None) => 0,
+                    Err(err) => return err,
+                };
+                if removed > 0 {
+                    let mut args = vec![Bytes::from_static(b"XDEL"), Bytes::from(cmd.key.clone())];
+                    args.extend(cmd.ids.iter().map(|id| id.to_bytes()));
+                    self.propagate(args);
+                    self.notify(NotifyEvents::STREAM, "xdel", &cmd.key);
+                }
+                Frame::Integer(removed as i64)
+            }
+            KVStoreCommand::XSetId(cmd) => {
+                match self.stream_mut(cmd.key(), false) {
+                    Ok(Some(stream)) => {
+                        if !stream.set_last_id(cmd.id) {
+                            return Frame::Error(
+                                "ERR The ID specified in XSETID is smaller than the target stream top item"
+                                    .to_string(),
+                            );
+                        }
+                    }
+                    Ok(None) => return Frame::Error("ERR no such key".to_string()),
+                    Err(err) => return err,
+                }
+                self.propagate(vec![
+                    Bytes::from_static(b"XSETID"),
+                    Bytes::from(cmd.key.clone()),
+                    cmd.id.to_bytes(),
+                ]);
+                self.notify(NotifyEvents::STREAM, "xsetid", &cmd.key);
+                Frame::Simple("OK".to_string())
+            }
+            KVStoreCommand::XGroup(cmd) => self.xgroup(cmd),
+            KVStoreCommand::XAck(cmd) => {
+                let acked = match self.stream_mut(cmd.key(), false) {
+                    Ok(Some(stream)) => match stream.group_mut(&cmd.group) {
+                        Some(group) => cmd.ids.iter().filter(|id| group.ack(id)).count(),
+                        None => 0,
+                    },
+                    Ok(None) => 0,
+                    Err(err) => return err,
+                };
+                if acked > 0 {
+                    let mut args = vec![
+                        Bytes::from_static(b"XACK"),
+                        Bytes::from(cmd.key.clone()),
+                        Bytes::from(cmd.group.clone()),
+                    ];
+                    args.extend(cmd.ids.iter().map(|id| id.to_bytes()));
+                    self.propagate(args);
+                }
+                Frame::Integer(acked as i64)
+            }
+            KVStoreCommand::XPending(cmd) => self.xpending(cmd),
+            KVStoreCommand::XClaim(cmd) => self.xclaim(cmd),
+            KVStoreCommand::XAutoClaim(cmd) => self.xautoclaim(cmd),
+            KVStoreCommand::XRead(read) => self.read_stream(read),
+            KVStoreCommand::Ttl(cmd) => {
+                let ttl = match self.db.get(cmd.key()) {
+                    None => -2,
+                    Some(entry) => match entry.expire_at {
+                        None => -1,
+                        Some(at) => {
+                            let remaining = at.saturating_sub(now_ms()) as i64;
+                            match cmd.unit {
+                                TimeUnit::Seconds => (remaining + 500) / 1000,
+                                TimeUnit::Milliseconds => remaining,
+                            }
+                        }
+                    },
+                };
+                Frame::Integer(ttl)
+            }
+            KVStoreCommand::Type(cmd) => {
+                let name = match self.db.get(cmd.key()) {
+                    Some(entry) => entry.value.type_name(),
+                    None => "none",
+                };
+                Frame::Simple(name.to_string())
+            }
+            KVStoreCommand::Del(cmd) => {
+                let mut removed = 0;
+                for key in cmd.keys {
+                    if self.db.get(&key).is_some() {
+                        self.db.remove(&key);
+                        self.notify(NotifyEvents::GENERIC, "del", &key);
+                        self.propagate(vec![Bytes::from_static(b"DEL"), Bytes::from(key)]);
+                        removed += 1;
+                    }
+                }
+                Frame::Integer(removed)
+            }
+            KVStoreCommand::Move(cmd) => self.move_key(cmd),
+            KVStoreCommand::Exists(cmd) => Frame::Integer(
+                cmd.keys
+                    .iter()
+                    .filter(|key| self.db.get(key).is_some())
+                    .count() as i64,
+            ),
+        }
+    }
+
+    /// Remove the entry at `key`, to be inserted at another key
+    pub fn take(&mut self, key: &str) -> Option<Entry> {
+        self.db.get(key)?;
+        let entry = self.db.remove(key)?;
+        self.propagate(vec![
+            Bytes::from_static(b"DEL"),
+            Bytes::from(key.to_string()),
+        ]);
+        self.touch(key);
+        self.account_memory();
+        Some(entry)
+    }
+
+    /// Insert an entry taken from another key, replacing the entry at `key`
+    pub fn insert(&mut self, key: String, entry: Entry) {
+        if self.db.get(&key).is_some() {
+            self.propagate(vec![Bytes::from_static(b"DEL"), Bytes::from(key.clone())]);
+        }
+        for write in rewrite_entry(key.clone(), entry.clone()) {
+            self.record(write);
+        }
+        self.db.set(key.clone(), entry.value, entry.expire_at);
+        self.touch(&key);
+        self.serve_blocked(&key);
+        self.account_memory();
+    }
+
+    /// Visit up to `count` keys from `position`, by increasing hash as done
+    /// by `HSCAN`. Returns the matching keys and the position to continue
+    /// from, `None` once all the keys were visited.
+    pub fn scan(&self, position: u64, count: usize, cmd: &Scan) -> (Vec<Frame>, Option<u64>) {
+        let mut keys: Vec<(u64, &String, &Entry)> = self
+            .db
+            .iter()
+            .map(|(key, entry)| (scan_position(key), key, entry))
+            .filter(|(p, _, _)| *p >= position)
+            .collect();
+        keys.sort_unstable_by_key(|(p, _, _)| *p);
+        let mut end = count.min(keys.len());
+        while end > 0 && end < keys.len() && keys[end].0 == keys[end - 1].0 {
+            end += 1;
+        }
+        let next = keys.get(end).map(|(p, _, _)| *p);
+
+        let matching = keys[..end]
+            .iter()
+            .filter(|(_, key, _)| {
+                cmd.pattern
+                    .as_ref()
+                    .is_none_or(|pattern| glob_match(pattern, key.as_bytes()))
+            })
+            .filter(|(_, _, entry)| {
+                cmd.value_type
+                    .as_ref()
+                    .is_none_or(|name| entry.value.type_name() == name)
+            })
+            .map(|(_, key, _)| Frame::Bulk(Bytes::from(key.to_string())))
+            .collect();
+        (matching, next)
+    }
+
+    /// The string at `key`, created empty when missing if `create` is set
+    fn string_mut(&mut self, key: &str, create: bool) -> Result<Option<&mut Vec<u8>>, Frame> {
+        if create && self.db.get(key).is_none() {
+            self.db.set(key.to_string(), Value::String(vec![]), None);
+        }
+        match self.db.get_mut(key) {
+            Some(Entry {
+                value: Value::String(value),
+                ..
+            }) => Ok(Some(value)),
+            Some(_) => Err(Frame::Error(WRONGTYPE.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// The list at `key`, created when missing if `create` is set
+    fn list_mut(&mut self, key: &str, create: bool) -> Result<Option<&mut VecDeque<Bytes>>, Frame> {
+        if create && self.db.get(key).is_none() {
+            self.db
+                .set(key.to_string(), Value::List(VecDeque::new()), None);
+        }
+        match self.db.get_mut(key) {
+            Some(Entry {
+                value: Value::List(list),
+                ..
+            }) => Ok(Some(list)),
+            Some(_) => Err(Frame::Error(WRONGTYPE.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// The hash at `key`, created when missing if `create` is set
+    fn hash_mut(
+        &mut self,
+        key: &str,
+        create: bool,
+    ) -> Result<Option<&mut HashMap<Bytes, Bytes>>, Frame> {
+        if create && self.db.get(key).is_none() {
+            self.db
+                .set(key.to_string(), Value::Hash(HashMap::new()), None);
+        }
+        match self.db.get_mut(key) {
+            Some(Entry {
+                value: Value::Hash(hash),
+                ..
+            }) => Ok(Some(hash)),
+            Some(_) => Err(Frame::Error(WRONGTYPE.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// The set at `key`, created when missing if `create` is set
+    fn set_mut(&mut self, key: &str, create: bool) -> Result<Option<&mut HashSet<Bytes>>, Frame> {
+        if create && self.db.get(key).is_none() {
+            self.db
+                .set(key.to_string(), Value::Set(HashSet::new()), None);
+        }
+        match self.db.get_mut(key) {
+            Some(Entry {
+                value: Value::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(Frame::Error(WRONGTYPE.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// The sorted set at `key`, created when missing if `create` is set
+    fn zset_mut(&mut self, key: &str, create: bool) -> Result<Option<&mut SortedSet>, Frame> {
+        if create && self.db.get(key).is_none() {
+            self.db
+                .set(key.to_string(), Value::ZSet(SortedSet::new()), None);
+        }
+        match self.db.get_mut(key) {
+            Some(Entry {
+                value: Value::ZSet(zset),
+                ..
+            }) => Ok(Some(zset)),
+            Some(_) => Err(Frame::Error(WRONGTYPE.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// The stream at `key`, created when missing if `create` is set
+    fn stream_mut(&mut self, key: &str, create: bool) -> Result<Option<&mut Stream>, Frame> {
+        if create && self.db.get(key).is_none() {
+            self.db
+                .set(key.to_string(), Value::Stream(Stream::new()), None);
+        }
+        match self.db.get_mut(key) {
+            Some(Entry {
+                value: Value::Stream(stream),
+                ..
+            }) => Ok(Some(stream)),
+            Some(_) => Err(Frame::Error(WRONGTYPE.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Collections are removed with their last element, but for streams
+    /// which keep their last id and groups
+    fn remove_if_empty(&mut self, key: &str) -> bool {
+        let empty = match self.db.get(key) {
+            Some(Entry {
+                value: Value::List(list),
+                ..
+            }) => list.is_empty(),
+            Some(Entry {
+                value: Value::Hash(hash),
+                ..
+            }) => hash.is_empty(),
+            Some(Entry {
+                value: Value::Set(set),
+                ..
+            }) => set.is_empty(),
+            Some(Entry {
+                value: Value::ZSet(zset),
+                ..
+            }) => zset.is_empty(),
+            _ => false,
+        };
+        if empty {
+            self.db.remove(key);
+        }
+        empty
+    }
+
+    /// Notify the removal of elements from a collection, followed by `del`
+    /// when it is removed with its last element
+    fn notify_removal(&mut self, class: NotifyEvents, event: &str, key: &str) {
+        self.notify(class, event, key);
+        if self.remove_if_empty(key) {
+            self.notify(NotifyEvents::GENERIC, "del", key);
+        }
+    }
+
+    fn notify_pop(&mut self, key: &str, side: ListSide) {
+        let event = match side {
+            ListSide::Left => "lpop",
+            ListSide::Right => "rpop",
+        };
+        self.notify_removal(NotifyEvents::LIST, event, key);
+    }
+
+    /// Serve a blocked client from the list or stream at `key`, or park it
+    pub fn block(&mut self, key: String, mut waiter: Waiter) {
+        if let Wait::Read(read) = &mut waiter.wait {
+            // `$` waits for the entries added once blocked
+            if read.group.is_none() && read.after.is_none() {
+                read.after = match self.stream_mut(&key, false) {
+                    Ok(Some(stream)) => Some(stream.last_id()),
+                    _ => Some(StreamId::MIN),
+                };
+            }
+        }
+        match self.ready(&key, &waiter.wait) {
+            Ok(true) => {
+                if waiter.claim() {
+                    self.serve(&key, waiter);
+                }
+            }
+            Ok(false) => {
+                if !waiter.is_claimed() {
+                    self.blocked.entry(key).or_default().push_back(waiter);
+                }
+            }
+            Err(err) => {
+                if waiter.claim() {
+                    let _ = waiter.respond.send(err);
+                }
+            }
+        }
+    }
+
+    /// Whether the key can serve the waiter, a group read fails once its
+    /// group is destroyed
+    fn ready(&mut self, key: &str, wait: &Wait) -> Result<bool, Frame> {
+        match wait {
+            Wait::Pop(_) | Wait::Ready => Ok(self.list_mut(key, false)?.is_some()),
+            Wait::Read(read) => {
+                let Some(stream) = self.stream_mut(key, false)? else {
+                    return Ok(false);
+                };
+                let after = match &read.group {
+                    Some(group_read) => match stream.group(&group_read.group) {
+                        Some(group) => group.last_id,
+                        None => {
+                            return Err(Frame::Error(
+                                "NOGROUP the consumer group this client was blocked on no \
+                                 longer exists"
+                                    .to_string(),
+                            ))
+                        }
+                    },
+                    None => read.after.unwrap_or(StreamId::MAX),
+                };
+                Ok(stream.after(after).next().is_some())
+            }
+        }
+    }
+
+    /// Serve the clients blocked on `key` while it can, in the order they
+    /// blocked
+    fn serve_blocked(&mut self, key: &str) {
+        let Some(waiters) = self.blocked.remove(key) else {
+            return;
+        };
+        let mut parked = VecDeque::new();
+        for waiter in waiters {
+            if waiter.is_claimed() {
+                continue;
+            }
+            match self.ready(key, &waiter.wait) {
+                Ok(true) => {
+                    if waiter.claim() {
+                        self.serve(key, waiter);
+                    }
+                }
+                Ok(false) => parked.push_back(waiter),
+                Err(err) => {
+                    if waiter.claim() {
+                        let _ = waiter.respond.send(err);
+                    }
+                }
+            }
+        }
+        if !parked.is_empty() {
+            self.blocked.insert(key.to_string(), parked);
+        }
+    }
+
+    /// Serve the clients blocked on any key of the selected database
+    fn serve_all_blocked(&mut self) {
+        let keys: Vec<String> = self.blocked.keys().cloned().collect();
+        for key in keys {
+            self.serve_blocked(&key);
+        }
+    }
+
+    /// Serve a claimed waiter, the key must be ready for it
+    fn serve(&mut self, key: &str, waiter: Waiter) {
+        let response = match waiter.wait {
+            Wait::Pop(side) => {
+                let element = match self.list_mut(key, false) {
+                    Ok(Some(list)) => match side {
+                        ListSide::Left => list.pop_front().unwrap(),
+                        ListSide::Right => list.pop_back().unwrap(),
+                    },
+                    _ => unreachable!(),
+                };
+                self.notify_pop(key, side);
+                self.propagate_pop(key, side, 1);
+                self.touch(key);
+                Frame::Array(vec![
+                    Frame::Bulk(Bytes::from(key.to_string())),
+                    Frame::Bulk(element),
+                ])
+            }
+            Wait::Ready => Frame::Null,
+            Wait::Read(read) => {
+                if read.group.is_some() {
+                    self.touch(key);
+                }
+                Frame::Array(vec![self.read_stream(read)])
+            }
+        };
+        let _ = waiter.respond.send(response);
+    }
+
+    /// Set the flag once the key is touched
+    pub fn watch(&mut self, key: String, dirty: Arc<AtomicBool>) {
+        self.watched.entry(key).or_default().push(dirty);
+    }
+
+    /// Flag the connections watching the key
+    fn touch(&mut self, key: &str) {
+        for dirty in self.watched.remove(key).into_iter().flatten() {
+            dirty.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Flag the connections watching any key of the selected database
+    fn touch_all(&mut self) {
+        for (_, flags) in self.watched.drain() {
+            for dirty in flags {
+                dirty.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Touch and notify the keys removed since they expired
+    pub fn touch_expired(&mut self) {
+        for key in self.db.take_expired() {
+            self.notify(NotifyEvents::EXPIRED, "expired", &key);
+            self.touch(&key);
+            self.stats.expired_keys.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Remove the key if it expired, which touches it
+    pub fn expire(&mut self, key: &str) {
+        self.db.get(key);
+        self.touch_expired();
+    }
+
+    /// Forget the watches of the connections which are gone
+    fn prune_watched(&mut self) {
+        self.watched.retain(|_, flags| {
+            flags.retain(|dirty| Arc::strong_count(dirty) > 1);
+            !flags.is_empty()
+        });
+    }
+
+    /// Forget the waiters served by another shard or timed out
+    fn prune_blocked(&mut self) {
+        self.blocked.retain(|_, waiters| {
+            waiters.retain(|w| !w.is_claimed());
+            !waiters.is_empty()
+        });
+    }
+
+    fn propagate_pop(&mut self, key: &str, side: ListSide, count: usize) {
+        let name = match side {
+            ListSide::Left => "LPOP",
+            ListSide::Right => "RPOP",
+        };
+        self.propagate(vec![
+            Bytes::from_static(name.as_bytes()),
+            Bytes::from(key.to_string()),
+            Bytes::from(count.to_string()),
+        ]);
+    }
+
+    /// Record an `XGROUP` subcommand taking a single argument
+    fn propagate_group(&mut self, key: &str, group: &str, subcommand: &'static str, arg: &str) {
+        self.propagate(vec![
+            Bytes::from_static(b"XGROUP"),
+            Bytes::from_static(subcommand.as_bytes()),
+            Bytes::from(key.to_string()),
+            Bytes::from(group.to_string()),
+            Bytes::from(arg.to_string()),
+        ]);
+    }
+
+    /// Record deliveries to the consumers of a group as forced claims, with
+    /// the last id delivered to the group
+    fn propagate_claims(
+        &mut self,
+        key: &str,
+        group: &str,
+        claims: Vec<(StreamId, PendingEntry)>,
+        last_id: StreamId,
+    ) {
+        let (key, group) = (Bytes::from(key.to_string()), Bytes::from(group.to_string()));
+        for (id, entry) in claims {
+            self.propagate(claim_args(&key, &group, id, &entry, Some(last_id)));
+        }
+    }
+
+    fn propagate_ack(&mut self, key: &str, group: &str, ids: &[StreamId]) {
+        if ids.is_empty() {
+            return;
+        }
+        let mut args = vec![
+            Bytes::from_static(b"XACK"),
+            Bytes::from(key.to_string()),
+            Bytes::from(group.to_string()),
+        ];
+        args.extend(ids.iter().map(|id| id.to_bytes()));
+        self.propagate(args);
+    }
+
+    /// Publish a keyspace event on a key of the selected database, when its
+    /// class is enabled by `notify-keyspace-events`
+    pub fn notify(&self, class: NotifyEvents, event: &str, key: &str) {
+        let Some(broker) = &self.broker else {
+            return;
+        };
+        if !self.notify_events.enabled(class) {
+            return;
+        }
+        if self.notify_events.contains(NotifyEvents::KEYSPACE) {
+            let channel = format!("__keyspace@{}__:{}", self.selected, key);
+            let message = Bytes::from(event.to_string());
+            let _ = broker.send(BrokerCommand::Notify(Publish { channel, message }));
+        }
+        if self.notify_events.contains(NotifyEvents::KEYEVENT) {
+            let channel = format!("__keyevent@{}__:{}", self.selected, event);
+            let message = Bytes::from(key.to_string());
+            let _ = broker.send(BrokerCommand::Notify(Publish { channel, message }));
+        }
+    }
+
+    /// Record a write, relative times have to be resolved so that replaying
+    /// the command later gives the same result
+    pub fn propagate(&mut self, args: Vec<Bytes>) {
+        self.record(Frame::Array(args.into_iter().map(Frame::Bulk).collect()));
+    }
+
+    fn record(&mut self, write: Frame) {
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+        self.writes.push((self.selected, write));
+    }
+
+    fn propagate_expire_at(&mut self, key: &str, expire_at: i64) {
+        self.propagate(vec![
+            Bytes::from_static(b"PEXPIREAT"),
+            Bytes::from(key.to_string()),
+            Bytes::from(expire_at.to_string()),
+        ]);
+    }
+
+    /// Take the writes recorded since the last call
+    pub fn take_writes(&mut self) -> Vec<(usize, Frame)> {
+        std::mem::take(&mut self.writes)
+    }
+}
+
+/// Serve a shard, writes are sent to the `feed`.
+///
+/// When `fsync_always` is set, the response to a write is only sent once the
+/// write is persisted.
+pub async fn process_kvstore(
+    mut shard: Shard,
+    feed: Feed,
+    fsync_always: bool,
+    cmd_rx: &mut mpsc::UnboundedReceiver<KVStoreMessage>,
+) {
+    let mut expire_interval = time::interval(ACTIVE_EXPIRE_PERIOD);
+    expire_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            received = cmd_rx.recv() => match received {
+                Some(KVStoreMessage::Command(db, cmd, respond, execution)) => {
+                    let started = Instant::now();
+                    shard.select(db);
+                    let response = shard.execute(cmd);
+                    if let Some(execution) = execution {
+                        execution.add(started.elapsed());
+                    }
+                    feed_writes(&feed, shard.take_writes(), fsync_always, Some((respond, response)));
+                }
+                Some(KVStoreMessage::Lock(lend)) => {
+                    let (give_back, given_back) = oneshot::channel();
+                    shard = match lend.send((shard, give_back)) {
+                        Ok(()) => given_back.await.expect("locked shard never given back"),
+                        Err((shard, _)) => shard,
+                    };
+                }
+                Some(KVStoreMessage::Watch(db, key, dirty)) => {
+                    shard.select(db);
+                    shard.watch(key, dirty);
+                }
+                Some(KVStoreMessage::MaxMemory(maxmemory)) => shard.maxmemory = maxmemory,
+                Some(KVStoreMessage::NotifyEvents(events)) => shard.notify_events = events,
+                None => break,
+            },
+            _ = expire_interval.tick() => {
+                for db in 0..shard.databases() {
+                    shard.select(db);
+                    let removed = shard.db.active_expire(now_ms(), ACTIVE_EXPIRE_KEYS_PER_CYCLE);
+                    shard.dirty.fetch_add(removed as u64, Ordering::Relaxed);
+                    shard.prune_blocked();
+                    shard.prune_watched();
+                    shard.touch_expired();
+                }
+                shard.account_memory();
+            }
+        }
+        shard.touch_expired();
+    }
+}
+
+/// Send writes to the feed, when `fsync_always` is set the response is only
+/// sent once they are persisted
+fn feed_writes(
+    feed: &Feed,
+    mut writes: Vec<(usize, Frame)>,
+    fsync_always: bool,
+    response: Option<(mpsc::UnboundedSender<Frame>, Frame)>,
+) {
+    let last = writes.pop();
+    for (db, write) in writes {
+        feed.send(FeedMessage::Command(db, write, None)).unwrap();
+    }
+    match (last, response) {
+        (Some((db, last)), response) if fsync_always => {
+            feed.send(FeedMessage::Command(db, last, response)).unwrap();
+        }
+        (last, response) => {
+            if let Some((db, last)) = last {
+                feed.send(FeedMessage::Command(db, last, None)).unwrap();
+            }
+            if let Some((respond, response)) = response {
+                let _ = respond.send(response);
+            }
+        }
+    }
+}
+
+/// Exclusive access to several shards, to update them atomically.
+///
+/// The shards lend themselves once they processed the request, and wait
+/// until they are given back with their writes fed, once dropped or released
+/// with a response.
+pub struct LockedShards {
+    shards: Vec<(usize, Shard, oneshot::Sender<Shard>)>,
+    /// Database selected on the shards when accessed
+    db: usize,
+    feed: Feed,
+    fsync_always: bool,
+}
+
+/// Lock requested to the shards, not yet acquired
+pub struct PendingLock {
+    lent: Vec<(usize, oneshot::Receiver<LentShard>)>,
+    feed: Feed,
+    fsync_always: bool,
+}
+
+impl PendingLock {
+    pub async fn acquire(self) -> LockedShards {
+        let mut shards = Vec::with_capacity(self.lent.len());
+        for (index, lent) in self.lent {
+            let (shard, give_back) = lent.await.unwrap();
+            shards.push((index, shard, give_back));
+        }
+        LockedShards {
+            shards,
+            db: 0,
+            feed: self.feed,
+            fsync_always: self.fsync_always,
+        }
+    }
+}
+
+impl LockedShards {
+    /// Request the shards at `indexes`.
+    ///
+    /// Two locks sharing shards would wait for each other forever if they
+    /// were queued in a different order by two of the shards, requests have
+    /// to be sent in the same order to every shard.
+    pub fn request(
+        kvs: &[KVStore],
+        indexes: impl IntoIterator<Item = usize>,
+        feed: Feed,
+        fsync_always: bool,
+    ) -> PendingLock {
+        let mut indexes: Vec<usize> = indexes.into_iter().collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        let lent = indexes
+            .into_iter()
+            .map(|index| {
+                let (lend, lent) = oneshot::channel();
+                kvs[index].send(KVStoreMessage::Lock(lend)).unwrap();
+                (index, lent)
+            })
+            .collect();
+        PendingLock {
+            lent,
+            feed,
+            fsync_always,
+        }
+    }
+
+    /// Select the database of the shards accessed from now on
+    pub fn select(&mut self, db: usize) {
+        self.db = db;
+    }
+
+    pub fn selected(&self) -> usize {
+        self.db
+    }
+
+    /// The locked shard at `index`
+    pub fn shard(&mut self, index: usize) -> &mut Shard {
+        let position = self
+            .shards
+            .binary_search_by_key(&index, |(i, _, _)| *i)
+            .expect("shard not locked");
+        let shard = &mut self.shards[position].1;
+        shard.select(self.db);
+        shard
+    }
+
+    pub fn shards(&mut self) -> impl Iterator<Item = &mut Shard> {
+        let db = self.db;
+        self.shards.iter_mut().map(move |(_, shard, _)| {
+            shard.select(db);
+            shard
+        })
+    }
+
+    /// Give the shards back, the response is sent as for a command executed
+    /// by a shard
+    pub fn release(mut self, respond: &mpsc::UnboundedSender<Frame>, response: Frame) {
+        self.give_back(Some((respond.clone(), response)));
+    }
+
+    fn give_back(&mut self, response: Option<(mpsc::UnboundedSender<Frame>, Frame)>) {
+        let writes = self
+            .shards()
+            .flat_map(|shard| shard.take_writes())
+            .collect();
+        feed_writes(&self.feed, writes, self.fsync_always, response);
+        for (_, shard, give_back) in self.shards.drain(..) {
+            let _ = give_back.send(shard);
+        }
+    }
+}
+
+impl Drop for LockedShards {
+    fn drop(&mut self) {
+        if !self.shards.is_empty() {
+            self.give_back(None);
+        }
+    }
+}
+
+/// A stream entry as replied, its id then its fields and values
+fn entry_frame(id: StreamId, fields: &Fields) -> Frame {
+    let fields = fields
+        .iter()
+        .flat_map(|(field, value)| [Frame::Bulk(field.clone()), Frame::Bulk(value.clone())])
+        .collect();
+    Frame::Array(vec![Frame::Bulk(id.to_bytes()), Frame::Array(fields)])
+}
+
+fn no_group(key: &str, group: &str) -> Frame {
+    Frame::Error(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        key, group
+    ))
+}
+
+/// Order of the fields for `HSCAN`
+fn field_hash(field: &[u8]) -> u64 {
+    let mut s = DefaultHasher::new();
+    field.hash(&mut s);
+    s.finish()
+}
+
+/// Position of a key for `SCAN`, keeping the bits held by a cursor
+fn scan_position(key: &str) -> u64 {
+    let (_, position) = split_scan_cursor(scan_cursor(0, field_hash(key.as_bytes())));
+    position
+}
+
+/// Resolve a possibly negative index
+fn list_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+/// Members matching `GEOSEARCH`, sorted and limited as requested
+pub fn geo_search(zset: Option<&SortedSet>, cmd: &GeoSearch) -> Result<Vec<GeoMatch>, Frame> {
+    let Some(zset) = zset else {
+        return Ok(vec![]);
+    };
+    let center = match cmd.origin {
+        GeoOrigin::Member(ref member) => match zset.score(member) {
+            Some(score) => geo::decode(score as u64),
+            None => {
+                return Err(Frame::Error(
+                    "ERR could not decode requested zset member".to_string(),
+                ))
+            }
+        },
+        GeoOrigin::Coordinates(lon, lat) => (lon, lat),
+    };
+    let limit = cmd.count.filter(|_| cmd.any);
+    let mut matches = geo::search(zset, center, cmd.shape, limit);
+    // The closest ones are kept by `COUNT`, unless any will do
+    let ascending = match cmd.ascending {
+        None if cmd.count.is_some() && !cmd.any => Some(true),
+        ascending => ascending,
+    };
+    if let Some(ascending) = ascending {
+        matches.sort_by(|a, b| match ascending {
+            true => a.distance.total_cmp(&b.distance),
+            false => b.distance.total_cmp(&a.distance),
+        });
+    }
+    if let Some(count) = cmd.count {
+        matches.truncate(count);
+    }
+    Ok(matches)
+}
+
+/// The members, along the information asked by the `WITH` options
+fn geo_reply(matches: Vec<GeoMatch>, cmd: &GeoSearch) -> Frame {
+    let frames = matches.into_iter().map(|found| {
+        if !(cmd.with_dist || cmd.with_hash || cmd.with_coord) {
+            return Frame::Bulk(found.member);
+        }
+        let mut frame = vec![Frame::Bulk(found.member)];
+        if cmd.with_dist {
+            let distance = format!("{:.4}", found.distance / cmd.unit);
+            frame.push(Frame::Bulk(Bytes::from(distance)));
+        }
+        if cmd.with_hash {
+            frame.push(Frame::Integer(found.hash as i64));
+        }
+        if cmd.with_coord {
+            frame.push(Frame::Array(vec![
+                Frame::Double(found.lon),
+                Frame::Double(found.lat),
+            ]));
+        }
+        Frame::Array(frame)
+    });
+    Frame::Array(frames.collect())
+}
+
+/// Resolve inclusive, possibly negative, bounds, `None` when the range is
+/// empty
+fn list_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Execute a command on the shard, as sent by a client
+    fn run(shard: &mut Shard, args: &[&str]) -> Frame {
+        let cmd = Command::from_frame(request(args)).unwrap();
+        shard.execute(KVStoreCommand::try_from(cmd).ok().unwrap())
+    }
+
+    #[test]
+    fn list_range_test() {
+        assert_eq!(list_range(0, -1, 3), Some((0, 2)));
+        assert_eq!(list_range(-2, 10, 3), Some((1, 2)));
+        assert_eq!(list_range(-10, 0, 3), Some((0, 0)));
+        assert_eq!(list_range(2, 1, 3), None);
+        assert_eq!(list_range(5, 10, 3), None);
+        assert_eq!(list_range(0, -1, 0), None);
+        assert_eq!(list_index(-1, 3), Some(2));
+        assert_eq!(list_index(3, 3), None);
+    }
+
+    #[test]
+    fn block_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        let claim = Arc::new(AtomicBool::new(false));
+        let (respond, mut served) = mpsc::unbounded_channel();
+        let waiter = |claim: &Arc<AtomicBool>| Waiter {
+            claim: claim.clone(),
+            wait: Wait::Pop(ListSide::Left),
+            respond: respond.clone(),
+        };
+
+        // Registered on two keys, served once
+        shard.block("a".to_string(), waiter(&claim));
+        shard.block("b".to_string(), waiter(&claim));
+        assert!(served.try_recv().is_err());
+
+        let push = |key: &str| {
+            KVStoreCommand::Push(Push {
+                key: key.to_string(),
+                elements: vec![Bytes::from("1"), Bytes::from("2")],
+                side: ListSide::Right,
+            })
+        };
+        shard.execute(push("b"));
+        shard.execute(push("a"));
+        assert_eq!(
+            served.try_recv().unwrap(),
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from("b")),
+                Frame::Bulk(Bytes::from("1"))
+            ])
+        );
+        assert!(served.try_recv().is_err());
+
+        shard.prune_blocked();
+        assert!(shard.blocked.is_empty());
+        assert_eq!(
+            shard.db.get("a").unwrap().value,
+            Value::List(VecDeque::from([Bytes::from("1"), Bytes::from("2")]))
+        );
+    }
+
+    #[test]
+    fn stream_group_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        let run =
+            |shard: &mut Shard, args: &[&str]| match Command::from_frame(request(args)).unwrap() {
+                Command::XRead(cmd) => shard.execute(KVStoreCommand::XRead(cmd.reads().remove(0))),
+                cmd => shard.execute(KVStoreCommand::try_from(cmd).ok().unwrap()),
+            };
+        let bulk = |data: &str| Frame::Bulk(Bytes::from(data.to_string()));
+
+        assert_eq!(
+            run(&mut shard, &["xgroup", "create", "s", "g", "$", "MKSTREAM"]),
+            Frame::Simple("OK".to_string())
+        );
+        assert_eq!(
+            run(&mut shard, &["xadd", "s", "1-1", "f", "v"]),
+            bulk("1-1")
+        );
+
+        // Blocked on the group, served by the next entry
+        let (respond, mut served) = mpsc::unbounded_channel();
+        let Ok(Command::XRead(cmd)) = Command::from_frame(Frame::Array(
+            [
+                "xreadgroup",
+                "group",
+                "g",
+                "c",
+                "block",
+                "0",
+                "streams",
+                "s",
+                ">",
+            ]
+            .iter()
+            .map(|arg| bulk(arg))
+            .collect(),
+        )) else {
+            panic!("unexpected command");
+        };
+        let waiter = Waiter {
+            claim: Arc::new(AtomicBool::new(false)),
+            wait: Wait::Read(cmd.reads().remove(0)),
+            respond,
+        };
+        shard.block("s".to_string(), waiter);
+        let entry =
+            |id: &str| Frame::Array(vec![bulk(id), Frame::Array(vec![bulk("f"), bulk("v")])]);
+        assert_eq!(
+            served.try_recv().unwrap(),
+            Frame::Array(vec![Frame::Array(vec![
+                bulk("s"),
+                Frame::Array(vec![entry("1-1")])
+            ])])
+        );
+        assert_eq!(
+            run(
+                &mut shard,
+                &["xreadgroup", "group", "g", "c", "streams", "s", ">"]
+            ),
+            Frame::Null
+        );
+
+        // Claimed by another consumer, then deleted from the stream
+        assert_eq!(
+            run(&mut shard, &["xclaim", "s", "g", "d", "0", "1-1", "JUSTID"]),
+            Frame::Array(vec![bulk("1-1")])
+        );
+        let Frame::Array(pending) = run(&mut shard, &["xpending", "s", "g", "-", "+", "10"]) else {
+            panic!("unexpected reply");
+        };
+        // The idle time depends on the clock, only the rest is checked
+        let Frame::Array(ref details) = pending[0] else {
+            panic!("unexpected reply");
+        };
+        assert_eq!(details[..2], [bulk("1-1"), bulk("d")]);
+        assert_eq!(details[3], Frame::Integer(1));
+        assert_eq!(run(&mut shard, &["xdel", "s", "1-1"]), Frame::Integer(1));
+        assert_eq!(
+            run(&mut shard, &["xautoclaim", "s", "g", "c", "0", "0"]),
+            Frame::Array(vec![
+                bulk("0-0"),
+                Frame::Array(vec![]),
+                Frame::Array(vec![bulk("1-1")])
+            ])
+        );
+        assert_eq!(
+            run(&mut shard, &["xpending", "s", "g"]),
+            Frame::Array(vec![
+                Frame::Integer(0),
+                Frame::Null,
+                Frame::Null,
+                Frame::NullArray
+            ])
+        );
+        // Empty streams are kept
+        assert_eq!(run(&mut shard, &["xlen", "s"]), Frame::Integer(0));
+        assert_eq!(
+            run(&mut shard, &["xadd", "s", "1-1", "f", "v"]),
+            Frame::Error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn notify_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        let (broker, mut published) = mpsc::unbounded_channel();
+        shard.notify_to(broker, NotifyEvents::parse("Elgx").unwrap());
+        let mut events = || {
+            let mut events = vec![];
+            while let Ok(BrokerCommand::Notify(publish)) = published.try_recv() {
+                events.push((publish.channel, publish.message));
+            }
+            events
+        };
+
+        shard.execute(KVStoreCommand::Push(Push {
+            key: "list".to_string(),
+            elements: vec![Bytes::from("a")],
+            side: ListSide::Left,
+        }));
+        shard.execute(KVStoreCommand::Pop(Pop {
+            key: "list".to_string(),
+            count: None,
+            side: ListSide::Right,
+        }));
+        let event = |event: &str, key: &str| {
+            (
+                format!("__keyevent@0__:{}", event),
+                Bytes::from(key.to_string()),
+            )
+        };
+        assert_eq!(
+            events(),
+            vec![
+                event("lpush", "list"),
+                event("rpop", "list"),
+                event("del", "list")
+            ]
+        );
+
+        // Strings are not enabled
+        shard.select(1);
+        let value = Value::String(b"v".to_vec());
+        shard.db().set("key".to_string(), value, Some(now_ms() - 1));
+        shard.execute(KVStoreCommand::Set(Set::new(
+            "other".to_string(),
+            Bytes::from("v"),
+        )));
+        shard.expire("key");
+        assert_eq!(
+            events(),
+            vec![(String::from("__keyevent@1__:expired"), Bytes::from("key"))]
+        );
+    }
+
+    #[test]
+    fn scan_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        for i in 0..50 {
+            let value = Value::String(b"v".to_vec());
+            shard.db().set(format!("key{}", i), value, None);
+        }
+        shard
+            .db()
+            .set("list".to_string(), Value::List(VecDeque::new()), None);
+
+        let scan = |value_type: Option<&str>| Scan {
+            cursor: 0,
+            pattern: Some(Bytes::from("key*")),
+            count: 7,
+            value_type: value_type.map(str::to_string),
+        };
+        let mut keys = HashSet::new();
+        let mut position = Some(0);
+        while let Some(from) = position {
+            let (matching, next) = shard.scan(from, 7, &scan(None));
+            assert!(matching.len() <= 7);
+            for key in matching {
+                let Frame::Bulk(key) = key else {
+                    panic!("unexpected key");
+                };
+                assert!(keys.insert(key));
+            }
+            position = next;
+        }
+        assert_eq!(keys.len(), 50);
+
+        let (matching, next) = shard.scan(0, 100, &scan(Some("list")));
+        assert!(matching.is_empty() && next.is_none());
+    }
+
+    #[test]
+    fn set_options_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        let set = |shard: &mut Shard, args: &[&str]| {
+            let frame = request(&[&["set"], args].concat());
+            let Ok(Command::Set(cmd)) = Command::from_frame(frame) else {
+                panic!("unexpected command");
+            };
+            shard.execute(KVStoreCommand::Set(cmd))
+        };
+
+        assert_eq!(
+            set(&mut shard, &["lock", "a", "NX", "PX", "30000"]),
+            Frame::Simple("OK".to_string())
+        );
+        assert_eq!(
+            set(&mut shard, &["lock", "b", "PX", "30000", "NX"]),
+            Frame::Null
+        );
+        assert_eq!(
+            set(&mut shard, &["lock", "b", "GET", "XX", "KEEPTTL"]),
+            Frame::Bulk(Bytes::from("a"))
+        );
+        assert!(shard.db().get("lock").unwrap().expire_at.is_some());
+        assert_eq!(
+            set(&mut shard, &["lock", "c", "GET"]),
+            Frame::Bulk(Bytes::from("b"))
+        );
+        assert!(shard.db().get("lock").unwrap().expire_at.is_none());
+        assert_eq!(set(&mut shard, &["missing", "c", "XX", "GET"]), Frame::Null);
+        assert!(shard.db().get("missing").is_none());
+    }
+
+    #[test]
+    fn strings_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        let bulk = |value: &str| Frame::Bulk(Bytes::from(value.to_string()));
+        let is_error = |frame: Frame| matches!(frame, Frame::Error(_));
+
+        assert_eq!(run(&mut shard, &["incr", "counter"]), Frame::Integer(1));
+        assert_eq!(
+            run(&mut shard, &["incrby", "counter", "-11"]),
+            Frame::Integer(-10)
+        );
+        assert_eq!(run(&mut shard, &["decr", "counter"]), Frame::Integer(-11));
+        assert_eq!(
+            run(&mut shard, &["decrby", "counter", "4"]),
+            Frame::Integer(-15)
+        );
+        let max = i64::MAX.to_string();
+        run(&mut shard, &["set", "counter", &max]);
+        assert!(is_error(run(&mut shard, &["incr", "counter"])));
+        assert_eq!(run(&mut shard, &["get", "counter"]), bulk(&max));
+        run(&mut shard, &["set", "counter", &i64::MIN.to_string()]);
+        assert!(is_error(run(&mut shard, &["decr", "counter"])));
+        let frame = request(&["decrby", "counter", &i64::MIN.to_string()]);
+        assert!(Command::from_frame(frame).is_err());
+        run(&mut shard, &["set", "counter", "1.5"]);
+        assert!(is_error(run(&mut shard, &["incr", "counter"])));
+        run(&mut shard, &["set", "counter", " 1"]);
+        assert!(is_error(run(&mut shard, &["incr", "counter"])));
+
+        assert_eq!(
+            run(&mut shard, &["append", "string", "Hello"]),
+            Frame::Integer(5)
+        );
+        assert_eq!(
+            run(&mut shard, &["append", "string", " World"]),
+            Frame::Integer(11)
+        );
+        assert_eq!(run(&mut shard, &["strlen", "string"]), Frame::Integer(11));
+        assert_eq!(
+            run(&mut shard, &["getrange", "string", "0", "4"]),
+            bulk("Hello")
+        );
+        assert_eq!(
+            run(&mut shard, &["getrange", "string", "-5", "-1"]),
+            bulk("World")
+        );
+        assert_eq!(
+            run(&mut shard, &["getrange", "string", "-100", "2"]),
+            bulk("Hel")
+        );
+        assert_eq!(
+            run(&mut shard, &["getrange", "string", "6", "100"]),
+            bulk("World")
+        );
+        assert_eq!(run(&mut shard, &["getrange", "string", "5", "2"]), bulk(""));
+        assert_eq!(
+            run(&mut shard, &["getrange", "string", "20", "30"]),
+            bulk("")
+        );
+        assert_eq!(
+            run(&mut shard, &["getrange", "missing", "0", "-1"]),
+            bulk("")
+        );
+
+        // Padded with zeros past the end
+        assert_eq!(
+            run(&mut shard, &["setrange", "string", "6", "Redis"]),
+            Frame::Integer(11)
+        );
+        assert_eq!(run(&mut shard, &["get", "string"]), bulk("Hello Redis"));
+        assert_eq!(
+            run(&mut shard, &["setrange", "padded", "3", "x"]),
+            Frame::Integer(4)
+        );
+        assert_eq!(run(&mut shard, &["get", "padded"]), bulk("\0\0\0x"));
+        assert_eq!(
+            run(&mut shard, &["setrange", "missing", "5", ""]),
+            Frame::Integer(0)
+        );
+        assert_eq!(run(&mut shard, &["exists", "missing"]), Frame::Integer(0));
+        let frame = request(&["setrange", "string", "-1", "x"]);
+        assert!(Command::from_frame(frame).is_err());
+
+        // GETEX sets or removes the expiration of the value it reads
+        let ttl = |shard: &mut Shard| run(shard, &["pttl", "string"]);
+        assert_eq!(run(&mut shard, &["getex", "string"]), bulk("Hello Redis"));
+        assert_eq!(ttl(&mut shard), Frame::Integer(-1));
+        run(&mut shard, &["getex", "string", "PX", "50000"]);
+        assert!(matches!(ttl(&mut shard), Frame::Integer(49_000..=50_000)));
+        run(&mut shard, &["getex", "string", "EX", "100"]);
+        assert!(matches!(ttl(&mut shard), Frame::Integer(99_000..=100_000)));
+        let at = (now_ms() + 200_000).to_string();
+        run(&mut shard, &["getex", "string", "PXAT", &at]);
+        assert!(matches!(ttl(&mut shard), Frame::Integer(199_000..=200_000)));
+        let at = (now_ms() / 1000 + 300).to_string();
+        run(&mut shard, &["getex", "string", "EXAT", &at]);
+        assert!(matches!(ttl(&mut shard), Frame::Integer(298_000..=300_000)));
+        assert_eq!(
+            run(&mut shard, &["getex", "string", "PERSIST"]),
+            bulk("Hello Redis")
+        );
+        assert_eq!(ttl(&mut shard), Frame::Integer(-1));
+        assert_eq!(
+            run(&mut shard, &["getex", "missing", "EX", "10"]),
+            Frame::Null
+        );
+        for args in [
+            &["getex", "string", "EX", "0"][..],
+            &["getex", "string", "EX", "10", "PX", "10"],
+            &["getex", "string", "PERSIST", "EX", "10"],
+            &["getex", "string", "KEEPTTL"],
+        ] {
+            assert!(Command::from_frame(request(args)).is_err());
+        }
+
+        run(&mut shard, &["rpush", "list", "a"]);
+        for args in [
+            &["incr", "list"][..],
+            &["append", "list", "a"],
+            &["getrange", "list", "0", "1"],
+            &["setrange", "list", "0", "a"],
+            &["getex", "list"],
+        ] {
+            assert!(matches!(
+                run(&mut shard, args),
+                Frame::Error(err) if err.starts_with("WRONGTYPE")
+            ));
+        }
+    }
+
+    #[test]
+    fn expire_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        let ok = Frame::Simple("OK".to_string());
+        assert_eq!(
+            run(&mut shard, &["expire", "missing", "100"]),
+            Frame::Integer(0)
+        );
+        assert_eq!(run(&mut shard, &["ttl", "missing"]), Frame::Integer(-2));
+        assert_eq!(run(&mut shard, &["set", "key", "value"]), ok);
+        assert_eq!(run(&mut shard, &["ttl", "key"]), Frame::Integer(-1));
+
+        assert_eq!(
+            run(&mut shard, &["expire", "key", "100"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(run(&mut shard, &["ttl", "key"]), Frame::Integer(100));
+        let Frame::Integer(pttl) = run(&mut shard, &["pttl", "key"]) else {
+            panic!("unexpected reply");
+        };
+        assert!(pttl > 99_000 && pttl <= 100_000);
+        assert_eq!(
+            run(&mut shard, &["pexpire", "key", "5000"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(run(&mut shard, &["ttl", "key"]), Frame::Integer(5));
+
+        // Conditions compare with the current expiration
+        assert_eq!(
+            run(&mut shard, &["expire", "key", "10", "NX"]),
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            run(&mut shard, &["expire", "key", "1", "GT"]),
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            run(&mut shard, &["expire", "key", "10", "GT"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(run(&mut shard, &["persist", "key"]), Frame::Integer(1));
+        assert_eq!(run(&mut shard, &["persist", "key"]), Frame::Integer(0));
+        assert_eq!(
+            run(&mut shard, &["expire", "key", "10", "XX"]),
+            Frame::Integer(0)
+        );
+        assert_eq!(run(&mut shard, &["ttl", "key"]), Frame::Integer(-1));
+
+        let at = (now_ms() / 1000 + 100).to_string();
+        assert_eq!(
+            run(&mut shard, &["expireat", "key", &at]),
+            Frame::Integer(1)
+        );
+        assert!(matches!(
+            run(&mut shard, &["ttl", "key"]),
+            Frame::Integer(99..=100)
+        ));
+        assert_eq!(
+            run(&mut shard, &["getex", "key", "PERSIST"]),
+            Frame::Bulk(Bytes::from("value"))
+        );
+        assert_eq!(run(&mut shard, &["ttl", "key"]), Frame::Integer(-1));
+        assert_eq!(
+            run(&mut shard, &["getex", "key", "EX", "20"]),
+            Frame::Bulk(Bytes::from("value"))
+        );
+        assert_eq!(run(&mut shard, &["ttl", "key"]), Frame::Integer(20));
+
+        // An expiration in the past deletes the key
+        assert_eq!(
+            run(&mut shard, &["pexpireat", "key", "1"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(run(&mut shard, &["get", "key"]), Frame::Null);
+        assert_eq!(run(&mut shard, &["set", "key", "value", "PX", "1"]), ok);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(run(&mut shard, &["ttl", "key"]), Frame::Integer(-2));
+        assert_eq!(run(&mut shard, &["get", "key"]), Frame::Null);
+    }
+
+    #[test]
+    fn hash_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        let bulk = |value: &str| Frame::Bulk(Bytes::from(value.to_string()));
+        let sorted = |frame: Frame| match frame {
+            Frame::Array(mut values) => {
+                values.sort_by_key(|value| format!("{:?}", value));
+                values
+            }
+            frame => panic!("unexpected reply {:?}", frame),
+        };
+        assert_eq!(
+            run(&mut shard, &["hset", "hash", "a", "1", "b", "2"]),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(&mut shard, &["hset", "hash", "b", "3", "c", "4"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(run(&mut shard, &["hget", "hash", "b"]), bulk("3"));
+        assert_eq!(run(&mut shard, &["hget", "hash", "missing"]), Frame::Null);
+        assert_eq!(run(&mut shard, &["hget", "missing", "a"]), Frame::Null);
+        assert_eq!(
+            run(&mut shard, &["hmget", "hash", "a", "missing", "c"]),
+            Frame::Array(vec![bulk("1"), Frame::Null, bulk("4")])
+        );
+        assert_eq!(run(&mut shard, &["hlen", "hash"]), Frame::Integer(3));
+        assert_eq!(
+            run(&mut shard, &["hexists", "hash", "a"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&mut shard, &["hexists", "hash", "d"]),
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            sorted(run(&mut shard, &["hkeys", "hash"])),
+            [bulk("a"), bulk("b"), bulk("c")]
+        );
+        assert_eq!(
+            sorted(run(&mut shard, &["hvals", "hash"])),
+            [bulk("1"), bulk("3"), bulk("4")]
+        );
+        let Frame::Map(mut pairs) = run(&mut shard, &["hgetall", "hash"]) else {
+            panic!("unexpected reply");
+        };
+        pairs.sort_by_key(|pair| format!("{:?}", pair));
+        assert_eq!(
+            pairs,
+            [
+                (bulk("a"), bulk("1")),
+                (bulk("b"), bulk("3")),
+                (bulk("c"), bulk("4"))
+            ]
+        );
+
+        assert_eq!(
+            run(&mut shard, &["hincrby", "hash", "a", "10"]),
+            Frame::Integer(11)
+        );
+        assert_eq!(
+            run(&mut shard, &["hincrby", "hash", "d", "-5"]),
+            Frame::Integer(-5)
+        );
+        run(&mut shard, &["hset", "hash", "text", "abc"]);
+        assert!(matches!(
+            run(&mut shard, &["hincrby", "hash", "text", "1"]),
+            Frame::Error(_)
+        ));
+        let max = i64::MAX.to_string();
+        assert!(matches!(
+            run(&mut shard, &["hincrby", "hash", "a", &max]),
+            Frame::Error(_)
+        ));
+        assert_eq!(run(&mut shard, &["hget", "hash", "a"]), bulk("11"));
+
+        // The hash is removed with its last field
+        assert_eq!(
+            run(&mut shard, &["hdel", "hash", "a", "b", "missing"]),
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(&mut shard, &["hdel", "hash", "c", "d", "text"]),
+            Frame::Integer(3)
+        );
+        assert_eq!(run(&mut shard, &["exists", "hash"]), Frame::Integer(0));
+        assert_eq!(run(&mut shard, &["hlen", "hash"]), Frame::Integer(0));
+
+        run(&mut shard, &["set", "string", "value"]);
+        assert!(matches!(
+            run(&mut shard, &["hget", "string", "a"]),
+            Frame::Error(err) if err.starts_with("WRONGTYPE")
+        ));
+        assert!(matches!(
+            run(&mut shard, &["hset", "string", "a", "1"]),
+            Frame::Error(err) if err.starts_with("WRONGTYPE")
+        ));
+    }
+
+    #[test]
+    fn databases_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        let value = || Value::String(b"v".to_vec());
+        shard.db().set("key".to_string(), value(), None);
+        let move_key = |shard: &mut Shard, db| {
+            let cmd = Move {
+                key: "key".to_string(),
+                db,
+            };
+            shard.execute(KVStoreCommand::Move(cmd))
+        };
+
+        assert!(matches!(move_key(&mut shard, 16), Frame::Error(_)));
+        assert_eq!(move_key(&mut shard, 1), Frame::Integer(1));
+        assert!(shard.db().is_empty());
+        assert_eq!(move_key(&mut shard, 1), Frame::Integer(0));
+        shard.select(1);
+        assert!(shard.db().get("key").is_some());
+
+        // The selected database follows its index
+        shard.swap_dbs(0, 1);
+        assert!(shard.db().is_empty());
+        shard.select(0);
+        assert!(shard.db().get("key").is_some());
+
+        shard.select(2);
+        shard.db().set("other".to_string(), value(), None);
+        shard.flush(false);
+        assert!(shard.db().is_empty());
+        let dbs = shard.dump();
+        assert_eq!(dbs.len(), 16);
+        assert_eq!(dbs[0].len(), 1);
+        assert!(dbs[1..].iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn hscan_test() {
+        let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+        shard.execute(KVStoreCommand::HSet(HSet {
+            key: "h".to_string(),
+            pairs: (0..50)
+                .map(|i| (Bytes::from(format!("f{}", i)), Bytes::from("v")))
+                .collect(),
+        }));
+
+        let mut fields = vec![];
+        let mut cursor = 0;
+        loop {
+            let response = shard.execute(KVStoreCommand::HScan(HScan {
+                key: "h".to_string(),
+                cursor,
+                pattern: None,
+                count: 7,
+                novalues: true,
+            }));
+            let Frame::Array(mut reply) = response else {
+                panic!("unexpected reply");
+            };
+            let Some(Frame::Array(items)) = reply.pop() else {
+                panic!("unexpected items");
+            };
+            let Some(Frame::Bulk(next)) = reply.pop() else {
+                panic!("unexpected cursor");
+            };
+
+            // Removing a returned field does not affect the iteration
+            if let Some(Frame::Bulk(field)) = items.first() {
+                shard.execute(KVStoreCommand::HDel(HDel {
+                    key: "h".to_string(),
+                    fields: vec![field.clone()],
+                }));
+            }
+            fields.extend(items);
+            cursor = std::str::from_utf8(&next).unwrap().parse().unwrap();
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        fields.sort_by_key(|f| format!("{:?}", f));
+        fields.dedup();
+        assert_eq!(fields.len(), 50);
+    }
+
+    #[tokio::test]
+    async fn lock_test() {
+        let (feed, mut fed) = mpsc::unbounded_channel();
+        let kvs: Vec<KVStore> = (0..2)
+            .map(|_| {
+                let (cmd_tx, mut cmd_rx) = mpsc::unbounded_channel();
+                let shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+                let feed = feed.clone();
+                tokio::spawn(async move {
+                    process_kvstore(shard, feed, false, &mut cmd_rx).await;
+                });
+                cmd_tx
+            })
+            .collect();
+
+        // Commands sent after the request are executed once the shards are
+        // given back
+        let lock = LockedShards::request(&kvs, [1, 0], feed.clone(), false);
+        let (respond, mut response) = mpsc::unbounded_channel();
+        let get = KVStoreCommand::Get(Get {
+            key: "key".to_string(),
+        });
+        kvs[0]
+            .send(KVStoreMessage::Command(0, get, respond.clone(), None))
+            .unwrap();
+
+        let mut locked = lock.acquire().await;
+        locked.shard(0).execute(KVStoreCommand::Set(Set::new(
+            "key".to_string(),
+            Bytes::from("value"),
+        )));
+        assert!(response.try_recv().is_err());
+        locked.release(&respond, Frame::Simple("OK".to_string()));
+
+        assert_eq!(
+            response.recv().await.unwrap(),
+            Frame::Simple("OK".to_string())
+        );
+        assert_eq!(
+            response.recv().await.unwrap(),
+            Frame::Bulk(Bytes::from("value"))
+        );
+        assert!(matches!(
+            fed.try_recv(),
+            Ok(FeedMessage::Command(0, _, None))
+        ));
+    }
+
+    #[test]
+    fn eviction_test() {
+        let used = Arc::new(AtomicU64::new(0));
+        let maxmemory = MaxMemory {
+            policy: EvictionPolicy::AllKeysLru,
+            ..MaxMemory::default()
+        };
+        let mut shards: Vec<Shard> = (0..4)
+            .map(|_| {
+                let mut shard = Shard::new(Arc::new(AtomicU64::new(0)), 16);
+                shard.limit_memory(used.clone(), maxmemory, 4);
+                shard
+            })
+            .collect();
+        let value = "v".repeat(100);
+
+        // Filled by a single shard, the others have no key to evict
+        for i in 0..1000 {
+            run(&mut shards[0], &["set", &format!("key:{}", i), &value]);
+        }
+        let limit = used.load(Ordering::Relaxed) / 2;
+        for shard in &mut shards {
+            shard.maxmemory.limit = limit;
+        }
+        assert_eq!(
+            run(&mut shards[1], &["set", "other", &value]),
+            Frame::Simple("OK".to_string())
+        );
+
+        // Evicted by the shards over their share
+        for i in 0..4000 {
+            let key = format!("spread:{}", i);
+            let reply = run(&mut shards[i % 4], &["set", &key, &value]);
+            assert_eq!(reply, Frame::Simple("OK".to_string()));
+        }
+        assert!(used.load(Ordering::Relaxed) <= limit + limit / 10);
+        assert!(shards[0].db().len() < 1000);
+    }
+}
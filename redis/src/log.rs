@@ -0,0 +1,134 @@
+use crate::db::now_ms;
+use crate::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Messages less severe than this level are not logged
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Notice as u8);
+/// Whether the server replicates a primary, marked in the log lines
+static REPLICA: AtomicBool = AtomicBool::new(false);
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Severity of a message, named as in `redis.conf`
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl LogLevel {
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        match &value.to_lowercase()[..] {
+            "debug" => Ok(LogLevel::Debug),
+            "verbose" => Ok(LogLevel::Verbose),
+            "notice" => Ok(LogLevel::Notice),
+            "warning" => Ok(LogLevel::Warning),
+            _ => Err(format!("invalid loglevel '{}'", value).into()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+        }
+    }
+
+    /// Mark of the level in the log lines
+    fn symbol(&self) -> char {
+        match self {
+            LogLevel::Debug => '.',
+            LogLevel::Verbose => '-',
+            LogLevel::Notice => '*',
+            LogLevel::Warning => '#',
+        }
+    }
+}
+
+/// Only log the messages at least as severe as `level`
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Mark the log lines with the role of a replica, `S`, instead of `M`
+pub fn set_replica(replica: bool) {
+    REPLICA.store(replica, Ordering::Relaxed);
+}
+
+/// Write a line to the standard output, as `pid:M 17 Oct 2026 12:00:00.000 * message`
+pub fn log(level: LogLevel, message: fmt::Arguments) {
+    if (level as u8) < LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    let role = if REPLICA.load(Ordering::Relaxed) {
+        'S'
+    } else {
+        'M'
+    };
+    println!(
+        "{}:{} {} {} {}",
+        std::process::id(),
+        role,
+        format_time(now_ms()),
+        level.symbol(),
+        message
+    );
+}
+
+pub fn warning(message: fmt::Arguments) {
+    log(LogLevel::Warning, message);
+}
+
+pub fn notice(message: fmt::Arguments) {
+    log(LogLevel::Notice, message);
+}
+
+pub fn verbose(message: fmt::Arguments) {
+    log(LogLevel::Verbose, message);
+}
+
+/// UTC date and time of a Unix time in milliseconds
+fn format_time(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (days, time) = (secs / 86400, secs % 86400);
+    // Civil date from the days since the epoch, with years starting in March
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:02} {} {} {:02}:{:02}:{:02}.{:03}",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_time_test() {
+        assert_eq!(format_time(0), "01 Jan 1970 00:00:00.000");
+        assert_eq!(format_time(951782400123), "29 Feb 2000 00:00:00.123");
+        assert_eq!(format_time(1792240496789), "17 Oct 2026 12:34:56.789");
+    }
+}
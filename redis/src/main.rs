@@ -1,30 +1,115 @@
+mod acl;
+mod aof;
+mod args;
+mod bitmap;
 mod buffer;
+mod client;
+mod cluster;
 mod command_parser;
+mod config;
+mod db;
 mod error;
+mod eviction;
 use crate::error::*;
 mod frame;
 use crate::frame::*;
 mod command;
+mod geo;
+mod glob;
+mod hyperloglog;
+mod kvstore;
+mod log;
+mod pubsub;
+mod rdb;
+mod replication;
+mod stats;
+mod stream;
+mod transaction;
+mod zset;
+use crate::aof::{process_feed, Feed, FeedMessage, FsyncPolicy, WriteStatus};
+use crate::bitmap::bitop;
 use crate::buffer::BufferedStream;
+use crate::client::{ClientInfo, Clients, Monitors};
+use crate::cluster::{key_slot, Route};
 use crate::command::*;
-use std::collections::HashMap;
-use std::hash::{DefaultHasher, Hash, Hasher};
+use crate::config::ServerConfig;
+use crate::db::{now_ms, random, Entry, Value, WRONGTYPE};
+use crate::glob::glob_match;
+use crate::hyperloglog::HyperLogLog;
+use crate::kvstore::*;
+use crate::pubsub::*;
+use crate::replication::{ReplicationLog, Resync};
+use crate::stats::{CommandStats, ExecutionTime, KeyspaceStats};
+use crate::transaction::{Queued, Transaction};
+use bytes::Bytes;
+use std::collections::HashSet;
 use std::io;
-use std::sync::Arc;
-use tokio::net::tcp::OwnedReadHalf;
-use tokio::net::tcp::OwnedWriteHalf;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc::error::TryRecvError;
 use tokio::{
     io::{AsyncWriteExt, BufWriter},
     net::{TcpListener, TcpStream},
-    sync::mpsc,
+    signal,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time,
 };
 
+/// Delay before retrying a snapshot after a failure
+const SAVE_RETRY_DELAY_SECS: u64 = 5;
+/// Delay before reconnecting to the primary after a failure
+const REPLICA_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Delay between two acknowledgements of the replicated offset
+const REPLICA_ACK_PERIOD: Duration = Duration::from_secs(1);
+
 #[tokio::main]
 async fn main() {
-    let backend = Arc::new(Backend::new());
+    let config = match ServerConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("*** FATAL CONFIG FILE ERROR *** {}", err);
+            std::process::exit(1);
+        }
+    };
+    log::set_level(config.loglevel);
+    let (bind, port) = (config.bind.clone(), config.port);
+    let primary = config.replicaof.clone();
+    let backend = match Backend::new(config).await {
+        Ok(backend) => Arc::new(backend),
+        Err(err) => {
+            log::warning(format_args!(
+                "Fatal error loading the DB: {}. Exiting.",
+                err
+            ));
+            std::process::exit(1);
+        }
+    };
+    tokio::spawn(serve_save_points(backend.clone()));
+    if primary.is_some() {
+        backend.replica_of(primary);
+    }
+
+    // Bind a listener to every address
+    for address in bind {
+        let listener = TcpListener::bind((&address[..], port)).await.unwrap();
+        tokio::spawn(serve_clients(backend.clone(), listener));
+    }
+
+    signal::ctrl_c().await.unwrap();
+    if !backend.config().save.is_empty() {
+        log::notice(format_args!(
+            "Saving the final RDB snapshot before exiting."
+        ));
+        backend.save().await.unwrap();
+    }
+}
 
-    // Bind the listener to the address
-    let listener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
+async fn serve_clients(backend: Arc<Backend>, listener: TcpListener) {
     loop {
         let (socket, _) = listener.accept().await.unwrap();
         let backend = backend.clone();
@@ -35,152 +120,3009 @@ async fn main() {
 }
 
 async fn process_client(backend: &Arc<Backend>, socket: TcpStream) {
-    let (cmd_tx, mut cmd_rx) = mpsc::unbounded_channel::<Frame>();
-
+    let id = backend.next_client_id();
+    let address =
+        |address: io::Result<SocketAddr>| address.map_or(String::new(), |a| a.to_string());
+    let addr = address(socket.peer_addr());
+    let info = ClientInfo::new(id, addr.clone(), address(socket.local_addr()));
+    let (client, killed) = backend.clients.register(info);
     let mut connection = Connection::new(socket);
+    let mut subscriber = Subscriber::new(id, backend.broker.clone(), connection.sender());
+    let mut transaction = Transaction::new();
+    // Index of the selected database
+    let mut db = 0;
+    // The connection of a replica only receives the stream of writes once
+    // synchronized
+    let mut replica = false;
+    // Set by `ASKING` for the next command only
+    let mut asking = false;
+    // ACL user the connection is authenticated as, the default user unless
+    // it requires a password
+    let mut user = match backend.acl.lock().unwrap().default_login() {
+        true => Some("default".to_string()),
+        false => None,
+    };
 
-    while let Some(frame) = connection.read_frame().await.unwrap() {
-        let response = match Command::from_frame(frame.clone()) {
+    // Requests are dispatched without waiting for the previous replies, the
+    // connection writes the replies back in request order
+    loop {
+        let frame = tokio::select! {
+            frame = connection.read_frame() => match frame {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(err) => {
+                    log::verbose(format_args!("Connection error with {}: {}", addr, err));
+                    break;
+                }
+            },
+            _ = killed.notified() => {
+                connection.close();
+                break;
+            }
+        };
+        // A RESP3 connection keeps accepting every command while subscribed
+        let restricted = subscriber.is_active() && connection.protocol == Protocol::Resp2;
+        let command = Command::from_frame(frame.clone());
+        let frame = match &command {
+            Ok(cmd) => redact(cmd, frame),
+            Err(_) => frame,
+        };
+        if replica {
+            // Acknowledged offsets are not used
+            continue;
+        }
+        // HELLO authenticates, then switches the protocol of its own reply
+        let mut hello_refused = None;
+        if let Ok(Command::Hello(cmd)) = &command {
+            match backend.check_hello(cmd, user.is_some()) {
+                Ok(authenticated) => {
+                    if let Some(name) = authenticated {
+                        client.lock().unwrap().user.clone_from(&name);
+                        user = Some(name);
+                    }
+                    if let Some(protocol) = cmd.protocol.and_then(Protocol::from_version) {
+                        connection.protocol = protocol;
+                    }
+                }
+                Err(reply) => hello_refused = Some(reply),
+            }
+        }
+        let respond = match connection.reply() {
+            Ok(respond) => respond,
+            Err(_) => break,
+        };
+        let execution = ExecutionTime::default();
+        let respond = match &command {
             Ok(cmd) => {
-                backend.process(cmd, &cmd_tx);
-                cmd_rx.recv().await.unwrap()
+                backend.monitors.feed(&frame, db, &addr);
+                client.lock().unwrap().interact(cmd.name());
+                let refused = match backend.authorize(user.as_deref(), cmd) {
+                    Ok(()) => backend
+                        .route(cmd, db, std::mem::take(&mut asking))
+                        .await
+                        .err(),
+                    Err(reply) => Some(reply),
+                };
+                if let Some(reply) = refused {
+                    if transaction.is_active() {
+                        transaction.fail();
+                    }
+                    backend.reject(cmd, &reply);
+                    let _ = respond.send(reply);
+                    continue;
+                }
+                // Queued commands are only counted once executed
+                match transaction.is_active()
+                    && !matches!(cmd, Command::Exec(_) | Command::Discard(_))
+                {
+                    true => respond,
+                    false => backend.measure(cmd, frame.clone(), &client, respond, &execution),
+                }
+            }
+            Err(_) => respond,
+        };
+
+        let started = Instant::now();
+        match command {
+            Ok(Command::Hello(cmd)) => {
+                let mode = match backend.cluster.is_some() {
+                    true => "cluster",
+                    false => "standalone",
+                };
+                let reply = match hello_refused {
+                    Some(reply) => reply,
+                    None => {
+                        if let Some(name) = cmd.setname {
+                            client.lock().unwrap().name = name;
+                        }
+                        hello(id, connection.protocol, mode)
+                    }
+                };
+                let _ = respond.send(reply);
+            }
+            Ok(Command::Exec(_)) => match transaction.exec() {
+                Ok(queued) => execution.scope(|| backend.exec(queued, db, &respond)),
+                Err(err) => {
+                    let _ = respond.send(err);
+                }
+            },
+            Ok(Command::Discard(_)) => {
+                let _ = respond.send(transaction.discard());
+            }
+            Ok(cmd) if cmd.is_write() && backend.is_read_only() => {
+                if transaction.is_active() {
+                    transaction.fail();
+                }
+                let reply = Frame::Error(
+                    "READONLY You can't write against a read only replica.".to_string(),
+                );
+                backend.reject(&cmd, &reply);
+                let _ = respond.send(reply);
             }
+            Ok(cmd) if cmd.is_write() && backend.aof_status.error().is_some() => {
+                if transaction.is_active() {
+                    transaction.fail();
+                }
+                let err = backend.aof_status.error().unwrap_or_default();
+                let reply = Frame::Error(aof::misconf(&err));
+                backend.reject(&cmd, &reply);
+                let _ = respond.send(reply);
+            }
+            Ok(cmd) if transaction.is_active() => {
+                let _ = respond.send(transaction.queue(cmd));
+            }
+            Ok(Command::PSync(cmd)) => {
+                backend.sync_replica(cmd, respond).await;
+                replica = true;
+            }
+            Ok(Command::Subscribe(cmd)) => subscriber.subscribe(cmd.channels, &respond),
+            Ok(Command::Unsubscribe(cmd)) => subscriber.unsubscribe(cmd.channels, &respond),
+            Ok(Command::PSubscribe(cmd)) => subscriber.psubscribe(cmd.patterns, &respond),
+            Ok(Command::PUnsubscribe(cmd)) => subscriber.punsubscribe(cmd.patterns, &respond),
+            Ok(Command::Ping(cmd)) if restricted => {
+                let _ = respond.send(Frame::Array(vec![
+                    Frame::Bulk(Bytes::from_static(b"pong")),
+                    Frame::Bulk(cmd.msg.unwrap_or_default()),
+                ]));
+            }
+            Ok(cmd) if restricted => {
+                let _ = respond.send(Frame::Error(format!(
+                    "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING are allowed in this context",
+                    cmd.name()
+                )));
+            }
+            Ok(Command::Multi(_)) => {
+                let _ = respond.send(transaction.multi());
+            }
+            Ok(Command::Auth(cmd)) => {
+                let reply = match backend.authenticate(cmd.username.as_deref(), &cmd.password) {
+                    Ok(name) => {
+                        client.lock().unwrap().user.clone_from(&name);
+                        user = Some(name);
+                        Frame::Simple("OK".to_string())
+                    }
+                    Err(reply) => reply,
+                };
+                let _ = respond.send(reply);
+            }
+            Ok(Command::Acl(cmd)) => {
+                let _ = respond.send(backend.acl(cmd, &client));
+            }
+            Ok(Command::Client(cmd)) => {
+                let _ = respond.send(backend.client(cmd, &client));
+            }
+            Ok(Command::Monitor(_)) => {
+                backend.monitors.add(connection.sender());
+                client.lock().unwrap().monitor = true;
+                let _ = respond.send(Frame::Simple("OK".to_string()));
+            }
+            Ok(Command::Asking(_)) => {
+                asking = true;
+                let _ = respond.send(Frame::Simple("OK".to_string()));
+            }
+            Ok(Command::Select(cmd)) => {
+                let response = if backend.cluster.is_some() && cmd.index != 0 {
+                    Frame::Error("ERR SELECT is not allowed in cluster mode".to_string())
+                } else if cmd.index < backend.config().databases {
+                    db = cmd.index;
+                    Frame::Simple("OK".to_string())
+                } else {
+                    Frame::Error("ERR DB index is out of range".to_string())
+                };
+                let _ = respond.send(response);
+            }
+            Ok(Command::Watch(cmd)) => {
+                backend.watch(db, &cmd.keys, transaction.watch(db, &cmd.keys));
+                let _ = respond.send(Frame::Simple("OK".to_string()));
+            }
+            Ok(Command::Unwatch(_)) => {
+                transaction.unwatch();
+                let _ = respond.send(Frame::Simple("OK".to_string()));
+            }
+            Ok(cmd) => execution.scope(|| backend.process(cmd, db, &respond)),
             Err(err) => {
-                println!("Unable to parse {:?}: {:?}", frame.clone(), err);
-                Frame::Simple(FrameSimple::Error("parse error".to_string()))
+                if transaction.is_active() {
+                    transaction.fail();
+                }
+                log::verbose(format_args!("Unable to parse {:?}: {:?}", frame, err));
+                let reply = Frame::Error(format!("ERR {}", err));
+                backend.stats.lock().unwrap().error(&reply);
+                let _ = respond.send(reply);
             }
-        };
+        }
+
+        execution.add(started.elapsed());
+
+        let mut info = client.lock().unwrap();
+        info.db = db;
+        (info.subscriptions, info.patterns) = subscriber.counts();
+        info.multi = transaction.queued();
+        info.replica = replica;
+    }
+    // The stream of a replica never completes
+    if replica {
+        connection.close();
+    }
+    backend.clients.unregister(id);
+}
+
+/// Client names are printable, without spaces
+fn valid_client_name(name: &str) -> bool {
+    name.bytes().all(|c| (b'!'..=b'~').contains(&c))
+}
 
-        // Respond with an error
-        connection.write_frame(&response).await.unwrap();
+/// Memory amount as `1.50M`
+fn human_bytes(bytes: u64) -> String {
+    let units = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+    match units.iter().find(|(size, _)| bytes >= *size) {
+        Some((size, unit)) => format!("{:.2}{}", bytes as f64 / *size as f64, unit),
+        None => format!("{}B", bytes),
     }
 }
 
+/// Hide the passwords of a request from `MONITOR` and the slow log
+fn redact(cmd: &Command, request: Frame) -> Frame {
+    let Frame::Array(mut args) = request else {
+        return request;
+    };
+    let arg = |index: usize| match args.get(index) {
+        Some(Frame::Bulk(arg)) => arg.clone(),
+        _ => Bytes::new(),
+    };
+    let redacted: Vec<usize> = match cmd {
+        Command::Auth(_) => (1..args.len()).collect(),
+        Command::Hello(Hello { auth: Some(_), .. }) => (2..args.len())
+            .find(|&index| arg(index).eq_ignore_ascii_case(b"auth"))
+            .map_or(vec![], |index| vec![index + 1, index + 2]),
+        Command::Acl(Acl::SetUser(..)) => (3..args.len())
+            .filter(|&index| matches!(arg(index).first(), Some(b'>' | b'<')))
+            .collect(),
+        _ => vec![],
+    };
+    for index in redacted {
+        args[index] = Frame::Bulk(Bytes::from_static(b"(redacted)"));
+    }
+    Frame::Array(args)
+}
+
+/// Reply to `HELLO` once validated, the protocol being already switched
+/// when requested
+fn hello(id: u64, protocol: Protocol, mode: &'static str) -> Frame {
+    let field = |name: &'static str, value: Frame| {
+        (Frame::Bulk(Bytes::from_static(name.as_bytes())), value)
+    };
+    Frame::Map(vec![
+        field("server", Frame::Bulk(Bytes::from_static(b"redis"))),
+        field("version", Frame::Bulk(Bytes::from_static(b"7.2.4"))),
+        field("proto", Frame::Integer(protocol.version())),
+        field("id", Frame::Integer(id as i64)),
+        field("mode", Frame::Bulk(Bytes::from_static(mode.as_bytes()))),
+        field("role", Frame::Bulk(Bytes::from_static(b"master"))),
+        field("modules", Frame::Array(vec![])),
+    ])
+}
+
+/// Replies to a single request, complete once every sender is dropped.
+///
+/// The replies are written with the protocol of the connection at the time
+/// of the request.
+type ReplySlot = (Protocol, mpsc::UnboundedReceiver<Frame>);
+
 struct Connection {
-    write_stream: BufWriter<OwnedWriteHalf>,
+    reply_tx: mpsc::UnboundedSender<ReplySlot>,
+    push_tx: mpsc::UnboundedSender<Frame>,
+    writer: JoinHandle<()>,
     read_buffer: BufferedStream<OwnedReadHalf>,
+    pub protocol: Protocol,
 }
 
 impl Connection {
-    pub fn new(mut socket: TcpStream) -> Self {
+    pub fn new(socket: TcpStream) -> Self {
+        // Replies are buffered and flushed once the queued ones are written,
+        // delaying them further only adds latency
+        let _ = socket.set_nodelay(true);
         let (read, write) = socket.into_split();
+        let (reply_tx, reply_rx) = mpsc::unbounded_channel::<ReplySlot>();
+        let (push_tx, push_rx) = mpsc::unbounded_channel::<Frame>();
+
+        // Frames are written by a dedicated task so that requests can be read
+        // while the previous ones are processed, and so that pushed messages
+        // (pub/sub) can be sent while waiting for the next request
+        let writer = tokio::spawn(async move {
+            let _ = write_frames(BufWriter::new(write), reply_rx, push_rx).await;
+        });
+
         Self {
-            write_stream: BufWriter::new(write),
+            reply_tx,
+            push_tx,
+            writer,
             read_buffer: BufferedStream::new(read),
+            protocol: Protocol::default(),
         }
     }
 
+    /// Sender for the frames pushed outside of any request
+    pub fn sender(&self) -> mpsc::UnboundedSender<Frame> {
+        self.push_tx.clone()
+    }
+
+    /// Reserve the place of the replies to the next request, they are written
+    /// after the replies to the previous requests
+    pub fn reply(&self) -> Result<mpsc::UnboundedSender<Frame>, Error> {
+        let (respond, slot) = mpsc::unbounded_channel::<Frame>();
+        self.reply_tx
+            .send((self.protocol, slot))
+            .map_err(|_| "connection closed".into())
+            .map(|()| respond)
+    }
+
+    /// Close the connection without writing the pending replies, which
+    /// may never complete (the stream of a replica)
+    pub fn close(&self) {
+        self.writer.abort();
+    }
+
     pub async fn read_frame(&mut self) -> Result<Option<Frame>, Error> {
-        self.read_buffer.reset().await;
         match Frame::parse(&mut self.read_buffer).await {
             Ok(frame) => Ok(Some(frame)),
             Err(FrameParseError::Incomplete) => Ok(None),
             Err(e) => Err(e.into()),
         }
     }
+}
 
-    pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
-        match frame {
-            Frame::Array(val) => {
-                self.write_stream.write_u8(b'*').await?;
-                write_decimal(&mut self.write_stream, val.len() as u64).await?;
+/// Write the replies in request order, pushed frames are written once every
+/// pending reply is written, so that a message never precedes the
+/// confirmation of its subscription
+async fn write_frames(
+    mut stream: BufWriter<OwnedWriteHalf>,
+    mut reply_rx: mpsc::UnboundedReceiver<ReplySlot>,
+    mut push_rx: mpsc::UnboundedReceiver<Frame>,
+) -> Result<(), Error> {
+    // Pushed frames follow the protocol of the last request
+    let mut protocol = Protocol::default();
 
-                for entry in &**val {
-                    entry.write(&mut self.write_stream).await?;
+    loop {
+        tokio::select! {
+            biased;
+            slot = reply_rx.recv() => {
+                let Some((slot_protocol, mut slot)) = slot else { break };
+                protocol = slot_protocol;
+                loop {
+                    let frame = match slot.try_recv() {
+                        Ok(frame) => frame,
+                        Err(TryRecvError::Empty) => {
+                            // Do not hold the written replies while waiting,
+                            // unless the next requests are already queued:
+                            // they are flushed together once written
+                            if reply_rx.is_empty() {
+                                stream.flush().await?;
+                            }
+                            match slot.recv().await {
+                                Some(frame) => frame,
+                                None => break,
+                            }
+                        }
+                        Err(TryRecvError::Disconnected) => break,
+                    };
+                    frame.write(&mut stream, protocol).await?;
                 }
             }
-            Frame::Simple(val) => val.write(&mut self.write_stream).await?,
+            frame = push_rx.recv() => match frame {
+                Some(frame) => frame.write(&mut stream, protocol).await?,
+                None => break,
+            },
         }
 
-        self.write_stream.flush().await
+        // Flush once every queued frame is written
+        if reply_rx.is_empty() && push_rx.is_empty() {
+            stream.flush().await?;
+        }
     }
-}
 
-const NUM_SHARDS: u64 = 128;
+    stream.flush().await?;
+    Ok(())
+}
 
 struct Backend {
+    /// Changed at runtime by `CONFIG SET`
+    config: Mutex<ServerConfig>,
     kvs: Vec<KVStore>,
+    feed: Feed,
+    /// Responses to writes are only sent once persisted
+    fsync_always: bool,
+    aof_status: WriteStatus,
+    /// Held while requesting locks, see `LockedShards::request`
+    lock_order: Mutex<()>,
+    broker: Broker,
+    client_ids: AtomicU64,
+    /// Writes since the last successful snapshot
+    dirty: Arc<AtomicU64>,
+    saving: AtomicBool,
+    /// Unix time in seconds of the last successful snapshot
+    last_save: AtomicU64,
+    rewriting: AtomicBool,
+    /// Address of the primary and the task replicating it, when a replica
+    primary: Mutex<Option<(String, u16, JoinHandle<()>)>>,
+    started: Instant,
+    clients: Clients,
+    monitors: Monitors,
+    stats: Mutex<CommandStats>,
+    slowlog: stats::SlowLog,
+    keyspace_stats: Arc<KeyspaceStats>,
+    /// Estimated memory used by the shards
+    used_memory: Arc<AtomicU64>,
+    /// Clients waiting for a blocking command to be served
+    blocked_clients: AtomicU64,
+    /// Slots served by the nodes, in cluster mode
+    cluster: Option<Mutex<cluster::Cluster>>,
+    acl: Mutex<acl::Acl>,
 }
 
 impl Backend {
-    pub fn new() -> Self {
-        let mut kvs = Vec::with_capacity(NUM_SHARDS as usize);
-        for _ in 0..NUM_SHARDS {
-            let (cmd_tx, mut cmd_rx) =
-                mpsc::unbounded_channel::<(KVStoreCommand, mpsc::UnboundedSender<Frame>)>();
+    pub async fn new(config: ServerConfig) -> Result<Self, Error> {
+        let dirty = Arc::new(AtomicU64::new(0));
+        let databases = config.databases;
+        let mut shards: Vec<Shard> = (0..config.shards)
+            .map(|_| Shard::new(dirty.clone(), databases))
+            .collect();
+
+        let aof_path = config.aof_path();
+        let aof_exists = aof_path.exists();
+        if config.appendonly && aof_exists {
+            let mut db = 0;
+            let replayed = aof::load(&aof_path, |frame| match Command::from_frame(frame) {
+                Ok(Command::Select(cmd)) if cmd.index < databases => db = cmd.index,
+                Ok(Command::Flush(cmd)) => {
+                    for shard in &mut shards {
+                        shard.select(db);
+                        Self::flush(shard, cmd.all, false);
+                    }
+                }
+                Ok(Command::SwapDb(cmd)) if cmd.first.max(cmd.second) < databases => {
+                    for shard in &mut shards {
+                        shard.swap_dbs(cmd.first, cmd.second);
+                    }
+                }
+                Ok(cmd) => match KVStoreCommand::try_from(cmd) {
+                    Ok(cmd) => {
+                        let shard = &mut shards[select_shard(cmd.key(), config.shards)];
+                        shard.select(db);
+                        shard.execute(cmd);
+                        shard.take_writes();
+                    }
+                    Err(cmd) => {
+                        log::warning(format_args!("Unable to replay {:?} from the AOF", cmd))
+                    }
+                },
+                Err(err) => log::warning(format_args!("Unable to parse the AOF: {:?}", err)),
+            })
+            .await?;
+            log::notice(format_args!(
+                "DB loaded from append only file: {} commands",
+                replayed
+            ));
+        } else {
+            match std::fs::read(config.rdb_path()) {
+                Ok(data) => {
+                    let dbs = rdb::decode(&data, databases)?;
+                    let keys: usize = dbs.iter().map(|entries| entries.len()).sum();
+                    log::notice(format_args!("DB loaded from disk: {} keys", keys));
+                    for (db, entries) in dbs.into_iter().enumerate() {
+                        for (key, entry) in entries {
+                            let shard = &mut shards[select_shard(&key, config.shards)];
+                            shard.select(db);
+                            shard.db().set(key, entry.value, entry.expire_at);
+                        }
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        dirty.store(0, Ordering::SeqCst);
+
+        let (feed, mut feed_rx) = mpsc::unbounded_channel::<FeedMessage>();
+        let (appendonly, fsync) = (config.appendonly, config.appendfsync);
+        let rewrite_path = config
+            .dir
+            .join(format!("temp-rewriteaof-{}.aof", std::process::id()));
+        let replication = ReplicationLog::new(config.repl_backlog_size);
+        let aof_status = WriteStatus::default();
+        let status = aof_status.clone();
+        tokio::spawn(async move {
+            process_feed(
+                aof_path,
+                appendonly,
+                rewrite_path,
+                fsync,
+                replication,
+                &mut feed_rx,
+                status,
+            )
+            .await;
+        });
+
+        let fsync_always = appendonly && fsync == FsyncPolicy::Always;
+        let used_memory = Arc::new(AtomicU64::new(0));
+        let keyspace_stats = Arc::new(KeyspaceStats::default());
+        let (broker, mut broker_rx) = mpsc::unbounded_channel::<BrokerCommand>();
+        tokio::spawn(async move {
+            process_broker(&mut broker_rx).await;
+        });
+
+        let mut kvs = Vec::with_capacity(config.shards);
+        for mut shard in shards {
+            shard.limit_memory(used_memory.clone(), config.max_memory(), config.shards);
+            shard.count_in(keyspace_stats.clone());
+            shard.notify_to(broker.clone(), config.notify_keyspace_events);
+            let (cmd_tx, mut cmd_rx) = mpsc::unbounded_channel::<KVStoreMessage>();
+            let feed = feed.clone();
             tokio::spawn(async move {
-                process_kvstore(&mut cmd_rx).await;
+                process_kvstore(shard, feed, fsync_always, &mut cmd_rx).await;
             });
 
             kvs.push(cmd_tx);
         }
 
-        Self { kvs }
+        let cluster = match config.cluster_enabled {
+            true => Some(Mutex::new(cluster::Cluster::new(
+                &config.cluster_nodes,
+                (&config.bind, config.port),
+            )?)),
+            false => None,
+        };
+
+        // The users of the ACL file replace the default user of `requirepass`
+        let acl = match &config.aclfile {
+            Some(path) => acl::Acl::load(path)?,
+            None => acl::Acl::new(&config.requirepass),
+        };
+
+        let slowlog = stats::SlowLog::new(config.slowlog_log_slower_than, config.slowlog_max_len);
+        let backend = Self {
+            config: Mutex::new(config),
+            kvs,
+            feed,
+            fsync_always,
+            aof_status,
+            lock_order: Mutex::new(()),
+            broker,
+            client_ids: AtomicU64::new(1),
+            dirty,
+            saving: AtomicBool::new(false),
+            last_save: AtomicU64::new(now_ms() / 1000),
+            rewriting: AtomicBool::new(false),
+            primary: Mutex::new(None),
+            cluster,
+            started: Instant::now(),
+            clients: Clients::default(),
+            monitors: Monitors::default(),
+            stats: Mutex::new(CommandStats::default()),
+            slowlog,
+            keyspace_stats,
+            used_memory,
+            blocked_clients: AtomicU64::new(0),
+            acl: Mutex::new(acl),
+        };
+
+        // Create the AOF from the loaded data, an empty AOF would lose it
+        if backend.config().appendonly && !aof_exists {
+            backend.rewrite_aof().await?;
+        }
+
+        Ok(backend)
+    }
+
+    pub fn next_client_id(&self) -> u64 {
+        self.client_ids.fetch_add(1, Ordering::Relaxed)
     }
 
-    pub fn process(&self, cmd: Command, respond: &mpsc::UnboundedSender<Frame>) {
+    pub fn process(
+        self: &Arc<Self>,
+        cmd: Command,
+        db: usize,
+        respond: &mpsc::UnboundedSender<Frame>,
+    ) {
         match cmd {
             Command::Ping(cmd) => {
                 let response = match cmd.msg {
-                    None => Frame::Simple(FrameSimple::Simple("PONG".to_string())),
+                    None => Frame::Simple("PONG".to_string()),
                     Some(value) => Frame::Array(vec![
-                        FrameSimple::Simple("PONG".to_string()),
-                        FrameSimple::Bulk(value.clone().into()),
+                        Frame::Simple("PONG".to_string()),
+                        Frame::Bulk(value.clone()),
                     ]),
                 };
-                respond.send(response).unwrap();
+                let _ = respond.send(response);
+            }
+            Command::LMove(cmd) => {
+                let shard = self.select_kvs(&cmd.source);
+                if shard == self.select_kvs(&cmd.destination) {
+                    self.dispatch(shard, db, KVStoreCommand::LMove(cmd), respond);
+                } else {
+                    self.process_locked(Command::LMove(cmd), db, respond);
+                }
+            }
+            Command::BPop(cmd) => {
+                // Locked right away, so that the client is served, or blocked,
+                // before the next requests
+                let lock = self.lock(cmd.keys.iter().map(|key| &key[..]));
+                let backend = self.clone();
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    backend.pop_blocking(db, cmd, lock, &respond).await;
+                });
+            }
+            Command::BLMove(cmd) => {
+                // Locked right away, so that the source is checked before the
+                // next requests
+                let lock = self.lock([&cmd.lmove.source[..], &cmd.lmove.destination[..]]);
+                let backend = self.clone();
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    backend.move_blocking(db, cmd, lock, &respond).await;
+                });
+            }
+            Command::XRead(cmd) => {
+                // Locked right away, so that `$` is the last entry when the
+                // request is received and the client blocks before the next
+                // requests
+                let lock = self.lock(cmd.streams.iter().map(|(key, _)| &key[..]));
+                let backend = self.clone();
+                let respond = respond.clone();
+                ExecutionTime::spawn(async move {
+                    backend.read_streams(db, cmd, lock, &respond).await;
+                });
+            }
+            Command::MGet(cmd) => {
+                // Sent to the shards right away, the replies are merged in the
+                // order of the keys
+                let mut values = Vec::with_capacity(cmd.keys.len());
+                for key in cmd.keys {
+                    let (value_tx, value_rx) = mpsc::unbounded_channel();
+                    let shard = self.select_kvs(&key);
+                    self.dispatch(shard, db, KVStoreCommand::Get(Get { key }), &value_tx);
+                    values.push(value_rx);
+                }
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let mut frames = Vec::with_capacity(values.len());
+                    for mut value in values {
+                        frames.push(match value.recv().await.unwrap() {
+                            Frame::Bulk(value) => Frame::Bulk(value),
+                            // Missing, or not a string
+                            _ => Frame::Null,
+                        });
+                    }
+                    let _ = respond.send(Frame::Array(frames));
+                });
+            }
+            cmd @ (Command::BitOp(_)
+            | Command::GeoSearchStore(_)
+            | Command::MSet(_)
+            | Command::PfMerge(_)
+            | Command::Rename(_)) => self.process_locked(cmd, db, respond),
+            cmd @ (Command::Del(_) | Command::Exists(_) | Command::PfCount(_))
+                if cmd.keys().len() > 1 =>
+            {
+                self.process_locked(cmd, db, respond)
             }
-            Command::Set(cmd) => {
-                self.kvs[Self::select_kvs(cmd.key())]
-                    .send((KVStoreCommand::Set(cmd), respond.clone()))
+            cmd if cmd.all_shards() => self.process_locked(cmd, db, respond),
+            Command::SetOp(cmd) => {
+                // Sent to the shards right away as for `MGET`, so that the
+                // sets are read before the writes of the next requests
+                let mut members = Vec::with_capacity(cmd.keys.len());
+                for key in cmd.keys {
+                    let (members_tx, members_rx) = mpsc::unbounded_channel();
+                    let shard = self.select_kvs(&key);
+                    let smembers = KVStoreCommand::SMembers(SMembers { key });
+                    self.dispatch(shard, db, smembers, &members_tx);
+                    members.push(members_rx);
+                }
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let mut replies = Vec::with_capacity(members.len());
+                    for mut reply in members {
+                        replies.push(reply.recv().await.unwrap());
+                    }
+                    let _ = respond.send(combine_members(cmd.kind, replies));
+                });
+            }
+            Command::Publish(cmd) => {
+                self.broker
+                    .send(BrokerCommand::Publish(cmd, respond.clone()))
                     .unwrap();
             }
-            Command::Get(cmd) => {
-                self.kvs[Self::select_kvs(cmd.key())]
-                    .send((KVStoreCommand::Get(cmd), respond.clone()))
+            Command::PubSub(cmd) => {
+                self.broker
+                    .send(BrokerCommand::PubSub(cmd, respond.clone()))
                     .unwrap();
             }
-            _ => {
-                println!("Unimplemented cmd: {:?}", cmd);
-                let response = Frame::Simple(FrameSimple::Error("unimplemented".to_string()));
-                respond.send(response).unwrap();
+            Command::Save(_) => {
+                let backend = self.clone();
+                let respond = respond.clone();
+                let execution = ExecutionTime::current();
+                tokio::spawn(async move {
+                    let started = Instant::now();
+                    let response = match backend.save().await {
+                        Ok(()) => Frame::Simple("OK".to_string()),
+                        Err(err) => Frame::Error(format!("ERR {}", err)),
+                    };
+                    if let Some(execution) = execution {
+                        execution.add(started.elapsed());
+                    }
+                    let _ = respond.send(response);
+                });
+            }
+            Command::BgSave(_) => {
+                let response = if self.saving.load(Ordering::SeqCst) {
+                    Frame::Error("ERR Background save already in progress".to_string())
+                } else {
+                    let backend = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = backend.save().await {
+                            log::warning(format_args!("Background saving error: {}", err));
+                        }
+                    });
+                    Frame::Simple("Background saving started".to_string())
+                };
+                let _ = respond.send(response);
+            }
+            Command::BgRewriteAof(_) => {
+                let response = if self.rewriting.load(Ordering::SeqCst) {
+                    Frame::Error(
+                        "ERR Background append only file rewriting already in progress".to_string(),
+                    )
+                } else {
+                    let backend = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = backend.rewrite_aof().await {
+                            log::warning(format_args!("Background AOF rewrite error: {}", err));
+                        }
+                    });
+                    Frame::Simple("Background append only file rewriting started".to_string())
+                };
+                let _ = respond.send(response);
+            }
+            Command::Config(cmd) => {
+                let _ = respond.send(self.configure(cmd));
+            }
+            Command::Cluster(cmd) => {
+                let _ = respond.send(self.cluster(cmd));
+            }
+            Command::ReplicaOf(cmd) => {
+                let _ = respond.send(self.replica_of(cmd.primary));
+            }
+            Command::ReplConf(cmd) => {
+                let unknown = cmd.options.iter().find(|(name, _)| {
+                    !matches!(&name[..], "listening-port" | "ip-address" | "capa" | "ack")
+                });
+                let _ = respond.send(match unknown {
+                    Some((name, _)) => {
+                        Frame::Error(format!("ERR Unrecognized REPLCONF option: {}", name))
+                    }
+                    None => Frame::Simple("OK".to_string()),
+                });
+            }
+            Command::Info(cmd) => {
+                let backend = self.clone();
+                let respond = respond.clone();
+                ExecutionTime::spawn(async move {
+                    let info = backend.info(&cmd.sections, None).await;
+                    let _ = respond.send(Frame::Bulk(Bytes::from(info)));
+                });
+            }
+            Command::SlowLog(cmd) => {
+                let _ = respond.send(match cmd {
+                    SlowLog::Get(count) => self.slowlog.get(count),
+                    SlowLog::Len => Frame::Integer(self.slowlog.len() as i64),
+                    SlowLog::Reset => {
+                        self.slowlog.reset();
+                        Frame::Simple("OK".to_string())
+                    }
+                });
+            }
+            Command::Latency(Latency::Histogram(names)) => {
+                let _ = respond.send(self.latency_histogram(&names));
+            }
+            Command::LastSave(_) => {
+                let last_save = self.last_save.load(Ordering::SeqCst) as i64;
+                let _ = respond.send(Frame::Integer(last_save));
             }
+            Command::Unknown(cmd) => {
+                let response = Frame::Error(format!("ERR unknown command '{}'", cmd.get_name()));
+                let _ = respond.send(response);
+            }
+            cmd => match KVStoreCommand::try_from(cmd) {
+                Ok(cmd) => self.dispatch(self.select_kvs(cmd.key()), db, cmd, respond),
+                Err(cmd) => {
+                    log::warning(format_args!("Unimplemented cmd: {:?}", cmd));
+                    let response = Frame::Error("unimplemented".to_string());
+                    let _ = respond.send(response);
+                }
+            },
         };
     }
 
-    fn select_kvs(key: &str) -> usize {
-        let mut s = DefaultHasher::new();
-        key.hash(&mut s);
-        (s.finish() % NUM_SHARDS) as usize
+    /// `CONFIG GET`, `CONFIG SET` and `CONFIG REWRITE`
+    fn configure(&self, cmd: Config) -> Frame {
+        let mut config = self.config();
+        match cmd {
+            Config::Get(patterns) => {
+                let mut options = vec![];
+                for pattern in patterns {
+                    for option in config.matching(&pattern) {
+                        if !options.contains(&option) {
+                            options.push(option);
+                        }
+                    }
+                }
+                Frame::Map(
+                    options
+                        .into_iter()
+                        .map(|(name, value)| {
+                            (
+                                Frame::Bulk(Bytes::from_static(name.as_bytes())),
+                                Frame::Bulk(Bytes::from(value)),
+                            )
+                        })
+                        .collect(),
+                )
+            }
+            Config::Set(pairs) => {
+                if let Err(err) = config.set_at_runtime(&pairs) {
+                    return Frame::Error(err);
+                }
+                if pairs.iter().any(|(name, _)| name.starts_with("maxmemory")) {
+                    for kvs in &self.kvs {
+                        kvs.send(KVStoreMessage::MaxMemory(config.max_memory()))
+                            .unwrap();
+                    }
+                }
+                if pairs
+                    .iter()
+                    .any(|(name, _)| name == "notify-keyspace-events")
+                {
+                    for kvs in &self.kvs {
+                        kvs.send(KVStoreMessage::NotifyEvents(config.notify_keyspace_events))
+                            .unwrap();
+                    }
+                }
+                self.slowlog
+                    .configure(config.slowlog_log_slower_than, config.slowlog_max_len);
+                log::set_level(config.loglevel);
+                if pairs.iter().any(|(name, _)| name == "requirepass") {
+                    self.acl.lock().unwrap().require_pass(&config.requirepass);
+                }
+                Frame::Simple("OK".to_string())
+            }
+            Config::Rewrite => match config.rewrite() {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(err) => Frame::Error(format!("ERR Rewriting config file: {}", err)),
+            },
+        }
     }
-}
 
-type KVStore = mpsc::UnboundedSender<(KVStoreCommand, mpsc::UnboundedSender<Frame>)>;
+    fn dispatch(
+        &self,
+        shard: usize,
+        db: usize,
+        cmd: KVStoreCommand,
+        respond: &mpsc::UnboundedSender<Frame>,
+    ) {
+        self.kvs[shard]
+            .send(KVStoreMessage::Command(
+                db,
+                cmd,
+                respond.clone(),
+                ExecutionTime::current(),
+            ))
+            .unwrap();
+    }
 
-enum KVStoreCommand {
-    Get(Get),
-    Set(Set),
-}
+    /// Execute a command once the shards it accesses are locked, so that it
+    /// is atomic
+    fn process_locked(
+        self: &Arc<Self>,
+        cmd: Command,
+        db: usize,
+        respond: &mpsc::UnboundedSender<Frame>,
+    ) {
+        let lock = match cmd.all_shards() {
+            true => self.lock_shards(0..self.kvs.len()),
+            false => self.lock(cmd.keys()),
+        };
+        let backend = self.clone();
+        let respond = respond.clone();
+        let execution = ExecutionTime::current();
+        tokio::spawn(async move {
+            let mut locked = lock.acquire().await;
+            let started = Instant::now();
+            locked.select(db);
+            let response = backend.execute_locked(&mut locked, cmd).await;
+            if let Some(execution) = execution {
+                execution.add(started.elapsed());
+            }
+            locked.release(&respond, response);
+        });
+    }
 
-async fn process_kvstore(
-    cmd_rx: &mut mpsc::UnboundedReceiver<(KVStoreCommand, mpsc::UnboundedSender<Frame>)>,
-) {
-    let mut db = HashMap::with_capacity(2048);
+    /// Register the flag of a connection on the shards of the watched keys
+    fn watch(&self, db: usize, keys: &[String], dirty: Arc<AtomicBool>) {
+        for key in keys {
+            self.kvs[self.select_kvs(key)]
+                .send(KVStoreMessage::Watch(db, key.clone(), dirty.clone()))
+                .unwrap();
+        }
+    }
 
-    while let Some((cmd, respond)) = cmd_rx.recv().await {
-        let response = match cmd {
-            KVStoreCommand::Set(cmd) => {
-                db.insert(cmd.key().to_string(), cmd.value().to_vec());
-                Frame::Simple(FrameSimple::Simple("OK".to_string()))
+    /// Execute a transaction atomically, once the shards of its keys and of
+    /// the watched keys are locked
+    fn exec(self: &Arc<Self>, queued: Queued, db: usize, respond: &mpsc::UnboundedSender<Frame>) {
+        // INFO reads the keyspace of every shard, which it can not lock
+        // while the transaction holds them
+        let all_shards = |cmd: &Command| cmd.all_shards() || matches!(cmd, Command::Info(_));
+        let lock = match queued.commands.iter().any(all_shards) {
+            true => self.lock_shards(0..self.kvs.len()),
+            false => {
+                let keys = queued.commands.iter().flat_map(|cmd| cmd.keys());
+                self.lock(queued.watched.iter().map(|(_, key)| &key[..]).chain(keys))
             }
-            KVStoreCommand::Get(cmd) => {
-                if let Some(value) = db.get(cmd.key()) {
-                    Frame::Simple(FrameSimple::Bulk(value.clone().into()))
-                } else {
-                    Frame::Simple(FrameSimple::Null)
+        };
+        let backend = self.clone();
+        let respond = respond.clone();
+        let execution = ExecutionTime::current();
+        tokio::spawn(async move {
+            let mut locked = lock.acquire().await;
+            let started = Instant::now();
+            for (db, key) in &queued.watched {
+                locked.select(*db);
+                locked.shard(backend.select_kvs(key)).expire(key);
+            }
+            if queued.is_dirty() {
+                locked.release(&respond, Frame::NullArray);
+                return;
+            }
+
+            locked.select(db);
+            let mut replies = Vec::with_capacity(queued.commands.len());
+            for cmd in queued.commands {
+                replies.push(backend.execute_locked(&mut locked, cmd).await);
+            }
+            if let Some(execution) = execution {
+                execution.add(started.elapsed());
+            }
+            locked.release(&respond, Frame::Array(replies));
+        });
+    }
+
+    /// Execute a command with the shards of its keys locked, blocking
+    /// commands do not block
+    async fn execute_locked(self: &Arc<Self>, locked: &mut LockedShards, cmd: Command) -> Frame {
+        match cmd {
+            Command::LMove(cmd) => self.move_locked(locked, cmd),
+            Command::BLMove(cmd) => self.move_locked(locked, cmd.lmove),
+            Command::BPop(cmd) => {
+                for key in cmd.keys {
+                    let popped = locked
+                        .shard(self.select_kvs(&key))
+                        .execute(KVStoreCommand::Pop(Pop {
+                            key: key.clone(),
+                            count: None,
+                            side: cmd.side,
+                        }));
+                    match popped {
+                        Frame::Null => {}
+                        Frame::Bulk(element) => {
+                            return Frame::Array(vec![
+                                Frame::Bulk(Bytes::from(key)),
+                                Frame::Bulk(element),
+                            ])
+                        }
+                        err => return err,
+                    }
+                }
+                Frame::NullArray
+            }
+            Command::MGet(cmd) => Frame::Array(
+                cmd.keys
+                    .into_iter()
+                    .map(|key| {
+                        let shard = locked.shard(self.select_kvs(&key));
+                        match shard.execute(KVStoreCommand::Get(Get { key })) {
+                            Frame::Bulk(value) => Frame::Bulk(value),
+                            _ => Frame::Null,
+                        }
+                    })
+                    .collect(),
+            ),
+            Command::MSet(cmd) => {
+                if cmd.nx
+                    && cmd
+                        .pairs
+                        .iter()
+                        .any(|(key, _)| locked.shard(self.select_kvs(key)).db().get(key).is_some())
+                {
+                    return Frame::Integer(0);
+                }
+                for shard in locked.shards() {
+                    if let Err(err) = shard.free_memory() {
+                        return err;
+                    }
+                }
+                for (key, value) in cmd.pairs {
+                    let shard = locked.shard(self.select_kvs(&key));
+                    shard.execute(KVStoreCommand::Set(Set::new(key, value)));
+                }
+                match cmd.nx {
+                    true => Frame::Integer(1),
+                    false => Frame::Simple("OK".to_string()),
+                }
+            }
+            Command::Del(cmd) => Frame::Integer(
+                cmd.keys
+                    .into_iter()
+                    .map(|key| {
+                        let shard = locked.shard(self.select_kvs(&key));
+                        let del = KVStoreCommand::Del(Del {
+                            keys: vec![key],
+                            unlink: cmd.unlink,
+                        });
+                        match shard.execute(del) {
+                            Frame::Integer(removed) => removed,
+                            _ => 0,
+                        }
+                    })
+                    .sum(),
+            ),
+            // Never blocks within a transaction
+            Command::XRead(cmd) => {
+                let mut streams = vec![];
+                for read in cmd.reads() {
+                    let shard = locked.shard(self.select_kvs(read.key()));
+                    match shard.execute(KVStoreCommand::XRead(read)) {
+                        Frame::Null => {}
+                        Frame::Error(err) => return Frame::Error(err),
+                        stream => streams.push(stream),
+                    }
+                }
+                match streams.is_empty() {
+                    true => Frame::NullArray,
+                    false => Frame::Array(streams),
+                }
+            }
+            Command::Exists(cmd) => Frame::Integer(
+                cmd.keys
+                    .iter()
+                    .filter(|key| locked.shard(self.select_kvs(key)).db().get(key).is_some())
+                    .count() as i64,
+            ),
+            Command::Rename(cmd) => {
+                let source = self.select_kvs(&cmd.source);
+                let destination = self.select_kvs(&cmd.destination);
+                if locked.shard(source).db().get(&cmd.source).is_none() {
+                    return Frame::Error("ERR no such key".to_string());
+                }
+                if cmd.nx
+                    && locked
+                        .shard(destination)
+                        .db()
+                        .get(&cmd.destination)
+                        .is_some()
+                {
+                    return Frame::Integer(0);
+                }
+                if cmd.source != cmd.destination {
+                    let entry = locked.shard(source).take(&cmd.source).unwrap();
+                    locked
+                        .shard(destination)
+                        .insert(cmd.destination.clone(), entry);
+                }
+                let shard = locked.shard(source);
+                shard.notify(NotifyEvents::GENERIC, "rename_from", &cmd.source);
+                let shard = locked.shard(destination);
+                shard.notify(NotifyEvents::GENERIC, "rename_to", &cmd.destination);
+                match cmd.nx {
+                    true => Frame::Integer(1),
+                    false => Frame::Simple("OK".to_string()),
+                }
+            }
+            Command::DbSize(_) => {
+                Frame::Integer(locked.shards().map(|shard| shard.db().len()).sum::<usize>() as i64)
+            }
+            Command::Keys(cmd) => Frame::Array(
+                locked
+                    .shards()
+                    .flat_map(|shard| {
+                        shard
+                            .db()
+                            .iter()
+                            .filter(|(key, _)| glob_match(&cmd.pattern, key.as_bytes()))
+                            .map(|(key, _)| Frame::Bulk(Bytes::from(key.clone())))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect(),
+            ),
+            Command::RandomKey(_) => {
+                // The shard is picked with a probability proportional to its
+                // number of keys
+                let total: usize = locked.shards().map(|shard| shard.db().len()).sum();
+                if total == 0 {
+                    return Frame::Null;
+                }
+                let mut index = random() as usize % total;
+                for shard in locked.shards() {
+                    let len = shard.db().len();
+                    if index >= len {
+                        index -= len;
+                        continue;
+                    }
+                    if let Some(key) = shard.db().random_key() {
+                        return Frame::Bulk(Bytes::from(key));
+                    }
+                }
+                // Only expired keys in the picked shard
+                locked
+                    .shards()
+                    .find_map(|shard| shard.db().random_key())
+                    .map_or(Frame::Null, |key| Frame::Bulk(Bytes::from(key)))
+            }
+            Command::Scan(cmd) => {
+                let (mut index, mut position) = split_scan_cursor(cmd.cursor);
+                let mut keys = vec![];
+                let mut visited = 0;
+                let mut cursor = 0;
+                while index < self.kvs.len() {
+                    let (matching, next) =
+                        locked
+                            .shard(index)
+                            .scan(position, cmd.count - visited, &cmd);
+                    visited += matching.len();
+                    keys.extend(matching);
+                    if let Some(next) = next {
+                        cursor = scan_cursor(index, next);
+                        break;
+                    }
+                    index += 1;
+                    position = 0;
+                    if visited >= cmd.count && index < self.kvs.len() {
+                        cursor = scan_cursor(index, 0);
+                        break;
+                    }
+                }
+                Frame::Array(vec![
+                    Frame::Bulk(Bytes::from(cursor.to_string())),
+                    Frame::Array(keys),
+                ])
+            }
+            Command::Flush(cmd) => {
+                let name = match cmd.all {
+                    false => "FLUSHDB",
+                    true => "FLUSHALL",
+                };
+                for shard in locked.shards() {
+                    Self::flush(shard, cmd.all, cmd.lazy);
+                }
+                // Replayed on every shard
+                locked
+                    .shard(0)
+                    .propagate(vec![Bytes::from_static(name.as_bytes())]);
+                Frame::Simple("OK".to_string())
+            }
+            Command::SwapDb(cmd) => {
+                if cmd.first.max(cmd.second) >= self.config().databases {
+                    return Frame::Error("ERR DB index is out of range".to_string());
+                }
+                // Recorded first, for the writes serving the blocked clients
+                // to follow it
+                locked.shard(0).propagate(vec![
+                    Bytes::from_static(b"SWAPDB"),
+                    Bytes::from(cmd.first.to_string()),
+                    Bytes::from(cmd.second.to_string()),
+                ]);
+                for shard in locked.shards() {
+                    shard.swap_dbs(cmd.first, cmd.second);
+                }
+                Frame::Simple("OK".to_string())
+            }
+            Command::SetOp(cmd) => {
+                let members = cmd
+                    .keys
+                    .into_iter()
+                    .map(|key| {
+                        let shard = locked.shard(self.select_kvs(&key));
+                        shard.execute(KVStoreCommand::SMembers(SMembers { key }))
+                    })
+                    .collect();
+                combine_members(cmd.kind, members)
+            }
+            Command::BitOp(cmd) => {
+                let mut values = Vec::with_capacity(cmd.keys.len());
+                for key in &cmd.keys {
+                    match locked.shard(self.select_kvs(key)).db().get(key) {
+                        Some(Entry {
+                            value: Value::String(value),
+                            ..
+                        }) => values.push(value.clone()),
+                        Some(_) => return Frame::Error(WRONGTYPE.to_string()),
+                        None => values.push(vec![]),
+                    }
+                }
+                let values: Vec<&[u8]> = values.iter().map(|value| &value[..]).collect();
+                let result = bitop(cmd.kind, &values);
+                let len = result.len() as i64;
+                let shard = locked.shard(self.select_kvs(&cmd.destination));
+                // An empty result removes the destination
+                let written = match result.is_empty() {
+                    true => shard.execute(KVStoreCommand::Del(Del {
+                        keys: vec![cmd.destination],
+                        unlink: false,
+                    })),
+                    false => shard.execute(KVStoreCommand::Set(Set::new(
+                        cmd.destination,
+                        Bytes::from(result),
+                    ))),
+                };
+                match written {
+                    Frame::Error(err) => Frame::Error(err),
+                    _ => Frame::Integer(len),
+                }
+            }
+            Command::GeoSearchStore(cmd) => {
+                let source = locked.shard(self.select_kvs(cmd.search.key()));
+                let matches = match source.db().get(cmd.search.key()) {
+                    Some(Entry {
+                        value: Value::ZSet(zset),
+                        ..
+                    }) => geo_search(Some(zset), &cmd.search),
+                    Some(_) => return Frame::Error(WRONGTYPE.to_string()),
+                    None => Ok(vec![]),
+                };
+                let matches = match matches {
+                    Ok(matches) => matches,
+                    Err(err) => return err,
+                };
+                let len = matches.len() as i64;
+                let shard = locked.shard(self.select_kvs(&cmd.destination));
+                if let Err(err) = shard.free_memory() {
+                    return err;
+                }
+                // Replaces the destination, removed when nothing matches
+                shard.execute(KVStoreCommand::Del(Del {
+                    keys: vec![cmd.destination.clone()],
+                    unlink: false,
+                }));
+                if matches.is_empty() {
+                    return Frame::Integer(0);
+                }
+                let pairs = matches
+                    .into_iter()
+                    .map(|found| match cmd.store_dist {
+                        true => (found.distance / cmd.search.unit, found.member),
+                        false => (found.hash as f64, found.member),
+                    })
+                    .collect();
+                let zadd = ZAdd {
+                    key: cmd.destination,
+                    existence: None,
+                    comparison: None,
+                    changed: false,
+                    incr: false,
+                    pairs,
+                };
+                match shard.execute(KVStoreCommand::ZAdd(zadd)) {
+                    Frame::Error(err) => Frame::Error(err),
+                    _ => Frame::Integer(len),
+                }
+            }
+            Command::Info(cmd) => {
+                Frame::Bulk(Bytes::from(self.info(&cmd.sections, Some(locked)).await))
+            }
+            Command::PfCount(cmd) => match self.merge_locked(locked, &cmd.keys) {
+                Ok(hll) => Frame::Integer(hll.count() as i64),
+                Err(err) => err,
+            },
+            Command::PfMerge(cmd) => {
+                let keys: Vec<String> = std::iter::once(cmd.destination.clone())
+                    .chain(cmd.keys)
+                    .collect();
+                let hll = match self.merge_locked(locked, &keys) {
+                    Ok(hll) => hll,
+                    Err(err) => return err,
+                };
+                let mut set = Set::new(cmd.destination, Bytes::from(hll.encode()));
+                set.keep_ttl = true;
+                let shard = locked.shard(self.select_kvs(set.key()));
+                match shard.execute(KVStoreCommand::Set(set)) {
+                    Frame::Error(err) => Frame::Error(err),
+                    _ => Frame::Simple("OK".to_string()),
+                }
+            }
+            cmd => match KVStoreCommand::try_from(cmd) {
+                Ok(cmd) => locked.shard(self.select_kvs(cmd.key())).execute(cmd),
+                // Does not access the shards
+                Err(cmd) => {
+                    let (respond, mut response) = mpsc::unbounded_channel();
+                    self.process(cmd, locked.selected(), &respond);
+                    response.recv().await.unwrap()
+                }
+            },
+        }
+    }
+
+    /// Merge the HyperLogLogs at `keys` with their shards locked, missing keys
+    /// are skipped
+    fn merge_locked(
+        &self,
+        locked: &mut LockedShards,
+        keys: &[String],
+    ) -> Result<HyperLogLog, Frame> {
+        let mut merged = HyperLogLog::default();
+        for key in keys {
+            match locked.shard(self.select_kvs(key)).db().get(key) {
+                Some(Entry {
+                    value: Value::String(value),
+                    ..
+                }) => match HyperLogLog::decode(value) {
+                    Ok(hll) => merged.merge(&hll),
+                    Err(err) => return Err(Frame::Error(err.to_string())),
+                },
+                Some(_) => return Err(Frame::Error(WRONGTYPE.to_string())),
+                None => {}
+            }
+        }
+        Ok(merged)
+    }
+
+    /// `LMOVE` with the shards of both keys locked
+    fn move_locked(&self, locked: &mut LockedShards, cmd: LMove) -> Frame {
+        let source = self.select_kvs(&cmd.source);
+        let destination = self.select_kvs(&cmd.destination);
+        if source == destination {
+            return locked.shard(source).execute(KVStoreCommand::LMove(cmd));
+        }
+
+        // The destination is checked before popping, to not lose the element
+        let len = locked
+            .shard(destination)
+            .execute(KVStoreCommand::LLen(LLen {
+                key: cmd.destination.clone(),
+            }));
+        if let Frame::Error(_) = len {
+            return len;
+        }
+        let popped = locked.shard(source).execute(KVStoreCommand::Pop(Pop {
+            key: cmd.source.clone(),
+            count: None,
+            side: cmd.from,
+        }));
+        if let Frame::Bulk(element) = &popped {
+            locked
+                .shard(destination)
+                .execute(KVStoreCommand::Push(Push {
+                    key: cmd.destination,
+                    elements: vec![element.clone()],
+                    side: cmd.to,
+                }));
+        }
+        popped
+    }
+
+    /// `BLPOP` and `BRPOP`, the keys are tried in order with their shards
+    /// locked, and the client blocks on all of them when they are empty
+    async fn pop_blocking(
+        self: &Arc<Self>,
+        db: usize,
+        cmd: BPop,
+        lock: PendingLock,
+        respond: &mpsc::UnboundedSender<Frame>,
+    ) {
+        let mut locked = lock.acquire().await;
+        locked.select(db);
+        let waits = cmd
+            .keys
+            .iter()
+            .map(|key| (key.clone(), Wait::Pop(cmd.side)))
+            .collect();
+        let timeout = cmd.timeout;
+        let popped = self.execute_locked(&mut locked, Command::BPop(cmd)).await;
+        if popped != Frame::NullArray {
+            locked.release(respond, popped);
+            return;
+        }
+        let blocked = self.block_locked(&mut locked, waits);
+        drop(locked);
+
+        if let Some(served) = blocked.wait(timeout, respond).await {
+            let _ = respond.send(served.unwrap_or(Frame::NullArray));
+        }
+    }
+
+    /// `BLMOVE`, the element is moved with the shards of both keys locked.
+    ///
+    /// When the source is empty, the client blocks until it is ready then
+    /// tries again, other clients may have emptied it meanwhile.
+    async fn move_blocking(
+        &self,
+        db: usize,
+        cmd: BLMove,
+        mut lock: PendingLock,
+        respond: &mpsc::UnboundedSender<Frame>,
+    ) {
+        // Forever when zero, or too far to be represented
+        let deadline = Some(cmd.timeout)
+            .filter(|timeout| !timeout.is_zero())
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            let mut locked = lock.acquire().await;
+            locked.select(db);
+            let moved = self.move_locked(&mut locked, cmd.lmove.clone());
+            if moved != Frame::Null {
+                locked.release(respond, moved);
+                return;
+            }
+
+            let timeout = match deadline {
+                None => Duration::ZERO,
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) if !timeout.is_zero() => timeout,
+                    _ => {
+                        locked.release(respond, Frame::Null);
+                        return;
+                    }
+                },
+            };
+            let waits = vec![(cmd.lmove.source.clone(), Wait::Ready)];
+            let blocked = self.block_locked(&mut locked, waits);
+            drop(locked);
+            match blocked.wait(timeout, respond).await {
+                Some(Some(_)) => {
+                    lock = self.lock([&cmd.lmove.source[..], &cmd.lmove.destination[..]]);
+                }
+                Some(None) => {
+                    let _ = respond.send(Frame::Null);
+                    return;
                 }
+                None => return,
             }
+        }
+    }
+
+    /// `XREAD` and `XREADGROUP`, the streams are read in order with their
+    /// shards locked, and the client only blocks when none of them had
+    /// entries to read
+    async fn read_streams(
+        self: &Arc<Self>,
+        db: usize,
+        cmd: XRead,
+        lock: PendingLock,
+        respond: &mpsc::UnboundedSender<Frame>,
+    ) {
+        let mut locked = lock.acquire().await;
+        locked.select(db);
+        let (block, reads) = (cmd.block, cmd.reads());
+        let read = self.execute_locked(&mut locked, Command::XRead(cmd)).await;
+        let Some(timeout) = block.filter(|_| read == Frame::NullArray) else {
+            locked.release(respond, read);
+            return;
         };
-        respond.send(response).unwrap();
+        let waits = reads
+            .into_iter()
+            .map(|read| (read.key.clone(), Wait::Read(read)))
+            .collect();
+        let blocked = self.block_locked(&mut locked, waits);
+        drop(locked);
+
+        if let Some(served) = blocked.wait(timeout, respond).await {
+            let _ = respond.send(served.unwrap_or(Frame::NullArray));
+        }
+    }
+
+    /// Block the client on the keys, checked as not ready while locked, so
+    /// that it waits before the next requests of its connection execute
+    fn block_locked(&self, locked: &mut LockedShards, waits: Vec<(String, Wait)>) -> Blocked {
+        let blocked = Blocked::default();
+        for (key, wait) in waits {
+            let waiter = blocked.waiter(wait);
+            locked.shard(self.select_kvs(&key)).block(key, waiter);
+        }
+        blocked
+    }
+
+    /// `FLUSHDB` of the selected database, or `FLUSHALL`
+    fn flush(shard: &mut Shard, all: bool, lazy: bool) {
+        if !all {
+            shard.flush(lazy);
+            return;
+        }
+        for db in 0..shard.databases() {
+            shard.select(db);
+            shard.flush(lazy);
+        }
+    }
+
+    /// Reply through the returned sender, the replies are forwarded to
+    /// `respond` and the command is counted once replied, with the time
+    /// accounted in `execution`
+    fn measure(
+        self: &Arc<Self>,
+        cmd: &Command,
+        request: Frame,
+        client: &Arc<Mutex<ClientInfo>>,
+        respond: mpsc::UnboundedSender<Frame>,
+        execution: &ExecutionTime,
+    ) -> mpsc::UnboundedSender<Frame> {
+        let (name, blocking) = (cmd.name(), cmd.is_blocking());
+        if blocking {
+            self.blocked_clients.fetch_add(1, Ordering::Relaxed);
+        }
+        let (measured, mut replies) = mpsc::unbounded_channel();
+        let backend = self.clone();
+        let client = client.clone();
+        let execution = execution.clone();
+        tokio::spawn(async move {
+            let mut failed = false;
+            while let Some(reply) = replies.recv().await {
+                if let Frame::Error(_) = reply {
+                    failed = true;
+                    backend.stats.lock().unwrap().error(&reply);
+                }
+                // Stop once the connection is closed, for the senders to
+                // know (the replication log then drops its replica)
+                if respond.send(reply).is_err() {
+                    break;
+                }
+            }
+
+            let micros = execution.micros();
+            if blocking {
+                backend.blocked_clients.fetch_sub(1, Ordering::Relaxed);
+            }
+            let micros = (!blocking).then_some(micros);
+            backend.stats.lock().unwrap().record(name, micros, failed);
+            if let Some(micros) = micros.filter(|micros| backend.slowlog.is_slow(*micros)) {
+                let (addr, client_name) = {
+                    let info = client.lock().unwrap();
+                    (info.addr.clone(), info.name.clone())
+                };
+                let at = now_ms() / 1000;
+                backend
+                    .slowlog
+                    .push(at, micros, &request, addr, client_name);
+            }
+        });
+        measured
+    }
+
+    /// Count a command refused before being executed
+    fn reject(&self, cmd: &Command, reply: &Frame) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.reject(cmd.name());
+        stats.error(reply);
+    }
+
+    /// Refuse the commands the user of the connection is not allowed to
+    /// run, all of them but `AUTH` and `HELLO` until authenticated
+    fn authorize(&self, user: Option<&str>, cmd: &Command) -> Result<(), Frame> {
+        if matches!(
+            cmd,
+            Command::Auth(_) | Command::Hello(_) | Command::Unknown(_)
+        ) {
+            return Ok(());
+        }
+        let acl = self.acl.lock().unwrap();
+        match user.and_then(|user| acl.user(user)) {
+            Some(user) => user.check(cmd).map_err(Frame::Error),
+            None => Err(Frame::Error("NOAUTH Authentication required.".to_string())),
+        }
+    }
+
+    /// `AUTH`, returns the name of the authenticated user
+    fn authenticate(&self, username: Option<&str>, password: &str) -> Result<String, Frame> {
+        let acl = self.acl.lock().unwrap();
+        let username = match username {
+            Some(username) => username,
+            None if acl.user("default").is_some_and(acl::User::nopass) => {
+                return Err(Frame::Error(
+                    "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+                        .to_string(),
+                ))
+            }
+            None => "default",
+        };
+        match acl.authenticate(username, password) {
+            true => Ok(username.to_string()),
+            false => Err(Frame::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+            )),
+        }
+    }
+
+    /// Validate `HELLO` before switching to its protocol, returns the user
+    /// it authenticates
+    fn check_hello(&self, cmd: &Hello, authenticated: bool) -> Result<Option<String>, Frame> {
+        if cmd
+            .protocol
+            .is_some_and(|v| Protocol::from_version(v).is_none())
+        {
+            return Err(Frame::Error(
+                "NOPROTO unsupported protocol version".to_string(),
+            ));
+        }
+        if cmd
+            .setname
+            .as_ref()
+            .is_some_and(|name| !valid_client_name(name))
+        {
+            return Err(Frame::Error(
+                "ERR Client names cannot contain spaces, newlines or special characters."
+                    .to_string(),
+            ));
+        }
+        match &cmd.auth {
+            Some((username, password)) => self.authenticate(Some(username), password).map(Some),
+            None if !authenticated => Err(Frame::Error(
+                "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time"
+                    .to_string(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// `ACL`, the clients of the deleted users are disconnected
+    fn acl(&self, cmd: Acl, client: &Mutex<ClientInfo>) -> Frame {
+        let ok = || Frame::Simple("OK".to_string());
+        let bulks = |values: Vec<String>| {
+            Frame::Array(
+                values
+                    .into_iter()
+                    .map(|value| Frame::Bulk(Bytes::from(value)))
+                    .collect(),
+            )
+        };
+        let disconnect = |users: &[String]| {
+            for user in users {
+                let filter = ClientKill {
+                    user: Some(user.clone()),
+                    ..Default::default()
+                };
+                self.clients.kill(&filter, 0);
+            }
+        };
+        // Read before locking the users, `CONFIG SET` locks them after the
+        // configuration
+        let aclfile = self.config().aclfile.clone();
+        let mut acl = self.acl.lock().unwrap();
+        match cmd {
+            Acl::Cat(category) => match acl::categories(category.as_deref()) {
+                Ok(names) => Frame::Array(
+                    names
+                        .into_iter()
+                        .map(|name| Frame::Bulk(Bytes::from_static(name.as_bytes())))
+                        .collect(),
+                ),
+                Err(err) => Frame::Error(err),
+            },
+            Acl::DelUser(names) => match acl.del_users(&names) {
+                Ok(deleted) => {
+                    disconnect(&deleted);
+                    Frame::Integer(deleted.len() as i64)
+                }
+                Err(err) => Frame::Error(err),
+            },
+            Acl::GetUser(name) => acl.get_user(&name),
+            Acl::List => bulks(acl.list()),
+            Acl::Load | Acl::Save if aclfile.is_none() => Frame::Error(
+                "ERR This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration."
+                    .to_string(),
+            ),
+            Acl::Load => match acl::Acl::load(aclfile.as_ref().unwrap()) {
+                Ok(loaded) => {
+                    let mut removed = acl.names();
+                    removed.retain(|name| loaded.user(name).is_none());
+                    *acl = loaded;
+                    disconnect(&removed);
+                    ok()
+                }
+                Err(err) => Frame::Error(format!("ERR {}", err)),
+            },
+            Acl::Save => match acl.save(aclfile.as_ref().unwrap()) {
+                Ok(()) => ok(),
+                Err(err) => Frame::Error(format!("ERR Unable to save the ACL file: {}", err)),
+            },
+            Acl::SetUser(name, rules) => match acl.set_user(&name, &rules) {
+                Ok(()) => ok(),
+                Err(err) => Frame::Error(err),
+            },
+            Acl::Users => bulks(acl.names()),
+            Acl::WhoAmI => Frame::Bulk(Bytes::from(client.lock().unwrap().user.clone())),
+        }
+    }
+
+    /// `CLIENT`
+    fn client(&self, cmd: Client, client: &Mutex<ClientInfo>) -> Frame {
+        let ok = || Frame::Simple("OK".to_string());
+        match cmd {
+            Client::GetName => match &client.lock().unwrap().name[..] {
+                "" => Frame::Null,
+                name => Frame::Bulk(Bytes::from(name.to_string())),
+            },
+            Client::Id => Frame::Integer(client.lock().unwrap().id as i64),
+            Client::Info => Frame::Bulk(Bytes::from(client.lock().unwrap().describe())),
+            Client::Kill(filter) => {
+                let myself = client.lock().unwrap().id;
+                Frame::Integer(self.clients.kill(&filter, myself) as i64)
+            }
+            Client::KillAddr(addr) => {
+                let filter = ClientKill {
+                    addr: Some(addr),
+                    ..Default::default()
+                };
+                match self.clients.kill(&filter, 0) {
+                    0 => Frame::Error("ERR No such client".to_string()),
+                    _ => ok(),
+                }
+            }
+            Client::List(kind, ids) => Frame::Bulk(Bytes::from(self.clients.list(kind, &ids))),
+            Client::SetName(name) if !valid_client_name(&name) => Frame::Error(
+                "ERR Client names cannot contain spaces, newlines or special characters."
+                    .to_string(),
+            ),
+            Client::SetName(name) => {
+                client.lock().unwrap().name = name;
+                ok()
+            }
+        }
+    }
+
+    /// `INFO`, the sections are given in lowercase. The keyspace is read from
+    /// the shards in `locked` when they are already held, from every shard
+    /// locked otherwise
+    async fn info(&self, sections: &[String], locked: Option<&mut LockedShards>) -> String {
+        let all = sections.iter().any(|s| s == "all" || s == "everything");
+        let default = sections.is_empty() || sections.iter().any(|s| s == "default");
+        let wanted = |name: &str, by_default: bool| {
+            all || (default && by_default) || sections.iter().any(|s| s == name)
+        };
+
+        let mut info = vec![];
+        if wanted("server", true) {
+            info.push(("Server", self.info_server()));
+        }
+        if wanted("clients", true) {
+            info.push((
+                "Clients",
+                vec![
+                    format!("connected_clients:{}", self.clients.len()),
+                    format!(
+                        "blocked_clients:{}",
+                        self.blocked_clients.load(Ordering::Relaxed)
+                    ),
+                ],
+            ));
+        }
+        if wanted("memory", true) {
+            info.push(("Memory", self.info_memory()));
+        }
+        if wanted("persistence", true) {
+            info.push(("Persistence", self.info_persistence()));
+        }
+        if wanted("stats", true) {
+            info.push(("Stats", self.info_stats()));
+        }
+        if wanted("replication", true) {
+            info.push(("Replication", self.info_replication()));
+        }
+        if wanted("commandstats", false) {
+            info.push(("Commandstats", self.stats.lock().unwrap().commandstats()));
+        }
+        if wanted("errorstats", true) {
+            info.push(("Errorstats", self.stats.lock().unwrap().errorstats()));
+        }
+        if wanted("latencystats", false) {
+            info.push(("Latencystats", self.stats.lock().unwrap().latencystats()));
+        }
+        if wanted("keyspace", true) {
+            let keyspace = match locked {
+                Some(locked) => self.info_keyspace(locked),
+                None => {
+                    let mut locked = self.lock_shards(0..self.kvs.len()).acquire().await;
+                    self.info_keyspace(&mut locked)
+                }
+            };
+            info.push(("Keyspace", keyspace));
+        }
+
+        info.into_iter()
+            .map(|(title, lines)| {
+                let mut section = format!("# {}\r\n", title);
+                for line in lines {
+                    section.push_str(&line);
+                    section.push_str("\r\n");
+                }
+                section
+            })
+            .collect::<Vec<_>>()
+            .join("\r\n")
+    }
+
+    fn info_server(&self) -> Vec<String> {
+        let config = self.config();
+        let uptime = self.started.elapsed().as_secs();
+        vec![
+            "redis_version:7.2.4".to_string(),
+            format!(
+                "redis_mode:{}",
+                match self.cluster.is_some() {
+                    true => "cluster",
+                    false => "standalone",
+                }
+            ),
+            format!("os:{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            format!("arch_bits:{}", usize::BITS),
+            format!("process_id:{}", std::process::id()),
+            format!("tcp_port:{}", config.port),
+            format!("uptime_in_seconds:{}", uptime),
+            format!("uptime_in_days:{}", uptime / 86400),
+            format!(
+                "config_file:{}",
+                config
+                    .config_file
+                    .as_ref()
+                    .map_or(String::new(), |path| path.display().to_string())
+            ),
+        ]
+    }
+
+    fn info_memory(&self) -> Vec<String> {
+        let config = self.config();
+        let used = self.used_memory.load(Ordering::Relaxed);
+        vec![
+            format!("used_memory:{}", used),
+            format!("used_memory_human:{}", human_bytes(used)),
+            format!("maxmemory:{}", config.maxmemory),
+            format!("maxmemory_human:{}", human_bytes(config.maxmemory)),
+            format!("maxmemory_policy:{}", config.maxmemory_policy.name()),
+        ]
+    }
+
+    fn info_persistence(&self) -> Vec<String> {
+        let flag = |flag: &AtomicBool| flag.load(Ordering::SeqCst) as u8;
+        vec![
+            "loading:0".to_string(),
+            format!(
+                "rdb_changes_since_last_save:{}",
+                self.dirty.load(Ordering::SeqCst)
+            ),
+            format!("rdb_bgsave_in_progress:{}", flag(&self.saving)),
+            format!(
+                "rdb_last_save_time:{}",
+                self.last_save.load(Ordering::SeqCst)
+            ),
+            format!("aof_enabled:{}", self.config().appendonly as u8),
+            format!("aof_rewrite_in_progress:{}", flag(&self.rewriting)),
+            format!(
+                "aof_last_write_status:{}",
+                match self.aof_status.error() {
+                    Some(_) => "err",
+                    None => "ok",
+                }
+            ),
+        ]
+    }
+
+    fn info_stats(&self) -> Vec<String> {
+        let stats = self.stats.lock().unwrap();
+        let keyspace = &self.keyspace_stats;
+        vec![
+            format!(
+                "total_connections_received:{}",
+                self.client_ids.load(Ordering::Relaxed) - 1
+            ),
+            format!("total_commands_processed:{}", stats.total_calls()),
+            format!(
+                "expired_keys:{}",
+                keyspace.expired_keys.load(Ordering::Relaxed)
+            ),
+            format!(
+                "evicted_keys:{}",
+                keyspace.evicted_keys.load(Ordering::Relaxed)
+            ),
+            format!("total_error_replies:{}", stats.total_errors()),
+        ]
+    }
+
+    fn info_replication(&self) -> Vec<String> {
+        let replicas = self.clients.list(Some(ClientType::Replica), &[]);
+        let mut lines = vec![];
+        match &*self.primary.lock().unwrap() {
+            Some((host, port, _)) => {
+                lines.push("role:slave".to_string());
+                lines.push(format!("master_host:{}", host));
+                lines.push(format!("master_port:{}", port));
+            }
+            None => lines.push("role:master".to_string()),
+        }
+        lines.push(format!("connected_slaves:{}", replicas.lines().count()));
+        lines
+    }
+
+    /// Keys and keys with an expiration time of every database with keys
+    fn info_keyspace(&self, locked: &mut LockedShards) -> Vec<String> {
+        let databases = self.config().databases;
+        let selected = locked.selected();
+        let mut lines = vec![];
+        for db in 0..databases {
+            locked.select(db);
+            let (mut keys, mut expires) = (0, 0);
+            for shard in locked.shards() {
+                keys += shard.db().len();
+                expires += shard.db().expires_len();
+            }
+            if keys > 0 {
+                lines.push(format!(
+                    "db{}:keys={},expires={},avg_ttl=0",
+                    db, keys, expires
+                ));
+            }
+        }
+        locked.select(selected);
+        lines
+    }
+
+    /// `LATENCY HISTOGRAM`, with the cumulative count of calls by upper
+    /// bound of their latency
+    fn latency_histogram(&self, names: &[String]) -> Frame {
+        let stats = self.stats.lock().unwrap();
+        let field = |name: &'static str| Frame::Bulk(Bytes::from_static(name.as_bytes()));
+        let histograms = stats
+            .commands()
+            .filter(|(name, _)| names.is_empty() || names.iter().any(|n| n == name))
+            .filter(|(_, stat)| stat.latency.count() > 0)
+            .map(|(name, stat)| {
+                let buckets = stat
+                    .latency
+                    .cumulative()
+                    .into_iter()
+                    .map(|(upper, count)| {
+                        (Frame::Integer(upper as i64), Frame::Integer(count as i64))
+                    })
+                    .collect();
+                (
+                    Frame::Bulk(Bytes::from_static(name.as_bytes())),
+                    Frame::Map(vec![
+                        (field("calls"), Frame::Integer(stat.calls as i64)),
+                        (field("histogram_usec"), Frame::Map(buckets)),
+                    ]),
+                )
+            });
+        Frame::Map(histograms.collect())
+    }
+
+    /// Check that this node serves the keys of the command in cluster mode,
+    /// the client is otherwise redirected
+    async fn route(self: &Arc<Self>, cmd: &Command, db: usize, asking: bool) -> Result<(), Frame> {
+        let Some(cluster) = &self.cluster else {
+            return Ok(());
+        };
+        let keys = cmd.keys();
+        let route = cluster.lock().unwrap().route(&keys, asking);
+        let (slot, address) = match route {
+            Route::Local => return Ok(()),
+            Route::Redirect(redirect) => return Err(redirect),
+            Route::Migrating(slot, address) => (slot, address),
+        };
+
+        // Keys missing from a migrating slot may already be on the target
+        let exists = Exists {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+        };
+        let (respond, mut replies) = mpsc::unbounded_channel();
+        self.process(Command::Exists(exists), db, &respond);
+        drop(respond);
+        match replies.recv().await {
+            Some(Frame::Integer(count)) if count as usize == keys.len() => Ok(()),
+            Some(Frame::Integer(0)) => Err(Frame::Error(format!("ASK {} {}", slot, address))),
+            _ => Err(Frame::Error(
+                "TRYAGAIN Multiple keys request during rehashing of slot".to_string(),
+            )),
+        }
+    }
+
+    /// `CLUSTER`
+    fn cluster(&self, cmd: Cluster) -> Frame {
+        let Some(cluster) = &self.cluster else {
+            return Frame::Error("ERR This instance has cluster support disabled".to_string());
+        };
+        let mut cluster = cluster.lock().unwrap();
+        match cmd {
+            Cluster::Info => Frame::Bulk(Bytes::from(cluster.info())),
+            Cluster::KeySlot(key) => Frame::Integer(key_slot(&key) as i64),
+            Cluster::MyId => Frame::Bulk(Bytes::from(cluster.myself().id.clone())),
+            Cluster::Nodes => Frame::Bulk(Bytes::from(cluster.nodes())),
+            Cluster::SetSlot(slot, state) => match cluster.set_slot(slot, state) {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(err) => Frame::Error(err),
+            },
+            Cluster::Shards => cluster.shards(),
+            Cluster::Slots => cluster.slots(),
+        }
+    }
+
+    /// Replicas refuse the writes of their clients, unless configured
+    /// otherwise
+    fn is_read_only(&self) -> bool {
+        self.primary.lock().unwrap().is_some() && self.config().replica_read_only
+    }
+
+    /// `REPLICAOF`, replicate the primary at the address, or stop
+    /// replicating when there is none
+    fn replica_of(self: &Arc<Self>, primary: Option<(String, u16)>) -> Frame {
+        let mut current = self.primary.lock().unwrap();
+        if let (Some((host, port)), Some((current_host, current_port, _))) = (&primary, &*current) {
+            if host == current_host && port == current_port {
+                return Frame::Simple("OK Already connected to specified master".to_string());
+            }
+        }
+        if let Some((_, _, task)) = current.take() {
+            task.abort();
+        }
+        if let Some((host, port)) = primary.clone() {
+            let task = tokio::spawn(self.clone().follow(host.clone(), port));
+            *current = Some((host, port, task));
+        }
+        log::set_replica(primary.is_some());
+        self.config().replicaof = primary;
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Replicate the primary until aborted, reconnecting after a failure
+    async fn follow(self: Arc<Self>, host: String, port: u16) {
+        let mut stream = ReplicatedStream::default();
+        loop {
+            if let Err(err) = self.replicate(&host, port, &mut stream).await {
+                log::warning(format_args!(
+                    "Replication from {}:{} failed: {}",
+                    host, port, err
+                ));
+            }
+            time::sleep(REPLICA_RETRY_DELAY).await;
+        }
+    }
+
+    /// Synchronize with the primary, then apply the stream of its writes
+    /// until the connection fails
+    async fn replicate(
+        self: &Arc<Self>,
+        host: &str,
+        port: u16,
+        stream: &mut ReplicatedStream,
+    ) -> Result<(), Error> {
+        let socket = TcpStream::connect((host, port)).await?;
+        let (read, mut write) = socket.into_split();
+        let mut read = BufferedStream::new(read);
+
+        let listening_port = self.config().port.to_string();
+        let (id, offset) = match &stream.id {
+            Some(id) => (id.clone(), (stream.offset + 1).to_string()),
+            None => ("?".to_string(), "-1".to_string()),
+        };
+        let handshake = [
+            vec!["PING"],
+            vec!["REPLCONF", "listening-port", &listening_port],
+            vec!["REPLCONF", "capa", "psync2"],
+            vec!["PSYNC", &id, &offset],
+        ];
+        let mut reply = Frame::Null;
+        for args in handshake {
+            request(&args).write(&mut write, Protocol::Resp2).await?;
+            reply = read_frame(&mut read).await?;
+            if let Frame::Error(err) = reply {
+                return Err(err.into());
+            }
+        }
+
+        let reply = match reply {
+            Frame::Simple(reply) => reply,
+            reply => return Err(format!("unexpected reply to PSYNC: {:?}", reply).into()),
+        };
+        let mut parts = reply.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("FULLRESYNC"), Some(id), Some(offset)) => {
+                let offset = offset.parse().map_err(|_| "invalid PSYNC offset")?;
+                let Frame::Bulk(rdb) = read_frame(&mut read).await? else {
+                    return Err("expected the RDB snapshot".into());
+                };
+                let databases = self.config().databases;
+                self.load_snapshot(rdb::decode(&rdb, databases)?).await;
+                log::notice(format_args!(
+                    "Full synchronization with {}:{} done",
+                    host, port
+                ));
+                *stream = ReplicatedStream {
+                    id: Some(id.to_string()),
+                    offset,
+                    db: 0,
+                };
+            }
+            (Some("CONTINUE"), _, _) => {
+                log::notice(format_args!(
+                    "Partial synchronization with {}:{} accepted",
+                    host, port
+                ));
+            }
+            _ => return Err(format!("unexpected reply to PSYNC: {}", reply).into()),
+        }
+
+        // Acknowledge the offset, until the stream is dropped
+        let acked = Arc::new(AtomicU64::new(stream.offset));
+        let ack = {
+            let acked = acked.clone();
+            tokio::spawn(async move {
+                let mut interval = time::interval(REPLICA_ACK_PERIOD);
+                loop {
+                    interval.tick().await;
+                    let offset = acked.load(Ordering::SeqCst).to_string();
+                    let ack = request(&["REPLCONF", "ACK", &offset]);
+                    if ack.write(&mut write, Protocol::Resp2).await.is_err() {
+                        break;
+                    }
+                }
+            })
+        };
+        let _ack = AbortOnDrop(ack);
+
+        loop {
+            let frame = read_frame(&mut read).await?;
+            let mut encoded = vec![];
+            frame.encode(&mut encoded, Protocol::Resp2);
+            match Command::from_frame(frame) {
+                Ok(Command::Select(cmd)) => stream.db = cmd.index,
+                Ok(Command::Ping(_)) => {}
+                // Applied one after the other, in the order of the primary
+                Ok(cmd) => {
+                    let (respond, mut replies) = mpsc::unbounded_channel();
+                    self.process(cmd, stream.db, &respond);
+                    drop(respond);
+                    while replies.recv().await.is_some() {}
+                }
+                Err(err) => {
+                    log::warning(format_args!("Unable to parse a replicated write: {}", err))
+                }
+            }
+            stream.offset += encoded.len() as u64;
+            acked.store(stream.offset, Ordering::SeqCst);
+        }
+    }
+
+    /// Replace every database with the snapshot of the primary
+    async fn load_snapshot(&self, dbs: Vec<Vec<(String, Entry)>>) {
+        let mut locked = self.lock_shards(0..self.kvs.len()).acquire().await;
+        for shard in locked.shards() {
+            Self::flush(shard, true, false);
+        }
+        locked
+            .shard(0)
+            .propagate(vec![Bytes::from_static(b"FLUSHALL")]);
+        let databases = self.config().databases;
+        for (db, entries) in dbs.into_iter().enumerate().take(databases) {
+            locked.select(db);
+            for (key, entry) in entries {
+                locked.shard(self.select_kvs(&key)).insert(key, entry);
+            }
+        }
+    }
+
+    /// `PSYNC`, the replica resumes from the backlog when possible, it is
+    /// otherwise sent a snapshot followed by the writes
+    async fn sync_replica(&self, cmd: PSync, respond: mpsc::UnboundedSender<Frame>) {
+        let (attached_tx, attached) = oneshot::channel();
+        let resync = Resync::Partial(cmd.id, cmd.offset);
+        self.feed
+            .send(FeedMessage::Replicate(respond.clone(), resync, attached_tx))
+            .unwrap();
+        if attached.await == Ok(true) {
+            return;
+        }
+
+        // The writes fed before the shards are locked are part of the
+        // snapshot, the ones fed after are streamed
+        let mut locked = self.lock_shards(0..self.kvs.len()).acquire().await;
+        let rdb = Bytes::from(rdb::encode(&self.dump(&mut locked)));
+        let (attached_tx, attached) = oneshot::channel();
+        self.feed
+            .send(FeedMessage::Replicate(
+                respond,
+                Resync::Full(rdb),
+                attached_tx,
+            ))
+            .unwrap();
+        drop(locked);
+        let _ = attached.await;
+    }
+
+    fn select_kvs(&self, key: &str) -> usize {
+        select_shard(key, self.kvs.len())
+    }
+
+    fn config(&self) -> MutexGuard<'_, ServerConfig> {
+        self.config.lock().unwrap()
+    }
+
+    /// Exclusive access to the shards of the keys, requested right away so
+    /// that the shards are locked in the order of the commands
+    fn lock<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> PendingLock {
+        self.lock_shards(keys.into_iter().map(|key| self.select_kvs(key)))
+    }
+
+    fn lock_shards(&self, indexes: impl IntoIterator<Item = usize>) -> PendingLock {
+        let _ordered = self.lock_order.lock().unwrap();
+        LockedShards::request(&self.kvs, indexes, self.feed.clone(), self.fsync_always)
+    }
+
+    /// Point-in-time copy of every shard, by database.
+    ///
+    /// Every shard is locked for the copy so that writes spanning several
+    /// shards, such as `MSET` or `EXEC`, are either fully in it or not at
+    /// all. The shards are released before the copy is encoded.
+    async fn snapshot(&self) -> Vec<Vec<(String, Entry)>> {
+        let mut locked = self.lock_shards(0..self.kvs.len()).acquire().await;
+        self.dump(&mut locked)
+    }
+
+    fn dump(&self, locked: &mut LockedShards) -> Vec<Vec<(String, Entry)>> {
+        let mut dbs = vec![vec![]; self.config().databases];
+        for shard in locked.shards() {
+            for (db, entries) in shard.dump().into_iter().enumerate() {
+                dbs[db].extend(entries);
+            }
+        }
+        dbs
+    }
+
+    /// Write a snapshot to the RDB file, the shards are only paused while
+    /// being copied
+    pub async fn save(&self) -> Result<(), Error> {
+        if self.saving.swap(true, Ordering::SeqCst) {
+            return Err("Background save already in progress".into());
+        }
+
+        let dirty = self.dirty.load(Ordering::SeqCst);
+        let entries = self.snapshot().await;
+        let path = self.config().rdb_path();
+        let result = tokio::task::spawn_blocking(move || write_rdb(&path, &entries))
+            .await
+            .unwrap();
+
+        if result.is_ok() {
+            self.dirty.fetch_sub(dirty, Ordering::SeqCst);
+            self.last_save.store(now_ms() / 1000, Ordering::SeqCst);
+        }
+        self.saving.store(false, Ordering::SeqCst);
+        result
+    }
+}
+
+impl Backend {
+    /// Rebuild the AOF from the content of the shards
+    pub async fn rewrite_aof(&self) -> Result<(), Error> {
+        if self.rewriting.swap(true, Ordering::SeqCst) {
+            return Err("Background append only file rewriting already in progress".into());
+        }
+
+        let (done_tx, done_rx) = oneshot::channel();
+        self.feed
+            .send(FeedMessage::StartRewrite(self.kvs.len(), done_tx))
+            .unwrap();
+        // The dump of a shard is sent through the feed while the shard is
+        // locked, so that it is ordered with the writes of the shard. The
+        // shards are locked one after the other, see `snapshot`
+        for index in 0..self.kvs.len() {
+            let mut locked = self.lock_shards([index]).acquire().await;
+            let dump = locked.shard(index).dump();
+            self.feed.send(FeedMessage::Dump(dump)).unwrap();
+        }
+
+        let result = done_rx.await.unwrap();
+        self.rewriting.store(false, Ordering::SeqCst);
+        result
+    }
+}
+
+/// Combine the sets replied to `SMEMBERS`, or the first error
+fn combine_members(kind: SetOpKind, replies: Vec<Frame>) -> Frame {
+    let mut sets = Vec::with_capacity(replies.len());
+    for reply in replies {
+        match reply {
+            Frame::Set(members) => sets.push(
+                members
+                    .into_iter()
+                    .filter_map(|member| match member {
+                        Frame::Bulk(member) => Some(member),
+                        _ => None,
+                    })
+                    .collect::<HashSet<Bytes>>(),
+            ),
+            err => return err,
+        }
+    }
+
+    let mut sets = sets.into_iter();
+    let mut result = sets.next().unwrap_or_default();
+    for set in sets {
+        match kind {
+            SetOpKind::Inter => result.retain(|member| set.contains(member)),
+            SetOpKind::Union => result.extend(set),
+            SetOpKind::Diff => result.retain(|member| !set.contains(member)),
+        }
+    }
+    Frame::Set(result.into_iter().map(Frame::Bulk).collect())
+}
+
+fn write_rdb(path: &Path, dbs: &[Vec<(String, Entry)>]) -> Result<(), Error> {
+    use std::io::Write;
+
+    let temp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let mut file = std::fs::File::create(&temp)?;
+    file.write_all(&rdb::encode(dbs))?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Position of a replica in the stream of its primary, kept to resume after a
+/// disconnection
+#[derive(Default)]
+struct ReplicatedStream {
+    /// Replication id of the primary, unknown until a first synchronization
+    id: Option<String>,
+    offset: u64,
+    /// Database selected by the stream
+    db: usize,
+}
+
+/// Abort a task once dropped
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn read_frame(stream: &mut BufferedStream<OwnedReadHalf>) -> Result<Frame, Error> {
+    match Frame::parse(stream).await {
+        Ok(frame) => Ok(frame),
+        Err(FrameParseError::Incomplete) => Err("connection closed".into()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Index of the shard owning the key, the keys of a hash slot sharing a
+/// shard
+fn select_shard(key: &str, shards: usize) -> usize {
+    key_slot(key.as_bytes()) as usize % shards
+}
+
+/// Trigger a snapshot when one of the configured save points is reached
+async fn serve_save_points(backend: Arc<Backend>) {
+    let mut interval = time::interval(Duration::from_secs(1));
+    let mut last_failure = 0;
+
+    loop {
+        interval.tick().await;
+
+        let now = now_ms() / 1000;
+        let dirty = backend.dirty.load(Ordering::SeqCst);
+        let elapsed = now.saturating_sub(backend.last_save.load(Ordering::SeqCst));
+        let reached = backend
+            .config()
+            .save
+            .iter()
+            .any(|point| dirty >= point.changes && elapsed >= point.seconds);
+
+        if reached && now.saturating_sub(last_failure) >= SAVE_RETRY_DELAY_SECS {
+            log::notice(format_args!(
+                "{} changes in {} seconds. Saving...",
+                dirty, elapsed
+            ));
+            if let Err(err) = backend.save().await {
+                log::warning(format_args!("Background saving error: {}", err));
+                last_failure = now;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Empty directory for the files of a server
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-test-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A server listening on a free port, without save points
+    async fn start(dir: &Path, args: &[&str]) -> (Arc<Backend>, SocketAddr) {
+        let dir = dir.display().to_string();
+        let defaults = ["--save", "", "--dir", &dir];
+        let args = defaults.iter().chain(args).map(|arg| arg.to_string());
+        let config = ServerConfig::from_args(args).unwrap();
+        let backend = Arc::new(Backend::new(config).await.unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_clients(backend.clone(), listener));
+        (backend, address)
+    }
+
+    struct Client {
+        reader: BufferedStream<OwnedReadHalf>,
+        writer: OwnedWriteHalf,
+    }
+
+    impl Client {
+        async fn connect(address: SocketAddr) -> Self {
+            let (read, writer) = TcpStream::connect(address).await.unwrap().into_split();
+            Self {
+                reader: BufferedStream::new(read),
+                writer,
+            }
+        }
+
+        /// Send the requests at once, without waiting for the replies
+        async fn send(&mut self, requests: &[Vec<&str>]) {
+            let mut out = vec![];
+            for args in requests {
+                request(args).encode(&mut out, Protocol::Resp2);
+            }
+            self.writer.write_all(&out).await.unwrap();
+        }
+
+        async fn read(&mut self) -> Frame {
+            read_frame(&mut self.reader).await.unwrap()
+        }
+
+        async fn call(&mut self, args: &[&str]) -> Frame {
+            self.send(&[args.to_vec()]).await;
+            self.read().await
+        }
+    }
+
+    fn bulk(val: &str) -> Frame {
+        Frame::Bulk(Bytes::from(val.to_string()))
+    }
+
+    #[tokio::test]
+    async fn pipelining_test() {
+        let (_, address) = start(&test_dir("pipelining"), &[]).await;
+        let mut client = Client::connect(address).await;
+
+        // Spread over the shards, the replies of a shard can be ready before
+        // the ones of the previous requests
+        let keys: Vec<String> = (0..500).map(|i| format!("key:{}", i)).collect();
+        let mut requests = vec![];
+        for key in &keys {
+            requests.push(vec!["SET", key, key]);
+            requests.push(vec!["GET", key]);
+            requests.push(vec!["INCR", "counter"]);
+        }
+
+        let started = Instant::now();
+        client.send(&requests).await;
+        let replies = async {
+            for (i, key) in keys.iter().enumerate() {
+                assert_eq!(client.read().await, Frame::Simple("OK".to_string()));
+                assert_eq!(client.read().await, bulk(key));
+                assert_eq!(client.read().await, Frame::Integer(i as i64 + 1));
+            }
+        };
+        time::timeout(Duration::from_secs(5), replies)
+            .await
+            .expect("pipelined replies stalled");
+
+        // Neither held by the server nor delayed by Nagle's algorithm
+        let round_trips = Instant::now();
+        for _ in 0..50 {
+            assert_eq!(client.call(&["PING"]).await, Frame::Simple("PONG".into()));
+        }
+        assert!(round_trips.elapsed() < Duration::from_secs(1));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn set_op_ordering_test() {
+        let (_, address) = start(&test_dir("set-op-ordering"), &[]).await;
+        let mut client = Client::connect(address).await;
+
+        // Keys spread over the shards, read before the next writes
+        let keys: Vec<String> = (0..8).map(|i| format!("set:{}", i)).collect();
+        let mut requests = vec![];
+        for key in &keys {
+            requests.push(vec!["SADD", key, "before", key]);
+        }
+        let mut sunion = vec!["SUNION"];
+        sunion.extend(keys.iter().map(|key| &key[..]));
+        requests.push(sunion);
+        for key in &keys {
+            requests.push(vec!["SADD", key, "after"]);
+        }
+        requests.push(vec!["DEL", &keys[0]]);
+        client.send(&requests).await;
+
+        for _ in &keys {
+            assert_eq!(client.read().await, Frame::Integer(2));
+        }
+        let Frame::Array(mut members) = client.read().await else {
+            panic!("expected the members of the union");
+        };
+        members.sort_by_key(|member| format!("{:?}", member));
+        let mut expected: Vec<Frame> = keys.iter().map(|key| bulk(key)).collect();
+        expected.push(bulk("before"));
+        expected.sort_by_key(|member| format!("{:?}", member));
+        assert_eq!(members, expected);
+    }
+
+    /// A key owned by another shard than `key`
+    fn other_shard(key: &str) -> String {
+        let shards = ServerConfig::default().shards;
+        (0..)
+            .map(|i| format!("other:{}", i))
+            .find(|other| select_shard(other, shards) != select_shard(key, shards))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn mset_test() {
+        let (_, address) = start(&test_dir("mset"), &[]).await;
+        let mut client = Client::connect(address).await;
+        let other = other_shard("key");
+        let ok = Frame::Simple("OK".to_string());
+
+        assert_eq!(client.call(&["MSET", "key", "1", &other, "2"]).await, ok);
+        let values = client.call(&["MGET", "key", &other, "missing"]).await;
+        assert_eq!(
+            values,
+            Frame::Array(vec![bulk("1"), bulk("2"), Frame::Null])
+        );
+
+        // Nothing is set when one of the keys exists, on any shard
+        let third = other_shard(&other);
+        let msetnx = client.call(&["MSETNX", &third, "3", &other, "4"]).await;
+        assert_eq!(msetnx, Frame::Integer(0));
+        assert_eq!(client.call(&["EXISTS", &third]).await, Frame::Integer(0));
+        assert_eq!(client.call(&["GET", &other]).await, bulk("2"));
+        client.call(&["DEL", &other]).await;
+        let msetnx = client.call(&["MSETNX", &third, "3", &other, "4"]).await;
+        assert_eq!(msetnx, Frame::Integer(1));
+        let values = client.call(&["MGET", &third, &other]).await;
+        assert_eq!(values, Frame::Array(vec![bulk("3"), bulk("4")]));
+
+        // The last value of a repeated key is kept
+        assert_eq!(client.call(&["MSET", "key", "5", "key", "6"]).await, ok);
+        assert_eq!(client.call(&["GET", "key"]).await, bulk("6"));
+        assert!(matches!(
+            client.call(&["MSET", "key", "1", &other]).await,
+            Frame::Error(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn move_across_shards_test() {
+        let (_, address) = start(&test_dir("move-across-shards"), &[]).await;
+        let mut client = Client::connect(address).await;
+        let destination = other_shard("source");
+
+        // Left untouched when the destination is not a list
+        client.call(&["RPUSH", "source", "a", "b"]).await;
+        client.call(&["EXPIRE", "source", "100"]).await;
+        client.call(&["SET", &destination, "string"]).await;
+        let wrongtype = Frame::Error(WRONGTYPE.to_string());
+        let lmove = ["LMOVE", "source", &destination, "LEFT", "RIGHT"];
+        assert_eq!(client.call(&lmove).await, wrongtype);
+        let blmove = ["BLMOVE", "source", &destination, "LEFT", "RIGHT", "0"];
+        assert_eq!(client.call(&blmove).await, wrongtype);
+        let range = client.call(&["LRANGE", "source", "0", "-1"]).await;
+        assert_eq!(range, Frame::Array(vec![bulk("a"), bulk("b")]));
+        assert!(matches!(client.call(&["TTL", "source"]).await, Frame::Integer(ttl) if ttl > 0));
+
+        // Moved once pushed by another client
+        client.call(&["DEL", "source", &destination]).await;
+        client.send(&[blmove.to_vec()]).await;
+        let mut other = Client::connect(address).await;
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            other.call(&["RPUSH", "source", "c"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(client.read().await, bulk("c"));
+        let range = other.call(&["LRANGE", &destination, "0", "-1"]).await;
+        assert_eq!(range, Frame::Array(vec![bulk("c")]));
+        assert_eq!(other.call(&["EXISTS", "source"]).await, Frame::Integer(0));
+
+        // Timed out
+        let blmove = ["BLMOVE", "source", &destination, "LEFT", "RIGHT", "0.05"];
+        assert_eq!(client.call(&blmove).await, Frame::Null);
+    }
+
+    #[tokio::test]
+    async fn blocking_order_test() {
+        let (_, address) = start(&test_dir("blocking-order"), &[]).await;
+        let mut client = Client::connect(address).await;
+        let other = other_shard("list");
+
+        // Served, or blocked, before the next requests of the connection
+        client
+            .send(&[
+                vec!["RPUSH", "list", "a"],
+                vec!["BLPOP", &other, "list", "0"],
+                vec!["RPUSH", "list", "b"],
+                vec!["LRANGE", "list", "0", "-1"],
+                vec!["DEL", "list"],
+                vec!["BRPOP", "list", &other, "0"],
+                vec!["RPUSH", &other, "c"],
+                vec!["LLEN", &other],
+            ])
+            .await;
+        let replies = async {
+            let mut replies = vec![];
+            for _ in 0..8 {
+                replies.push(client.read().await);
+            }
+            replies
+        };
+        let replies = time::timeout(Duration::from_secs(5), replies)
+            .await
+            .expect("blocked client never served");
+        assert_eq!(
+            replies,
+            vec![
+                Frame::Integer(1),
+                Frame::Array(vec![bulk("list"), bulk("a")]),
+                Frame::Integer(1),
+                Frame::Array(vec![bulk("b")]),
+                Frame::Integer(1),
+                Frame::Array(vec![bulk(&other), bulk("c")]),
+                Frame::Integer(1),
+                Frame::Integer(0),
+            ]
+        );
+
+        // `$` is the last entry when the read is received
+        client
+            .send(&[
+                vec!["XADD", "stream", "1-1", "f", "a"],
+                vec!["XREAD", "BLOCK", "0", "STREAMS", &other, "stream", "$", "$"],
+                vec!["XADD", "stream", "2-1", "f", "b"],
+                vec!["XLEN", "stream"],
+            ])
+            .await;
+        let replies = async {
+            let mut replies = vec![];
+            for _ in 0..4 {
+                replies.push(client.read().await);
+            }
+            replies
+        };
+        let replies = time::timeout(Duration::from_secs(5), replies)
+            .await
+            .expect("blocked client never served");
+        let entry = Frame::Array(vec![bulk("2-1"), Frame::Array(vec![bulk("f"), bulk("b")])]);
+        assert_eq!(
+            replies,
+            vec![
+                bulk("1-1"),
+                Frame::Array(vec![Frame::Array(vec![
+                    bulk("stream"),
+                    Frame::Array(vec![entry]),
+                ])]),
+                bulk("2-1"),
+                Frame::Integer(2),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn kill_replica_test() {
+        let (_, address) = start(&test_dir("kill-replica"), &[]).await;
+        let mut replica = Client::connect(address).await;
+        replica.send(&[vec!["PSYNC", "?", "-1"]]).await;
+        assert!(
+            matches!(replica.read().await, Frame::Simple(reply) if reply.starts_with("FULLRESYNC"))
+        );
+
+        let mut client = Client::connect(address).await;
+        let kill = ["CLIENT", "KILL", "TYPE", "replica"];
+        assert_eq!(client.call(&kill).await, Frame::Integer(1));
+        client.call(&["SET", "key", "value"]).await;
+        let closed = async { while read_frame(&mut replica.reader).await.is_ok() {} };
+        time::timeout(Duration::from_secs(5), closed)
+            .await
+            .expect("replica link left open");
+    }
+
+    #[tokio::test]
+    async fn latency_test() {
+        let args = ["--slowlog-log-slower-than", "100000"];
+        let (backend, address) = start(&test_dir("latency"), &args).await;
+        let mut client = Client::connect(address).await;
+
+        // Waiting for the shard is not part of the latency
+        let locked = backend.lock(["key"]).acquire().await;
+        client.send(&[vec!["GET", "key"]]).await;
+        time::sleep(Duration::from_millis(200)).await;
+        drop(locked);
+        assert_eq!(client.read().await, Frame::Null);
+
+        // Counted once replied
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(client.call(&["SLOWLOG", "LEN"]).await, Frame::Integer(0));
+        let stats = backend.stats.lock().unwrap();
+        let (_, get) = stats.commands().find(|(name, _)| *name == "get").unwrap();
+        assert_eq!(get.calls, 1);
+        assert!(get.usec < 100000);
+    }
+
+    #[tokio::test]
+    async fn aof_replay_test() {
+        let dir = test_dir("aof-replay");
+        let args = ["--appendonly", "yes", "--appendfsync", "always"];
+        let (_, address) = start(&dir, &args).await;
+        let mut client = Client::connect(address).await;
+        client.call(&["SET", "string", "value"]).await;
+        client.call(&["RPUSH", "list", "a", "b", "c"]).await;
+        client.call(&["LPOP", "list"]).await;
+        client.call(&["EXPIRE", "list", "100"]).await;
+        client.call(&["SELECT", "1"]).await;
+        client.call(&["HSET", "hash", "field", "value"]).await;
+
+        // The tail of a write interrupted by a crash is ignored
+        let mut truncated = vec![];
+        request(&["SET", "lost", "value"]).encode(&mut truncated, Protocol::Resp2);
+        truncated.truncate(truncated.len() - 4);
+        let mut aof = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("appendonly.aof"))
+            .unwrap();
+        std::io::Write::write_all(&mut aof, &truncated).unwrap();
+
+        let (_, address) = start(&dir, &args).await;
+        let mut client = Client::connect(address).await;
+        assert_eq!(client.call(&["GET", "string"]).await, bulk("value"));
+        let range = client.call(&["LRANGE", "list", "0", "-1"]).await;
+        assert_eq!(range, Frame::Array(vec![bulk("b"), bulk("c")]));
+        assert!(matches!(client.call(&["TTL", "list"]).await, Frame::Integer(ttl) if ttl > 0));
+        assert_eq!(client.call(&["EXISTS", "lost"]).await, Frame::Integer(0));
+        client.call(&["SELECT", "1"]).await;
+        assert_eq!(client.call(&["HGET", "hash", "field"]).await, bulk("value"));
+    }
+
+    #[tokio::test]
+    async fn aof_rewrite_test() {
+        let dir = test_dir("aof-rewrite");
+        let args = ["--appendonly", "yes", "--appendfsync", "always"];
+        let (backend, address) = start(&dir, &args).await;
+        let mut client = Client::connect(address).await;
+        client.call(&["RPUSH", "list", "a", "b"]).await;
+        client.call(&["LPOP", "list"]).await;
+        for _ in 0..3 {
+            client.call(&["INCR", "counter"]).await;
+        }
+        client.call(&["SET", "deleted", "value"]).await;
+        client.call(&["DEL", "deleted"]).await;
+
+        // Only the commands recreating the keys are left
+        backend.rewrite_aof().await.unwrap();
+        let mut commands = vec![];
+        aof::load(&dir.join("appendonly.aof"), |frame| commands.push(frame))
+            .await
+            .unwrap();
+        commands.sort_by_key(|command| format!("{:?}", command));
+        let mut expected = vec![
+            request(&["RPUSH", "list", "b"]),
+            request(&["SELECT", "0"]),
+            request(&["SET", "counter", "3"]),
+        ];
+        expected.sort_by_key(|command| format!("{:?}", command));
+        assert_eq!(commands, expected);
+
+        // Followed by the next writes
+        client.call(&["SET", "after", "value"]).await;
+        let (_, address) = start(&dir, &args).await;
+        let mut client = Client::connect(address).await;
+        assert_eq!(client.call(&["GET", "counter"]).await, bulk("3"));
+        assert_eq!(client.call(&["GET", "after"]).await, bulk("value"));
+        let range = client.call(&["LRANGE", "list", "0", "-1"]).await;
+        assert_eq!(range, Frame::Array(vec![bulk("b")]));
+    }
+
+    #[tokio::test]
+    async fn exec_info_test() {
+        let (_, address) = start(&test_dir("exec-info"), &[]).await;
+        let mut client = Client::connect(address).await;
+        client.call(&["MULTI"]).await;
+        client.call(&["SET", "key", "value"]).await;
+        client.call(&["INFO", "keyspace"]).await;
+
+        // INFO reads the keyspace from the shards locked by the transaction
+        let exec = time::timeout(Duration::from_secs(5), client.call(&["EXEC"]));
+        let Frame::Array(replies) = exec.await.unwrap() else {
+            panic!("unexpected reply");
+        };
+        assert_eq!(replies[0], Frame::Simple("OK".to_string()));
+        let Frame::Bulk(info) = &replies[1] else {
+            panic!("unexpected reply");
+        };
+        assert!(std::str::from_utf8(info).unwrap().contains("db0:keys=1,"));
+        let mut other = Client::connect(address).await;
+        let get = time::timeout(Duration::from_secs(5), other.call(&["GET", "key"]));
+        assert_eq!(get.await.unwrap(), bulk("value"));
+    }
+
+    #[tokio::test]
+    async fn exec_test() {
+        let (_, address) = start(&test_dir("exec"), &[]).await;
+        let mut client = Client::connect(address).await;
+        let other = other_shard("key");
+
+        // The writes of a transaction are seen all at once
+        let reads = {
+            let other = other.clone();
+            tokio::spawn(async move {
+                let mut client = Client::connect(address).await;
+                for _ in 0..200 {
+                    let Frame::Array(values) = client.call(&["MGET", "key", &other]).await else {
+                        panic!("unexpected reply");
+                    };
+                    assert_eq!(values[0], values[1]);
+                }
+            })
+        };
+        for i in 0..200 {
+            let value = i.to_string();
+            client
+                .send(&[
+                    vec!["MULTI"],
+                    vec!["SET", "key", &value],
+                    vec!["SET", &other, &value],
+                    vec!["EXEC"],
+                ])
+                .await;
+            for _ in 0..3 {
+                client.read().await;
+            }
+            let ok = Frame::Simple("OK".to_string());
+            assert_eq!(client.read().await, Frame::Array(vec![ok.clone(), ok]));
+        }
+        reads.await.unwrap();
+
+        // An error while executing does not stop the other commands
+        client.call(&["MULTI"]).await;
+        client.call(&["SET", "string", "value"]).await;
+        client.call(&["INCR", "string"]).await;
+        client.call(&["SET", &other, "last"]).await;
+        let Frame::Array(replies) = client.call(&["EXEC"]).await else {
+            panic!("unexpected reply");
+        };
+        assert!(matches!(&replies[1], Frame::Error(_)));
+        assert_eq!(client.call(&["GET", &other]).await, bulk("last"));
+    }
+
+    #[tokio::test]
+    async fn exec_abort_test() {
+        let (_, address) = start(&test_dir("exec-abort"), &[]).await;
+        let mut client = Client::connect(address).await;
+        client.call(&["MULTI"]).await;
+        client.call(&["SET", "key", "value"]).await;
+        assert!(matches!(
+            client.call(&["SET", "key"]).await,
+            Frame::Error(_)
+        ));
+        assert!(matches!(
+            client.call(&["EXEC"]).await,
+            Frame::Error(err) if err.starts_with("EXECABORT")
+        ));
+        assert_eq!(client.call(&["GET", "key"]).await, Frame::Null);
+
+        client.call(&["MULTI"]).await;
+        client.call(&["SET", "key", "value"]).await;
+        client.call(&["UNKNOWN"]).await;
+        assert!(matches!(
+            client.call(&["EXEC"]).await,
+            Frame::Error(err) if err.starts_with("EXECABORT")
+        ));
+        assert_eq!(client.call(&["GET", "key"]).await, Frame::Null);
+    }
+
+    #[tokio::test]
+    async fn watch_test() {
+        let (_, address) = start(&test_dir("watch"), &[]).await;
+        let mut client = Client::connect(address).await;
+        let mut other = Client::connect(address).await;
+        let queued = Frame::Simple("QUEUED".to_string());
+
+        // Touched by another client
+        client.call(&["SET", "key", "1"]).await;
+        client.call(&["WATCH", "key"]).await;
+        other.call(&["SET", "key", "2"]).await;
+        client.call(&["MULTI"]).await;
+        assert_eq!(client.call(&["SET", "key", "3"]).await, queued);
+        assert_eq!(client.call(&["EXEC"]).await, Frame::NullArray);
+        assert_eq!(client.call(&["GET", "key"]).await, bulk("2"));
+
+        // Untouched, the watch ended with the previous EXEC
+        other.call(&["SET", "key", "4"]).await;
+        client.call(&["WATCH", "key"]).await;
+        client.call(&["MULTI"]).await;
+        client.call(&["INCR", "key"]).await;
+        let exec = client.call(&["EXEC"]).await;
+        assert_eq!(exec, Frame::Array(vec![Frame::Integer(5)]));
+
+        // Expired while watched
+        client.call(&["SET", "expiring", "value", "PX", "50"]).await;
+        client.call(&["WATCH", "expiring"]).await;
+        time::sleep(Duration::from_millis(100)).await;
+        client.call(&["MULTI"]).await;
+        client.call(&["SET", "key", "6"]).await;
+        assert_eq!(client.call(&["EXEC"]).await, Frame::NullArray);
+        assert_eq!(client.call(&["GET", "key"]).await, bulk("5"));
+
+        // Discarded with UNWATCH
+        client.call(&["WATCH", "key"]).await;
+        other.call(&["SET", "key", "7"]).await;
+        client.call(&["UNWATCH"]).await;
+        client.call(&["MULTI"]).await;
+        client.call(&["GET", "key"]).await;
+        assert_eq!(client.call(&["EXEC"]).await, Frame::Array(vec![bulk("7")]));
+    }
+
+    #[tokio::test]
+    async fn aof_write_error_test() {
+        let (backend, address) = start(&test_dir("aof-write-error"), &[]).await;
+        let mut client = Client::connect(address).await;
+        client.call(&["SET", "key", "value"]).await;
+
+        // Only the writes are refused until the AOF can be written again
+        let full = std::io::Error::other("No space left on device");
+        backend.aof_status.update(Err(full));
+        assert_eq!(
+            client.call(&["SET", "key", "other"]).await,
+            Frame::Error("MISCONF Errors writing to the AOF file: No space left on device".into())
+        );
+        assert_eq!(client.call(&["GET", "key"]).await, bulk("value"));
+        client.call(&["MULTI"]).await;
+        client.call(&["DEL", "key"]).await;
+        assert!(matches!(
+            client.call(&["EXEC"]).await,
+            Frame::Error(err) if err.starts_with("EXECABORT")
+        ));
+
+        backend.aof_status.update(Ok(()));
+        let ok = Frame::Simple("OK".to_string());
+        assert_eq!(client.call(&["SET", "key", "other"]).await, ok);
     }
 }
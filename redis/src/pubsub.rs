@@ -0,0 +1,515 @@
+use crate::command::*;
+use crate::frame::*;
+use crate::glob::glob_match;
+use bytes::Bytes;
+use std::collections::{BTreeSet, HashMap};
+use tokio::sync::mpsc;
+
+pub type Broker = mpsc::UnboundedSender<BrokerCommand>;
+
+type ClientId = u64;
+
+pub enum BrokerCommand {
+    Subscribe(ClientId, Vec<String>, mpsc::UnboundedSender<Frame>),
+    Unsubscribe(ClientId, Vec<String>),
+    PSubscribe(ClientId, Vec<String>, mpsc::UnboundedSender<Frame>),
+    PUnsubscribe(ClientId, Vec<String>),
+    Publish(Publish, mpsc::UnboundedSender<Frame>),
+    /// A keyspace event from a shard, published without a reply
+    Notify(Publish),
+    PubSub(PubSub, mpsc::UnboundedSender<Frame>),
+}
+
+/// Route published messages to the subscribed clients.
+pub async fn process_broker(cmd_rx: &mut mpsc::UnboundedReceiver<BrokerCommand>) {
+    let mut channels: HashMap<String, HashMap<ClientId, mpsc::UnboundedSender<Frame>>> =
+        HashMap::new();
+    let mut patterns: HashMap<String, HashMap<ClientId, mpsc::UnboundedSender<Frame>>> =
+        HashMap::new();
+
+    while let Some(cmd) = cmd_rx.recv().await {
+        match cmd {
+            BrokerCommand::Subscribe(client, names, sender) => {
+                for name in names {
+                    channels
+                        .entry(name)
+                        .or_default()
+                        .insert(client, sender.clone());
+                }
+            }
+            BrokerCommand::Unsubscribe(client, names) => unregister(&mut channels, client, names),
+            BrokerCommand::PSubscribe(client, names, sender) => {
+                for name in names {
+                    patterns
+                        .entry(name)
+                        .or_default()
+                        .insert(client, sender.clone());
+                }
+            }
+            BrokerCommand::PUnsubscribe(client, names) => unregister(&mut patterns, client, names),
+            BrokerCommand::Publish(cmd, respond) => {
+                let receivers = publish(&channels, &patterns, &cmd);
+                let _ = respond.send(Frame::Integer(receivers));
+            }
+            BrokerCommand::Notify(cmd) => {
+                publish(&channels, &patterns, &cmd);
+            }
+            BrokerCommand::PubSub(PubSub::Channels(pattern), respond) => {
+                let names = channels
+                    .keys()
+                    .filter(|name| match &pattern {
+                        Some(pattern) => glob_match(pattern.as_bytes(), name.as_bytes()),
+                        None => true,
+                    })
+                    .map(|name| Frame::Bulk(Bytes::from(name.clone())))
+                    .collect();
+                let _ = respond.send(Frame::Array(names));
+            }
+            BrokerCommand::PubSub(PubSub::NumSub(names), respond) => {
+                let mut counts = Vec::with_capacity(names.len() * 2);
+                for name in names {
+                    let count = channels.get(&name).map_or(0, |s| s.len());
+                    counts.push(Frame::Bulk(Bytes::from(name)));
+                    counts.push(Frame::Integer(count as i64));
+                }
+                let _ = respond.send(Frame::Array(counts));
+            }
+            BrokerCommand::PubSub(PubSub::NumPat, respond) => {
+                let _ = respond.send(Frame::Integer(patterns.len() as i64));
+            }
+        }
+    }
+}
+
+/// Send the message to the subscribers of the channel and of the matching
+/// patterns, returns the number of receivers
+fn publish(
+    channels: &HashMap<String, HashMap<ClientId, mpsc::UnboundedSender<Frame>>>,
+    patterns: &HashMap<String, HashMap<ClientId, mpsc::UnboundedSender<Frame>>>,
+    cmd: &Publish,
+) -> i64 {
+    let mut receivers = 0;
+
+    if let Some(subscribers) = channels.get(&cmd.channel) {
+        let message = Frame::Push(vec![
+            Frame::Bulk(Bytes::from_static(b"message")),
+            Frame::Bulk(Bytes::from(cmd.channel.clone())),
+            Frame::Bulk(cmd.message.clone()),
+        ]);
+        for subscriber in subscribers.values() {
+            if subscriber.send(message.clone()).is_ok() {
+                receivers += 1;
+            }
+        }
+    }
+
+    for (pattern, subscribers) in patterns {
+        if !glob_match(pattern.as_bytes(), cmd.channel.as_bytes()) {
+            continue;
+        }
+        let message = Frame::Push(vec![
+            Frame::Bulk(Bytes::from_static(b"pmessage")),
+            Frame::Bulk(Bytes::from(pattern.clone())),
+            Frame::Bulk(Bytes::from(cmd.channel.clone())),
+            Frame::Bulk(cmd.message.clone()),
+        ]);
+        for subscriber in subscribers.values() {
+            if subscriber.send(message.clone()).is_ok() {
+                receivers += 1;
+            }
+        }
+    }
+
+    receivers
+}
+
+fn unregister(
+    registry: &mut HashMap<String, HashMap<ClientId, mpsc::UnboundedSender<Frame>>>,
+    client: ClientId,
+    names: Vec<String>,
+) {
+    for name in names {
+        if let Some(subscribers) = registry.get_mut(&name) {
+            subscribers.remove(&client);
+            if subscribers.is_empty() {
+                registry.remove(&name);
+            }
+        }
+    }
+}
+
+/// Subscriptions of a connection.
+///
+/// Confirmations are replies to the (un)subscribe commands, messages are then
+/// pushed by the broker through `sender`.
+pub struct Subscriber {
+    id: ClientId,
+    broker: Broker,
+    sender: mpsc::UnboundedSender<Frame>,
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+}
+
+impl Subscriber {
+    pub fn new(id: ClientId, broker: Broker, sender: mpsc::UnboundedSender<Frame>) -> Self {
+        Self {
+            id,
+            broker,
+            sender,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+        }
+    }
+
+    /// Whether the connection is in subscriber mode
+    pub fn is_active(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty()
+    }
+
+    /// Number of subscribed channels and patterns
+    pub fn counts(&self) -> (usize, usize) {
+        (self.channels.len(), self.patterns.len())
+    }
+
+    pub fn subscribe(&mut self, channels: Vec<String>, respond: &mpsc::UnboundedSender<Frame>) {
+        for channel in &channels {
+            self.channels.insert(channel.clone());
+            self.confirm(respond, "subscribe", Some(channel));
+        }
+        self.broker
+            .send(BrokerCommand::Subscribe(
+                self.id,
+                channels,
+                self.sender.clone(),
+            ))
+            .unwrap();
+    }
+
+    pub fn unsubscribe(&mut self, channels: Vec<String>, respond: &mpsc::UnboundedSender<Frame>) {
+        let channels = if channels.is_empty() {
+            self.channels.iter().cloned().collect()
+        } else {
+            channels
+        };
+
+        self.broker
+            .send(BrokerCommand::Unsubscribe(self.id, channels.clone()))
+            .unwrap();
+        if channels.is_empty() {
+            self.confirm(respond, "unsubscribe", None);
+        }
+        for channel in &channels {
+            self.channels.remove(channel);
+            self.confirm(respond, "unsubscribe", Some(channel));
+        }
+    }
+
+    pub fn psubscribe(&mut self, patterns: Vec<String>, respond: &mpsc::UnboundedSender<Frame>) {
+        for pattern in &patterns {
+            self.patterns.insert(pattern.clone());
+            self.confirm(respond, "psubscribe", Some(pattern));
+        }
+        self.broker
+            .send(BrokerCommand::PSubscribe(
+                self.id,
+                patterns,
+                self.sender.clone(),
+            ))
+            .unwrap();
+    }
+
+    pub fn punsubscribe(&mut self, patterns: Vec<String>, respond: &mpsc::UnboundedSender<Frame>) {
+        let patterns = if patterns.is_empty() {
+            self.patterns.iter().cloned().collect()
+        } else {
+            patterns
+        };
+
+        self.broker
+            .send(BrokerCommand::PUnsubscribe(self.id, patterns.clone()))
+            .unwrap();
+        if patterns.is_empty() {
+            self.confirm(respond, "punsubscribe", None);
+        }
+        for pattern in &patterns {
+            self.patterns.remove(pattern);
+            self.confirm(respond, "punsubscribe", Some(pattern));
+        }
+    }
+
+    fn confirm(
+        &self,
+        respond: &mpsc::UnboundedSender<Frame>,
+        kind: &'static str,
+        name: Option<&String>,
+    ) {
+        let count = self.channels.len() + self.patterns.len();
+        let name = match name {
+            Some(name) => Frame::Bulk(Bytes::from(name.clone())),
+            None => Frame::Null,
+        };
+        let _ = respond.send(Frame::Push(vec![
+            Frame::Bulk(Bytes::from_static(kind.as_bytes())),
+            name,
+            Frame::Integer(count as i64),
+        ]));
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        if !self.channels.is_empty() {
+            let channels = std::mem::take(&mut self.channels).into_iter().collect();
+            let _ = self
+                .broker
+                .send(BrokerCommand::Unsubscribe(self.id, channels));
+        }
+        if !self.patterns.is_empty() {
+            let patterns = std::mem::take(&mut self.patterns).into_iter().collect();
+            let _ = self
+                .broker
+                .send(BrokerCommand::PUnsubscribe(self.id, patterns));
+        }
+    }
+}
+
+/// Classes of keyspace events published by the shards, as set by
+/// `notify-keyspace-events`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NotifyEvents(u16);
+
+impl NotifyEvents {
+    /// Published to `__keyspace@<db>__:<key>`, with the event as message
+    pub const KEYSPACE: NotifyEvents = NotifyEvents(1);
+    /// Published to `__keyevent@<db>__:<event>`, with the key as message
+    pub const KEYEVENT: NotifyEvents = NotifyEvents(1 << 1);
+    /// Commands of any type, such as `DEL` and `EXPIRE`
+    pub const GENERIC: NotifyEvents = NotifyEvents(1 << 2);
+    pub const STRING: NotifyEvents = NotifyEvents(1 << 3);
+    pub const LIST: NotifyEvents = NotifyEvents(1 << 4);
+    pub const SET: NotifyEvents = NotifyEvents(1 << 5);
+    pub const HASH: NotifyEvents = NotifyEvents(1 << 6);
+    pub const ZSET: NotifyEvents = NotifyEvents(1 << 7);
+    pub const EXPIRED: NotifyEvents = NotifyEvents(1 << 8);
+    pub const EVICTED: NotifyEvents = NotifyEvents(1 << 9);
+    pub const STREAM: NotifyEvents = NotifyEvents(1 << 10);
+
+    /// Flags by character, `A` standing for all the classes
+    const FLAGS: &'static [(char, NotifyEvents)] = &[
+        ('g', NotifyEvents::GENERIC),
+        ('$', NotifyEvents::STRING),
+        ('l', NotifyEvents::LIST),
+        ('s', NotifyEvents::SET),
+        ('h', NotifyEvents::HASH),
+        ('z', NotifyEvents::ZSET),
+        ('x', NotifyEvents::EXPIRED),
+        ('e', NotifyEvents::EVICTED),
+        ('t', NotifyEvents::STREAM),
+        ('K', NotifyEvents::KEYSPACE),
+        ('E', NotifyEvents::KEYEVENT),
+    ];
+    const ALL: NotifyEvents = NotifyEvents(0b111_1111_1100);
+
+    pub fn parse(value: &str) -> Result<NotifyEvents, String> {
+        let mut events = NotifyEvents::default();
+        for flag in value.chars() {
+            let class = match flag {
+                'A' => NotifyEvents::ALL,
+                flag => match NotifyEvents::FLAGS.iter().find(|(c, _)| *c == flag) {
+                    Some((_, class)) => *class,
+                    None => return Err(format!("invalid event class '{}'", flag)),
+                },
+            };
+            events.0 |= class.0;
+        }
+        Ok(events)
+    }
+
+    /// Whether events of the class are published to any channel
+    pub fn enabled(self, class: NotifyEvents) -> bool {
+        self.0 & (NotifyEvents::KEYSPACE.0 | NotifyEvents::KEYEVENT.0) != 0 && self.0 & class.0 != 0
+    }
+
+    pub fn contains(self, class: NotifyEvents) -> bool {
+        self.0 & class.0 == class.0
+    }
+}
+
+impl std::fmt::Display for NotifyEvents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let all = self.contains(NotifyEvents::ALL);
+        if all {
+            write!(f, "A")?;
+        }
+        for (flag, class) in NotifyEvents::FLAGS {
+            let in_all = NotifyEvents::ALL.contains(*class);
+            if self.contains(*class) && !(all && in_all) {
+                write!(f, "{}", flag)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bulk(value: &str) -> Frame {
+        Frame::Bulk(Bytes::from(value.to_string()))
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    /// A reply of the broker
+    async fn call(
+        broker: &Broker,
+        cmd: impl FnOnce(mpsc::UnboundedSender<Frame>) -> BrokerCommand,
+    ) -> Frame {
+        let (respond, mut response) = mpsc::unbounded_channel();
+        broker.send(cmd(respond)).unwrap();
+        response.recv().await.unwrap()
+    }
+
+    async fn publish(broker: &Broker, channel: &str, message: &str) -> Frame {
+        let cmd = Publish {
+            channel: channel.to_string(),
+            message: Bytes::from(message.to_string()),
+        };
+        call(broker, |respond| BrokerCommand::Publish(cmd, respond)).await
+    }
+
+    #[tokio::test]
+    async fn broker_test() {
+        let (broker, mut broker_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move { process_broker(&mut broker_rx).await });
+        let (respond, mut replies) = mpsc::unbounded_channel();
+        let (sender, mut pushed) = mpsc::unbounded_channel();
+        let mut channels = Subscriber::new(1, broker.clone(), sender);
+        let (sender, mut pattern_pushed) = mpsc::unbounded_channel();
+        let mut patterns = Subscriber::new(2, broker.clone(), sender);
+        let confirm = |kind: &str, name: Frame, count| {
+            Frame::Push(vec![bulk(kind), name, Frame::Integer(count)])
+        };
+
+        channels.subscribe(strings(&["news", "sport"]), &respond);
+        assert_eq!(
+            replies.recv().await.unwrap(),
+            confirm("subscribe", bulk("news"), 1)
+        );
+        assert_eq!(
+            replies.recv().await.unwrap(),
+            confirm("subscribe", bulk("sport"), 2)
+        );
+        patterns.psubscribe(strings(&["n?w*"]), &respond);
+        assert_eq!(
+            replies.recv().await.unwrap(),
+            confirm("psubscribe", bulk("n?w*"), 1)
+        );
+        assert!(channels.is_active());
+
+        // Delivered to the channel and to the matching patterns
+        assert_eq!(publish(&broker, "news", "hello").await, Frame::Integer(2));
+        assert_eq!(
+            pushed.recv().await.unwrap(),
+            Frame::Push(vec![bulk("message"), bulk("news"), bulk("hello")])
+        );
+        assert_eq!(
+            pattern_pushed.recv().await.unwrap(),
+            Frame::Push(vec![
+                bulk("pmessage"),
+                bulk("n?w*"),
+                bulk("news"),
+                bulk("hello")
+            ])
+        );
+        assert_eq!(publish(&broker, "newsletter", "a").await, Frame::Integer(1));
+        assert_eq!(publish(&broker, "weather", "b").await, Frame::Integer(0));
+        assert!(pushed.try_recv().is_err());
+
+        let numsub = PubSub::NumSub(strings(&["news", "weather"]));
+        assert_eq!(
+            call(&broker, |respond| BrokerCommand::PubSub(numsub, respond)).await,
+            Frame::Array(vec![
+                bulk("news"),
+                Frame::Integer(1),
+                bulk("weather"),
+                Frame::Integer(0)
+            ])
+        );
+        let Frame::Array(mut names) = call(&broker, |respond| {
+            BrokerCommand::PubSub(PubSub::Channels(None), respond)
+        })
+        .await
+        else {
+            panic!("unexpected reply");
+        };
+        names.sort_by_key(|name| format!("{:?}", name));
+        assert_eq!(names, [bulk("news"), bulk("sport")]);
+        let matching = PubSub::Channels(Some("s*".to_string()));
+        assert_eq!(
+            call(&broker, |respond| BrokerCommand::PubSub(matching, respond)).await,
+            Frame::Array(vec![bulk("sport")])
+        );
+        assert_eq!(
+            call(&broker, |respond| BrokerCommand::PubSub(
+                PubSub::NumPat,
+                respond
+            ))
+            .await,
+            Frame::Integer(1)
+        );
+
+        // The counts are of the remaining subscriptions
+        channels.unsubscribe(strings(&["news"]), &respond);
+        assert_eq!(
+            replies.recv().await.unwrap(),
+            confirm("unsubscribe", bulk("news"), 1)
+        );
+        assert_eq!(publish(&broker, "news", "c").await, Frame::Integer(1));
+        channels.unsubscribe(vec![], &respond);
+        assert_eq!(
+            replies.recv().await.unwrap(),
+            confirm("unsubscribe", bulk("sport"), 0)
+        );
+        assert!(!channels.is_active());
+        channels.unsubscribe(vec![], &respond);
+        assert_eq!(
+            replies.recv().await.unwrap(),
+            confirm("unsubscribe", Frame::Null, 0)
+        );
+
+        // Unsubscribed when the connection is closed
+        drop(patterns);
+        assert_eq!(publish(&broker, "news", "d").await, Frame::Integer(0));
+        assert_eq!(
+            call(&broker, |respond| BrokerCommand::PubSub(
+                PubSub::NumPat,
+                respond
+            ))
+            .await,
+            Frame::Integer(0)
+        );
+    }
+
+    #[test]
+    fn notify_events_test() {
+        let events = NotifyEvents::parse("Ex").unwrap();
+        assert!(events.enabled(NotifyEvents::EXPIRED));
+        assert!(!events.enabled(NotifyEvents::GENERIC));
+        assert_eq!(events.to_string(), "xE");
+
+        // Classes without a channel publish nothing
+        assert!(!NotifyEvents::parse("g")
+            .unwrap()
+            .enabled(NotifyEvents::GENERIC));
+
+        let events = NotifyEvents::parse("KEA").unwrap();
+        assert!(events.enabled(NotifyEvents::STREAM));
+        assert_eq!(events.to_string(), "AKE");
+        assert_eq!(NotifyEvents::parse("").unwrap().to_string(), "");
+        assert!(NotifyEvents::parse("Kq").is_err());
+    }
+}
@@ -0,0 +1,968 @@
+//! Redis RDB file format
+//!
+//! * https://rdb.fnordig.de/file_format.html
+//! * https://github.com/redis/redis/blob/unstable/src/rdb.h
+use crate::command_parser::parse_float;
+use crate::db::{now_ms, Entry, Value};
+use crate::error::Error;
+use crate::stream::{Consumer, ConsumerGroup, Fields, PendingEntry, Stream, StreamId};
+use crate::zset::SortedSet;
+use bytes::Bytes;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+const RDB_VERSION: &[u8] = b"0009";
+
+const RDB_OPCODE_IDLE: u8 = 0xF8;
+const RDB_OPCODE_FREQ: u8 = 0xF9;
+const RDB_OPCODE_AUX: u8 = 0xFA;
+const RDB_OPCODE_RESIZEDB: u8 = 0xFB;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const RDB_OPCODE_EXPIRETIME: u8 = 0xFD;
+const RDB_OPCODE_SELECTDB: u8 = 0xFE;
+const RDB_OPCODE_EOF: u8 = 0xFF;
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+/// Sorted set with scores as strings, only read
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+/// Sorted set with binary scores
+const RDB_TYPE_ZSET_2: u8 = 5;
+/// Stream as listpacks of entries, with its consumer groups
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+/// Stream with its first id, maximal deleted id and number of added entries,
+/// and the number of entries read by its groups, only read
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+/// Stream with the active time of its consumers, only read
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+/// Entries of a stream listpack, as the default `stream-node-max-entries`
+const STREAM_NODE_ENTRIES: usize = 100;
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+/// The entry has the fields of the first entry of its listpack, which are
+/// not repeated
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+/// Serialize the entries of every database, by index, as a RDB file
+pub fn encode(dbs: &[Vec<(String, Entry)>]) -> Vec<u8> {
+    let len: usize = dbs.iter().map(|entries| entries.len()).sum();
+    let mut out = Vec::with_capacity(1024 + len * 32);
+    out.extend_from_slice(b"REDIS");
+    out.extend_from_slice(RDB_VERSION);
+
+    write_aux(&mut out, "redis-ver", "7.2.4");
+    write_aux(&mut out, "redis-bits", "64");
+    write_aux(&mut out, "ctime", &(now_ms() / 1000).to_string());
+
+    for (index, entries) in dbs.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+        out.push(RDB_OPCODE_SELECTDB);
+        write_length(&mut out, index as u64);
+        out.push(RDB_OPCODE_RESIZEDB);
+        write_length(&mut out, entries.len() as u64);
+        write_length(
+            &mut out,
+            entries
+                .iter()
+                .filter(|(_, e)| e.expire_at.is_some())
+                .count() as u64,
+        );
+
+        for (key, entry) in entries {
+            if let Some(at) = entry.expire_at {
+                out.push(RDB_OPCODE_EXPIRETIME_MS);
+                out.extend_from_slice(&at.to_le_bytes());
+            }
+            write_value(&mut out, key, &entry.value);
+        }
+    }
+
+    out.push(RDB_OPCODE_EOF);
+    let checksum = crc64(0, &out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// Load the entries of every database of a RDB file, by index, expired keys
+/// are skipped. The file may have been corrupted: its lengths are checked
+/// against the remaining bytes and its indexes against `databases`.
+pub fn decode(data: &[u8], databases: usize) -> Result<Vec<Vec<(String, Entry)>>, Error> {
+    let mut reader = Reader { data, pos: 0 };
+
+    if reader.take(5)? != b"REDIS" {
+        return Err("invalid RDB file, wrong signature".into());
+    }
+    let version = std::str::from_utf8(reader.take(4)?)
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .ok_or("invalid RDB file, wrong version")?;
+    if version == 0 || version > 11 {
+        return Err(format!("unsupported RDB version {}", version).into());
+    }
+
+    let now = now_ms();
+    let mut dbs = vec![vec![]];
+    let mut selected = 0;
+    let mut expire_at = None;
+
+    loop {
+        match reader.u8()? {
+            RDB_OPCODE_EOF => break,
+            RDB_OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            RDB_OPCODE_SELECTDB => {
+                selected = reader.length()? as usize;
+                if selected >= databases {
+                    return Err(format!(
+                        "invalid RDB file, database {} out of the {} databases",
+                        selected, databases
+                    )
+                    .into());
+                }
+                if selected >= dbs.len() {
+                    dbs.resize_with(selected + 1, Vec::new);
+                }
+            }
+            RDB_OPCODE_RESIZEDB => {
+                reader.length()?;
+                reader.length()?;
+            }
+            RDB_OPCODE_EXPIRETIME_MS => {
+                let bytes = reader.take(8)?.try_into().unwrap();
+                expire_at = Some(u64::from_le_bytes(bytes));
+            }
+            RDB_OPCODE_EXPIRETIME => {
+                let bytes = reader.take(4)?.try_into().unwrap();
+                expire_at = Some(u32::from_le_bytes(bytes) as u64 * 1000);
+            }
+            RDB_OPCODE_IDLE => {
+                reader.length()?;
+            }
+            RDB_OPCODE_FREQ => {
+                reader.u8()?;
+            }
+            t @ (RDB_TYPE_STRING
+            | RDB_TYPE_LIST
+            | RDB_TYPE_SET
+            | RDB_TYPE_ZSET
+            | RDB_TYPE_HASH
+            | RDB_TYPE_ZSET_2
+            | RDB_TYPE_STREAM_LISTPACKS
+            | RDB_TYPE_STREAM_LISTPACKS_2
+            | RDB_TYPE_STREAM_LISTPACKS_3) => {
+                let key = String::from_utf8(reader.string()?)
+                    .map_err(|_| "invalid RDB file, non UTF-8 key")?;
+                let value = reader.value(t)?;
+                match expire_at.take() {
+                    Some(at) if at <= now => {}
+                    expire_at => dbs[selected].push((key, Entry { value, expire_at })),
+                }
+            }
+            t => return Err(format!("unsupported RDB value type {}", t).into()),
+        }
+    }
+
+    if version >= 5 && reader.data.len() >= reader.pos + 8 {
+        let expected = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        if expected != 0 && expected != crc64(0, &data[..reader.pos - 8]) {
+            return Err("invalid RDB file, wrong checksum".into());
+        }
+    }
+
+    Ok(dbs)
+}
+
+fn write_value(out: &mut Vec<u8>, key: &str, value: &Value) {
+    match value {
+        Value::String(value) => {
+            out.push(RDB_TYPE_STRING);
+            write_string(out, key.as_bytes());
+            write_string(out, value);
+        }
+        Value::List(list) => {
+            out.push(RDB_TYPE_LIST);
+            write_string(out, key.as_bytes());
+            write_length(out, list.len() as u64);
+            for element in list {
+                write_string(out, element);
+            }
+        }
+        Value::Hash(hash) => {
+            out.push(RDB_TYPE_HASH);
+            write_string(out, key.as_bytes());
+            write_length(out, hash.len() as u64);
+            for (field, value) in hash {
+                write_string(out, field);
+                write_string(out, value);
+            }
+        }
+        Value::Set(set) => {
+            out.push(RDB_TYPE_SET);
+            write_string(out, key.as_bytes());
+            write_length(out, set.len() as u64);
+            for member in set {
+                write_string(out, member);
+            }
+        }
+        Value::ZSet(zset) => {
+            out.push(RDB_TYPE_ZSET_2);
+            write_string(out, key.as_bytes());
+            write_length(out, zset.len() as u64);
+            for (member, score) in zset.iter() {
+                write_string(out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        Value::Stream(stream) => {
+            out.push(RDB_TYPE_STREAM_LISTPACKS);
+            write_string(out, key.as_bytes());
+            write_stream(out, stream);
+        }
+    }
+}
+
+fn write_stream(out: &mut Vec<u8>, stream: &Stream) {
+    let entries: Vec<_> = stream.entries().collect();
+    let nodes = entries.chunks(STREAM_NODE_ENTRIES);
+    write_length(out, nodes.len() as u64);
+    for node in nodes {
+        let master = *node[0].0;
+        write_string(out, &stream_id_bytes(master));
+        write_string(out, &stream_node(master, node));
+    }
+    write_length(out, stream.len() as u64);
+    write_length(out, stream.last_id().ms);
+    write_length(out, stream.last_id().seq);
+
+    write_length(out, stream.groups().count() as u64);
+    for (name, group) in stream.groups() {
+        write_string(out, name.as_bytes());
+        write_length(out, group.last_id.ms);
+        write_length(out, group.last_id.seq);
+        write_length(out, group.pending.len() as u64);
+        for (id, entry) in &group.pending {
+            out.extend_from_slice(&stream_id_bytes(*id));
+            out.extend_from_slice(&entry.delivered_at.to_le_bytes());
+            write_length(out, entry.deliveries);
+        }
+        write_length(out, group.consumers.len() as u64);
+        for (name, consumer) in &group.consumers {
+            write_string(out, name.as_bytes());
+            out.extend_from_slice(&consumer.seen_at.to_le_bytes());
+            write_length(out, consumer.pending.len() as u64);
+            for id in &consumer.pending {
+                out.extend_from_slice(&stream_id_bytes(*id));
+            }
+        }
+    }
+}
+
+/// Listpack of stream entries: a master entry with the fields of the first
+/// entry, then the entries with their id relative to the first one
+fn stream_node(master: StreamId, entries: &[(&StreamId, &Fields)]) -> Vec<u8> {
+    let master_fields = entries[0].1;
+    let mut listpack = Listpack::default();
+    listpack.int(entries.len() as i64);
+    listpack.int(0);
+    listpack.int(master_fields.len() as i64);
+    for (field, _) in master_fields {
+        listpack.string(field);
+    }
+    listpack.int(0);
+
+    for (id, fields) in entries {
+        let same = fields.len() == master_fields.len()
+            && fields
+                .iter()
+                .zip(master_fields)
+                .all(|((field, _), (master, _))| field == master);
+        listpack.int(if same { STREAM_ITEM_FLAG_SAMEFIELDS } else { 0 });
+        listpack.int(id.ms.wrapping_sub(master.ms) as i64);
+        listpack.int(id.seq.wrapping_sub(master.seq) as i64);
+        if !same {
+            listpack.int(fields.len() as i64);
+        }
+        for (field, value) in fields.iter() {
+            if !same {
+                listpack.string(field);
+            }
+            listpack.string(value);
+        }
+        let count = match same {
+            true => fields.len() + 3,
+            false => 2 * fields.len() + 4,
+        };
+        listpack.int(count as i64);
+    }
+    listpack.finish()
+}
+
+/// Big endian, so that ids compare as bytes
+fn stream_id_bytes(id: StreamId) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&id.ms.to_be_bytes());
+    bytes[8..].copy_from_slice(&id.seq.to_be_bytes());
+    bytes
+}
+
+fn parse_stream_id(bytes: &[u8]) -> Result<StreamId, Error> {
+    if bytes.len() != 16 {
+        return Err("invalid RDB file, invalid stream id".into());
+    }
+    Ok(StreamId::new(
+        u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+        u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+    ))
+}
+
+/// A list of strings and integers serialized with the length of each element
+/// after it, so that it can be walked from both ends.
+///
+/// * https://github.com/antirez/listpack/blob/master/listpack.md
+#[derive(Default)]
+struct Listpack {
+    elements: Vec<u8>,
+    len: usize,
+}
+
+impl Listpack {
+    fn int(&mut self, value: i64) {
+        let start = self.elements.len();
+        match value {
+            0..=127 => self.elements.push(value as u8),
+            -4096..=4095 => {
+                let value = value as u16 & 0x1FFF;
+                self.elements.push(0xC0 | (value >> 8) as u8);
+                self.elements.push(value as u8);
+            }
+            -32768..=32767 => {
+                self.elements.push(0xF1);
+                self.elements
+                    .extend_from_slice(&(value as i16).to_le_bytes());
+            }
+            -8388608..=8388607 => {
+                self.elements.push(0xF2);
+                self.elements
+                    .extend_from_slice(&(value as i32).to_le_bytes()[..3]);
+            }
+            -2147483648..=2147483647 => {
+                self.elements.push(0xF3);
+                self.elements
+                    .extend_from_slice(&(value as i32).to_le_bytes());
+            }
+            _ => {
+                self.elements.push(0xF4);
+                self.elements.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        self.end_element(start);
+    }
+
+    fn string(&mut self, value: &[u8]) {
+        let start = self.elements.len();
+        let len = value.len();
+        if len < 64 {
+            self.elements.push(0x80 | len as u8);
+        } else if len < 4096 {
+            self.elements.push(0xE0 | (len >> 8) as u8);
+            self.elements.push(len as u8);
+        } else {
+            self.elements.push(0xF0);
+            self.elements.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        self.elements.extend_from_slice(value);
+        self.end_element(start);
+    }
+
+    /// Write the length of the element, from its most significant 7 bits,
+    /// the other bytes flagged by their highest bit
+    fn end_element(&mut self, start: usize) {
+        let len = self.elements.len() - start;
+        let bytes = backlen_size(len);
+        for i in (0..bytes).rev() {
+            let group = ((len >> (7 * i)) & 127) as u8;
+            self.elements
+                .push(if i == bytes - 1 { group } else { group | 128 });
+        }
+        self.len += 1;
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let total = 4 + 2 + self.elements.len() + 1;
+        let mut out = Vec::with_capacity(total);
+        out.extend_from_slice(&(total as u32).to_le_bytes());
+        out.extend_from_slice(&(self.len.min(u16::MAX as usize) as u16).to_le_bytes());
+        out.extend_from_slice(&self.elements);
+        out.push(0xFF);
+        out
+    }
+}
+
+/// Bytes taken by the length of an element of `len` bytes
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Elements of a listpack, integers as their decimal digits
+fn parse_listpack(data: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = Reader { data, pos: 6 };
+    let mut elements = vec![];
+    loop {
+        let start = reader.pos;
+        let first = reader.u8()?;
+        let element = match first {
+            0xFF => break,
+            0x00..=0x7F => (first as i64).to_string().into_bytes(),
+            0x80..=0xBF => reader.take((first & 0x3F) as usize)?.to_vec(),
+            0xC0..=0xDF => {
+                let value = (((first & 0x1F) as i64) << 8) | reader.u8()? as i64;
+                let value = if value >= 1 << 12 {
+                    value - (1 << 13)
+                } else {
+                    value
+                };
+                value.to_string().into_bytes()
+            }
+            0xE0..=0xEF => {
+                let len = (((first & 0x0F) as usize) << 8) | reader.u8()? as usize;
+                reader.take(len)?.to_vec()
+            }
+            0xF0 => {
+                let len = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+                reader.take(len as usize)?.to_vec()
+            }
+            0xF1..=0xF4 => {
+                let size = match first {
+                    0xF1 => 2,
+                    0xF2 => 3,
+                    0xF3 => 4,
+                    _ => 8,
+                };
+                let bytes = reader.take(size)?;
+                // Sign extended from the most significant byte
+                let mut value = bytes[size - 1] as i8 as i64;
+                for byte in bytes[..size - 1].iter().rev() {
+                    value = (value << 8) | *byte as i64;
+                }
+                value.to_string().into_bytes()
+            }
+            _ => return Err("invalid RDB file, invalid listpack encoding".into()),
+        };
+        reader.take(backlen_size(reader.pos - start))?;
+        elements.push(element);
+    }
+    Ok(elements)
+}
+
+/// Add the entries of a listpack written by `stream_node`, deleted entries
+/// are skipped
+fn read_stream_node(stream: &mut Stream, master: StreamId, data: &[u8]) -> Result<(), Error> {
+    let elements = parse_listpack(data)?;
+    let mut elements = elements.into_iter();
+    let mut next = || {
+        elements
+            .next()
+            .ok_or("invalid RDB file, truncated stream listpack")
+    };
+    let int = |element: Vec<u8>| {
+        atoi::atoi::<i64>(&element).ok_or("invalid RDB file, invalid stream listpack integer")
+    };
+
+    let count = int(next()?)?;
+    let deleted = int(next()?)?;
+    let master_fields = (0..int(next()?)?)
+        .map(|_| next().map(Bytes::from))
+        .collect::<Result<Vec<_>, _>>()?;
+    next()?;
+
+    for _ in 0..count + deleted {
+        let flags = int(next()?)?;
+        let ms = master.ms.wrapping_add(int(next()?)? as u64);
+        let seq = master.seq.wrapping_add(int(next()?)? as u64);
+        let mut fields = vec![];
+        if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            for field in &master_fields {
+                fields.push((field.clone(), Bytes::from(next()?)));
+            }
+        } else {
+            for _ in 0..int(next()?)? {
+                fields.push((Bytes::from(next()?), Bytes::from(next()?)));
+            }
+        }
+        next()?;
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            stream.add(StreamId::new(ms, seq), fields);
+        }
+    }
+    Ok(())
+}
+
+fn write_aux(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.push(RDB_OPCODE_AUX);
+    write_string(out, key.as_bytes());
+    write_string(out, value.as_bytes());
+}
+
+fn write_length(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.push(0x40 | (len >> 8) as u8);
+        out.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, value: &[u8]) {
+    write_length(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+enum Length {
+    Plain(u64),
+    Encoded(u8),
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let end = match self.pos.checked_add(n) {
+            Some(end) if end <= self.data.len() => end,
+            _ => return Err("invalid RDB file, unexpected end of file".into()),
+        };
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    /// Capacity for `len` elements read from the file, each taking at least
+    /// a byte, so that a corrupted length can not exhaust the memory
+    fn capacity(&self, len: u64) -> usize {
+        len.min((self.data.len() - self.pos) as u64) as usize
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn raw_length(&mut self) -> Result<Length, Error> {
+        let first = self.u8()?;
+        Ok(match first >> 6 {
+            0 => Length::Plain((first & 0x3F) as u64),
+            1 => Length::Plain((((first & 0x3F) as u64) << 8) | self.u8()? as u64),
+            2 if first == 0x80 => {
+                Length::Plain(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64)
+            }
+            2 if first == 0x81 => {
+                Length::Plain(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+            }
+            3 => Length::Encoded(first & 0x3F),
+            _ => return Err("invalid RDB file, unknown length encoding".into()),
+        })
+    }
+
+    fn length(&mut self) -> Result<u64, Error> {
+        match self.raw_length()? {
+            Length::Plain(len) => Ok(len),
+            Length::Encoded(_) => Err("invalid RDB file, unexpected encoded length".into()),
+        }
+    }
+
+    fn value(&mut self, t: u8) -> Result<Value, Error> {
+        Ok(match t {
+            RDB_TYPE_STRING => Value::String(self.string()?),
+            RDB_TYPE_LIST => {
+                let len = self.length()?;
+                let mut list = VecDeque::with_capacity(self.capacity(len));
+                for _ in 0..len {
+                    list.push_back(Bytes::from(self.string()?));
+                }
+                Value::List(list)
+            }
+            RDB_TYPE_HASH => {
+                let len = self.length()?;
+                let mut hash = HashMap::with_capacity(self.capacity(len));
+                for _ in 0..len {
+                    hash.insert(Bytes::from(self.string()?), Bytes::from(self.string()?));
+                }
+                Value::Hash(hash)
+            }
+            RDB_TYPE_SET => {
+                let len = self.length()?;
+                let mut set = HashSet::with_capacity(self.capacity(len));
+                for _ in 0..len {
+                    set.insert(Bytes::from(self.string()?));
+                }
+                Value::Set(set)
+            }
+            RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+                let len = self.length()?;
+                let mut zset = SortedSet::new();
+                for _ in 0..len {
+                    let member = Bytes::from(self.string()?);
+                    let score = if t == RDB_TYPE_ZSET_2 {
+                        f64::from_le_bytes(self.take(8)?.try_into().unwrap())
+                    } else {
+                        let len = self.u8()? as usize;
+                        parse_float(self.take(len)?)
+                            .ok_or("invalid RDB file, invalid sorted set score")?
+                    };
+                    zset.insert(member, score);
+                }
+                Value::ZSet(zset)
+            }
+            RDB_TYPE_STREAM_LISTPACKS
+            | RDB_TYPE_STREAM_LISTPACKS_2
+            | RDB_TYPE_STREAM_LISTPACKS_3 => Value::Stream(self.stream(t)?),
+            t => return Err(format!("unsupported RDB value type {}", t).into()),
+        })
+    }
+
+    fn stream(&mut self, t: u8) -> Result<Stream, Error> {
+        let mut stream = Stream::new();
+        for _ in 0..self.length()? {
+            let master = parse_stream_id(&self.string()?)?;
+            read_stream_node(&mut stream, master, &self.string()?)?;
+        }
+        self.length()?;
+        let last_id = StreamId::new(self.length()?, self.length()?);
+        stream.set_last_id(last_id);
+        if t != RDB_TYPE_STREAM_LISTPACKS {
+            // First id, maximal deleted id and number of added entries
+            for _ in 0..5 {
+                self.length()?;
+            }
+        }
+
+        for _ in 0..self.length()? {
+            let name = String::from_utf8(self.string()?)
+                .map_err(|_| "invalid RDB file, non UTF-8 consumer group")?;
+            let mut group = ConsumerGroup::new(StreamId::new(self.length()?, self.length()?));
+            if t != RDB_TYPE_STREAM_LISTPACKS {
+                // Entries read
+                self.length()?;
+            }
+            for _ in 0..self.length()? {
+                let id = parse_stream_id(self.take(16)?)?;
+                let delivered_at = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+                let entry = PendingEntry {
+                    consumer: String::new(),
+                    delivered_at,
+                    deliveries: self.length()?,
+                };
+                group.pending.insert(id, entry);
+            }
+            for _ in 0..self.length()? {
+                let name = String::from_utf8(self.string()?)
+                    .map_err(|_| "invalid RDB file, non UTF-8 consumer")?;
+                let seen_at = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+                if t == RDB_TYPE_STREAM_LISTPACKS_3 {
+                    // Active time
+                    self.take(8)?;
+                }
+                let mut pending = BTreeSet::new();
+                for _ in 0..self.length()? {
+                    let id = parse_stream_id(self.take(16)?)?;
+                    let entry = group
+                        .pending
+                        .get_mut(&id)
+                        .ok_or("invalid RDB file, consumer entry not pending in its group")?;
+                    entry.consumer.clone_from(&name);
+                    pending.insert(id);
+                }
+                group.consumers.insert(name, Consumer { seen_at, pending });
+            }
+            stream.create_group(name, group);
+        }
+        Ok(stream)
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, Error> {
+        match self.raw_length()? {
+            Length::Plain(len) => Ok(self.take(len as usize)?.to_vec()),
+            Length::Encoded(RDB_ENC_INT8) => Ok((self.u8()? as i8).to_string().into_bytes()),
+            Length::Encoded(RDB_ENC_INT16) => {
+                let value = i16::from_le_bytes(self.take(2)?.try_into().unwrap());
+                Ok(value.to_string().into_bytes())
+            }
+            Length::Encoded(RDB_ENC_INT32) => {
+                let value = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+                Ok(value.to_string().into_bytes())
+            }
+            Length::Encoded(RDB_ENC_LZF) => {
+                let compressed_len = self.length()? as usize;
+                let len = self.length()? as usize;
+                lzf_decompress(self.take(compressed_len)?, len)
+            }
+            Length::Encoded(e) => Err(format!("invalid RDB file, unknown encoding {}", e).into()),
+        }
+    }
+}
+
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    const MSG: &str = "invalid RDB file, corrupted LZF string";
+
+    // A back reference of 3 bytes expands to at most 264 bytes
+    let mut out = Vec::with_capacity(len.min(input.len().saturating_mul(88)));
+    let mut ip = 0;
+
+    while ip < input.len() {
+        let ctrl = input[ip] as usize;
+        ip += 1;
+
+        if ctrl < 32 {
+            let run = ctrl + 1;
+            if ip + run > input.len() || out.len() + run > len {
+                return Err(MSG.into());
+            }
+            out.extend_from_slice(&input[ip..ip + run]);
+            ip += run;
+        } else {
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(ip).ok_or(MSG)? as usize;
+                ip += 1;
+            }
+            let offset = ((ctrl & 0x1F) << 8) + *input.get(ip).ok_or(MSG)? as usize + 1;
+            ip += 1;
+            if offset > out.len() {
+                return Err(MSG.into());
+            }
+            if out.len() + run + 2 > len {
+                return Err(MSG.into());
+            }
+            let start = out.len() - offset;
+            for i in 0..run + 2 {
+                out.push(out[start + i]);
+            }
+        }
+    }
+
+    if out.len() != len {
+        return Err(MSG.into());
+    }
+    Ok(out)
+}
+
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+/// Reflected polynomial of the CRC-64-Jones used by redis
+const fn crc64_table() -> [u64; 256] {
+    const POLY: u64 = 0x95AC9329AC4BC9B5;
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    for byte in data {
+        crc = CRC64_TABLE[((crc ^ *byte as u64) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc64_test() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn roundtrip_test() {
+        let far = now_ms() + 60_000;
+        let entries = vec![
+            (
+                "plain".to_string(),
+                Entry {
+                    value: Value::String(b"value".to_vec()),
+                    expire_at: None,
+                },
+            ),
+            (
+                "long".to_string(),
+                Entry {
+                    value: Value::String(vec![b'x'; 20_000]),
+                    expire_at: Some(far),
+                },
+            ),
+            (
+                "list".to_string(),
+                Entry {
+                    value: Value::List(VecDeque::from([Bytes::from("a"), Bytes::from("b")])),
+                    expire_at: None,
+                },
+            ),
+            (
+                "hash".to_string(),
+                Entry {
+                    value: Value::Hash(HashMap::from([(Bytes::from("f"), Bytes::from("v"))])),
+                    expire_at: None,
+                },
+            ),
+            (
+                "set".to_string(),
+                Entry {
+                    value: Value::Set(HashSet::from([Bytes::from("a"), Bytes::from("b")])),
+                    expire_at: None,
+                },
+            ),
+            (
+                "zset".to_string(),
+                Entry {
+                    value: Value::ZSet({
+                        let mut zset = SortedSet::new();
+                        zset.insert(Bytes::from("a"), 1.5);
+                        zset.insert(Bytes::from("b"), f64::NEG_INFINITY);
+                        zset
+                    }),
+                    expire_at: None,
+                },
+            ),
+            (
+                "stream".to_string(),
+                Entry {
+                    value: Value::Stream({
+                        // Spans several nodes, with fields differing from
+                        // the master entry and integer values
+                        let mut stream = Stream::new();
+                        for i in 0..250u64 {
+                            let mut fields = vec![(Bytes::from("n"), Bytes::from(i.to_string()))];
+                            if i % 7 == 0 {
+                                fields.push((Bytes::from("extra"), Bytes::from("-12")));
+                            }
+                            stream.add(StreamId::new(1_700_000_000_000 + i / 3, i), fields);
+                        }
+                        stream.remove(&StreamId::new(1_700_000_000_000, 1));
+                        let mut group = ConsumerGroup::new(StreamId::new(1_700_000_000_001, 4));
+                        group.create_consumer("alice", 42);
+                        group.create_consumer("bob", 43);
+                        group.deliver(StreamId::new(1_700_000_000_000, 2), "alice", 44, 3);
+                        stream.create_group("workers".to_string(), group);
+                        stream.set_last_id(StreamId::new(1_800_000_000_000, 0));
+                        stream
+                    }),
+                    expire_at: None,
+                },
+            ),
+            (
+                "expired".to_string(),
+                Entry {
+                    value: Value::String(vec![]),
+                    expire_at: Some(1),
+                },
+            ),
+        ];
+
+        let other = vec![entries[0].clone()];
+        let dbs = decode(&encode(&[entries.clone(), vec![], other]), 16).unwrap();
+        assert_eq!(dbs.len(), 3);
+        assert!(dbs[1].is_empty());
+        assert_eq!(dbs[2][0].0, "plain");
+
+        let decoded = &dbs[0];
+        assert_eq!(decoded.len(), 7);
+        assert_eq!(decoded[0].0, "plain");
+        assert_eq!(decoded[0].1.value, Value::String(b"value".to_vec()));
+        assert_eq!(decoded[1].0, "long");
+        assert_eq!(decoded[1].1.value, Value::String(vec![b'x'; 20_000]));
+        assert_eq!(decoded[1].1.expire_at, Some(far));
+        assert_eq!(decoded[2].1.value, entries[2].1.value);
+        assert_eq!(decoded[3].1.value, entries[3].1.value);
+        assert_eq!(decoded[4].1.value, entries[4].1.value);
+        assert_eq!(decoded[5].1.value, entries[5].1.value);
+        assert_eq!(decoded[6].1.value, entries[6].1.value);
+    }
+
+    #[test]
+    fn decode_redis_encodings_test() {
+        // Integer and LZF encoded strings as written by redis
+        let mut data = b"REDIS0011".to_vec();
+        data.extend_from_slice(&[RDB_OPCODE_SELECTDB, 0, RDB_TYPE_STRING]);
+        data.extend_from_slice(&[3, b'i', b'n', b't', 0xC1, 0xD2, 0x04]);
+        data.extend_from_slice(&[RDB_TYPE_STRING, 3, b'l', b'z', b'f']);
+        data.extend_from_slice(&[0xC3, 5, 40, 0x00, b'a', 0xE0, 0x1E, 0x00]);
+        data.push(RDB_OPCODE_EOF);
+        data.extend_from_slice(&[0; 8]);
+
+        let decoded = &decode(&data, 16).unwrap()[0];
+        assert_eq!(decoded[0].1.value, Value::String(b"1234".to_vec()));
+        assert_eq!(decoded[1].1.value, Value::String(vec![b'a'; 40]));
+    }
+
+    #[test]
+    fn decode_corrupted_test() {
+        let entries = vec![(
+            "list".to_string(),
+            Entry {
+                value: Value::List(VecDeque::from([Bytes::from("a"), Bytes::from("b")])),
+                expire_at: None,
+            },
+        )];
+        let data = encode(&[vec![], entries]);
+        assert_eq!(decode(&data, 2).unwrap()[1].len(), 1);
+        assert!(decode(&data, 1).is_err());
+        for len in 0..data.len() - 8 {
+            assert!(decode(&data[..len], 16).is_err());
+        }
+
+        // Lengths larger than the file
+        let header = b"REDIS0011".to_vec();
+        let mut data = header.clone();
+        data.extend_from_slice(&[RDB_OPCODE_SELECTDB, 0x81]);
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(decode(&data, 16).is_err());
+        for t in [RDB_TYPE_LIST, RDB_TYPE_SET, RDB_TYPE_HASH, RDB_TYPE_STRING] {
+            let mut data = header.clone();
+            data.extend_from_slice(&[t, 1, b'k', 0x81]);
+            data.extend_from_slice(&u64::MAX.to_be_bytes());
+            assert!(decode(&data, 16).is_err());
+        }
+        let mut data = header.clone();
+        data.extend_from_slice(&[RDB_TYPE_STRING, 1, b'k', 0xC3, 2, 0x81]);
+        data.extend_from_slice(&(u32::MAX as u64).to_be_bytes());
+        data.extend_from_slice(&[0x00, b'a']);
+        assert!(decode(&data, 16).is_err());
+    }
+}
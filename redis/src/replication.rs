@@ -0,0 +1,166 @@
+use crate::db::random;
+use crate::frame::*;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use tokio::sync::mpsc;
+
+/// Way a replica starts receiving the stream, see `ReplicationLog::attach`
+pub enum Resync {
+    /// Resume from the backlog, with the replication id and the offset of
+    /// the first byte the replica is missing
+    Partial(String, i64),
+    /// Send a snapshot taken at the current offset, as an RDB file
+    Full(Bytes),
+}
+
+/// Stream of the writes sent to the replicas, with a backlog of its last
+/// writes for a disconnected replica to resume from.
+///
+/// Offsets count the bytes of the stream, as written in RESP2. The stream
+/// is only recorded once a first replica attached.
+pub struct ReplicationLog {
+    id: String,
+    /// Offset of the end of the stream
+    offset: u64,
+    /// Last writes with the offset they start at
+    backlog: VecDeque<(u64, Frame)>,
+    /// Bytes kept in the backlog, at most `backlog_size` once the oldest
+    /// writes are dropped
+    backlog_len: u64,
+    backlog_size: u64,
+    /// Database of the last write, see `feed`
+    selected: Option<usize>,
+    replicas: Vec<mpsc::UnboundedSender<Frame>>,
+    active: bool,
+}
+
+impl ReplicationLog {
+    pub fn new(backlog_size: u64) -> Self {
+        Self {
+            id: format!("{:016x}{:016x}{:08x}", random(), random(), random() as u32),
+            offset: 0,
+            backlog: VecDeque::new(),
+            backlog_len: 0,
+            backlog_size,
+            selected: None,
+            replicas: vec![],
+            active: false,
+        }
+    }
+
+    /// Send a write to the database at `db` to the replicas, preceded by a
+    /// `SELECT` when the previous write applied to another database
+    pub fn feed(&mut self, db: usize, frame: &Frame) {
+        if !self.active {
+            return;
+        }
+        if self.selected != Some(db) {
+            self.append(Frame::Array(vec![
+                Frame::Bulk(Bytes::from_static(b"SELECT")),
+                Frame::Bulk(Bytes::from(db.to_string())),
+            ]));
+            self.selected = Some(db);
+        }
+        self.append(frame.clone());
+    }
+
+    fn append(&mut self, frame: Frame) {
+        let mut encoded = vec![];
+        frame.encode(&mut encoded, Protocol::Resp2);
+        self.replicas
+            .retain(|replica| replica.send(frame.clone()).is_ok());
+
+        self.backlog.push_back((self.offset, frame));
+        self.offset += encoded.len() as u64;
+        self.backlog_len += encoded.len() as u64;
+        while self.backlog_len > self.backlog_size && self.backlog.len() > 1 {
+            let (start, _) = self.backlog.pop_front().unwrap();
+            let (next, _) = self.backlog.front().unwrap();
+            self.backlog_len -= next - start;
+        }
+    }
+
+    /// Start sending the stream to a replica, returns `false` when a partial
+    /// resynchronization is not possible.
+    ///
+    /// The replica is first sent `+CONTINUE` and the writes it missed, or
+    /// `+FULLRESYNC` and the snapshot.
+    pub fn attach(&mut self, replica: mpsc::UnboundedSender<Frame>, resync: Resync) -> bool {
+        match resync {
+            Resync::Partial(id, offset) => {
+                let start = offset - 1;
+                if !self.active || id != self.id || start < 0 || start as u64 > self.offset {
+                    return false;
+                }
+                let start = start as u64;
+                let missed = match self.backlog.iter().position(|(at, _)| *at == start) {
+                    Some(first) => self.backlog.range(first..),
+                    None if start == self.offset => self.backlog.range(self.backlog.len()..),
+                    None => return false,
+                };
+                let _ = replica.send(Frame::Simple(format!("CONTINUE {}", self.id)));
+                for (_, frame) in missed {
+                    let _ = replica.send(frame.clone());
+                }
+            }
+            Resync::Full(rdb) => {
+                self.active = true;
+                // The replica starts with no database selected
+                self.selected = None;
+                let _ = replica.send(Frame::Simple(format!(
+                    "FULLRESYNC {} {}",
+                    self.id, self.offset
+                )));
+                let _ = replica.send(Frame::Bulk(rdb));
+            }
+        }
+        self.replicas.push(replica);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(key: &str) -> Frame {
+        request(&["SET", key, "value"])
+    }
+
+    #[test]
+    fn attach_test() {
+        let mut log = ReplicationLog::new(200);
+        log.feed(0, &set("ignored"));
+        assert_eq!(log.offset, 0);
+
+        let (replica, mut stream) = mpsc::unbounded_channel();
+        assert!(!log.attach(replica.clone(), Resync::Partial(log.id.clone(), 1)));
+        assert!(log.attach(replica, Resync::Full(Bytes::from("rdb"))));
+        assert!(matches!(stream.try_recv(), Ok(Frame::Simple(reply)) if reply.ends_with(" 0")));
+        assert_eq!(stream.try_recv().unwrap(), Frame::Bulk(Bytes::from("rdb")));
+
+        log.feed(1, &set("a"));
+        assert!(matches!(stream.try_recv(), Ok(Frame::Array(select)) if select.len() == 2));
+        assert_eq!(stream.try_recv().unwrap(), set("a"));
+        let resumed = log.offset;
+        log.feed(1, &set("b"));
+
+        // Resume after the first write
+        let (replica, mut stream) = mpsc::unbounded_channel();
+        let id = log.id.clone();
+        assert!(log.attach(replica, Resync::Partial(id.clone(), resumed as i64 + 1)));
+        assert!(
+            matches!(stream.try_recv(), Ok(Frame::Simple(reply)) if reply.starts_with("CONTINUE"))
+        );
+        assert_eq!(stream.try_recv().unwrap(), set("b"));
+        assert!(stream.try_recv().is_err());
+
+        // The first writes are dropped from the backlog
+        for _ in 0..10 {
+            log.feed(1, &set("c"));
+        }
+        let (replica, _) = mpsc::unbounded_channel();
+        assert!(!log.attach(replica, Resync::Partial(id, resumed as i64 + 1)));
+        assert!(log.backlog_len <= 200);
+    }
+}
@@ -0,0 +1,430 @@
+use crate::frame::*;
+use bytes::Bytes;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Arguments of a command kept in a slow log entry
+const SLOWLOG_ENTRY_MAX_ARGC: usize = 32;
+/// Bytes of an argument kept in a slow log entry
+const SLOWLOG_ENTRY_MAX_STRING: usize = 128;
+/// Percentiles of `INFO latencystats`
+const LATENCY_PERCENTILES: &[f64] = &[50.0, 99.0, 99.9];
+
+/// Latencies in microseconds, bucketed by power of two
+#[derive(Clone)]
+pub struct Histogram {
+    /// Durations up to `1 << i` microseconds, and above the previous bucket
+    buckets: [u64; 64],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self { buckets: [0; 64] }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, micros: u64) {
+        let bucket = 64 - (micros.max(1) - 1).leading_zeros() as usize;
+        self.buckets[bucket.min(63)] += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Upper bound of the bucket of the latency at the percentile
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let rank = (self.count() as f64 * percentile / 100.0).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return 1 << bucket;
+            }
+        }
+        0
+    }
+
+    /// Cumulative counts by upper bound, from the first to the last
+    /// bucket with latencies
+    pub fn cumulative(&self) -> Vec<(u64, u64)> {
+        let first = self.buckets.iter().position(|count| *count > 0);
+        let last = self.buckets.iter().rposition(|count| *count > 0);
+        let (Some(first), Some(last)) = (first, last) else {
+            return vec![];
+        };
+        let mut seen = 0;
+        (first..=last)
+            .map(|bucket| {
+                seen += self.buckets[bucket];
+                (1 << bucket, seen)
+            })
+            .collect()
+    }
+}
+
+tokio::task_local! {
+    static EXECUTION: ExecutionTime;
+}
+
+/// Time spent executing a command, accumulated by the shards it runs on, so
+/// that its latency leaves out the time it waited for them
+#[derive(Clone, Default)]
+pub struct ExecutionTime(Arc<AtomicU64>);
+
+impl ExecutionTime {
+    /// Run `f`, the commands it sends to the shards being accounted here
+    pub fn scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        EXECUTION.sync_scope(self.clone(), f)
+    }
+
+    /// Spawn a task of the command being processed, the commands it sends
+    /// to the shards being accounted too
+    pub fn spawn(task: impl Future<Output = ()> + Send + 'static) {
+        match Self::current() {
+            Some(execution) => tokio::spawn(EXECUTION.scope(execution, task)),
+            None => tokio::spawn(task),
+        };
+    }
+
+    /// Execution time of the command being processed, if measured
+    pub fn current() -> Option<ExecutionTime> {
+        EXECUTION.try_with(Clone::clone).ok()
+    }
+
+    pub fn add(&self, elapsed: Duration) {
+        self.0
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn micros(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counters shared by the shards
+#[derive(Default)]
+pub struct KeyspaceStats {
+    pub expired_keys: AtomicU64,
+    pub evicted_keys: AtomicU64,
+}
+
+#[derive(Clone, Default)]
+pub struct CommandStat {
+    pub calls: u64,
+    /// Total execution time in microseconds
+    pub usec: u64,
+    /// Refused before being executed, as with `MOVED`
+    pub rejected_calls: u64,
+    /// Executed but replied with an error
+    pub failed_calls: u64,
+    pub latency: Histogram,
+}
+
+/// Statistics of the executed commands, for `INFO`
+#[derive(Default)]
+pub struct CommandStats {
+    commands: BTreeMap<&'static str, CommandStat>,
+    /// Error replies by prefix, as `ERR`
+    errors: BTreeMap<String, u64>,
+}
+
+impl CommandStats {
+    /// Count a call, its duration is unknown for a blocking command, which
+    /// may wait for long
+    pub fn record(&mut self, name: &'static str, micros: Option<u64>, failed: bool) {
+        let stat = self.commands.entry(name).or_default();
+        stat.calls += 1;
+        if let Some(micros) = micros {
+            stat.usec += micros;
+            stat.latency.record(micros);
+        }
+        if failed {
+            stat.failed_calls += 1;
+        }
+    }
+
+    pub fn reject(&mut self, name: &'static str) {
+        self.commands.entry(name).or_default().rejected_calls += 1;
+    }
+
+    /// Count an error reply
+    pub fn error(&mut self, reply: &Frame) {
+        if let Frame::Error(err) = reply {
+            let prefix = err.split(' ').next().unwrap_or_default();
+            *self.errors.entry(prefix.to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn total_calls(&self) -> u64 {
+        self.commands.values().map(|stat| stat.calls).sum()
+    }
+
+    pub fn total_errors(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = (&'static str, &CommandStat)> {
+        self.commands.iter().map(|(name, stat)| (*name, stat))
+    }
+
+    /// `INFO commandstats`
+    pub fn commandstats(&self) -> Vec<String> {
+        self.commands()
+            .map(|(name, stat)| {
+                format!(
+                    "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}",
+                    name,
+                    stat.calls,
+                    stat.usec,
+                    stat.usec as f64 / stat.calls.max(1) as f64,
+                    stat.rejected_calls,
+                    stat.failed_calls
+                )
+            })
+            .collect()
+    }
+
+    /// `INFO latencystats`
+    pub fn latencystats(&self) -> Vec<String> {
+        self.commands()
+            .filter(|(_, stat)| stat.latency.count() > 0)
+            .map(|(name, stat)| {
+                let percentiles: Vec<String> = LATENCY_PERCENTILES
+                    .iter()
+                    .map(|p| format!("p{}={:.3}", p, stat.latency.percentile(*p) as f64))
+                    .collect();
+                format!(
+                    "latency_percentiles_usec_{}:{}",
+                    name,
+                    percentiles.join(",")
+                )
+            })
+            .collect()
+    }
+
+    /// `INFO errorstats`
+    pub fn errorstats(&self) -> Vec<String> {
+        self.errors
+            .iter()
+            .map(|(prefix, count)| format!("errorstat_{}:count={}", prefix, count))
+            .collect()
+    }
+}
+
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// Unix time in seconds
+    pub at: u64,
+    pub micros: u64,
+    pub args: Vec<Bytes>,
+    pub addr: String,
+    pub name: String,
+}
+
+impl SlowLogEntry {
+    fn to_frame(&self) -> Frame {
+        Frame::Array(vec![
+            Frame::Integer(self.id as i64),
+            Frame::Integer(self.at as i64),
+            Frame::Integer(self.micros as i64),
+            Frame::Array(self.args.iter().cloned().map(Frame::Bulk).collect()),
+            Frame::Bulk(Bytes::from(self.addr.clone())),
+            Frame::Bulk(Bytes::from(self.name.clone())),
+        ])
+    }
+}
+
+/// Commands slower than `slowlog-log-slower-than`, the last
+/// `slowlog-max-len` being kept
+pub struct SlowLog {
+    /// Threshold in microseconds, negative to log nothing
+    threshold: AtomicI64,
+    max_len: AtomicUsize,
+    next_id: AtomicU64,
+    /// Newest entries first
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+}
+
+impl SlowLog {
+    pub fn new(threshold: i64, max_len: usize) -> Self {
+        Self {
+            threshold: AtomicI64::new(threshold),
+            max_len: AtomicUsize::new(max_len),
+            next_id: AtomicU64::new(0),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn configure(&self, threshold: i64, max_len: usize) {
+        self.threshold.store(threshold, Ordering::Relaxed);
+        self.max_len.store(max_len, Ordering::Relaxed);
+        self.entries.lock().unwrap().truncate(max_len);
+    }
+
+    pub fn is_slow(&self, micros: u64) -> bool {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        threshold >= 0 && micros >= threshold as u64
+    }
+
+    /// Log a slow command, given as the frame of the request
+    pub fn push(&self, at: u64, micros: u64, request: &Frame, addr: String, name: String) {
+        let entry = SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            at,
+            micros,
+            args: slowlog_args(request),
+            addr,
+            name,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(entry);
+        entries.truncate(self.max_len.load(Ordering::Relaxed));
+    }
+
+    /// `SLOWLOG GET`, every entry when `count` is `None`
+    pub fn get(&self, count: Option<usize>) -> Frame {
+        let entries = self.entries.lock().unwrap();
+        let count = count.unwrap_or(entries.len());
+        Frame::Array(entries.iter().take(count).map(|e| e.to_frame()).collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Arguments of a request, long ones being truncated
+fn slowlog_args(request: &Frame) -> Vec<Bytes> {
+    let Frame::Array(parts) = request else {
+        return vec![];
+    };
+    let argc = parts.len();
+    parts
+        .iter()
+        .enumerate()
+        .take(SLOWLOG_ENTRY_MAX_ARGC)
+        .map(|(index, part)| {
+            if index == SLOWLOG_ENTRY_MAX_ARGC - 1 && argc > SLOWLOG_ENTRY_MAX_ARGC {
+                let more = argc - index;
+                return Bytes::from(format!("... ({} more arguments)", more));
+            }
+            let arg = match part {
+                Frame::Bulk(arg) => arg.clone(),
+                Frame::Simple(arg) => Bytes::from(arg.clone()),
+                _ => Bytes::new(),
+            };
+            if arg.len() <= SLOWLOG_ENTRY_MAX_STRING {
+                return arg;
+            }
+            let mut truncated = arg[..SLOWLOG_ENTRY_MAX_STRING].to_vec();
+            let more = arg.len() - SLOWLOG_ENTRY_MAX_STRING;
+            truncated.extend_from_slice(format!("... ({} more bytes)", more).as_bytes());
+            Bytes::from(truncated)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_test() {
+        let mut histogram = Histogram::default();
+        for micros in [0, 1, 2, 3, 4, 5, 100] {
+            histogram.record(micros);
+        }
+        assert_eq!(
+            histogram.cumulative(),
+            vec![
+                (1, 2),
+                (2, 3),
+                (4, 5),
+                (8, 6),
+                (16, 6),
+                (32, 6),
+                (64, 6),
+                (128, 7)
+            ]
+        );
+        assert_eq!(histogram.percentile(50.0), 4);
+        assert_eq!(histogram.percentile(99.9), 128);
+    }
+
+    #[test]
+    fn command_stats_test() {
+        let mut stats = CommandStats::default();
+        stats.record("get", Some(3), false);
+        stats.record("get", Some(5), true);
+        stats.record("blpop", None, false);
+        stats.reject("set");
+        stats.error(&Frame::Error("MOVED 1 127.0.0.1:7001".to_string()));
+        stats.error(&Frame::Error("ERR syntax error".to_string()));
+        assert_eq!(stats.total_calls(), 3);
+        assert_eq!(stats.total_errors(), 2);
+        assert_eq!(
+            stats.commandstats(),
+            [
+                "cmdstat_blpop:calls=1,usec=0,usec_per_call=0.00,rejected_calls=0,failed_calls=0",
+                "cmdstat_get:calls=2,usec=8,usec_per_call=4.00,rejected_calls=0,failed_calls=1",
+                "cmdstat_set:calls=0,usec=0,usec_per_call=0.00,rejected_calls=1,failed_calls=0",
+            ]
+        );
+        assert_eq!(
+            stats.latencystats(),
+            ["latency_percentiles_usec_get:p50=4.000,p99=8.000,p99.9=8.000"]
+        );
+        assert_eq!(
+            stats.errorstats(),
+            ["errorstat_ERR:count=1", "errorstat_MOVED:count=1"]
+        );
+    }
+
+    #[test]
+    fn slowlog_test() {
+        let slowlog = SlowLog::new(10, 2);
+        assert!(!slowlog.is_slow(9));
+        assert!(slowlog.is_slow(10));
+
+        let mut args = vec![Frame::Bulk(Bytes::from("rpush"))];
+        args.extend((0..40).map(|_| Frame::Bulk(Bytes::from(vec![b'a'; 200]))));
+        let request = Frame::Array(args);
+        for _ in 0..3 {
+            slowlog.push(0, 20, &request, "127.0.0.1:1234".to_string(), String::new());
+        }
+        assert_eq!(slowlog.len(), 2);
+
+        let entries = slowlog.get(Some(1));
+        let Frame::Array(entries) = entries else {
+            panic!()
+        };
+        let Frame::Array(entry) = &entries[0] else {
+            panic!()
+        };
+        assert_eq!(entry[0], Frame::Integer(2));
+        let Frame::Array(args) = &entry[3] else {
+            panic!()
+        };
+        assert_eq!(args.len(), SLOWLOG_ENTRY_MAX_ARGC);
+        assert_eq!(args[0], Frame::Bulk(Bytes::from("rpush")));
+        assert!(matches!(&args[1], Frame::Bulk(arg) if arg.ends_with(b"... (72 more bytes)")));
+        assert_eq!(
+            args[31],
+            Frame::Bulk(Bytes::from("... (10 more arguments)"))
+        );
+
+        slowlog.configure(-1, 2);
+        assert!(!slowlog.is_slow(u64::MAX));
+        slowlog.reset();
+        assert_eq!(slowlog.len(), 0);
+    }
+}
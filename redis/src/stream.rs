@@ -0,0 +1,477 @@
+use bytes::Bytes;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Bound;
+
+pub const INVALID_ID: &str = "Invalid stream ID specified as stream command argument";
+
+/// Id of a stream entry, the milliseconds time it was added at then a
+/// sequence number among the entries of the same millisecond
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    /// Parse `ms-seq`, or `ms` alone with the sequence `seq`
+    pub fn parse(data: &[u8], seq: u64) -> Option<StreamId> {
+        let data = std::str::from_utf8(data).ok()?;
+        match data.split_once('-') {
+            Some((ms, seq)) => Some(StreamId::new(parse_u64(ms)?, parse_u64(seq)?)),
+            None => Some(StreamId::new(parse_u64(data)?, seq)),
+        }
+    }
+
+    /// The id right after, `None` for the last possible id
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The id right before, `None` for `0-0`
+    pub fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+
+    pub fn to_bytes(self) -> Bytes {
+        Bytes::from(self.to_string())
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Digits only, without the sign accepted by `str::parse`
+fn parse_u64(digits: &str) -> Option<u64> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Bound of `XRANGE`, as `-`, `+`, an id or a time in milliseconds, which is
+/// exclusive when prefixed by `(`. `start` tells which end of the range it
+/// is, a time alone starting at its first sequence or ending at its last.
+pub fn parse_range_bound(data: &[u8], start: bool) -> Result<StreamId, &'static str> {
+    match data {
+        b"-" => return Ok(StreamId::MIN),
+        b"+" => return Ok(StreamId::MAX),
+        _ => {}
+    }
+    let (exclusive, data) = match data.strip_prefix(b"(") {
+        Some(data) => (true, data),
+        None => (false, data),
+    };
+    let seq = if start { 0 } else { u64::MAX };
+    let id = StreamId::parse(data, seq).ok_or(INVALID_ID)?;
+    match (exclusive, start) {
+        (false, _) => Ok(id),
+        (true, true) => id.next().ok_or("invalid start ID for the interval"),
+        (true, false) => id.prev().ok_or("invalid end ID for the interval"),
+    }
+}
+
+/// Id requested to `XADD`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NewId {
+    /// `*`, the current time
+    Auto,
+    /// `ms-*`, the next sequence of the given time
+    Seq(u64),
+    Explicit(StreamId),
+}
+
+impl NewId {
+    pub fn parse(data: &[u8]) -> Option<NewId> {
+        if data == b"*" {
+            return Some(NewId::Auto);
+        }
+        if let Some(ms) = data.strip_suffix(b"-*") {
+            return Some(NewId::Seq(parse_u64(std::str::from_utf8(ms).ok()?)?));
+        }
+        StreamId::parse(data, 0).map(NewId::Explicit)
+    }
+}
+
+/// Entries evicted from the head of a stream by `XADD`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trim {
+    /// Keep at most this number of entries
+    MaxLen(u64),
+    /// Evict the entries with a lower id
+    MinId(StreamId),
+}
+
+/// A field and its value, in the order given to `XADD`
+pub type Fields = Vec<(Bytes, Bytes)>;
+
+/// Entries by increasing id, with the consumer groups reading them.
+///
+/// The id of the last added entry is kept once deleted so that ids only ever
+/// increase.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    last_id: StreamId,
+    groups: BTreeMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Set the last id, which must not be lower than the id of an entry
+    pub fn set_last_id(&mut self, id: StreamId) -> bool {
+        if self
+            .entries
+            .last_key_value()
+            .is_some_and(|(last, _)| *last > id)
+        {
+            return false;
+        }
+        self.last_id = id;
+        true
+    }
+
+    /// The id of the entry to add, which must be greater than the last id
+    pub fn next_id(&self, id: NewId, now: u64) -> Result<StreamId, &'static str> {
+        let last = self.last_id;
+        let id = match id {
+            NewId::Auto if now > last.ms => StreamId::new(now, 0),
+            NewId::Auto => last.next().ok_or(
+                "ERR The stream has exhausted the last possible ID, unable to add more items",
+            )?,
+            NewId::Seq(ms) if ms == last.ms && self.last_id != StreamId::MIN => {
+                let seq = last.seq.checked_add(1).ok_or(
+                    "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+                )?;
+                StreamId::new(ms, seq)
+            }
+            NewId::Seq(ms) => StreamId::new(ms, if ms == 0 { 1 } else { 0 }),
+            NewId::Explicit(id) => id,
+        };
+        if id == StreamId::MIN {
+            return Err("ERR The ID specified in XADD must be greater than 0-0");
+        }
+        if id <= last {
+            return Err(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+            );
+        }
+        Ok(id)
+    }
+
+    /// Add an entry, its id has to be given by `next_id`
+    pub fn add(&mut self, id: StreamId, fields: Fields) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+    }
+
+    pub fn remove(&mut self, id: &StreamId) -> bool {
+        self.entries.remove(id).is_some()
+    }
+
+    /// Entries from `start` to `end` included, by decreasing id when `rev`
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        rev: bool,
+    ) -> Box<dyn Iterator<Item = (&StreamId, &Fields)> + '_> {
+        if start > end {
+            return Box::new(std::iter::empty());
+        }
+        let range = self.entries.range(start..=end);
+        match rev {
+            true => Box::new(range.rev()),
+            false => Box::new(range),
+        }
+    }
+
+    /// Entries with an id greater than `id`
+    pub fn after(&self, id: StreamId) -> impl Iterator<Item = (&StreamId, &Fields)> {
+        self.entries.range((Bound::Excluded(id), Bound::Unbounded))
+    }
+
+    /// Evict entries from the head, up to `limit` when not zero, returns the
+    /// number of evicted entries
+    pub fn trim(&mut self, trim: Trim, limit: u64) -> usize {
+        let mut evicted = 0;
+        while limit == 0 || evicted < limit as usize {
+            let Some((first, _)) = self.entries.first_key_value() else {
+                break;
+            };
+            let evict = match trim {
+                Trim::MaxLen(len) => self.entries.len() as u64 > len,
+                Trim::MinId(id) => *first < id,
+            };
+            if !evict {
+                break;
+            }
+            self.entries.pop_first();
+            evicted += 1;
+        }
+        evicted
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&StreamId, &Fields)> {
+        self.entries.iter()
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = (&String, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    pub fn group(&self, name: &str) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// A group along with the entries, to deliver them
+    pub fn group_with_entries(
+        &mut self,
+        name: &str,
+    ) -> Option<(&mut ConsumerGroup, &BTreeMap<StreamId, Fields>)> {
+        Some((self.groups.get_mut(name)?, &self.entries))
+    }
+
+    /// Create a group, returns `false` when it already exists
+    pub fn create_group(&mut self, name: String, group: ConsumerGroup) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        self.groups.insert(name, group);
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Number of entries pending in all the groups
+    pub fn pending_len(&self) -> usize {
+        self.groups.values().map(|group| group.pending.len()).sum()
+    }
+}
+
+/// Entry delivered to a consumer of a group, until acknowledged
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: String,
+    /// Unix time in milliseconds of the last delivery
+    pub delivered_at: u64,
+    pub deliveries: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Consumer {
+    /// Unix time in milliseconds of its last read or claim
+    pub seen_at: u64,
+    /// Ids of the entries delivered to it and not yet acknowledged
+    pub pending: BTreeSet<StreamId>,
+}
+
+/// Consumers sharing the entries of a stream, each entry being delivered
+/// to a single consumer which has to acknowledge it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsumerGroup {
+    /// Id of the last entry delivered to the group
+    pub last_id: StreamId,
+    /// Entries delivered and not yet acknowledged, by id
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_id: StreamId) -> Self {
+        Self {
+            last_id,
+            ..Self::default()
+        }
+    }
+
+    /// Create the consumer when missing, returns whether it was created
+    pub fn create_consumer(&mut self, name: &str, now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        let consumer = Consumer {
+            seen_at: now,
+            pending: BTreeSet::new(),
+        };
+        self.consumers.insert(name.to_string(), consumer);
+        true
+    }
+
+    /// Create the consumer when missing and mark it as seen now, returns
+    /// whether it was created
+    pub fn seen(&mut self, name: &str, now: u64) -> bool {
+        let created = self.create_consumer(name, now);
+        if let Some(consumer) = self.consumers.get_mut(name) {
+            consumer.seen_at = now;
+        }
+        created
+    }
+
+    /// Remove a consumer with its pending entries, returns their number
+    pub fn delete_consumer(&mut self, name: &str) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+
+    /// Deliver an entry to a consumer, taken from its previous consumer when
+    /// already pending. The consumer must exist.
+    pub fn deliver(&mut self, id: StreamId, consumer: &str, at: u64, deliveries: u64) {
+        let previous = self.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.to_string(),
+                delivered_at: at,
+                deliveries,
+            },
+        );
+        if let Some(previous) = previous {
+            if let Some(owner) = self.consumers.get_mut(&previous.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        if let Some(consumer) = self.consumers.get_mut(consumer) {
+            consumer.pending.insert(id);
+        }
+    }
+
+    /// Acknowledge a pending entry, returns `false` when it was not pending
+    pub fn ack(&mut self, id: &StreamId) -> bool {
+        let Some(entry) = self.pending.remove(id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(id);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_test() {
+        assert_eq!(StreamId::parse(b"5-3", 0), Some(StreamId::new(5, 3)));
+        assert_eq!(StreamId::parse(b"5", 7), Some(StreamId::new(5, 7)));
+        assert_eq!(StreamId::parse(b"5-", 0), None);
+        assert_eq!(StreamId::parse(b"-5", 0), None);
+        assert_eq!(StreamId::parse(b"+5-1", 0), None);
+        assert_eq!(StreamId::new(5, u64::MAX).next(), Some(StreamId::new(6, 0)));
+        assert_eq!(StreamId::MAX.next(), None);
+        assert_eq!(StreamId::new(5, 0).prev(), Some(StreamId::new(4, u64::MAX)));
+        assert_eq!(StreamId::MIN.prev(), None);
+
+        assert_eq!(
+            parse_range_bound(b"7", false),
+            Ok(StreamId::new(7, u64::MAX))
+        );
+        assert_eq!(parse_range_bound(b"(7-1", true), Ok(StreamId::new(7, 2)));
+        assert_eq!(
+            parse_range_bound(b"(7-0", false),
+            Ok(StreamId::new(6, u64::MAX))
+        );
+        assert!(parse_range_bound(b"(0-0", false).is_err());
+        assert_eq!(NewId::parse(b"7-*"), Some(NewId::Seq(7)));
+    }
+
+    #[test]
+    fn next_id_test() {
+        let mut stream = Stream::new();
+        assert!(stream.next_id(NewId::Explicit(StreamId::MIN), 10).is_err());
+        assert_eq!(stream.next_id(NewId::Seq(0), 10), Ok(StreamId::new(0, 1)));
+        assert_eq!(stream.next_id(NewId::Auto, 10), Ok(StreamId::new(10, 0)));
+
+        stream.add(StreamId::new(10, 5), vec![]);
+        // A clock going backwards does not go back in the stream
+        assert_eq!(stream.next_id(NewId::Auto, 3), Ok(StreamId::new(10, 6)));
+        assert_eq!(stream.next_id(NewId::Seq(10), 3), Ok(StreamId::new(10, 6)));
+        assert_eq!(stream.next_id(NewId::Seq(11), 3), Ok(StreamId::new(11, 0)));
+        assert!(stream.next_id(NewId::Seq(9), 3).is_err());
+        assert!(stream
+            .next_id(NewId::Explicit(StreamId::new(10, 5)), 3)
+            .is_err());
+
+        // Deleting the last entry keeps its id
+        stream.remove(&StreamId::new(10, 5));
+        assert!(stream
+            .next_id(NewId::Explicit(StreamId::new(10, 5)), 3)
+            .is_err());
+    }
+
+    #[test]
+    fn trim_test() {
+        let mut stream = Stream::new();
+        for ms in 1..=10 {
+            stream.add(StreamId::new(ms, 0), vec![]);
+        }
+        assert_eq!(stream.trim(Trim::MaxLen(8), 0), 2);
+        assert_eq!(stream.trim(Trim::MinId(StreamId::new(6, 0)), 2), 2);
+        assert_eq!(stream.len(), 6);
+        assert_eq!(stream.trim(Trim::MinId(StreamId::new(6, 0)), 0), 1);
+        assert_eq!(stream.entries().next().unwrap().0, &StreamId::new(6, 0));
+        assert_eq!(stream.last_id(), StreamId::new(10, 0));
+    }
+
+    #[test]
+    fn group_test() {
+        let mut group = ConsumerGroup::new(StreamId::MIN);
+        assert!(group.create_consumer("alice", 0));
+        assert!(!group.create_consumer("alice", 0));
+        group.create_consumer("bob", 0);
+
+        let id = StreamId::new(1, 0);
+        group.deliver(id, "alice", 100, 1);
+        group.deliver(StreamId::new(2, 0), "alice", 100, 1);
+        group.deliver(id, "bob", 200, 2);
+        assert_eq!(group.consumers["alice"].pending.len(), 1);
+        assert_eq!(group.consumers["bob"].pending.len(), 1);
+        assert_eq!(group.pending[&id].consumer, "bob");
+
+        assert!(group.ack(&id));
+        assert!(!group.ack(&id));
+        assert!(group.consumers["bob"].pending.is_empty());
+        assert_eq!(group.delete_consumer("alice"), Some(1));
+        assert!(group.pending.is_empty());
+    }
+}
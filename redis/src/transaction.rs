@@ -0,0 +1,171 @@
+use crate::command::Command;
+use crate::frame::Frame;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// `MULTI` state of a connection, and the keys it watches.
+///
+/// The shards of the watched keys set the `dirty` flag once one of them is
+/// touched, a new flag is used once the watches are cleared.
+pub struct Transaction {
+    /// Commands queued since `MULTI`, `None` outside of a transaction
+    queued: Option<Vec<Command>>,
+    /// A command could not be queued, `EXEC` then discards the transaction
+    failed: bool,
+    /// Watched keys, with the index of their database
+    watched: Vec<(usize, String)>,
+    dirty: Arc<AtomicBool>,
+}
+
+/// A transaction ready to be executed
+pub struct Queued {
+    pub commands: Vec<Command>,
+    pub watched: Vec<(usize, String)>,
+    pub dirty: Arc<AtomicBool>,
+}
+
+impl Queued {
+    /// A watched key was touched, the transaction is aborted
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::SeqCst)
+    }
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self {
+            queued: None,
+            failed: false,
+            watched: vec![],
+            dirty: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    /// Number of queued commands, when in a transaction
+    pub fn queued(&self) -> Option<usize> {
+        self.queued.as_ref().map(|queued| queued.len())
+    }
+
+    pub fn multi(&mut self) -> Frame {
+        self.queued = Some(vec![]);
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Queue a command until `EXEC`
+    pub fn queue(&mut self, cmd: Command) -> Frame {
+        match cmd {
+            Command::Multi(_) => Frame::Error("ERR MULTI calls can not be nested".to_string()),
+            Command::Watch(_) => Frame::Error("ERR WATCH inside MULTI is not allowed".to_string()),
+            Command::Unknown(cmd) => {
+                self.failed = true;
+                Frame::Error(format!("ERR unknown command '{}'", cmd.get_name()))
+            }
+            // The database of the following commands would only be known
+            // once executed
+            Command::Select(_) => {
+                self.failed = true;
+                Frame::Error("ERR SELECT is not allowed inside a transaction".to_string())
+            }
+            Command::Save(_)
+            | Command::Acl(_)
+            | Command::Auth(_)
+            | Command::Client(_)
+            | Command::Monitor(_)
+            | Command::Subscribe(_)
+            | Command::Unsubscribe(_)
+            | Command::PSubscribe(_)
+            | Command::PUnsubscribe(_) => {
+                self.failed = true;
+                Frame::Error("ERR Command not allowed inside a transaction".to_string())
+            }
+            cmd => {
+                self.queued.get_or_insert_with(Vec::new).push(cmd);
+                Frame::Simple("QUEUED".to_string())
+            }
+        }
+    }
+
+    /// A command could not be parsed while queuing
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    pub fn discard(&mut self) -> Frame {
+        if self.queued.take().is_none() {
+            return Frame::Error("ERR DISCARD without MULTI".to_string());
+        }
+        self.failed = false;
+        self.unwatch();
+        Frame::Simple("OK".to_string())
+    }
+
+    /// End the transaction, the watches are cleared
+    pub fn exec(&mut self) -> Result<Queued, Frame> {
+        let Some(commands) = self.queued.take() else {
+            return Err(Frame::Error("ERR EXEC without MULTI".to_string()));
+        };
+        let queued = Queued {
+            commands,
+            watched: std::mem::take(&mut self.watched),
+            dirty: std::mem::replace(&mut self.dirty, Arc::new(AtomicBool::new(false))),
+        };
+        if std::mem::take(&mut self.failed) {
+            return Err(Frame::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string(),
+            ));
+        }
+        Ok(queued)
+    }
+
+    /// Watch keys of the database at `db`, returns the flag to register on
+    /// their shards
+    pub fn watch(&mut self, db: usize, keys: &[String]) -> Arc<AtomicBool> {
+        self.watched
+            .extend(keys.iter().map(|key| (db, key.clone())));
+        self.dirty.clone()
+    }
+
+    pub fn unwatch(&mut self) {
+        self.watched.clear();
+        self.dirty = Arc::new(AtomicBool::new(false));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Ping;
+
+    #[test]
+    fn exec_test() {
+        let mut transaction = Transaction::new();
+        assert!(transaction.exec().is_err());
+
+        let dirty = transaction.watch(1, &["key".to_string()]);
+        transaction.multi();
+        assert_eq!(
+            transaction.queue(Command::Ping(Ping::default())),
+            Frame::Simple("QUEUED".to_string())
+        );
+        dirty.store(true, Ordering::SeqCst);
+        let queued = transaction.exec().ok().unwrap();
+        assert_eq!(queued.commands.len(), 1);
+        assert_eq!(queued.watched, vec![(1, "key".to_string())]);
+        assert!(queued.is_dirty());
+
+        // The next transaction starts clean
+        transaction.multi();
+        let queued = transaction.exec().ok().unwrap();
+        assert!(queued.commands.is_empty() && !queued.is_dirty());
+
+        // A refused command discards the whole transaction
+        transaction.multi();
+        transaction.fail();
+        assert!(transaction.exec().is_err());
+        assert!(!transaction.is_active());
+    }
+}
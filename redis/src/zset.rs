@@ -0,0 +1,295 @@
+use crate::command_parser::parse_float;
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+/// Score of a sorted set member, never NaN
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Score(pub f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members ordered by score then lexicographically, with their score
+/// indexed by member.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    index: BTreeSet<(Score, Bytes)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Set the score of a member, returns the previous score
+    pub fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        // -0 and 0 are the same score
+        let score = if score == 0.0 { 0.0 } else { score };
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.index.remove(&(Score(previous), member.clone()));
+        }
+        self.index.insert((Score(score), member));
+        previous
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove_entry(member) {
+            Some((member, score)) => {
+                self.index.remove(&(Score(score), member));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Members by increasing score
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        self.index.iter().map(|(score, member)| (member, score.0))
+    }
+
+    /// Position of the member by increasing score, or decreasing when `rev`
+    pub fn rank(&self, member: &[u8], rev: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let rank = self
+            .index
+            .range(..(Score(score), Bytes::copy_from_slice(member)))
+            .count();
+        Some(if rev { self.len() - 1 - rank } else { rank })
+    }
+
+    /// Members with a score in the bounds, by increasing score, or
+    /// decreasing when `rev`
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+        rev: bool,
+    ) -> Box<dyn Iterator<Item = (&Bytes, f64)> + '_> {
+        let start = Bound::Included((Score(min.value()), Bytes::new()));
+        let range = self
+            .index
+            .range((start, Bound::Unbounded))
+            .map(|(score, member)| (member, score.0));
+        if rev {
+            Box::new(
+                range
+                    .rev()
+                    .skip_while(move |(_, score)| !max.above(*score))
+                    .take_while(move |(_, score)| min.below(*score)),
+            )
+        } else {
+            Box::new(
+                range
+                    .skip_while(move |(_, score)| !min.below(*score))
+                    .take_while(move |(_, score)| max.above(*score)),
+            )
+        }
+    }
+
+    /// Members in the lexicographical bounds, assuming they all have the
+    /// same score
+    pub fn range_by_lex<'a>(
+        &'a self,
+        min: &'a LexBound,
+        max: &'a LexBound,
+        rev: bool,
+    ) -> Box<dyn Iterator<Item = (&'a Bytes, f64)> + 'a> {
+        let range = self.iter();
+        if rev {
+            Box::new(
+                range
+                    .rev()
+                    .skip_while(move |(member, _)| !max.above(member))
+                    .take_while(move |(member, _)| min.below(member)),
+            )
+        } else {
+            Box::new(
+                range
+                    .skip_while(move |(member, _)| !min.below(member))
+                    .take_while(move |(member, _)| max.above(member)),
+            )
+        }
+    }
+}
+
+/// Bound of a score range, `(1.5` being exclusive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl ScoreBound {
+    pub fn parse(bound: &[u8]) -> Option<ScoreBound> {
+        let (exclusive, value) = match bound.strip_prefix(b"(") {
+            Some(value) => (true, value),
+            None => (false, bound),
+        };
+        let value = parse_float(value)?;
+        Some(if exclusive {
+            ScoreBound::Exclusive(value)
+        } else {
+            ScoreBound::Inclusive(value)
+        })
+    }
+
+    fn value(self) -> f64 {
+        match self {
+            ScoreBound::Inclusive(value) | ScoreBound::Exclusive(value) => value,
+        }
+    }
+
+    /// Whether the score is above this lower bound
+    fn below(self, score: f64) -> bool {
+        match self {
+            ScoreBound::Inclusive(min) => score >= min,
+            ScoreBound::Exclusive(min) => score > min,
+        }
+    }
+
+    /// Whether the score is below this upper bound
+    fn above(self, score: f64) -> bool {
+        match self {
+            ScoreBound::Inclusive(max) => score <= max,
+            ScoreBound::Exclusive(max) => score < max,
+        }
+    }
+}
+
+/// Bound of a lexicographical range, `-` and `+` being the infinites
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+impl LexBound {
+    pub fn parse(bound: &[u8]) -> Option<LexBound> {
+        match bound.first()? {
+            b'-' if bound.len() == 1 => Some(LexBound::Min),
+            b'+' if bound.len() == 1 => Some(LexBound::Max),
+            b'[' => Some(LexBound::Inclusive(Bytes::copy_from_slice(&bound[1..]))),
+            b'(' => Some(LexBound::Exclusive(Bytes::copy_from_slice(&bound[1..]))),
+            _ => None,
+        }
+    }
+
+    /// Whether the member is above this lower bound
+    fn below(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(min) => member >= &min[..],
+            LexBound::Exclusive(min) => member > &min[..],
+        }
+    }
+
+    /// Whether the member is below this upper bound
+    fn above(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(max) => member <= &max[..],
+            LexBound::Exclusive(max) => member < &max[..],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members<'a>(range: impl Iterator<Item = (&'a Bytes, f64)>) -> Vec<&'a [u8]> {
+        range.map(|(member, _)| &member[..]).collect()
+    }
+
+    #[test]
+    fn rank_test() {
+        let mut zset = SortedSet::new();
+        zset.insert(Bytes::from("b"), 2.0);
+        zset.insert(Bytes::from("a"), 2.0);
+        zset.insert(Bytes::from("c"), 1.0);
+        assert_eq!(zset.insert(Bytes::from("c"), 3.0), Some(1.0));
+
+        assert_eq!(members(zset.iter()), vec![b"a", b"b", b"c"]);
+        assert_eq!(zset.rank(b"a", false), Some(0));
+        assert_eq!(zset.rank(b"c", false), Some(2));
+        assert_eq!(zset.rank(b"c", true), Some(0));
+        assert_eq!(zset.rank(b"d", false), None);
+
+        assert!(zset.remove(b"a"));
+        assert!(!zset.remove(b"a"));
+        assert_eq!(zset.len(), 2);
+    }
+
+    #[test]
+    fn range_by_score_test() {
+        let mut zset = SortedSet::new();
+        for (i, member) in ["a", "b", "c", "d"].iter().enumerate() {
+            zset.insert(Bytes::from(*member), i as f64);
+        }
+
+        let bound = |s: &str| ScoreBound::parse(s.as_bytes()).unwrap();
+        assert_eq!(
+            members(zset.range_by_score(bound("1"), bound("2"), false)),
+            vec![b"b", b"c"]
+        );
+        assert_eq!(
+            members(zset.range_by_score(bound("(1"), bound("+inf"), false)),
+            vec![b"c", b"d"]
+        );
+        assert_eq!(
+            members(zset.range_by_score(bound("-inf"), bound("(2"), true)),
+            vec![b"b", b"a"]
+        );
+        assert!(ScoreBound::parse(b"nan").is_none());
+    }
+
+    #[test]
+    fn range_by_lex_test() {
+        let mut zset = SortedSet::new();
+        for member in ["a", "b", "c", "d"] {
+            zset.insert(Bytes::from(member), 0.0);
+        }
+
+        let bound = |s: &str| LexBound::parse(s.as_bytes()).unwrap();
+        assert_eq!(
+            members(zset.range_by_lex(&bound("[b"), &bound("(d"), false)),
+            vec![b"b", b"c"]
+        );
+        assert_eq!(
+            members(zset.range_by_lex(&bound("-"), &bound("[b"), true)),
+            vec![b"b", b"a"]
+        );
+        assert!(LexBound::parse(b"b").is_none());
+    }
+}